@@ -0,0 +1,118 @@
+use {
+    anyhow::{anyhow, Result},
+    bytes::BytesMut,
+    rtmp::session::{common::Common, define::SessionType},
+    std::time::Duration,
+    streamhub::define::StreamHubEventSender,
+    tokio::time::Instant,
+    xflv::{amf0::amf0_writer::Amf0Writer, define::FlvData, demuxer::FlvDemuxer},
+};
+
+/*milliseconds between the last tag of the file and the first tag of the next loop*/
+const LOOP_GAP: u32 = 40;
+
+/*
+ Publishes a flv file to the local stream hub as a live stream. The tags are
+ sent at the pace of their timestamps like `ffmpeg -re`, and the timestamps
+ continue when the file is looped.
+*/
+pub struct FlvFilePublisher {
+    data: BytesMut,
+    is_loop: bool,
+    common: Common,
+    app_name: String,
+    stream_name: String,
+}
+
+impl FlvFilePublisher {
+    pub fn new(
+        data: BytesMut,
+        is_loop: bool,
+        event_producer: StreamHubEventSender,
+        app_name: String,
+        stream_name: String,
+    ) -> Self {
+        Self {
+            data,
+            is_loop,
+            common: Common::new(None, event_producer, SessionType::Client, None),
+            app_name,
+            stream_name,
+        }
+    }
+
+    pub async fn publish(&mut self) -> Result<()> {
+        self.common
+            .publish_to_channels(self.app_name.clone(), self.stream_name.clone(), 1)
+            .await
+            .map_err(|err| anyhow!("publish flv file error: {}", err))
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let mut offset: u32 = 0;
+
+        loop {
+            let mut demuxer = FlvDemuxer::new(self.data.clone());
+            demuxer
+                .read_flv_header()
+                .map_err(|err| anyhow!("read flv header error: {}", err))?;
+
+            let mut first_timestamp = None;
+            let mut last_timestamp = offset;
+            while !demuxer.is_end() {
+                let tag = match demuxer.read_flv_tag() {
+                    Ok(Some(tag)) => tag,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        log::warn!("the flv file is truncated: {}", err);
+                        break;
+                    }
+                };
+
+                let (FlvData::Video { timestamp, .. }
+                | FlvData::Audio { timestamp, .. }
+                | FlvData::MetaData { timestamp, .. }) = tag;
+                let first_timestamp = *first_timestamp.get_or_insert(timestamp);
+                let timestamp = offset + timestamp.saturating_sub(first_timestamp);
+                tokio::time::sleep_until(start + Duration::from_millis(timestamp as u64)).await;
+
+                self.publish_tag(tag, timestamp).await?;
+                last_timestamp = last_timestamp.max(timestamp);
+            }
+
+            if !self.is_loop {
+                break;
+            }
+            offset = last_timestamp + LOOP_GAP;
+            log::info!("loop the flv file from {}ms", offset);
+        }
+
+        self.common
+            .unpublish_to_channels(self.app_name.clone(), self.stream_name.clone())
+            .await
+            .map_err(|err| anyhow!("unpublish flv file error: {}", err))
+    }
+
+    async fn publish_tag(&mut self, tag: FlvData, timestamp: u32) -> Result<()> {
+        let result = match tag {
+            FlvData::Video { mut data, .. } => {
+                self.common.on_video_data(&mut data, &timestamp).await
+            }
+            FlvData::Audio { mut data, .. } => {
+                self.common.on_audio_data(&mut data, &timestamp).await
+            }
+            FlvData::MetaData { data, .. } => {
+                /*the metadata of a rtmp publish is sent by @setDataFrame*/
+                let mut amf_writer = Amf0Writer::new();
+                amf_writer
+                    .write_string(&String::from("@setDataFrame"))
+                    .map_err(|err| anyhow!("write metadata error: {}", err))?;
+                let mut metadata = amf_writer.extract_current_bytes();
+                metadata.extend_from_slice(&data[..]);
+                self.common.on_meta_data(&mut metadata, &timestamp).await
+            }
+        };
+        result.map_err(|err| anyhow!("publish flv tag error: {}", err))
+    }
+}
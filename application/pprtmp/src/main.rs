@@ -1,6 +1,10 @@
+mod flv_publisher;
+
 use {
     anyhow::Result,
-    clap::{value_parser, Arg, Command},
+    bytes::BytesMut,
+    clap::{value_parser, Arg, ArgAction, ArgGroup, Command},
+    flv_publisher::FlvFilePublisher,
     rtmp::session::client_session::ClientSession,
     rtmp::session::client_session::ClientType,
     rtmp::utils::RtmpUrlParser,
@@ -28,7 +32,26 @@ async fn main() -> Result<()> {
                 .short('i')
                 .value_name("path")
                 .help("Specify the pull rtmp url.")
-                .value_parser(value_parser!(String))
+                .value_parser(value_parser!(String)),
+        )
+        .arg(
+            Arg::new("flvfile")
+                .long("flv_file")
+                .short('f')
+                .value_name("path")
+                .help("Specify the flv file to be pushed as a live stream.")
+                .value_parser(value_parser!(String)),
+        )
+        .arg(
+            Arg::new("loop")
+                .long("loop")
+                .help("Push the flv file repeatedly.")
+                .action(ArgAction::SetTrue)
+                .requires("flvfile"),
+        )
+        .group(
+            ArgGroup::new("input")
+                .args(["pullrtmp", "flvfile"])
                 .required(true),
         )
         .arg(
@@ -47,54 +70,83 @@ async fn main() -> Result<()> {
         return Ok(());
     }
     let matches = cmd.clone().get_matches();
-    let pull_rtmp_url = matches.get_one::<String>("pullrtmp").unwrap().clone();
     let push_rtmp_url = matches.get_one::<String>("pushrtmp").unwrap().clone();
 
     let mut stream_hub = StreamsHub::new(None);
     let producer = stream_hub.get_hub_event_sender();
     tokio::spawn(async move { stream_hub.run().await });
 
-    let mut pull_parser = RtmpUrlParser::new(pull_rtmp_url);
-    if let Err(err) = pull_parser.parse_url() {
-        log::error!("err: {}", err);
-    }
-    pull_parser.append_port(String::from("1935"));
-    let stream1 = TcpStream::connect(pull_parser.raw_domain_name.clone()).await?;
-    let mut pull_client_session = ClientSession::new(
-        stream1,
-        ClientType::Play,
-        pull_parser.raw_domain_name,
-        pull_parser.app_name.clone(),
-        pull_parser.raw_stream_name,
-        producer.clone(),
-        0,
-    );
-    tokio::spawn(async move {
-        if let Err(err) = pull_client_session.run().await {
-            log::error!("pull_client_session as pull client run error: {}", err);
-        }
-    });
-
-    tokio::time::sleep(Duration::from_secs(2)).await;
-
     let mut push_parser = RtmpUrlParser::new(push_rtmp_url);
     if let Err(err) = push_parser.parse_url() {
         log::error!("err: {}", err);
     }
     push_parser.append_port(String::from("1935"));
+
+    // the local stream to be pushed
+    let (sub_app_name, sub_stream_name) =
+        if let Some(flv_file) = matches.get_one::<String>("flvfile") {
+            let data = BytesMut::from(&tokio::fs::read(flv_file).await?[..]);
+            // the file is published locally by the name of the push url
+            let mut flv_publisher = FlvFilePublisher::new(
+                data,
+                matches.get_flag("loop"),
+                producer.clone(),
+                push_parser.app_name.clone(),
+                push_parser.stream_name.clone(),
+            );
+            flv_publisher.publish().await?;
+            tokio::spawn(async move {
+                if let Err(err) = flv_publisher.run().await {
+                    log::error!("flv publisher run error: {}", err);
+                }
+                log::info!("the flv file is pushed");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                exit(0);
+            });
+            (
+                push_parser.app_name.clone(),
+                push_parser.stream_name.clone(),
+            )
+        } else {
+            let pull_rtmp_url = matches.get_one::<String>("pullrtmp").unwrap().clone();
+            let mut pull_parser = RtmpUrlParser::new(pull_rtmp_url);
+            if let Err(err) = pull_parser.parse_url() {
+                log::error!("err: {}", err);
+            }
+            pull_parser.append_port(String::from("1935"));
+            let stream1 = TcpStream::connect(pull_parser.host_with_port.clone()).await?;
+            let mut pull_client_session = ClientSession::new(
+                stream1,
+                ClientType::Play,
+                pull_parser.host_with_port,
+                pull_parser.app_name.clone(),
+                pull_parser.stream_name_with_query,
+                producer.clone(),
+                0,
+            );
+            tokio::spawn(async move {
+                if let Err(err) = pull_client_session.run().await {
+                    log::error!("pull_client_session as pull client run error: {}", err);
+                }
+            });
+
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            (pull_parser.app_name, pull_parser.stream_name)
+        };
+
     // push the rtmp stream from local to remote rtmp server
-    let stream2 = TcpStream::connect(push_parser.raw_domain_name.clone()).await?;
+    let stream2 = TcpStream::connect(push_parser.host_with_port.clone()).await?;
     let mut push_client_session = ClientSession::new(
         stream2,
         ClientType::Publish,
-        push_parser.raw_domain_name,
+        push_parser.host_with_port,
         push_parser.app_name,
-        push_parser.raw_stream_name,
+        push_parser.stream_name_with_query,
         producer.clone(),
         0,
     );
 
-    push_client_session.subscribe(pull_parser.app_name, pull_parser.stream_name);
+    push_client_session.subscribe(sub_app_name, sub_stream_name);
     tokio::spawn(async move {
         if let Err(err) = push_client_session.run().await {
             log::error!("push_client_session as push client run error: {}", err);
@@ -1,6 +1,6 @@
 use commonlib::config::save_config;
 use commonlib::config::load_config;
-use axum::routing::put;
+use axum::routing::{post, put};
 use axum::body::Body;
 use axum::extract::Path;
 use axum::http::{Request, StatusCode};
@@ -24,11 +24,16 @@ use {
         define::{self, StreamHubEventSender},
         utils::Uuid,
     },
-    tokio::{self, sync::oneshot},
+    tokio::{
+        self,
+        sync::{oneshot, Mutex},
+    },
 };
 
 //use pulse::run_stats;
-use commonlib::config::Streams;
+use commonlib::config::{generate_publish_key, Config, PublishKey, Streams};
+use commonlib::config::RecordFormat;
+use http::record::manager::RecordManager;
 
 #[derive(serde::Serialize)]
 struct ApiResponse<T> {
@@ -55,18 +60,114 @@ struct QueryStream {
     stream_name: String,
 }
 
+#[derive(Deserialize)]
+struct QueryRecordings {
+    app_name: Option<String>,
+    stream_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RecordParams {
+    app_name: String,
+    stream_name: String,
+    //"flv" or "mp4", the format of the record settings by default
+    format: Option<RecordFormat>,
+}
+
+#[derive(Deserialize)]
+struct PublishKeyParams {
+    //app_name/stream_name
+    name: String,
+    label: Option<String>,
+    //unix seconds
+    expires_at: Option<u64>,
+}
+
+fn normalize_stream_name(name: &str) -> String {
+    if name.contains('/') {
+        name.to_string()
+    } else {
+        format!("static/{}", name)
+    }
+}
+
+fn failed(message: &str) -> Json<ApiResponse<Value>> {
+    Json(ApiResponse {
+        success: false,
+        message: String::from(message),
+        data: serde_json::json!(""),
+    })
+}
+
 #[derive(Clone)]
 struct ApiService {
     channel_event_producer: StreamHubEventSender,
+    record_manager: Option<Arc<RecordManager>>,
+    /*held across the read-modify-write of config.json by the stream and key apis*/
+    config_lock: Arc<Mutex<()>>,
 }
 
 impl ApiService {
+    /*Load config.json, modify the stream entry and save it back, the publish
+    sessions read config.json on every publish, so no restart is needed.*/
+    async fn update_stream_config<F>(&self, name: &str, update: F) -> Json<ApiResponse<Value>>
+    where
+        F: FnOnce(&mut Streams) -> Result<Value, String>,
+    {
+        let config_path = "config.json";
+        let _config_guard = self.config_lock.lock().await;
+
+        let mut config: Config = match load_config(config_path) {
+            Ok(config) => config,
+            Err(err) => {
+                log::error!("Failed to load config: {}", err);
+                return failed("failed to load config");
+            }
+        };
+
+        let name = normalize_stream_name(name);
+        let stream = match config
+            .streams
+            .as_mut()
+            .and_then(|streams| streams.iter_mut().find(|s| s.name == name))
+        {
+            Some(stream) => stream,
+            None => return failed("stream not found"),
+        };
+
+        let data = match update(stream) {
+            Ok(data) => data,
+            Err(message) => return failed(&message),
+        };
+
+        match save_config(config_path, &config) {
+            Ok(_) => Json(ApiResponse {
+                success: true,
+                message: String::from("success"),
+                data,
+            }),
+            Err(err) => {
+                log::error!("Failed to save config: {}", err);
+                failed("failed to save config")
+            }
+        }
+    }
+
     async fn root(&self) -> String {
         String::from(
             "Usage of xiu http api:
                 ./api/streams(get) query whole streams' information or top streams' information.
                 ./api/stream?app_name=demo&stream_name=demo(get) query stream information by identifier and uuid.
-                ./api/session/<session_id>(delete) kick off client by publish/subscribe id.\n",
+                ./api/session/<session_id>(delete) kick off client by publish/subscribe id.
+                ./api/stream/keys?name=app/stream(get) list the publish keys of a stream.
+                ./api/stream/key(post) generate a publish key: {\"name\", \"label\", \"expires_at\"}.
+                ./api/stream/key(put) rotate a publish key and kick off its publisher: {\"name\", \"label\", \"expires_at\"}.
+                ./api/stream/key(delete) revoke a publish key and kick off its publisher: {\"name\", \"label\"}.
+                ./api/recordings?app_name=demo&stream_name=demo(get) list the finished recordings, both are optional.
+                ./api/recordings/<id>(delete) delete a finished recording and its files.
+                ./api/record?app_name=demo&stream_name=demo(get) list the active recordings, both are optional.
+                ./api/record(post) start recording a live stream: {\"app_name\", \"stream_name\", \"format\"}.
+                ./api/record(delete) stop recording a stream: {\"app_name\", \"stream_name\"}.\n",
         )
     }
 
@@ -153,6 +254,7 @@ impl ApiService {
 
     pub async fn add_stream(&self, mut stream: Streams) -> Json<ApiResponse<Value>> {
         let config_path = "config.json";
+        let _config_guard = self.config_lock.lock().await;
 
         // Ensure stream.name follows the correct format
         if !stream.name.contains('/') {
@@ -177,6 +279,10 @@ impl ApiService {
 
         // Check if the stream exists by name and update or add it
         if let Some(existing_stream) = streams.iter_mut().find(|s| s.name == stream.name) {
+            // the publish keys are managed by the key api
+            if stream.publish_keys.is_none() {
+                stream.publish_keys = existing_stream.publish_keys.clone();
+            }
             *existing_stream = stream.clone();  // Update existing stream
         } else {
             streams.push(stream.clone());  // Add new stream
@@ -202,6 +308,7 @@ impl ApiService {
 
     pub async fn delete_stream(&self, mut del_stream: Streams) -> Json<ApiResponse<Value>> {
         let config_path = "config.json";
+        let _config_guard = self.config_lock.lock().await;
 
         // Ensure stream.name follows the correct format
         if !del_stream.name.contains('/') {
@@ -264,6 +371,221 @@ impl ApiService {
         }
     }
 
+    /*the keys themselves are only returned when they are generated*/
+    fn list_publish_keys(&self, params: PublishKeyParams) -> Json<ApiResponse<Value>> {
+        let config: Config = match load_config("config.json") {
+            Ok(config) => config,
+            Err(err) => {
+                log::error!("Failed to load config: {}", err);
+                return failed("failed to load config");
+            }
+        };
+
+        let name = normalize_stream_name(&params.name);
+        match config.streams.iter().flatten().find(|s| s.name == name) {
+            Some(stream) => {
+                let keys: Vec<Value> = stream
+                    .publish_keys
+                    .iter()
+                    .flatten()
+                    .map(|k| serde_json::json!({"label": k.label, "expires_at": k.expires_at}))
+                    .collect();
+                Json(ApiResponse {
+                    success: true,
+                    message: String::from("success"),
+                    data: serde_json::json!(keys),
+                })
+            }
+            None => failed("stream not found"),
+        }
+    }
+
+    async fn generate_publish_key(&self, params: PublishKeyParams) -> Json<ApiResponse<Value>> {
+        let label = match params.label {
+            Some(label) => label,
+            None => return failed("label is required"),
+        };
+        let expires_at = params.expires_at;
+
+        self.update_stream_config(&params.name, |stream| {
+            let keys = stream.publish_keys.get_or_insert_with(Vec::new);
+            if keys.iter().any(|k| k.label == label) {
+                return Err(format!("publish key {} already exists", label));
+            }
+
+            let key = generate_publish_key();
+            keys.push(PublishKey {
+                label: label.clone(),
+                key: key.clone(),
+                expires_at,
+            });
+            Ok(serde_json::json!({"label": label, "key": key, "expires_at": expires_at}))
+        })
+        .await
+    }
+
+    async fn rotate_publish_key(&self, params: PublishKeyParams) -> Json<ApiResponse<Value>> {
+        let label = match params.label {
+            Some(label) => label,
+            None => return failed("label is required"),
+        };
+        let expires_at = params.expires_at;
+
+        let response = self
+            .update_stream_config(&params.name, |stream| {
+                let publish_key = match stream
+                    .publish_keys
+                    .iter_mut()
+                    .flatten()
+                    .find(|k| k.label == label)
+                {
+                    Some(publish_key) => publish_key,
+                    None => return Err(format!("publish key {} not found", label)),
+                };
+
+                publish_key.key = generate_publish_key();
+                publish_key.expires_at = expires_at;
+                Ok(serde_json::json!({
+                    "label": label,
+                    "key": publish_key.key,
+                    "expires_at": expires_at
+                }))
+            })
+            .await;
+        if response.success {
+            self.kick_off_publisher(&params.name, &label);
+        }
+        response
+    }
+
+    async fn revoke_publish_key(&self, params: PublishKeyParams) -> Json<ApiResponse<Value>> {
+        let label = match params.label {
+            Some(label) => label,
+            None => return failed("label is required"),
+        };
+
+        let response = self
+            .update_stream_config(&params.name, |stream| {
+                let keys = stream.publish_keys.get_or_insert_with(Vec::new);
+                let original_len = keys.len();
+                keys.retain(|k| k.label != label);
+                if keys.len() == original_len {
+                    return Err(format!("publish key {} not found", label));
+                }
+                Ok(serde_json::json!(""))
+            })
+            .await;
+        if response.success {
+            self.kick_off_publisher(&params.name, &label);
+        }
+        response
+    }
+
+    /*the publisher connected with the old key is kicked off, it has to publish
+    again with the new one*/
+    fn kick_off_publisher(&self, name: &str, label: &str) {
+        let hub_event = define::StreamHubEvent::ApiKickPublisher {
+            name: normalize_stream_name(name),
+            publish_key: label.to_string(),
+        };
+        if let Err(err) = self.channel_event_producer.send(hub_event) {
+            log::error!("send api kick_off_publisher event error: {}", err);
+        }
+    }
+
+    fn query_recordings(&self, params: QueryRecordings) -> Json<ApiResponse<Value>> {
+        let record_manager = match &self.record_manager {
+            Some(record_manager) => record_manager,
+            None => return failed("recording is not enabled"),
+        };
+
+        let recordings = record_manager
+            .record_service()
+            .index()
+            .list(params.app_name.as_deref(), params.stream_name.as_deref());
+        Json(ApiResponse {
+            success: true,
+            message: String::from("success"),
+            data: serde_json::json!(recordings),
+        })
+    }
+
+    fn delete_recording(&self, id: u64) -> Json<ApiResponse<Value>> {
+        let record_manager = match &self.record_manager {
+            Some(record_manager) => record_manager,
+            None => return failed("recording is not enabled"),
+        };
+
+        match record_manager.record_service().index().remove(id) {
+            Some(recording) => Json(ApiResponse {
+                success: true,
+                message: String::from("success"),
+                data: serde_json::json!(recording),
+            }),
+            None => failed("recording not found"),
+        }
+    }
+
+    fn query_active_recordings(&self, params: QueryRecordings) -> Json<ApiResponse<Value>> {
+        let record_manager = match &self.record_manager {
+            Some(record_manager) => record_manager,
+            None => return failed("recording is not enabled"),
+        };
+
+        let recordings =
+            record_manager.active(params.app_name.as_deref(), params.stream_name.as_deref());
+        Json(ApiResponse {
+            success: true,
+            message: String::from("success"),
+            data: serde_json::json!(recordings),
+        })
+    }
+
+    /*the path is set when the first key frame is received, the paths of the
+    files are sent to the record notifications*/
+    async fn start_recording(&self, params: RecordParams) -> Json<ApiResponse<Value>> {
+        let record_manager = match &self.record_manager {
+            Some(record_manager) => record_manager,
+            None => return failed("recording is not enabled"),
+        };
+
+        match record_manager
+            .start(&params.app_name, &params.stream_name, params.format)
+            .await
+        {
+            Ok(recording) => Json(ApiResponse {
+                success: true,
+                message: String::from("success"),
+                data: serde_json::json!(recording),
+            }),
+            Err(err) => {
+                log::error!(
+                    "start recording {}/{} error: {}",
+                    params.app_name,
+                    params.stream_name,
+                    err
+                );
+                failed(&err.to_string())
+            }
+        }
+    }
+
+    fn stop_recording(&self, params: RecordParams) -> Json<ApiResponse<Value>> {
+        let record_manager = match &self.record_manager {
+            Some(record_manager) => record_manager,
+            None => return failed("recording is not enabled"),
+        };
+
+        match record_manager.stop(&params.app_name, &params.stream_name) {
+            Some(recording) => Json(ApiResponse {
+                success: true,
+                message: String::from("success"),
+                data: serde_json::json!(recording),
+            }),
+            None => failed("the stream is not being recorded"),
+        }
+    }
+
     async fn kick_off_client(&self, id: KickOffClient) -> Json<ApiResponse<Value>> {
         match Uuid::from_str2(&id.uuid) {
             Some(id) => {
@@ -332,12 +654,15 @@ async fn basic_auth(
 
 pub async fn run(
     producer: StreamHubEventSender,
+    record_manager: Option<Arc<RecordManager>>,
     port: usize,
     username: String,
     password: String
 ) {
     let api = Arc::new(ApiService {
         channel_event_producer: producer,
+        record_manager,
+        config_lock: Arc::new(Mutex::new(())),
     });
 
     let api_root = api.clone();
@@ -362,6 +687,51 @@ pub async fn run(
         api_delete_stream.delete_stream(stream).await
     };
 
+    let api_list_keys = api.clone();
+    let list_publish_keys = move |Query(params): Query<PublishKeyParams>| async move {
+        api_list_keys.list_publish_keys(params)
+    };
+
+    let api_generate_key = api.clone();
+    let generate_publish_key = move |Json(params): Json<PublishKeyParams>| async move {
+        api_generate_key.generate_publish_key(params).await
+    };
+
+    let api_rotate_key = api.clone();
+    let rotate_publish_key = move |Json(params): Json<PublishKeyParams>| async move {
+        api_rotate_key.rotate_publish_key(params).await
+    };
+
+    let api_revoke_key = api.clone();
+    let revoke_publish_key = move |Json(params): Json<PublishKeyParams>| async move {
+        api_revoke_key.revoke_publish_key(params).await
+    };
+
+    let api_query_recordings = api.clone();
+    let query_recordings = move |Query(params): Query<QueryRecordings>| async move {
+        api_query_recordings.query_recordings(params)
+    };
+
+    let api_delete_recording = api.clone();
+    let delete_recording = move |Path(id): Path<u64>| async move {
+        api_delete_recording.delete_recording(id)
+    };
+
+    let api_query_active = api.clone();
+    let query_active_recordings = move |Query(params): Query<QueryRecordings>| async move {
+        api_query_active.query_active_recordings(params)
+    };
+
+    let api_start_recording = api.clone();
+    let start_recording = move |Json(params): Json<RecordParams>| async move {
+        api_start_recording.start_recording(params).await
+    };
+
+    let api_stop_recording = api.clone();
+    let stop_recording = move |Json(params): Json<RecordParams>| async move {
+        api_stop_recording.stop_recording(params)
+    };
+
     let api_kick_off = api.clone();
     let kick_off = move |Path(id): Path<String>| async move {
         api_kick_off.kick_off_client(KickOffClient { uuid: id }).await
@@ -378,6 +748,21 @@ pub async fn run(
         .route("/api/stream", get(query_stream))
         .route("/api/stream", put(add_stream))
         .route("/api/stream", delete(delete_stream))
+        .route("/api/stream/keys", get(list_publish_keys))
+        .route(
+            "/api/stream/key",
+            post(generate_publish_key)
+                .put(rotate_publish_key)
+                .delete(revoke_publish_key),
+        )
+        .route("/api/recordings", get(query_recordings))
+        .route("/api/recordings/:id", delete(delete_recording))
+        .route(
+            "/api/record",
+            get(query_active_recordings)
+                .post(start_recording)
+                .delete(stop_recording),
+        )
         .route("/api/session/:id", delete(kick_off))
         // .route("/api/pulse", get(pulse))
         .layer(ServiceBuilder::new().layer(middleware::from_fn(move |req, next| {
@@ -1,6 +1,12 @@
 use commonlib::auth::AuthType;
 use std::sync::Arc;
-use commonlib::config::{AuthConfig, AuthSecretConfig};
+use commonlib::access::AccessControl;
+use commonlib::config::{
+    AccessConfig, AuthConfig, AuthSecretConfig, RecordConfig, VirtualChannelConfig,
+};
+use commonlib::record::RecordService;
+use commonlib::vod::VodService;
+use commonlib::jwt::JwtValidator;
 
 use {
     super::api,
@@ -10,23 +16,94 @@ use {
     rtmp::{
         relay::{pull_client::PullClient, push_client::PushClient},
         rtmp::RtmpServer,
+        virtual_channel::VirtualChannel,
     },
-    streamhub::{notify::Notifier, notify::http::HttpNotifier, StreamsHub},
+    std::time::Duration,
+    streamhub::{notify::Notifier, notify::http::HttpNotifier, stream::Protocol, StreamsHub},
     tokio,
 };
-use http::hls::remuxer::HlsRemuxer;
+use http::dash::remuxer::DashRemuxer;
+use http::hls::{registry::HlsRegistry, remuxer::HlsRemuxer};
+use http::record::{manager::RecordManager, remuxer::RecordRemuxer};
 use commonlib::config::Config;
 
 pub struct Service {
     cfg: Config,
+    record_service: Option<Arc<RecordService>>,
+    record_manager: Option<Arc<RecordManager>>,
 }
 
 impl Service {
     pub fn new(cfg: Config) -> Self {
-        Service { cfg }
+        Service {
+            cfg,
+            record_service: None,
+            record_manager: None,
+        }
+    }
+
+    /*the legacy need_record keeps every stream under {output_root}/{app}/{stream},
+    a directory per recording named by its start time,
+    without a record config the streams are only recorded on demand by the api*/
+    fn gen_record_config(cfg: &Config) -> RecordConfig {
+        match &cfg.record {
+            Some(record) => record.clone(),
+            None if cfg.http.as_ref().is_some_and(|http| http.need_record) => RecordConfig {
+                enabled: true,
+                path: Some(String::from("{app}/{stream}")),
+                ..Default::default()
+            },
+            None => RecordConfig::default(),
+        }
+    }
+
+    /*the grace period is hub wide, the key in the rtmp config is kept for the
+    existing config files*/
+    fn gen_grace_period(cfg: &Config) -> u64 {
+        cfg.grace_period
+            .or_else(|| cfg.rtmp.as_ref().and_then(|rtmp| rtmp.grace_period))
+            .unwrap_or(0)
+    }
+
+    /*the recorded files are looked up under the root of the recordings if the
+    vod config has no root*/
+    fn gen_vod(cfg: &Config) -> Option<Arc<VodService>> {
+        let vod = cfg.vod.as_ref().filter(|vod| vod.enabled)?;
+        let record_config = Self::gen_record_config(cfg);
+        Some(Arc::new(VodService::new(vod, record_config.root.as_deref())))
     }
 
-    fn gen_auth(auth_config: &Option<AuthConfig>, authsecret: &AuthSecretConfig) -> Option<Auth> {
+    fn start_record(&mut self, stream_hub: &mut StreamsHub) {
+        let record_service = Arc::new(RecordService::new(Self::gen_record_config(&self.cfg)));
+        let record_manager = Arc::new(RecordManager::new(
+            stream_hub.get_hub_event_sender(),
+            record_service.clone(),
+        ));
+
+        let mut record_remuxer =
+            RecordRemuxer::new(stream_hub.get_client_event_consumer(), record_manager.clone());
+        tokio::spawn(async move {
+            if let Err(err) = record_remuxer.run().await {
+                log::error!("record remuxer error: {}", err);
+            }
+        });
+
+        let retention_service = record_service.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                retention_service.apply_retention();
+            }
+        });
+        self.record_service = Some(record_service);
+        self.record_manager = Some(record_manager);
+    }
+
+    fn gen_auth(
+        auth_config: &Option<AuthConfig>,
+        authsecret: &AuthSecretConfig,
+    ) -> Result<Option<Auth>> {
         if let Some(cfg) = auth_config {
             let auth_type = if let Some(push_enabled) = cfg.push_enabled {
                 if push_enabled && cfg.pull_enabled {
@@ -44,15 +121,34 @@ impl Service {
                     false => AuthType::None,
                 }
             };
-            Some(Auth::new(
+            let jwt = match &cfg.jwt {
+                Some(jwt_cfg) => Some(
+                    JwtValidator::new(jwt_cfg)
+                        .map_err(|err| anyhow::anyhow!("jwt config error: {}", err))?,
+                ),
+                None => None,
+            };
+            Ok(Some(Auth::new(
                 authsecret.key.clone(),
                 authsecret.password.clone(),
                 authsecret.push_password.clone(),
                 cfg.algorithm.clone(),
                 auth_type,
-            ))
+                cfg.bind_ip.unwrap_or(false),
+                jwt,
+            )))
         } else {
-            None
+            Ok(None)
+        }
+    }
+
+    fn gen_access(access_config: &Option<AccessConfig>) -> Result<Option<Arc<AccessControl>>> {
+        if let Some(cfg) = access_config {
+            let access = AccessControl::new(cfg)
+                .map_err(|err| anyhow::anyhow!("access config error: {}", err))?;
+            Ok(Some(Arc::new(access)))
+        } else {
+            Ok(None)
         }
     }
 
@@ -66,6 +162,8 @@ impl Service {
                     httpnotifier.on_unpublish.clone(),
                     httpnotifier.on_play.clone(),
                     httpnotifier.on_stop.clone(),
+                    httpnotifier.on_record_start.clone(),
+                    httpnotifier.on_record_stop.clone(),
                 )))
             }
         } else {
@@ -75,9 +173,13 @@ impl Service {
         let edit_auth = self.cfg.edit_auth.clone();
         println!("edit_auth: {:?}", edit_auth);
         let mut stream_hub = StreamsHub::new(notifier);
+        stream_hub.set_grace_period(Duration::from_millis(Self::gen_grace_period(&self.cfg)));
 
+        self.start_record(&mut stream_hub);
         self.start_http(&mut stream_hub).await?;
         self.start_rtmp(&mut stream_hub).await?;
+        self.start_virtual_channels(&mut stream_hub)?;
+        self.start_failover(&mut stream_hub)?;
         self.start_http_api_server(&mut stream_hub, edit_auth.username, edit_auth.password).await?;
 
         tokio::spawn(async move {
@@ -89,6 +191,7 @@ impl Service {
 
     async fn start_http_api_server(&mut self, stream_hub: &mut StreamsHub, username: String, password: String) -> Result<()> {
         let producer = stream_hub.get_hub_event_sender();
+        let record_manager = self.record_manager.clone();
 
         let http_api_port = if let Some(api) = &self.cfg.api {
             api.port
@@ -97,7 +200,7 @@ impl Service {
         };
 
         tokio::spawn(async move {
-            api::run(producer, http_api_port, username, password).await;
+            api::run(producer, record_manager, http_api_port, username, password).await;
         });
         Ok(())
     }
@@ -170,11 +273,22 @@ impl Service {
                 }
             }
 
+            //shared by all the rtmp ports, so the per ip limits are counted together
+            let access = Self::gen_access(&rtmp_cfg_value.access)?;
+            let vod = Self::gen_vod(&self.cfg);
             for listen_port in rtmp_cfg_value.port.clone() {
                 let address = format!("0.0.0.0:{listen_port}");
 
-                let auth = Self::gen_auth(&rtmp_cfg_value.auth, &self.cfg.authsecret);
-                let mut rtmp_server = RtmpServer::new(address, producer.clone(), gop_num, auth);
+                let auth = Self::gen_auth(&rtmp_cfg_value.auth, &self.cfg.authsecret)?;
+                let mut rtmp_server = RtmpServer::new(
+                    address,
+                    producer.clone(),
+                    gop_num,
+                    auth,
+                    rtmp_cfg_value.proxy_protocol.unwrap_or(false),
+                    access.clone(),
+                    vod.clone(),
+                );
                 tokio::spawn(async move {
                     if let Err(err) = rtmp_server.run().await {
                         log::error!("rtmp server error on port {}: {}", listen_port, err);
@@ -186,6 +300,92 @@ impl Service {
         Ok(())
     }
 
+    fn gop_num(&self) -> usize {
+        self.cfg
+            .rtmp
+            .as_ref()
+            .and_then(|rtmp| rtmp.gop_num)
+            .unwrap_or(1)
+    }
+
+    /*the channels are published like the rtmp streams, with the same gop cache*/
+    fn start_virtual_channels(&mut self, stream_hub: &mut StreamsHub) -> Result<()> {
+        for channel_cfg in self.cfg.virtual_channels.iter().flatten() {
+            if !channel_cfg.enabled {
+                continue;
+            }
+            Self::spawn_virtual_channel(channel_cfg, stream_hub, self.gop_num())?;
+        }
+        Ok(())
+    }
+
+    fn spawn_virtual_channel(
+        channel_cfg: &VirtualChannelConfig,
+        stream_hub: &mut StreamsHub,
+        gop_num: usize,
+    ) -> Result<()> {
+        let mut channel =
+            VirtualChannel::new(channel_cfg, stream_hub.get_hub_event_sender(), gop_num)
+                .map_err(|err| {
+                    anyhow::anyhow!(
+                        "virtual channel {}/{} config error: {}",
+                        channel_cfg.app,
+                        channel_cfg.stream,
+                        err
+                    )
+                })?;
+        tokio::spawn(async move {
+            if let Err(err) = channel.run().await {
+                log::error!("virtual channel error: {}", err);
+            }
+        });
+        Ok(())
+    }
+
+    /*the backups of a stream are tried in order, the slate file is published
+    as the "{stream}_slate" virtual channel and it is tried last*/
+    fn start_failover(&mut self, stream_hub: &mut StreamsHub) -> Result<()> {
+        for stream_cfg in self.cfg.streams.iter().flatten() {
+            let failover_cfg = match &stream_cfg.failover {
+                Some(failover_cfg) => failover_cfg,
+                None => continue,
+            };
+            let (app_name, stream_name) = match stream_cfg.name.split_once('/') {
+                Some(names) => names,
+                None => {
+                    log::error!("failover: invalid stream name {}", stream_cfg.name);
+                    continue;
+                }
+            };
+
+            let mut backups: Vec<String> = failover_cfg
+                .backups
+                .iter()
+                .flatten()
+                .map(|backup| format!("{}/{}", app_name, backup))
+                .collect();
+            if let Some(slate) = &failover_cfg.slate {
+                let channel_cfg = VirtualChannelConfig {
+                    enabled: true,
+                    app: app_name.to_string(),
+                    stream: format!("{}_slate", stream_name),
+                    playlist: vec![slate.clone()],
+                    schedule: None,
+                };
+                Self::spawn_virtual_channel(&channel_cfg, stream_hub, self.gop_num())?;
+                backups.push(format!("{}/{}", channel_cfg.app, channel_cfg.stream));
+            }
+
+            stream_hub.add_failover_group(
+                Protocol::Rtmp,
+                stream_cfg.name.clone(),
+                backups,
+                Duration::from_millis(failover_cfg.stall_timeout.unwrap_or(3000)),
+            );
+        }
+        Ok(())
+    }
+
     async fn start_http(&mut self, stream_hub: &mut StreamsHub) -> Result<()> {
         let hls_cfg = &self.cfg.http;
 
@@ -196,10 +396,29 @@ impl Service {
 
             let event_producer = stream_hub.get_hub_event_sender();
             let cient_event_consumer = stream_hub.get_client_event_consumer();
+            let part_duration = match &http_cfg_value.ll_hls {
+                Some(ll_hls) if ll_hls.enabled => {
+                    Some(ll_hls.part_duration.unwrap_or(1000) as i64)
+                }
+                _ => None,
+            };
+            let on_demand_idle_timeout = match &http_cfg_value.hls_on_demand {
+                Some(on_demand) if on_demand.enabled => {
+                    Some(on_demand.idle_timeout.unwrap_or(30) as i64 * 1000)
+                }
+                _ => None,
+            };
+            //shared by the hls remuxer and the http servers for the LL-HLS blocking requests
+            let hls_registry = Arc::new(HlsRegistry::default());
+            hls_registry.set_groups(http_cfg_value.hls_groups.clone().unwrap_or_default());
             let mut hls_remuxer = HlsRemuxer::new(
                 cient_event_consumer,
                 event_producer.clone(),
-                http_cfg_value.need_record,
+                self.record_service.clone(),
+                part_duration,
+                http_cfg_value.hls_apps.clone().unwrap_or_default(),
+                hls_registry.clone(),
+                on_demand_idle_timeout,
             );
 
             tokio::spawn(async move {
@@ -208,11 +427,43 @@ impl Service {
                 }
             });
 
+            if let Some(dash_cfg) = &http_cfg_value.dash {
+                if dash_cfg.enabled {
+                    let mut dash_remuxer = DashRemuxer::new(
+                        stream_hub.get_client_event_consumer(),
+                        event_producer.clone(),
+                        dash_cfg.segment_duration.unwrap_or(4) as i64 * 1000,
+                        dash_cfg.time_shift_buffer_depth.unwrap_or(30) as i64 * 1000,
+                    );
+                    tokio::spawn(async move {
+                        if let Err(err) = dash_remuxer.run().await {
+                            log::error!("dash remuxer error: {}", err);
+                        }
+                    });
+                }
+            }
+
+            let proxy_protocol = http_cfg_value.proxy_protocol.unwrap_or(false);
+            let access = Self::gen_access(&http_cfg_value.access)?;
+            let vod = Self::gen_vod(&self.cfg);
             for port in http_cfg_value.port.clone() {
-                let auth = Self::gen_auth(&http_cfg_value.auth, &self.cfg.authsecret);
+                let auth = Self::gen_auth(&http_cfg_value.auth, &self.cfg.authsecret)?;
                 let event_producer = event_producer.clone();
+                let access = access.clone();
+                let hls_registry = hls_registry.clone();
+                let vod = vod.clone();
                 tokio::spawn(async move {
-                    if let Err(err) = http_server::run(event_producer, port, auth).await {
+                    if let Err(err) = http_server::run(
+                        event_producer,
+                        port,
+                        auth,
+                        proxy_protocol,
+                        access,
+                        hls_registry,
+                        vod,
+                    )
+                    .await
+                    {
                         log::error!("http server error on port {}: {}", port, err);
                     }
                 });
@@ -10,3 +10,5 @@ pub mod bytes_reader;
 pub mod bytes_writer;
 pub mod bytesio;
 pub mod bytesio_errors;
+pub mod proxy_protocol;
+pub mod proxy_protocol_errors;
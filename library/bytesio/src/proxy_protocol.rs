@@ -0,0 +1,322 @@
+use {
+    super::proxy_protocol_errors::{ProxyProtocolError, ProxyProtocolErrorValue},
+    std::{
+        io,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+        time::Duration,
+    },
+    tokio::{io::AsyncReadExt, net::TcpStream, time::sleep},
+};
+
+//https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt
+const V1_PREFIX: &[u8] = b"PROXY ";
+//a v1 header line is at most 107 bytes including the CRLF
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+const V2_COMMAND_LOCAL: u8 = 0x00;
+const V2_COMMAND_PROXY: u8 = 0x01;
+
+const V2_FAMILY_INET: u8 = 0x01;
+const V2_FAMILY_INET6: u8 = 0x02;
+
+/*The addresses carried by a PROXY protocol header. Both are None when the
+load balancer sends "UNKNOWN"(v1) or the LOCAL command(v2), e.g. for health
+checks, in which case the connection address should be used.*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyHeader {
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+}
+
+impl ProxyHeader {
+    /*return the real client address, falling back to the TCP peer address*/
+    pub fn remote_addr_or(&self, peer_addr: SocketAddr) -> SocketAddr {
+        self.source.unwrap_or(peer_addr)
+    }
+}
+
+fn invalid_header(reason: &str) -> ProxyProtocolError {
+    ProxyProtocolError {
+        value: ProxyProtocolErrorValue::InvalidHeader(reason.to_string()),
+    }
+}
+
+/*Read exactly one PROXY protocol(v1 or v2) header from the stream. Nothing beyond
+the header is consumed, so the stream can be handed to the RTMP/HTTP session as is.*/
+pub async fn read_proxy_header(
+    stream: &mut TcpStream,
+    duration: Duration,
+) -> Result<ProxyHeader, ProxyProtocolError> {
+    match tokio::time::timeout(duration, read_header(stream)).await {
+        Ok(header) => header,
+        Err(err) => Err(ProxyProtocolError {
+            value: ProxyProtocolErrorValue::TimeoutError(err),
+        }),
+    }
+}
+
+async fn read_header(stream: &mut TcpStream) -> Result<ProxyHeader, ProxyProtocolError> {
+    /*"PROXY UNKNOWN\r\n" is the shortest v1 header(15 bytes), so reading
+    the 12 bytes signature length never consumes any payload data.*/
+    let mut prefix = [0_u8; 12];
+    stream.read_exact(&mut prefix).await?;
+
+    if prefix == V2_SIGNATURE {
+        let mut fixed = [0_u8; 4];
+        stream.read_exact(&mut fixed).await?;
+        let length = u16::from_be_bytes([fixed[2], fixed[3]]) as usize;
+        let mut body = vec![0_u8; length];
+        stream.read_exact(&mut body).await?;
+        return parse_v2(fixed[0], fixed[1], &body);
+    }
+
+    if !prefix.starts_with(V1_PREFIX) {
+        return Err(ProxyProtocolError {
+            value: ProxyProtocolErrorValue::InvalidSignature,
+        });
+    }
+
+    /*the rest of the line is peeked until its CRLF shows up, then only the
+    line itself is consumed*/
+    let mut line = [0_u8; V1_MAX_LENGTH];
+    line[..prefix.len()].copy_from_slice(&prefix);
+    let length = loop {
+        let peeked = stream.peek(&mut line[prefix.len()..]).await?;
+        if peeked == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let end = prefix.len() + peeked;
+        if let Some(pos) = line[..end].windows(2).position(|w| w == b"\r\n") {
+            break pos + 2;
+        }
+        if end >= V1_MAX_LENGTH {
+            return Err(ProxyProtocolError {
+                value: ProxyProtocolErrorValue::HeaderTooLong,
+            });
+        }
+        //peek returns at once while any data is queued, wait for the rest of the line
+        sleep(Duration::from_millis(10)).await;
+    };
+    stream.read_exact(&mut line[prefix.len()..length]).await?;
+
+    let line = std::str::from_utf8(&line[..length - 2])
+        .map_err(|_| invalid_header("v1 header is not valid ascii"))?;
+    parse_v1(line)
+}
+
+/*PROXY TCP4 192.168.0.1 192.168.0.11 56324 443*/
+pub fn parse_v1(line: &str) -> Result<ProxyHeader, ProxyProtocolError> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    if fields.len() < 2 || fields[0] != "PROXY" {
+        return Err(invalid_header("v1 header must start with PROXY"));
+    }
+
+    match fields[1] {
+        "UNKNOWN" => Ok(ProxyHeader::default()),
+        "TCP4" | "TCP6" => {
+            if fields.len() != 6 {
+                return Err(invalid_header("v1 header field count is not correct"));
+            }
+            let source_ip: IpAddr = fields[2]
+                .parse()
+                .map_err(|_| invalid_header("v1 source address is not valid"))?;
+            let destination_ip: IpAddr = fields[3]
+                .parse()
+                .map_err(|_| invalid_header("v1 destination address is not valid"))?;
+            let source_port: u16 = fields[4]
+                .parse()
+                .map_err(|_| invalid_header("v1 source port is not valid"))?;
+            let destination_port: u16 = fields[5]
+                .parse()
+                .map_err(|_| invalid_header("v1 destination port is not valid"))?;
+
+            if (fields[1] == "TCP4") != source_ip.is_ipv4() {
+                return Err(invalid_header("v1 address family does not match"));
+            }
+
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(source_ip, source_port)),
+                destination: Some(SocketAddr::new(destination_ip, destination_port)),
+            })
+        }
+        _ => Err(invalid_header("v1 protocol is not supported")),
+    }
+}
+
+/*ver_cmd: the 13th byte, fam: the 14th byte, body: the address block*/
+pub fn parse_v2(ver_cmd: u8, fam: u8, body: &[u8]) -> Result<ProxyHeader, ProxyProtocolError> {
+    let version = ver_cmd >> 4;
+    if version != 2 {
+        return Err(ProxyProtocolError {
+            value: ProxyProtocolErrorValue::UnsupportedVersion(version),
+        });
+    }
+
+    match ver_cmd & 0x0F {
+        V2_COMMAND_LOCAL => return Ok(ProxyHeader::default()),
+        V2_COMMAND_PROXY => {}
+        _ => return Err(invalid_header("v2 command is not supported")),
+    }
+
+    match fam >> 4 {
+        V2_FAMILY_INET => {
+            if body.len() < 12 {
+                return Err(invalid_header("v2 ipv4 address block is too short"));
+            }
+            let source_ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let destination_ip = Ipv4Addr::new(body[4], body[5], body[6], body[7]);
+            let source_port = u16::from_be_bytes([body[8], body[9]]);
+            let destination_port = u16::from_be_bytes([body[10], body[11]]);
+
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(IpAddr::V4(source_ip), source_port)),
+                destination: Some(SocketAddr::new(
+                    IpAddr::V4(destination_ip),
+                    destination_port,
+                )),
+            })
+        }
+        V2_FAMILY_INET6 => {
+            if body.len() < 36 {
+                return Err(invalid_header("v2 ipv6 address block is too short"));
+            }
+            let mut source_ip = [0_u8; 16];
+            source_ip.copy_from_slice(&body[0..16]);
+            let mut destination_ip = [0_u8; 16];
+            destination_ip.copy_from_slice(&body[16..32]);
+            let source_port = u16::from_be_bytes([body[32], body[33]]);
+            let destination_port = u16::from_be_bytes([body[34], body[35]]);
+
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(
+                    IpAddr::V6(Ipv6Addr::from(source_ip)),
+                    source_port,
+                )),
+                destination: Some(SocketAddr::new(
+                    IpAddr::V6(Ipv6Addr::from(destination_ip)),
+                    destination_port,
+                )),
+            })
+        }
+        //AF_UNSPEC or AF_UNIX, there is no usable ip address.
+        _ => Ok(ProxyHeader::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_proxy_header, ProxyHeader, V2_SIGNATURE};
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /*the accepted stream and the client which has written the data*/
+    async fn accept(data: &[u8]) -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        client.write_all(data).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        (stream, client)
+    }
+
+    async fn remaining(stream: &mut TcpStream, client: TcpStream) -> Vec<u8> {
+        drop(client);
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).await.unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_read_v1_header() {
+        let (mut stream, client) =
+            accept(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 1935\r\n\x03rtmp").await;
+        let header = read_proxy_header(&mut stream, Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            header.source,
+            Some("192.168.0.1:56324".parse::<SocketAddr>().unwrap())
+        );
+        assert_eq!(
+            header.destination,
+            Some("192.168.0.11:1935".parse::<SocketAddr>().unwrap())
+        );
+        //the payload after the header must be left untouched
+        assert_eq!(remaining(&mut stream, client).await, b"\x03rtmp");
+    }
+
+    #[tokio::test]
+    async fn test_read_v1_split_header() {
+        let (mut stream, mut client) = accept(b"PROXY TCP4 192.168.0.1 ").await;
+        let header = tokio::spawn(async move {
+            let header = read_proxy_header(&mut stream, Duration::from_secs(1)).await;
+            (header, stream)
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client
+            .write_all(b"192.168.0.11 56324 1935\r\nGET")
+            .await
+            .unwrap();
+
+        let (header, mut stream) = header.await.unwrap();
+        assert_eq!(
+            header.unwrap().source,
+            Some("192.168.0.1:56324".parse::<SocketAddr>().unwrap())
+        );
+        assert_eq!(remaining(&mut stream, client).await, b"GET");
+    }
+
+    #[tokio::test]
+    async fn test_read_v1_unknown() {
+        let (mut stream, _client) = accept(b"PROXY UNKNOWN\r\n").await;
+        let header = read_proxy_header(&mut stream, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(header, ProxyHeader::default());
+    }
+
+    #[tokio::test]
+    async fn test_read_v1_too_long() {
+        let mut data = b"PROXY TCP4 ".to_vec();
+        data.resize(200, b'1');
+        let (mut stream, _client) = accept(&data).await;
+        assert!(read_proxy_header(&mut stream, Duration::from_secs(1))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_v2_header() {
+        let mut data = V2_SIGNATURE.to_vec();
+        //version 2, PROXY command, TCP over IPv4, 12 bytes address block
+        data.extend_from_slice(&[0x21, 0x11, 0x00, 0x0C]);
+        data.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2, 0x1F, 0x90, 0x07, 0x8F]);
+        data.extend_from_slice(b"GET");
+
+        let (mut stream, client) = accept(&data).await;
+        let header = read_proxy_header(&mut stream, Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            header.source,
+            Some("10.0.0.1:8080".parse::<SocketAddr>().unwrap())
+        );
+        assert_eq!(remaining(&mut stream, client).await, b"GET");
+    }
+
+    #[tokio::test]
+    async fn test_read_invalid_header() {
+        let (mut stream, _client) = accept(b"GET / HTTP/1.1\r\n\r\n").await;
+        assert!(read_proxy_header(&mut stream, Duration::from_secs(1))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,55 @@
+#![allow(non_local_definitions)]
+
+use failure::{Backtrace, Fail};
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Fail)]
+pub enum ProxyProtocolErrorValue {
+    #[fail(display = "io error: {}", _0)]
+    IOError(io::Error),
+    #[fail(display = "time out error")]
+    TimeoutError(tokio::time::error::Elapsed),
+    #[fail(display = "invalid proxy protocol signature")]
+    InvalidSignature,
+    #[fail(display = "proxy protocol v1 header is too long")]
+    HeaderTooLong,
+    #[fail(display = "invalid proxy protocol header: {}", _0)]
+    InvalidHeader(String),
+    #[fail(display = "unsupported proxy protocol version: {}", _0)]
+    UnsupportedVersion(u8),
+}
+#[derive(Debug)]
+pub struct ProxyProtocolError {
+    pub value: ProxyProtocolErrorValue,
+}
+
+impl From<ProxyProtocolErrorValue> for ProxyProtocolError {
+    fn from(val: ProxyProtocolErrorValue) -> Self {
+        ProxyProtocolError { value: val }
+    }
+}
+
+impl From<io::Error> for ProxyProtocolError {
+    fn from(error: io::Error) -> Self {
+        ProxyProtocolError {
+            value: ProxyProtocolErrorValue::IOError(error),
+        }
+    }
+}
+
+impl fmt::Display for ProxyProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.value, f)
+    }
+}
+
+impl Fail for ProxyProtocolError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.value.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.value.backtrace()
+    }
+}
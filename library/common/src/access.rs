@@ -0,0 +1,344 @@
+use {
+    crate::{
+        config::{AccessConfig, AppAccessConfig},
+        errors::{AccessError, AccessErrorValue},
+    },
+    ipnet::IpNet,
+    std::{
+        collections::{HashMap, VecDeque},
+        net::IpAddr,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+//prune the idle ip entries when the table grows over this size.
+const PRUNE_THRESHOLD: usize = 1024;
+
+fn parse_cidr_list(cidrs: &Option<Vec<String>>) -> Result<Vec<IpNet>, AccessError> {
+    let mut nets = Vec::new();
+    for cidr in cidrs.iter().flatten() {
+        let net = if let Ok(net) = cidr.parse::<IpNet>() {
+            net
+        } else if let Ok(ip) = cidr.parse::<IpAddr>() {
+            IpNet::from(ip)
+        } else {
+            return Err(AccessError {
+                value: AccessErrorValue::InvalidCidr(cidr.clone()),
+            });
+        };
+        nets.push(net);
+    }
+    Ok(nets)
+}
+
+#[derive(Debug, Clone, Default)]
+struct IpList {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpList {
+    /*deny wins over allow, an empty allow list allows everyone*/
+    fn is_allowed(&self, ip: &IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(ip))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct AppAccessList {
+    publish: IpList,
+    play: IpList,
+}
+
+impl AppAccessList {
+    fn new(cfg: &AppAccessConfig) -> Result<Self, AccessError> {
+        Ok(Self {
+            publish: IpList {
+                allow: parse_cidr_list(&cfg.publish_allow)?,
+                deny: parse_cidr_list(&cfg.publish_deny)?,
+            },
+            play: IpList {
+                allow: parse_cidr_list(&cfg.play_allow)?,
+                deny: parse_cidr_list(&cfg.play_deny)?,
+            },
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct IpState {
+    connections: usize,
+    connect_times: VecDeque<Instant>,
+    auth_failure_times: VecDeque<Instant>,
+    banned_until: Option<Instant>,
+}
+
+impl IpState {
+    fn expire(&mut self, now: Instant, failure_window: Duration) {
+        while let Some(t) = self.connect_times.front() {
+            if now.duration_since(*t) < RATE_WINDOW {
+                break;
+            }
+            self.connect_times.pop_front();
+        }
+        while let Some(t) = self.auth_failure_times.front() {
+            if now.duration_since(*t) < failure_window {
+                break;
+            }
+            self.auth_failure_times.pop_front();
+        }
+        if matches!(self.banned_until, Some(until) if until <= now) {
+            self.banned_until = None;
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.connections == 0
+            && self.connect_times.is_empty()
+            && self.auth_failure_times.is_empty()
+            && self.banned_until.is_none()
+    }
+}
+
+/*Network level access control shared by all the listeners of one protocol:
+per app CIDR allow/deny lists, per ip connection limits and temporary bans
+after repeated auth failures.*/
+#[derive(Debug, Default)]
+pub struct AccessControl {
+    apps: HashMap<String, AppAccessList>,
+    max_connections_per_ip: Option<usize>,
+    max_connections_per_minute: Option<usize>,
+    //(max failures, window, ban duration)
+    auth_failure_ban: Option<(usize, Duration, Duration)>,
+    ip_states: Mutex<HashMap<IpAddr, IpState>>,
+}
+
+impl AccessControl {
+    pub fn new(cfg: &AccessConfig) -> Result<Self, AccessError> {
+        let mut apps = HashMap::new();
+        for app_cfg in cfg.apps.iter().flatten() {
+            apps.insert(app_cfg.app.clone(), AppAccessList::new(app_cfg)?);
+        }
+
+        Ok(Self {
+            apps,
+            max_connections_per_ip: cfg.max_connections_per_ip,
+            max_connections_per_minute: cfg.max_connections_per_minute,
+            auth_failure_ban: cfg.auth_failure_ban.as_ref().map(|ban| {
+                (
+                    ban.max_failures,
+                    Duration::from_secs(ban.window_secs),
+                    Duration::from_secs(ban.ban_secs),
+                )
+            }),
+            ip_states: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn failure_window(&self) -> Duration {
+        self.auth_failure_ban
+            .map(|(_, window, _)| window)
+            .unwrap_or_default()
+    }
+
+    /*Called when a new connection is accepted, before any protocol data is read.
+    The returned guard holds the connection slot until it is dropped.*/
+    pub fn on_connect(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionGuard, AccessError> {
+        let now = Instant::now();
+        let failure_window = self.failure_window();
+        let mut ip_states = self.ip_states.lock().unwrap();
+
+        if ip_states.len() > PRUNE_THRESHOLD {
+            ip_states.retain(|_, state| {
+                state.expire(now, failure_window);
+                !state.is_idle()
+            });
+        }
+
+        let state = ip_states.entry(ip).or_default();
+        state.expire(now, failure_window);
+
+        if state.banned_until.is_some() {
+            return Err(AccessError {
+                value: AccessErrorValue::Banned,
+            });
+        }
+        if let Some(max_connections) = self.max_connections_per_ip {
+            if state.connections >= max_connections {
+                return Err(AccessError {
+                    value: AccessErrorValue::TooManyConnections,
+                });
+            }
+        }
+        if let Some(max_rate) = self.max_connections_per_minute {
+            if state.connect_times.len() >= max_rate {
+                return Err(AccessError {
+                    value: AccessErrorValue::ConnectionRateExceeded,
+                });
+            }
+        }
+
+        state.connections += 1;
+        state.connect_times.push_back(now);
+
+        Ok(ConnectionGuard {
+            access: self.clone(),
+            ip,
+        })
+    }
+
+    fn on_disconnect(&self, ip: &IpAddr) {
+        let mut ip_states = self.ip_states.lock().unwrap();
+        if let Some(state) = ip_states.get_mut(ip) {
+            state.connections = state.connections.saturating_sub(1);
+            state.expire(Instant::now(), self.failure_window());
+            if state.is_idle() {
+                ip_states.remove(ip);
+            }
+        }
+    }
+
+    /*Check the per app allow/deny lists when the app name is known.*/
+    pub fn check(&self, app_name: &str, ip: &IpAddr, is_pull: bool) -> Result<(), AccessError> {
+        let list = match self.apps.get(app_name).or_else(|| self.apps.get("*")) {
+            Some(list) => list,
+            None => return Ok(()),
+        };
+
+        let allowed = if is_pull {
+            list.play.is_allowed(ip)
+        } else {
+            list.publish.is_allowed(ip)
+        };
+
+        if allowed {
+            Ok(())
+        } else {
+            log::warn!(
+                "access denied, app_name: {} ip: {} pull: {}",
+                app_name,
+                ip,
+                is_pull
+            );
+            Err(AccessError {
+                value: AccessErrorValue::Denied,
+            })
+        }
+    }
+
+    /*Record an auth failure and ban the ip if it fails too many times.*/
+    pub fn on_auth_failure(&self, ip: &IpAddr) {
+        let (max_failures, window, ban_duration) = match self.auth_failure_ban {
+            Some(ban) => ban,
+            None => return,
+        };
+
+        let now = Instant::now();
+        let mut ip_states = self.ip_states.lock().unwrap();
+        let state = ip_states.entry(*ip).or_default();
+        state.expire(now, window);
+        state.auth_failure_times.push_back(now);
+
+        if state.auth_failure_times.len() >= max_failures {
+            log::warn!(
+                "ip: {} is banned for {} seconds after {} auth failures",
+                ip,
+                ban_duration.as_secs(),
+                state.auth_failure_times.len()
+            );
+            state.banned_until = Some(now + ban_duration);
+            state.auth_failure_times.clear();
+        }
+    }
+}
+
+/*Release the connection slot of an ip when the connection is closed.*/
+pub struct ConnectionGuard {
+    access: Arc<AccessControl>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.access.on_disconnect(&self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AccessControl;
+    use crate::config::{AccessConfig, AppAccessConfig, AuthFailureBanConfig};
+    use std::net::IpAddr;
+    use std::sync::Arc;
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn test_app_allow_deny() {
+        let cfg = AccessConfig {
+            apps: Some(vec![AppAccessConfig {
+                app: String::from("live"),
+                publish_allow: Some(vec![String::from("10.0.0.0/8")]),
+                publish_deny: Some(vec![String::from("10.1.0.0/16")]),
+                play_allow: None,
+                play_deny: Some(vec![String::from("192.168.1.10")]),
+            }]),
+            ..Default::default()
+        };
+        let access = AccessControl::new(&cfg).unwrap();
+
+        assert!(access.check("live", &ip("10.2.3.4"), false).is_ok());
+        assert!(access.check("live", &ip("10.1.3.4"), false).is_err());
+        assert!(access.check("live", &ip("8.8.8.8"), false).is_err());
+        assert!(access.check("live", &ip("8.8.8.8"), true).is_ok());
+        assert!(access.check("live", &ip("192.168.1.10"), true).is_err());
+        //no rules for other apps
+        assert!(access.check("vod", &ip("8.8.8.8"), false).is_ok());
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let cfg = AccessConfig {
+            max_connections_per_ip: Some(2),
+            ..Default::default()
+        };
+        let access = Arc::new(AccessControl::new(&cfg).unwrap());
+
+        let first = access.on_connect(ip("1.1.1.1")).unwrap();
+        let _second = access.on_connect(ip("1.1.1.1")).unwrap();
+        assert!(access.on_connect(ip("1.1.1.1")).is_err());
+        assert!(access.on_connect(ip("2.2.2.2")).is_ok());
+
+        drop(first);
+        assert!(access.on_connect(ip("1.1.1.1")).is_ok());
+    }
+
+    #[test]
+    fn test_auth_failure_ban() {
+        let cfg = AccessConfig {
+            auth_failure_ban: Some(AuthFailureBanConfig {
+                max_failures: 3,
+                window_secs: 60,
+                ban_secs: 600,
+            }),
+            ..Default::default()
+        };
+        let access = Arc::new(AccessControl::new(&cfg).unwrap());
+
+        for _ in 0..2 {
+            access.on_auth_failure(&ip("1.1.1.1"));
+        }
+        assert!(access.on_connect(ip("1.1.1.1")).is_ok());
+
+        access.on_auth_failure(&ip("1.1.1.1"));
+        assert!(access.on_connect(ip("1.1.1.1")).is_err());
+        assert!(access.on_connect(ip("2.2.2.2")).is_ok());
+    }
+}
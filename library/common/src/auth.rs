@@ -1,9 +1,15 @@
+use hmac::{Hmac, Mac, NewMac};
 use indexmap::IndexMap;
 use md5;
 use serde_derive::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::errors::{AuthError, AuthErrorValue};
+use crate::jwt::{JwtClaims, JwtValidator};
 use crate::scanf;
+use std::sync::Arc;
 
 #[derive(Debug,  Serialize, Deserialize, Clone, Default)]
 pub enum AuthAlgorithm {
@@ -12,6 +18,13 @@ pub enum AuthAlgorithm {
     Simple,
     #[serde(rename = "md5")]
     Md5,
+    /*HMAC-SHA256 over the action, stream name, expiry time and optionally the
+    client ip, the url carries "token=<hex signature>&expires=<unix seconds>"*/
+    #[serde(rename = "secure_link")]
+    SecureLink,
+    /*HS256/RS256 json web token, see jwt::JwtClaims*/
+    #[serde(rename = "jwt")]
+    Jwt,
 }
 
 pub enum SecretCarrier {
@@ -19,18 +32,23 @@ pub enum SecretCarrier {
     Bearer(String),
 }
 
+pub fn parse_query(query: &str) -> IndexMap<String, String> {
+    let mut query_pairs = IndexMap::new();
+    let pars_array: Vec<&str> = query.split('&').collect();
+    for ele in pars_array {
+        let (k, v) = scanf!(ele, '=', String, String);
+        if k.is_none() || v.is_none() {
+            continue;
+        }
+        query_pairs.insert(k.unwrap(), v.unwrap());
+    }
+    query_pairs
+}
+
 pub fn get_secret(carrier: &SecretCarrier) -> Result<String, AuthError> {
     match carrier {
         SecretCarrier::Query(query) => {
-            let mut query_pairs = IndexMap::new();
-            let pars_array: Vec<&str> = query.split('&').collect();
-            for ele in pars_array {
-                let (k, v) = scanf!(ele, '=', String, String);
-                if k.is_none() || v.is_none() {
-                    continue;
-                }
-                query_pairs.insert(k.unwrap(), v.unwrap());
-            }
+            let query_pairs = parse_query(query);
 
             query_pairs.get("token").map_or(
                 Err(AuthError {
@@ -57,6 +75,48 @@ pub fn get_secret(carrier: &SecretCarrier) -> Result<String, AuthError> {
     }
 }
 
+fn secure_link_action(is_pull: bool) -> &'static str {
+    if is_pull {
+        "play"
+    } else {
+        "publish"
+    }
+}
+
+fn secure_link_mac(
+    key: &str,
+    stream_name: &str,
+    expires: u64,
+    client_ip: Option<IpAddr>,
+    is_pull: bool,
+) -> Hmac<Sha256> {
+    let ip = client_ip.map(|ip| ip.to_string()).unwrap_or_default();
+    let raw_data = format!(
+        "{}\n{}\n{}\n{}",
+        secure_link_action(is_pull),
+        stream_name,
+        expires,
+        ip
+    );
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+    mac.update(raw_data.as_bytes());
+    mac
+}
+
+/*Generate the token of a secure link, the url should look like:
+rtmp://host/app/stream?token=<token>&expires=<expires>
+client_ip must be set if the server is configured with bind_ip.*/
+pub fn sign_secure_link(
+    key: &str,
+    stream_name: &str,
+    expires: u64,
+    client_ip: Option<IpAddr>,
+    is_pull: bool,
+) -> String {
+    let mac = secure_link_mac(key, stream_name, expires, client_ip, is_pull);
+    hex::encode(mac.finalize().into_bytes())
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum AuthType {
     Pull,
@@ -71,6 +131,9 @@ pub struct Auth {
     password: String,
     push_password: Option<String>,
     pub auth_type: AuthType,
+    /*secure_link: the client ip is part of the signature*/
+    bind_ip: bool,
+    jwt: Option<Arc<JwtValidator>>,
 }
 
 impl Auth {
@@ -80,6 +143,8 @@ impl Auth {
         push_password: Option<String>,
         algorithm: AuthAlgorithm,
         auth_type: AuthType,
+        bind_ip: bool,
+        jwt: Option<JwtValidator>,
     ) -> Self {
         Self {
             algorithm,
@@ -87,15 +152,21 @@ impl Auth {
             password,
             push_password,
             auth_type,
+            bind_ip,
+            jwt: jwt.map(Arc::new),
         }
     }
 
+    /*The claims are returned for the jwt algorithm, the caller should enforce
+    the session limits(max_bitrate/max_duration) carried by them.*/
     pub fn authenticate(
         &self,
+        app_name: &str,
         stream_name: &String,
         secret: &Option<SecretCarrier>,
         is_pull: bool,
-    ) -> Result<(), AuthError> {
+        client_ip: Option<IpAddr>,
+    ) -> Result<Option<JwtClaims>, AuthError> {
         if self.auth_type == AuthType::Both
             || is_pull && (self.auth_type == AuthType::Pull)
             || !is_pull && (self.auth_type == AuthType::Push)
@@ -106,11 +177,27 @@ impl Auth {
             /*Here we should do auth and it must be successful. */
             if let Some(secret_value) = secret {
                 let token = get_secret(secret_value)?;
-                if self.check(stream_name, token.as_str(), is_pull) {
-                    return Ok(());
+                let result = match self.algorithm {
+                    AuthAlgorithm::SecureLink => self
+                        .check_secure_link(stream_name, secret_value, &token, is_pull, client_ip)
+                        .map(|_| None),
+                    AuthAlgorithm::Jwt => self.check_jwt(&token, app_name, stream_name, is_pull),
+                    _ => {
+                        if self.check(stream_name, token.as_str(), is_pull) {
+                            Ok(None)
+                        } else {
+                            Err(AuthErrorValue::TokenIsNotCorrect)
+                        }
+                    }
+                };
+
+                match result {
+                    Ok(claims) => return Ok(claims),
+                    Err(check_err) => {
+                        auth_err_reason = format!("{} token: {}", check_err, token);
+                        err = check_err;
+                    }
                 }
-                auth_err_reason = format!("token is not correct: {}", token);
-                err = AuthErrorValue::TokenIsNotCorrect;
             }
 
             log::error!(
@@ -122,7 +209,57 @@ impl Auth {
             );
             return Err(AuthError { value: err });
         }
-        Ok(())
+        Ok(None)
+    }
+
+    fn check_jwt(
+        &self,
+        token: &str,
+        app_name: &str,
+        stream_name: &str,
+        is_pull: bool,
+    ) -> Result<Option<JwtClaims>, AuthErrorValue> {
+        match &self.jwt {
+            Some(jwt) => jwt
+                .validate(token, app_name, stream_name, is_pull)
+                .map(Some)
+                .map_err(|err| err.value),
+            None => Err(AuthErrorValue::JwtKeyError(String::from(
+                "jwt is not configured",
+            ))),
+        }
+    }
+
+    fn check_secure_link(
+        &self,
+        stream_name: &str,
+        secret: &SecretCarrier,
+        token: &str,
+        is_pull: bool,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(), AuthErrorValue> {
+        let expires = match secret {
+            SecretCarrier::Query(query) => parse_query(query)
+                .get("expires")
+                .and_then(|v| v.parse::<u64>().ok()),
+            SecretCarrier::Bearer(_) => None,
+        }
+        .ok_or(AuthErrorValue::InvalidTokenFormat)?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        if expires < now {
+            return Err(AuthErrorValue::TokenIsExpired);
+        }
+
+        let signature = hex::decode(token).map_err(|_| AuthErrorValue::InvalidTokenFormat)?;
+        let client_ip = if self.bind_ip { client_ip } else { None };
+        /*verify in constant time*/
+        secure_link_mac(&self.key, stream_name, expires, client_ip, is_pull)
+            .verify(&signature)
+            .map_err(|_| AuthErrorValue::TokenIsNotCorrect)
     }
 
     fn check(&self, stream_name: &String, auth_str: &str, is_pull: bool) -> bool {
@@ -139,6 +276,86 @@ impl Auth {
                 let digest_str = format!("{:x}", md5::compute(raw_data));
                 auth_str == digest_str
             }
+            AuthAlgorithm::SecureLink | AuthAlgorithm::Jwt => false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{sign_secure_link, Auth, AuthAlgorithm, AuthType, SecretCarrier};
+    use std::net::IpAddr;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn query(token: &str, expires: u64) -> Option<SecretCarrier> {
+        Some(SecretCarrier::Query(format!(
+            "token={token}&expires={expires}"
+        )))
+    }
+
+    #[test]
+    fn test_secure_link() {
+        let auth = Auth::new(
+            String::from("key"),
+            String::new(),
+            None,
+            AuthAlgorithm::SecureLink,
+            AuthType::Both,
+            false,
+            None,
+        );
+        let stream_name = String::from("test");
+        let expires = now() + 60;
+
+        let play_token = sign_secure_link("key", "test", expires, None, true);
+        assert!(auth
+            .authenticate("live", &stream_name, &query(&play_token, expires), true, None)
+            .is_ok());
+        //a play token cannot be used to publish
+        assert!(auth
+            .authenticate("live", &stream_name, &query(&play_token, expires), false, None)
+            .is_err());
+        //the expiry time is signed
+        assert!(auth
+            .authenticate("live", &stream_name, &query(&play_token, expires + 1), true, None)
+            .is_err());
+
+        let expired = now() - 1;
+        let expired_token = sign_secure_link("key", "test", expired, None, true);
+        assert!(auth
+            .authenticate("live", &stream_name, &query(&expired_token, expired), true, None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_secure_link_bind_ip() {
+        let auth = Auth::new(
+            String::from("key"),
+            String::new(),
+            None,
+            AuthAlgorithm::SecureLink,
+            AuthType::Pull,
+            true,
+            None,
+        );
+        let stream_name = String::from("test");
+        let expires = now() + 60;
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other_ip: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let token = sign_secure_link("key", "test", expires, Some(ip), true);
+        assert!(auth
+            .authenticate("live", &stream_name, &query(&token, expires), true, Some(ip))
+            .is_ok());
+        assert!(auth
+            .authenticate("live", &stream_name, &query(&token, expires), true, Some(other_ip))
+            .is_err());
+    }
+}
@@ -7,6 +7,8 @@ use serde_derive::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::BufReader;
 use std::vec::Vec;
+use rand::Rng;
+use subtle::ConstantTimeEq;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
@@ -18,6 +20,12 @@ pub struct Config {
     pub authsecret: AuthSecretConfig,
     pub streams: Option<Vec<Streams>>,
     pub log: Option<LogConfig>,
+    pub record: Option<RecordConfig>,
+    pub vod: Option<VodConfig>,
+    pub virtual_channels: Option<Vec<VirtualChannelConfig>>,
+    //milliseconds the players and the hls of a stream are kept after its publisher is gone,
+    //it applies to the streams of all the protocols, 0 by default
+    pub grace_period: Option<u64>,
 }
 
 impl Config {
@@ -35,6 +43,9 @@ impl Config {
                 pull: None,
                 push: None,
                 auth: None,
+                proxy_protocol: None,
+                access: None,
+                grace_period: None,
             });
         }
 
@@ -46,6 +57,13 @@ impl Config {
                 port: http_port,
                 need_record: false,
                 auth: None,
+                proxy_protocol: None,
+                access: None,
+                ll_hls: None,
+                hls_on_demand: None,
+                hls_apps: None,
+                hls_groups: None,
+                dash: None,
             });
         }
 
@@ -59,6 +77,8 @@ impl Config {
             max_bitrate: None,
             on_publish_url: None,
             max_sessions: None,
+            publish_keys: None,
+            failover: None,
         }]);
 
         Self {
@@ -70,6 +90,10 @@ impl Config {
             authsecret: AuthSecretConfig::default(),
             streams: streams_config,
             log: log_config,
+            record: None,
+            vod: None,
+            virtual_channels: None,
+            grace_period: None,
         }
     }
 }
@@ -81,6 +105,49 @@ pub struct Streams{
     pub max_bitrate: Option<usize>,
     pub on_publish_url: Option<String>,
     pub max_sessions: Option<usize>,
+    /*if not empty, the publisher must carry one of the keys by the "key" query
+    parameter, e.g. rtmp://host/live/stream?key=<key>*/
+    pub publish_keys: Option<Vec<PublishKey>>,
+    /*the sources which the players are switched to when the stream stalls or
+    unpublishes, it takes effect after a restart*/
+    pub failover: Option<FailoverConfig>,
+}
+
+impl Streams {
+    pub fn requires_publish_key(&self) -> bool {
+        self.publish_keys.as_ref().is_some_and(|keys| !keys.is_empty())
+    }
+
+    /*now: unix seconds*/
+    pub fn find_publish_key(&self, key: &str, now: u64) -> Option<&PublishKey> {
+        self.publish_keys
+            .iter()
+            .flatten()
+            /*compare in constant time, the key is a secret*/
+            .find(|k| bool::from(k.key.as_bytes().ct_eq(key.as_bytes())) && !k.is_expired(now))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PublishKey {
+    //identifies the encoder, reported in the publish notification
+    pub label: String,
+    pub key: String,
+    //unix seconds, never expires if not set
+    pub expires_at: Option<u64>,
+}
+
+impl PublishKey {
+    pub fn is_expired(&self, now: u64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+}
+
+/*32 random hex characters*/
+pub fn generate_publish_key() -> String {
+    let mut bytes = [0_u8; 16];
+    rand::thread_rng().fill(&mut bytes);
+    hex::encode(bytes)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -91,6 +158,12 @@ pub struct RtmpConfig {
     pub pull: Option<RtmpPullConfig>,
     pub push: Option<Vec<RtmpPushConfig>>,
     pub auth: Option<AuthConfig>,
+    //parse the PROXY protocol(v1/v2) header sent by a L4 load balancer
+    pub proxy_protocol: Option<bool>,
+    pub access: Option<AccessConfig>,
+    //deprecated, the same as the top level grace_period which takes precedence,
+    //it applies to all the streams, not only the rtmp ones
+    pub grace_period: Option<u64>,
 }
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RtmpPullConfig {
@@ -109,9 +182,211 @@ pub struct RtmpPushConfig {
 pub struct HttpConfig {
     pub enabled: bool,
     pub port: Vec<usize>,
-    //record or not
+    //record all the streams with the legacy layout({output_root}/{app}/{stream}),
+    //it is ignored if the record config is set
     pub need_record: bool,
     pub auth: Option<AuthConfig>,
+    //parse the PROXY protocol(v1/v2) header sent by a L4 load balancer
+    pub proxy_protocol: Option<bool>,
+    pub access: Option<AccessConfig>,
+    pub ll_hls: Option<LlHlsConfig>,
+    pub hls_on_demand: Option<HlsOnDemandConfig>,
+    pub hls_apps: Option<Vec<HlsAppConfig>>,
+    pub hls_groups: Option<Vec<HlsGroupConfig>>,
+    pub dash: Option<DashConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecordFormat {
+    //the hls segments with a vod playlist in a directory
+    #[default]
+    #[serde(rename = "hls")]
+    Hls,
+    //the flv tags as they are published, with a keyframes index
+    #[serde(rename = "flv")]
+    Flv,
+    //fragmented mp4 written while live, so that a crash only loses the last
+    //fragment, it can be finalized in place into a progressive mp4 when it is
+    //finished
+    #[serde(rename = "mp4")]
+    Mp4,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RecordConfig {
+    //record all the streams, the apps and the streams can be enabled or disabled
+    //in apps
+    pub enabled: bool,
+    //"hls" by default
+    pub format: Option<RecordFormat>,
+    //the root directory of the recordings, the output_root of the hls settings
+    //for hls and "./record" for the files by default
+    pub root: Option<String>,
+    //the path of a recording under the root without the file extension, {app}, {stream}, {yyyy}, {mm}, {dd},
+    //{hh} and {start_time} are replaced, "{app}/{stream}/{yyyy}/{mm}/{dd}/{start_time}"
+    //by default. "/{start_time}" is appended to a path without it, so that every
+    //recording has its own file or directory
+    pub path: Option<String>,
+    //seconds, a new recording is started after it, unlimited by default
+    pub max_duration: Option<u64>,
+    //MB, a new recording is started after it, unlimited by default
+    pub max_size: Option<u64>,
+    //mp4 only, turn a finished recording in place into a progressive mp4 with the
+    //moov box at the end, a new recording is started after about 1M samples, false
+    //by default
+    pub finalize: Option<bool>,
+    //hours, older recordings are deleted, unlimited by default
+    pub max_age: Option<u64>,
+    //MB, the oldest recordings are deleted when they take more space, unlimited
+    //by default
+    pub quota: Option<u64>,
+    //the json file of the recordings index, "./recordings.json" by default
+    pub index: Option<String>,
+    pub apps: Option<Vec<RecordAppConfig>>,
+}
+
+//the record settings of an app or of one of its streams, they win over the
+//settings of the record config
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RecordAppConfig {
+    pub app: String,
+    pub stream: Option<String>,
+    pub enabled: Option<bool>,
+    pub format: Option<RecordFormat>,
+    pub path: Option<String>,
+    pub max_duration: Option<u64>,
+    pub max_size: Option<u64>,
+    pub finalize: Option<bool>,
+}
+
+//play the recorded flv and mp4 files over rtmp and http-flv, e.g. the rtmp url
+//"rtmp://host/vod/2024/show.flv" plays the file "{root}/2024/show.flv"
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct VodConfig {
+    pub enabled: bool,
+    //the app of the recorded files, "vod" by default
+    pub app: Option<String>,
+    //the directory of the recorded files, the root of the record config by default
+    pub root: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FailoverConfig {
+    //the backup stream names in the same app, they are tried in order
+    pub backups: Option<Vec<String>>,
+    //a flv or mp4 file which is looped when no backup is publishing
+    pub slate: Option<String>,
+    //milliseconds without frames before the publisher is stalled, 3000 by default
+    pub stall_timeout: Option<u64>,
+}
+
+//a live stream published from a playlist of recorded flv and mp4 files,
+//the scheduled programs interrupt the playlist at their wall-clock start
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct VirtualChannelConfig {
+    pub enabled: bool,
+    //the stream is published as "{app}/{stream}"
+    pub app: String,
+    pub stream: String,
+    //the files are played in order and the playlist is repeated
+    pub playlist: Vec<String>,
+    pub schedule: Option<Vec<ScheduledProgramConfig>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ScheduledProgramConfig {
+    pub file: String,
+    //the local time "HH:MM[:SS]" every day, or "YYYY-MM-DD HH:MM[:SS]" once
+    pub start: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LlHlsConfig {
+    pub enabled: bool,
+    //the target duration of the partial segments in milliseconds, 1000 by default
+    pub part_duration: Option<u64>,
+}
+
+//the streams are packaged from the first playlist request instead of from the
+//publish, the recorded streams are always packaged
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HlsOnDemandConfig {
+    pub enabled: bool,
+    //seconds without playlist requests before the packaging stops, 30 by default
+    pub idle_timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HlsSegmentFormat {
+    #[default]
+    #[serde(rename = "ts")]
+    Ts,
+    //fragmented mp4(CMAF) segments with an init segment
+    #[serde(rename = "fmp4")]
+    Fmp4,
+}
+
+//the hls settings of an app, the apps not listed use the defaults. They are
+//loaded again when a stream is published, so the changes apply to the new streams.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HlsAppConfig {
+    pub app: String,
+    //only for this stream of the app, it wins over the settings of the app
+    pub stream: Option<String>,
+    pub segment_format: Option<HlsSegmentFormat>,
+    //seconds, 5 by default
+    pub target_duration: Option<u64>,
+    //how many segments are listed in the live playlist, 6 by default
+    pub playlist_length: Option<usize>,
+    //how many segments are kept after they leave the playlist, 0 by default
+    pub delete_after: Option<usize>,
+    //seconds, a longer gap between two segments is marked as a discontinuity, 15 by default
+    pub discontinuity_threshold: Option<u64>,
+    //the root directory of the recorded files, "./hls" by default
+    pub output_root: Option<String>,
+    //the segment file name without the extension, {app}, {stream} and {seq}
+    //are replaced, "{seq}" by default
+    pub segment_name: Option<String>,
+    pub encryption: Option<HlsEncryptionConfig>,
+    //minutes of media retained for the time-shift playlists, 0 by default which
+    //disables the DVR window, the segments out of the live playlist are kept
+    //on disk under {output_root}/dvr/{app}/{stream}
+    pub dvr_window: Option<u64>,
+    //the audio-only streams are segmented as packed audio(.aac with ID3
+    //timestamps) instead of mpegts, only for the ts segment format, false by default
+    pub packed_audio: Option<bool>,
+}
+
+//AES-128 encryption of the mpegts segments, it is not applied to the fmp4 or
+//LL-HLS streams. The keys are generated per stream.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HlsEncryptionConfig {
+    pub enabled: bool,
+    //a new key every N segments, 0 keeps one key for the whole stream, 0 by default
+    pub key_rotation: Option<u32>,
+    //the URI of the EXT-X-KEY tags, {app}, {stream} and {key} are replaced,
+    //"{key}.key" by default which is served by the authenticated key endpoint
+    //at /{app}/{stream}/{key}.key
+    pub key_uri: Option<String>,
+}
+
+//the renditions of a show published as separate streams of an app, e.g.
+//live/show_1080 and live/show_720, the master playlist is /{app}/{name}/index.m3u8
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HlsGroupConfig {
+    pub app: String,
+    pub name: String,
+    //the stream names, listed in the master playlist in this order
+    pub variants: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DashConfig {
+    pub enabled: bool,
+    //seconds, 4 by default
+    pub segment_duration: Option<u64>,
+    //the seconds of media kept in the manifest, 30 by default
+    pub time_shift_buffer_depth: Option<u64>,
 }
 
 pub enum LogLevel {
@@ -147,6 +422,9 @@ pub struct HttpNotifierConfig {
     pub on_unpublish: Option<String>,
     pub on_play: Option<String>,
     pub on_stop: Option<String>,
+    //a recorder started or finished a file, the body has the path of the file
+    pub on_record_start: Option<String>,
+    pub on_record_stop: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -162,11 +440,52 @@ pub struct EditAuthConfig{
     pub password: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AccessConfig {
+    //how many connections can be kept by one ip at the same time
+    pub max_connections_per_ip: Option<usize>,
+    //how many new connections one ip can make in a minute
+    pub max_connections_per_minute: Option<usize>,
+    pub auth_failure_ban: Option<AuthFailureBanConfig>,
+    pub apps: Option<Vec<AppAccessConfig>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AuthFailureBanConfig {
+    //ban the ip after max_failures auth failures within window_secs
+    pub max_failures: usize,
+    pub window_secs: u64,
+    pub ban_secs: u64,
+}
+
+//CIDR lists, e.g. "10.0.0.0/8" or "192.168.1.10", app "*" applies to all the other apps.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AppAccessConfig {
+    pub app: String,
+    pub publish_allow: Option<Vec<String>>,
+    pub publish_deny: Option<Vec<String>>,
+    pub play_allow: Option<Vec<String>>,
+    pub play_deny: Option<Vec<String>>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct AuthConfig {
     pub pull_enabled: bool,
     pub push_enabled: Option<bool>,
     pub algorithm: AuthAlgorithm,
+    //secure_link: sign the client ip into the url
+    pub bind_ip: Option<bool>,
+    pub jwt: Option<JwtConfig>,
+}
+
+//set one of secret(HS256), public_key_file(RS256 PEM) or jwks_file
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct JwtConfig {
+    pub secret: Option<String>,
+    pub public_key_file: Option<String>,
+    pub jwks_file: Option<String>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
 }
 
 
@@ -177,11 +496,15 @@ pub fn load_config(path: &str) -> Result<Config, Error> {
     Ok(config)
 }
 
+/*the config is written to a temporary file which is renamed over the old one,
+the publish sessions reading it never see a half written file*/
 pub fn save_config(path: &str, config: &Config) -> Result<(), Error> {
-    let file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
-    let writer = BufWriter::new(file);
-    to_writer_pretty(writer, config)?;
-    Ok(())
+    let temp_path = format!("{}.tmp", path);
+    let file = OpenOptions::new().write(true).create(true).truncate(true).open(&temp_path)?;
+    let mut writer = BufWriter::new(file);
+    to_writer_pretty(&mut writer, config)?;
+    writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+    std::fs::rename(&temp_path, path)
 }
 
 
@@ -1,3 +1,5 @@
+#![allow(non_local_definitions)]
+
 use failure::{Backtrace, Fail};
 use std::fmt;
 
@@ -10,10 +12,24 @@ pub struct AuthError {
 pub enum AuthErrorValue {
     #[fail(display = "token is not correct.")]
     TokenIsNotCorrect,
+    #[fail(display = "token is expired.")]
+    TokenIsExpired,
     #[fail(display = "no token found.")]
     NoTokenFound,
     #[fail(display = "invalid token format.")]
-    InvalidTokenFormat
+    InvalidTokenFormat,
+    #[fail(display = "jwt error: {}", _0)]
+    JwtError(#[cause] jsonwebtoken::errors::Error),
+    #[fail(display = "jwt claims do not match: {}", _0)]
+    ClaimsNotMatch(String),
+    #[fail(display = "jwt key error: {}", _0)]
+    JwtKeyError(String),
+}
+
+impl From<AuthErrorValue> for AuthError {
+    fn from(val: AuthErrorValue) -> Self {
+        AuthError { value: val }
+    }
 }
 
 impl fmt::Display for AuthError {
@@ -31,3 +47,38 @@ impl Fail for AuthError {
         self.value.backtrace()
     }
 }
+
+#[derive(Debug)]
+pub struct AccessError {
+    pub value: AccessErrorValue,
+}
+
+#[derive(Debug, Fail)]
+pub enum AccessErrorValue {
+    #[fail(display = "the ip is denied.")]
+    Denied,
+    #[fail(display = "the ip is banned temporarily.")]
+    Banned,
+    #[fail(display = "too many connections from the ip.")]
+    TooManyConnections,
+    #[fail(display = "too many new connections from the ip.")]
+    ConnectionRateExceeded,
+    #[fail(display = "invalid cidr: {}", _0)]
+    InvalidCidr(String),
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.value, f)
+    }
+}
+
+impl Fail for AccessError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.value.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.value.backtrace()
+    }
+}
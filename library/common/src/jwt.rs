@@ -0,0 +1,256 @@
+use {
+    crate::{
+        config::JwtConfig,
+        errors::{AuthError, AuthErrorValue},
+    },
+    jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation},
+    serde_derive::{Deserialize, Serialize},
+    std::time::Duration,
+};
+
+/*The claims of a publish/play token, e.g.
+{"exp": 1700000000, "app": "live", "stream": "room_*", "action": "play"}
+app and stream are patterns where '*' matches any characters, a missing
+claim matches everything.*/
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct JwtClaims {
+    pub exp: u64,
+    pub app: Option<String>,
+    pub stream: Option<String>,
+    //"play", "publish" or "*"
+    pub action: Option<String>,
+    //kbps, only enforced on publishers
+    pub max_bitrate: Option<u64>,
+    //seconds
+    pub max_duration: Option<u64>,
+}
+
+impl JwtClaims {
+    pub fn max_duration(&self) -> Option<Duration> {
+        self.max_duration.map(Duration::from_secs)
+    }
+
+    fn check(&self, app_name: &str, stream_name: &str, is_pull: bool) -> Result<(), AuthError> {
+        let not_match = |reason: &str| {
+            Err(AuthError {
+                value: AuthErrorValue::ClaimsNotMatch(reason.to_string()),
+            })
+        };
+
+        if let Some(app) = &self.app {
+            if !match_pattern(app, app_name) {
+                return not_match("app");
+            }
+        }
+        if let Some(stream) = &self.stream {
+            if !match_pattern(stream, stream_name) {
+                return not_match("stream");
+            }
+        }
+        if let Some(action) = &self.action {
+            let expected = if is_pull { "play" } else { "publish" };
+            if action != "*" && action != expected {
+                return not_match("action");
+            }
+        }
+        Ok(())
+    }
+}
+
+/*glob style matching, only '*' is supported*/
+pub fn match_pattern(pattern: &str, value: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == value;
+    }
+
+    let first = parts[0];
+    let last = parts[parts.len() - 1];
+    if value.len() < first.len() + last.len() || !value.starts_with(first) || !value.ends_with(last)
+    {
+        return false;
+    }
+
+    let mut rest = &value[first.len()..value.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+enum JwtKeys {
+    Single(DecodingKey, Algorithm),
+    Jwks(JwkSet),
+}
+
+impl std::fmt::Debug for JwtKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JwtKeys::Single(_, algorithm) => write!(f, "Single({:?})", algorithm),
+            JwtKeys::Jwks(set) => write!(f, "Jwks({} keys)", set.keys.len()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct JwtValidator {
+    keys: JwtKeys,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+fn key_error(reason: String) -> AuthError {
+    AuthError {
+        value: AuthErrorValue::JwtKeyError(reason),
+    }
+}
+
+impl JwtValidator {
+    /*HS256 uses secret, RS256 uses the PEM file public_key_file, or the keys are
+    loaded from jwks_file and selected by the "kid" of the token header.*/
+    pub fn new(cfg: &JwtConfig) -> Result<Self, AuthError> {
+        let keys = if let Some(jwks_file) = &cfg.jwks_file {
+            let content = std::fs::read_to_string(jwks_file)
+                .map_err(|err| key_error(format!("read {jwks_file}: {err}")))?;
+            let set: JwkSet = serde_json::from_str(&content)
+                .map_err(|err| key_error(format!("parse {jwks_file}: {err}")))?;
+            JwtKeys::Jwks(set)
+        } else if let Some(public_key_file) = &cfg.public_key_file {
+            let content = std::fs::read(public_key_file)
+                .map_err(|err| key_error(format!("read {public_key_file}: {err}")))?;
+            let key = DecodingKey::from_rsa_pem(&content)
+                .map_err(|err| key_error(format!("parse {public_key_file}: {err}")))?;
+            JwtKeys::Single(key, Algorithm::RS256)
+        } else if let Some(secret) = &cfg.secret {
+            JwtKeys::Single(DecodingKey::from_secret(secret.as_bytes()), Algorithm::HS256)
+        } else {
+            return Err(key_error(String::from(
+                "one of secret, public_key_file or jwks_file must be set",
+            )));
+        };
+
+        Ok(Self {
+            keys,
+            issuer: cfg.issuer.clone(),
+            audience: cfg.audience.clone(),
+        })
+    }
+
+    fn validation(&self, algorithm: Algorithm) -> Validation {
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = 5;
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        validation
+    }
+
+    pub fn validate(
+        &self,
+        token: &str,
+        app_name: &str,
+        stream_name: &str,
+        is_pull: bool,
+    ) -> Result<JwtClaims, AuthError> {
+        let jwt_error = |err| AuthError {
+            value: AuthErrorValue::JwtError(err),
+        };
+
+        let data = match &self.keys {
+            JwtKeys::Single(key, algorithm) => {
+                jsonwebtoken::decode::<JwtClaims>(token, key, &self.validation(*algorithm))
+                    .map_err(jwt_error)?
+            }
+            JwtKeys::Jwks(set) => {
+                let header = jsonwebtoken::decode_header(token).map_err(jwt_error)?;
+                let jwk = header
+                    .kid
+                    .as_ref()
+                    .and_then(|kid| set.find(kid))
+                    .ok_or_else(|| key_error(format!("no key found for kid {:?}", header.kid)))?;
+                let key = DecodingKey::from_jwk(jwk).map_err(jwt_error)?;
+                jsonwebtoken::decode::<JwtClaims>(token, &key, &self.validation(header.alg))
+                    .map_err(jwt_error)?
+            }
+        };
+
+        data.claims.check(app_name, stream_name, is_pull)?;
+        Ok(data.claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{match_pattern, JwtClaims, JwtValidator};
+    use crate::config::JwtConfig;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_match_pattern() {
+        assert!(match_pattern("*", "anything"));
+        assert!(match_pattern("room_*", "room_1"));
+        assert!(match_pattern("*_hd", "room_hd"));
+        assert!(match_pattern("a*b*c", "a12b34c"));
+        assert!(match_pattern("live", "live"));
+        assert!(!match_pattern("live", "live2"));
+        assert!(!match_pattern("room_*", "hall_1"));
+        assert!(!match_pattern("a*b*c", "acb"));
+    }
+
+    #[test]
+    fn test_validate_hs256() {
+        let validator = JwtValidator::new(&JwtConfig {
+            secret: Some(String::from("secret")),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let sign = |claims: &JwtClaims, secret: &str| {
+            encode(
+                &Header::default(),
+                claims,
+                &EncodingKey::from_secret(secret.as_bytes()),
+            )
+            .unwrap()
+        };
+
+        let claims = JwtClaims {
+            exp: now + 60,
+            app: Some(String::from("live")),
+            stream: Some(String::from("room_*")),
+            action: Some(String::from("play")),
+            max_duration: Some(30),
+            ..Default::default()
+        };
+        let token = sign(&claims, "secret");
+
+        let result = validator.validate(&token, "live", "room_1", true).unwrap();
+        assert_eq!(result.max_duration, Some(30));
+        assert!(validator.validate(&token, "live", "room_1", false).is_err());
+        assert!(validator.validate(&token, "vod", "room_1", true).is_err());
+        assert!(validator.validate(&token, "live", "hall", true).is_err());
+        assert!(validator
+            .validate(&sign(&claims, "other"), "live", "room_1", true)
+            .is_err());
+
+        let expired = JwtClaims {
+            exp: now - 3600,
+            ..Default::default()
+        };
+        assert!(validator
+            .validate(&sign(&expired, "secret"), "live", "room_1", true)
+            .is_err());
+    }
+}
@@ -1,6 +1,10 @@
+pub mod access;
 pub mod auth;
 pub mod define;
 pub mod errors;
 pub mod http;
+pub mod jwt;
+pub mod record;
 pub mod utils;
+pub mod vod;
 pub mod config;
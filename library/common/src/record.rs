@@ -0,0 +1,706 @@
+use {
+    crate::config::{load_config, RecordAppConfig, RecordConfig, RecordFormat},
+    chrono::{DateTime, Local},
+    serde_derive::{Deserialize, Serialize},
+    serde_json::{from_reader, to_writer_pretty},
+    std::{
+        collections::HashMap,
+        fs::{self, File},
+        io::{BufReader, BufWriter},
+        path::Path,
+        sync::{Arc, Mutex},
+    },
+};
+
+pub const DEFAULT_RECORD_PATH: &str = "{app}/{stream}/{yyyy}/{mm}/{dd}/{start_time}";
+pub const DEFAULT_RECORD_INDEX: &str = "./recordings.json";
+/*the root directory of the recorded files*/
+pub const DEFAULT_RECORD_ROOT: &str = "./record";
+
+/*A finished recording, the times are unix seconds.*/
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Recording {
+    pub id: u64,
+    pub app: String,
+    pub stream: String,
+    pub format: RecordFormat,
+    /*the recorded file or directory*/
+    pub path: String,
+    /*the files of a recorded directory, relative to it*/
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+    pub start_time: i64,
+    pub end_time: i64,
+    /*milliseconds*/
+    pub duration: i64,
+    /*bytes*/
+    pub size: u64,
+}
+
+/*A recording in progress, the start time is unix seconds.*/
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ActiveRecording {
+    pub app: String,
+    pub stream: String,
+    pub format: RecordFormat,
+    /*the file being recorded, None before the first one is started*/
+    pub path: Option<String>,
+    pub start_time: i64,
+    /*started by the api, otherwise by the record config*/
+    pub on_demand: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RecordingList {
+    next_id: u64,
+    recordings: Vec<Recording>,
+}
+
+/*The index of the finished recordings, it is saved as json after every change
+and shared by the recorders, the retention task and the api.*/
+#[derive(Debug)]
+pub struct RecordIndex {
+    path: String,
+    list: Mutex<RecordingList>,
+    /*the sizes of the recordings in progress by path, they count towards the
+    quota*/
+    active: Mutex<HashMap<String, u64>>,
+    /*false if a corrupt index could not be moved aside, it is never overwritten*/
+    writable: bool,
+}
+
+impl RecordIndex {
+    /*A corrupt index is moved to "{path}.corrupt.{yyyymmddHHMMSS}" before an
+    empty one replaces it.*/
+    pub fn load(path: &str) -> Self {
+        let mut writable = true;
+        let list = match File::open(path) {
+            Ok(file) => match from_reader(BufReader::new(file)) {
+                Ok(list) => list,
+                Err(err) => {
+                    let backup = format!("{path}.corrupt.{}", Local::now().format("%Y%m%d%H%M%S"));
+                    log::error!(
+                        "load the recordings index {} error: {}, it is moved to {}",
+                        path,
+                        err,
+                        backup
+                    );
+                    if let Err(err) = fs::rename(path, &backup) {
+                        log::error!(
+                            "move the recordings index {} error: {}, it will not be saved",
+                            path,
+                            err
+                        );
+                        writable = false;
+                    }
+                    RecordingList::default()
+                }
+            },
+            Err(_) => RecordingList::default(),
+        };
+
+        Self {
+            path: path.to_string(),
+            list: Mutex::new(list),
+            active: Mutex::new(HashMap::new()),
+            writable,
+        }
+    }
+
+    /*the index is written to "{path}.tmp" and moved over the old one, so that
+    a crash never leaves it truncated*/
+    fn save(&self, list: &RecordingList) {
+        if !self.writable {
+            return;
+        }
+        let temp_path = format!("{}.tmp", self.path);
+        let result = File::create(&temp_path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                to_writer_pretty(&mut writer, list).map_err(|err| err.to_string())?;
+                writer
+                    .into_inner()
+                    .map_err(|err| err.to_string())?
+                    .sync_all()
+                    .map_err(|err| err.to_string())
+            })
+            .and_then(|_| fs::rename(&temp_path, &self.path).map_err(|err| err.to_string()));
+        if let Err(err) = result {
+            log::error!("save the recordings index {} error: {}", self.path, err);
+        }
+    }
+
+    fn set_active_size(&self, path: &str, size: u64) {
+        self.active.lock().unwrap().insert(path.to_string(), size);
+    }
+
+    fn remove_active(&self, path: &str) {
+        self.active.lock().unwrap().remove(path);
+    }
+
+    /*the id of the recording is assigned by the index*/
+    pub fn add(&self, mut recording: Recording) -> Recording {
+        let mut list = self.list.lock().unwrap();
+        recording.id = list.next_id;
+        list.next_id += 1;
+        list.recordings.push(recording.clone());
+        self.save(&list);
+        recording
+    }
+
+    pub fn list(&self, app: Option<&str>, stream: Option<&str>) -> Vec<Recording> {
+        self.list
+            .lock()
+            .unwrap()
+            .recordings
+            .iter()
+            .filter(|r| app.is_none_or(|app| r.app == app))
+            .filter(|r| stream.is_none_or(|stream| r.stream == stream))
+            .cloned()
+            .collect()
+    }
+
+    /*the recorded files are deleted with the entry*/
+    pub fn remove(&self, id: u64) -> Option<Recording> {
+        let mut list = self.list.lock().unwrap();
+        let index = list.recordings.iter().position(|r| r.id == id)?;
+        let recording = list.recordings.remove(index);
+        self.save(&list);
+        drop(list);
+
+        delete_files(&recording);
+        Some(recording)
+    }
+
+    /*Delete the recordings older than max_age seconds, then the oldest ones
+    until the total size, including the recordings in progress, is within the
+    quota. The removed recordings are returned.*/
+    pub fn apply_retention(
+        &self,
+        now: i64,
+        max_age: Option<i64>,
+        quota: Option<u64>,
+    ) -> Vec<Recording> {
+        let mut list = self.list.lock().unwrap();
+        list.recordings.sort_by_key(|r| r.start_time);
+
+        let mut removed = Vec::new();
+        if let Some(max_age) = max_age {
+            let (expired, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut list.recordings)
+                .into_iter()
+                .partition(|r| now - r.end_time > max_age);
+            list.recordings = kept;
+            removed = expired;
+        }
+        if let Some(quota) = quota {
+            let active: u64 = self.active.lock().unwrap().values().sum();
+            let mut total: u64 = active + list.recordings.iter().map(|r| r.size).sum::<u64>();
+            while total > quota && !list.recordings.is_empty() {
+                let recording = list.recordings.remove(0);
+                total -= recording.size;
+                removed.push(recording);
+            }
+        }
+
+        if !removed.is_empty() {
+            self.save(&list);
+        }
+        drop(list);
+
+        for recording in &removed {
+            delete_files(recording);
+        }
+        removed
+    }
+}
+
+/*Only the files of the recording are deleted, its directory is removed if
+nothing else is left in it.*/
+fn delete_files(recording: &Recording) {
+    let path = Path::new(&recording.path);
+    if !path.is_dir() {
+        if let Err(err) = fs::remove_file(path) {
+            log::warn!("delete the recording {} error: {}", path.display(), err);
+        }
+        return;
+    }
+
+    for file in &recording.files {
+        if let Err(err) = fs::remove_file(path.join(file)) {
+            log::warn!(
+                "delete the file {} of the recording {} error: {}",
+                file,
+                path.display(),
+                err
+            );
+        }
+    }
+    if let Err(err) = fs::remove_dir(path) {
+        log::warn!(
+            "the directory of the recording {} is kept: {}",
+            path.display(),
+            err
+        );
+    }
+}
+
+/*{app}, {stream}, {yyyy}, {mm}, {dd}, {hh} and {start_time}(yyyymmddHHMMSS)
+are replaced*/
+pub fn render_path(
+    template: &str,
+    app: &str,
+    stream: &str,
+    start_time: &DateTime<Local>,
+) -> String {
+    template
+        .replace("{app}", app)
+        .replace("{stream}", stream)
+        .replace("{yyyy}", &start_time.format("%Y").to_string())
+        .replace("{mm}", &start_time.format("%m").to_string())
+        .replace("{dd}", &start_time.format("%d").to_string())
+        .replace("{hh}", &start_time.format("%H").to_string())
+        .replace(
+            "{start_time}",
+            &start_time.format("%Y%m%d%H%M%S").to_string(),
+        )
+}
+
+/*The record settings of a stream, the settings of the record config overridden
+by the ones of its app and then by the ones of the stream.*/
+#[derive(Debug, Clone)]
+pub struct RecordSettings {
+    pub format: RecordFormat,
+    /*None for the default root of the format*/
+    pub root: Option<String>,
+    pub path: String,
+    /*milliseconds*/
+    pub max_duration: Option<i64>,
+    /*bytes*/
+    pub max_size: Option<u64>,
+    /*the finished mp4 files are turned into progressive files*/
+    pub finalize: bool,
+    pub index: Arc<RecordIndex>,
+}
+
+impl RecordSettings {
+    /*A new file or directory of a recording under the root, the default root
+    is used if no root is configured. Every recording gets its own file or
+    directory: the start time is appended if the template has none, and a
+    counter if the path is taken, so that a recording never overwrites or
+    is deleted with another one.*/
+    pub fn start(&self, app: &str, stream: &str, default_root: &str) -> RecordFile {
+        let start_time = Local::now();
+        let root = self.root.as_deref().unwrap_or(default_root);
+        let extension = match self.format {
+            RecordFormat::Hls => "",
+            RecordFormat::Flv => ".flv",
+            RecordFormat::Mp4 => ".mp4",
+        };
+        let mut base = format!(
+            "{}/{}",
+            root.trim_end_matches('/'),
+            render_path(&self.path, app, stream, &start_time)
+        );
+        if !self.path.contains("{start_time}") {
+            base = format!("{base}/{}", start_time.format("%Y%m%d%H%M%S"));
+        }
+        let mut path = format!("{base}{extension}");
+        let mut count = 1;
+        while Path::new(&path).exists() {
+            path = format!("{base}_{count}{extension}");
+            count += 1;
+        }
+
+        RecordFile {
+            path,
+            app: app.to_string(),
+            stream: stream.to_string(),
+            format: self.format,
+            start_time,
+            duration: 0,
+            size: 0,
+            max_duration: self.max_duration,
+            max_size: self.max_size,
+            files: Vec::new(),
+            index: self.index.clone(),
+        }
+    }
+}
+
+/*The file or the directory being recorded, a new one is started when it
+reaches the max duration or the max size.*/
+#[derive(Debug)]
+pub struct RecordFile {
+    pub path: String,
+    app: String,
+    stream: String,
+    format: RecordFormat,
+    start_time: DateTime<Local>,
+    duration: i64,
+    size: u64,
+    max_duration: Option<i64>,
+    max_size: Option<u64>,
+    files: Vec<String>,
+    index: Arc<RecordIndex>,
+}
+
+impl RecordFile {
+    pub fn add(&mut self, duration: i64, size: u64) {
+        self.duration += duration;
+        self.size += size;
+        self.index.set_active_size(&self.path, self.size);
+    }
+
+    /*the size of a file which is rewritten when it is closed*/
+    pub fn set_size(&mut self, size: u64) {
+        self.size = size;
+        self.index.set_active_size(&self.path, self.size);
+    }
+
+    /*the files written in a recorded directory*/
+    pub fn set_files(&mut self, files: Vec<String>) {
+        self.files = files;
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.max_duration.is_some_and(|max| self.duration >= max)
+            || self.max_size.is_some_and(|max| self.size >= max)
+    }
+
+    /*the finished recording is added to the index*/
+    pub fn finish(mut self) -> Recording {
+        self.index.add(Recording {
+            id: 0,
+            app: std::mem::take(&mut self.app),
+            stream: std::mem::take(&mut self.stream),
+            format: self.format,
+            path: self.path.clone(),
+            files: std::mem::take(&mut self.files),
+            start_time: self.start_time.timestamp(),
+            end_time: Local::now().timestamp(),
+            duration: self.duration,
+            size: self.size,
+        })
+    }
+}
+
+/*a recording is no longer in progress once it is finished or abandoned*/
+impl Drop for RecordFile {
+    fn drop(&mut self) {
+        self.index.remove_active(&self.path);
+    }
+}
+
+/*Resolves the record settings of the streams and applies the retention
+policy. The record section of the config file is loaded again for every new
+stream, so that it can be changed without a restart.*/
+pub struct RecordService {
+    /*the config at startup, used if the config file cannot be loaded*/
+    config: RecordConfig,
+    index: Arc<RecordIndex>,
+}
+
+impl RecordService {
+    pub fn new(config: RecordConfig) -> Self {
+        let index = RecordIndex::load(config.index.as_deref().unwrap_or(DEFAULT_RECORD_INDEX));
+        Self {
+            config,
+            index: Arc::new(index),
+        }
+    }
+
+    fn load_config(&self) -> RecordConfig {
+        match load_config("config.json") {
+            Ok(config) => config.record.unwrap_or_else(|| self.config.clone()),
+            Err(err) => {
+                log::warn!("load record settings from config.json error: {}", err);
+                self.config.clone()
+            }
+        }
+    }
+
+    pub fn index(&self) -> &Arc<RecordIndex> {
+        &self.index
+    }
+
+    /*None if the stream is not recorded*/
+    pub fn settings(&self, app: &str, stream: &str) -> Option<RecordSettings> {
+        Self::resolve(&self.load_config(), self.index.clone(), app, stream)
+    }
+
+    /*the settings of a recording started by the api, it does not matter whether
+    the stream is recorded by the config*/
+    pub fn on_demand_settings(&self, app: &str, stream: &str) -> RecordSettings {
+        Self::resolve_enabled(&self.load_config(), self.index.clone(), app, stream).0
+    }
+
+    fn resolve(
+        config: &RecordConfig,
+        index: Arc<RecordIndex>,
+        app: &str,
+        stream: &str,
+    ) -> Option<RecordSettings> {
+        let (settings, enabled) = Self::resolve_enabled(config, index, app, stream);
+        Some(settings).filter(|_| enabled)
+    }
+
+    fn resolve_enabled(
+        config: &RecordConfig,
+        index: Arc<RecordIndex>,
+        app: &str,
+        stream: &str,
+    ) -> (RecordSettings, bool) {
+        let mut enabled = config.enabled;
+        let mut settings = RecordSettings {
+            format: config.format.unwrap_or_default(),
+            root: config.root.clone(),
+            path: config
+                .path
+                .clone()
+                .unwrap_or_else(|| String::from(DEFAULT_RECORD_PATH)),
+            max_duration: config.max_duration.map(|d| d as i64 * 1000),
+            max_size: config.max_size.map(|s| s * 1024 * 1024),
+            finalize: config.finalize.unwrap_or(false),
+            index,
+        };
+
+        let apps = config.apps.as_deref().unwrap_or_default();
+        let app_config = apps.iter().find(|c| c.app == app && c.stream.is_none());
+        let stream_config = apps
+            .iter()
+            .find(|c| c.app == app && c.stream.as_deref() == Some(stream));
+        for c in app_config.into_iter().chain(stream_config) {
+            Self::apply(&mut enabled, &mut settings, c);
+        }
+
+        (settings, enabled)
+    }
+
+    fn apply(enabled: &mut bool, settings: &mut RecordSettings, config: &RecordAppConfig) {
+        if let Some(app_enabled) = config.enabled {
+            *enabled = app_enabled;
+        }
+        if let Some(format) = config.format {
+            settings.format = format;
+        }
+        if let Some(path) = &config.path {
+            settings.path = path.clone();
+        }
+        if let Some(max_duration) = config.max_duration {
+            settings.max_duration = Some(max_duration as i64 * 1000);
+        }
+        if let Some(max_size) = config.max_size {
+            settings.max_size = Some(max_size * 1024 * 1024);
+        }
+        if let Some(finalize) = config.finalize {
+            settings.finalize = finalize;
+        }
+    }
+
+    /*run periodically, the limits are loaded from the config file each time*/
+    pub fn apply_retention(&self) {
+        let config = self.load_config();
+        let max_age = config.max_age.map(|hours| hours as i64 * 3600);
+        let quota = config.quota.map(|mb| mb * 1024 * 1024);
+        if max_age.is_none() && quota.is_none() {
+            return;
+        }
+
+        for recording in self
+            .index
+            .apply_retention(Local::now().timestamp(), max_age, quota)
+        {
+            log::info!(
+                "recording {} of {}/{} is removed by the retention policy: {}",
+                recording.id,
+                recording.app,
+                recording.stream,
+                recording.path
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        render_path, RecordIndex, RecordService, RecordSettings, Recording, DEFAULT_RECORD_PATH,
+    };
+    use crate::config::{RecordAppConfig, RecordConfig, RecordFormat};
+    use chrono::{Local, TimeZone};
+    use std::{path::Path, sync::Arc};
+
+    #[test]
+    fn test_record_settings() {
+        let start_time = Local.with_ymd_and_hms(2024, 5, 1, 8, 30, 5).unwrap();
+        assert_eq!(
+            render_path(DEFAULT_RECORD_PATH, "live", "news", &start_time),
+            "live/news/2024/05/01/20240501083005"
+        );
+
+        let config = RecordConfig {
+            enabled: false,
+            max_duration: Some(3600),
+            apps: Some(vec![
+                RecordAppConfig {
+                    app: String::from("live"),
+                    enabled: Some(true),
+                    format: Some(RecordFormat::Mp4),
+                    max_size: Some(100),
+                    finalize: Some(true),
+                    ..Default::default()
+                },
+                RecordAppConfig {
+                    app: String::from("live"),
+                    stream: Some(String::from("test")),
+                    enabled: Some(false),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+        let index = Arc::new(RecordIndex::load("/nonexistent/recordings.json"));
+
+        let settings = RecordService::resolve(&config, index.clone(), "live", "news").unwrap();
+        assert_eq!(settings.max_duration, Some(3600 * 1000));
+        assert_eq!(settings.max_size, Some(100 * 1024 * 1024));
+        assert_eq!(settings.format, RecordFormat::Mp4);
+        assert!(settings.finalize);
+        assert!(RecordService::resolve(&config, index.clone(), "live", "test").is_none());
+        /*the settings of the disabled streams are used by the api*/
+        let (settings, enabled) =
+            RecordService::resolve_enabled(&config, index.clone(), "live", "test");
+        assert!(!enabled);
+        assert_eq!(settings.format, RecordFormat::Mp4);
+        assert!(RecordService::resolve(&config, index, "other", "news").is_none());
+    }
+
+    #[test]
+    fn test_record_retention() {
+        let path = std::env::temp_dir().join(format!("xiu_recordings_{}.json", std::process::id()));
+        let index = RecordIndex::load(path.to_str().unwrap());
+        for (start_time, size) in [(1000, 40), (2000, 40), (3000, 40), (4000, 40)] {
+            index.add(Recording {
+                id: 0,
+                app: String::from("live"),
+                stream: String::from("news"),
+                format: RecordFormat::Hls,
+                path: format!("/nonexistent/{start_time}"),
+                files: Vec::new(),
+                start_time,
+                end_time: start_time + 500,
+                duration: 500 * 1000,
+                size,
+            });
+        }
+
+        /*the first one is too old, then the second one is over the quota*/
+        let removed = index.apply_retention(4000, Some(2000), Some(100));
+        assert_eq!(removed.iter().map(|r| r.id).collect::<Vec<_>>(), vec![0, 1]);
+
+        /*the index is persisted*/
+        let loaded = RecordIndex::load(path.to_str().unwrap());
+        assert_eq!(loaded.list(Some("live"), None).len(), 2);
+        assert_eq!(loaded.remove(3).map(|r| r.start_time), Some(4000));
+        assert_eq!(loaded.list(None, Some("news")), index.list(None, None)[..1]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_record_quota_with_active() {
+        let path = std::env::temp_dir().join(format!("xiu_active_{}.json", std::process::id()));
+        let settings = RecordSettings {
+            format: RecordFormat::Flv,
+            root: Some(String::from("/nonexistent")),
+            path: String::from("{app}/{stream}"),
+            max_duration: None,
+            max_size: None,
+            finalize: false,
+            index: Arc::new(RecordIndex::load(path.to_str().unwrap())),
+        };
+        let index = settings.index.clone();
+        for start_time in [1000, 2000] {
+            index.add(Recording {
+                id: 0,
+                app: String::from("live"),
+                stream: String::from("news"),
+                format: RecordFormat::Flv,
+                path: format!("/nonexistent/{start_time}.flv"),
+                files: Vec::new(),
+                start_time,
+                end_time: start_time + 500,
+                duration: 500 * 1000,
+                size: 40,
+            });
+        }
+        /*the index is moved over the old one, no temp file is left*/
+        assert!(path.is_file());
+        assert!(!Path::new(&format!("{}.tmp", path.display())).exists());
+
+        /*the recording in progress takes the place of the oldest one*/
+        let mut active = settings.start("live", "news", "");
+        active.add(1000, 50);
+        let removed = index.apply_retention(3000, None, Some(100));
+        assert_eq!(removed.iter().map(|r| r.id).collect::<Vec<_>>(), vec![0]);
+
+        /*it is not counted twice once it is finished*/
+        active.set_size(20);
+        active.finish();
+        assert!(index.apply_retention(3000, None, Some(100)).is_empty());
+        assert_eq!(index.list(None, None).len(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_record_files() {
+        let root = std::env::temp_dir().join(format!("xiu_record_files_{}", std::process::id()));
+        let index_path = root.join("recordings.json");
+        std::fs::create_dir_all(&root).unwrap();
+        let settings = RecordSettings {
+            format: RecordFormat::Hls,
+            root: Some(root.to_str().unwrap().to_string()),
+            path: String::from("{app}/{stream}"),
+            max_duration: None,
+            max_size: None,
+            finalize: false,
+            index: Arc::new(RecordIndex::load(index_path.to_str().unwrap())),
+        };
+
+        /*the recordings of a stream started in the same second do not share
+        a directory*/
+        let mut first = settings.start("live", "news", "");
+        std::fs::create_dir_all(&first.path).unwrap();
+        let second = settings.start("live", "news", "");
+        assert_ne!(first.path, second.path);
+        assert!(second.path.starts_with(&format!("{}/live/news/", root.display())));
+
+        /*only the files of the recording are deleted*/
+        for file in ["0.ts", "vod_news.m3u8", "other.ts"] {
+            std::fs::write(format!("{}/{file}", first.path), b"data").unwrap();
+        }
+        first.set_files(vec![String::from("0.ts"), String::from("vod_news.m3u8")]);
+        let recording = first.finish();
+        settings.index.remove(recording.id).unwrap();
+        assert!(!Path::new(&format!("{}/0.ts", recording.path)).exists());
+        assert!(Path::new(&format!("{}/other.ts", recording.path)).exists());
+
+        /*a corrupt index is moved aside instead of being overwritten*/
+        std::fs::write(&index_path, b"{corrupt").unwrap();
+        let index = RecordIndex::load(index_path.to_str().unwrap());
+        assert!(index.list(None, None).is_empty());
+        let backup = std::fs::read_dir(&root)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .find(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("recordings.json.corrupt.")
+            })
+            .unwrap();
+        assert_eq!(std::fs::read(backup.path()).unwrap(), b"{corrupt");
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
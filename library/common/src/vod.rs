@@ -0,0 +1,115 @@
+use {
+    crate::{config::VodConfig, record::DEFAULT_RECORD_ROOT},
+    std::path::{Component, PathBuf},
+};
+
+pub const DEFAULT_VOD_APP: &str = "vod";
+
+/*Maps the streams played on the vod app to the recorded files under the
+root, e.g. the stream "2024/show.flv" is the file "{root}/2024/show.flv".*/
+#[derive(Debug, Clone)]
+pub struct VodService {
+    app: String,
+    root: PathBuf,
+}
+
+impl VodService {
+    /*the root of the recordings is used if no root is configured*/
+    pub fn new(config: &VodConfig, record_root: Option<&str>) -> Self {
+        let root = config
+            .root
+            .as_deref()
+            .or(record_root)
+            .unwrap_or(DEFAULT_RECORD_ROOT);
+        Self {
+            app: config
+                .app
+                .clone()
+                .unwrap_or_else(|| String::from(DEFAULT_VOD_APP)),
+            root: PathBuf::from(root),
+        }
+    }
+
+    pub fn app(&self) -> &str {
+        &self.app
+    }
+
+    pub fn is_vod_app(&self, app: &str) -> bool {
+        self.app == app
+    }
+
+    /*The "flv:" and "mp4:" prefixes of the stream names used by the flash
+    players are removed, an mp4 stream name may omit the extension. A ".flv"
+    stream is played from the mp4 file of the same name if there is no flv
+    file, so that the recordings of both formats can be played by http-flv.
+    None if the file is not found or is outside of the root.*/
+    pub fn file_path(&self, stream: &str) -> Option<PathBuf> {
+        let (name, default_extension) = match stream.split_once(':') {
+            Some(("mp4", name)) => (name, Some("mp4")),
+            Some(("flv", name)) => (name, Some("flv")),
+            _ => (stream, None),
+        };
+
+        let mut relative = PathBuf::from(name.trim_start_matches('/'));
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return None;
+        }
+        if let (None, Some(extension)) = (relative.extension(), default_extension) {
+            relative.set_extension(extension);
+        }
+
+        let path = self.root.join(&relative);
+        let candidates = match path.extension().and_then(|e| e.to_str()) {
+            Some("flv") => vec![path.clone(), path.with_extension("mp4")],
+            Some("mp4") => vec![path],
+            _ => return None,
+        };
+        candidates.into_iter().find(|path| path.is_file())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VodService;
+    use crate::config::VodConfig;
+
+    #[test]
+    fn test_vod_file_path() {
+        let root = std::env::temp_dir().join(format!("xiu_vod_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("2024")).unwrap();
+        for name in ["2024/show.flv", "2024/news.mp4"] {
+            std::fs::write(root.join(name), b"").unwrap();
+        }
+
+        let service = VodService::new(
+            &VodConfig {
+                enabled: true,
+                app: None,
+                root: Some(root.to_str().unwrap().to_string()),
+            },
+            None,
+        );
+        assert!(service.is_vod_app("vod"));
+        assert_eq!(
+            service.file_path("2024/show.flv"),
+            Some(root.join("2024/show.flv"))
+        );
+        /*the mp4 recording is played by its flv name*/
+        assert_eq!(
+            service.file_path("2024/news.flv"),
+            Some(root.join("2024/news.mp4"))
+        );
+        assert_eq!(
+            service.file_path("mp4:2024/news"),
+            Some(root.join("2024/news.mp4"))
+        );
+        assert_eq!(service.file_path("2024/missing.flv"), None);
+        assert_eq!(service.file_path("../2024/show.flv"), None);
+        assert_eq!(service.file_path("2024/show.txt"), None);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
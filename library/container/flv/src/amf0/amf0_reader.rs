@@ -47,6 +47,7 @@ impl Amf0Reader {
             amf0_markers::OBJECT => self.read_object(),
             amf0_markers::NULL => self.read_null(),
             amf0_markers::ECMA_ARRAY => self.read_ecma_array(),
+            amf0_markers::STRICT_ARRAY => self.read_strict_array(),
             amf0_markers::LONG_STRING => self.read_long_string(),
             _ => Err(Amf0ReadError {
                 value: Amf0ReadErrorValue::UnknownMarker { marker: markers },
@@ -146,6 +147,17 @@ impl Amf0Reader {
         Ok(Amf0ValueType::Object(properties))
     }
 
+    pub fn read_strict_array(&mut self) -> Result<Amf0ValueType, Amf0ReadError> {
+        let len = self.reader.read_u32::<BigEndian>()?;
+
+        let mut values = Vec::new();
+        for _ in 0..len {
+            values.push(self.read_any()?);
+        }
+
+        Ok(Amf0ValueType::StrictArray(values))
+    }
+
     pub fn read_long_string(&mut self) -> Result<Amf0ValueType, Amf0ReadError> {
         let l = self.reader.read_u32::<BigEndian>()?;
 
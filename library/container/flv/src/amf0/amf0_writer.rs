@@ -32,6 +32,7 @@ impl Amf0Writer {
             Amf0ValueType::UTF8String(ref val) => self.write_string(val),
             Amf0ValueType::Object(ref val) => self.write_object(val),
             Amf0ValueType::EcmaArray(ref val) => self.write_eacm_array(val),
+            Amf0ValueType::StrictArray(ref val) => self.write_strict_array(val),
             _ => Ok(()),
         }
     }
@@ -107,6 +108,16 @@ impl Amf0Writer {
         Ok(())
     }
 
+    pub fn write_strict_array(&mut self, values: &[Amf0ValueType]) -> Result<(), Amf0WriteError> {
+        self.writer.write_u8(amf0_markers::STRICT_ARRAY)?;
+        self.writer.write_u32::<BigEndian>(values.len() as u32)?;
+
+        for value in values {
+            self.write_any(value)?;
+        }
+        Ok(())
+    }
+
     // pub async fn flush(&mut self) -> Result<(), Amf0WriteError> {
     //     self.writer.flush()?;
     // }
@@ -8,6 +8,7 @@ pub enum Amf0ValueType {
     Object(IndexMap<String, Amf0ValueType>),
     Null,
     EcmaArray(IndexMap<String, Amf0ValueType>),
+    StrictArray(Vec<Amf0ValueType>),
     LongUTF8String(String),
     END,
 }
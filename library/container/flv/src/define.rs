@@ -19,6 +19,17 @@ pub mod avc_packet_type {
     pub const AVC_EOS: u8 = 2;
 }
 
+/*the video packet types of the Enhanced RTMP extended video tag header*/
+pub mod ex_video_packet_type {
+    pub const SEQUENCE_START: u8 = 0;
+    pub const CODED_FRAMES: u8 = 1;
+    pub const SEQUENCE_END: u8 = 2;
+    /*the composition time is 0 and not carried*/
+    pub const CODED_FRAMES_X: u8 = 3;
+}
+
+pub const FOURCC_HEVC: &[u8; 4] = b"hvc1";
+
 pub mod frame_type {
     /*
         1: keyframe (for AVC, a seekable frame)
@@ -59,6 +70,16 @@ pub mod h264_nal_type {
     pub const H264_NAL_PPS: u8 = 8;
     pub const H264_NAL_AUD: u8 = 9;
 }
+
+pub mod hevc_nal_type {
+    /*the IRAP(BLA/IDR/CRA) pictures are 16 to 23*/
+    pub const HEVC_NAL_BLA_W_LP: u8 = 16;
+    pub const HEVC_NAL_RSV_IRAP_23: u8 = 23;
+    pub const HEVC_NAL_VPS: u8 = 32;
+    pub const HEVC_NAL_SPS: u8 = 33;
+    pub const HEVC_NAL_PPS: u8 = 34;
+    pub const HEVC_NAL_AUD: u8 = 35;
+}
 #[derive(Debug, Clone, Serialize, Default)]
 pub enum AacProfile {
     // @see @see ISO_IEC_14496-3-AAC-2001.pdf, page 23
@@ -9,6 +9,7 @@ use {
         errors::FlvDemuxerError,
         mpeg4_aac::Mpeg4AacProcessor,
         mpeg4_avc::Mpeg4AvcProcessor,
+        mpeg4_hevc::Mpeg4HevcProcessor,
     },
     byteorder::BigEndian,
     bytes::BytesMut,
@@ -114,14 +115,24 @@ impl FlvDemuxerVideoData {
 #[derive(Default)]
 pub struct FlvVideoTagDemuxer {
     avc_processor: Mpeg4AvcProcessor,
+    hevc_processor: Mpeg4HevcProcessor,
+    /*the codec of the last sequence header*/
+    codec_id: u8,
 }
 
 impl FlvVideoTagDemuxer {
     pub fn new() -> Self {
         Self {
             avc_processor: Mpeg4AvcProcessor::new(),
+            hevc_processor: Mpeg4HevcProcessor::new(),
+            codec_id: AvcCodecId::UNKNOWN as u8,
         }
     }
+
+    pub fn codec_id(&self) -> u8 {
+        self.codec_id
+    }
+
     pub fn demux(
         &mut self,
         timestamp: u32,
@@ -135,6 +146,7 @@ impl FlvVideoTagDemuxer {
                 avc_packet_type::AVC_SEQHDR => {
                     self.avc_processor
                         .decoder_configuration_record_load(&mut reader)?;
+                    self.codec_id = tag_header.codec_id;
 
                     return Ok(None);
                 }
@@ -153,6 +165,29 @@ impl FlvVideoTagDemuxer {
                 }
                 _ => {}
             }
+        } else if tag_header.codec_id == AvcCodecId::HEVC as u8 {
+            match tag_header.avc_packet_type {
+                avc_packet_type::AVC_SEQHDR => {
+                    self.hevc_processor
+                        .decoder_configuration_record_load(&mut reader)?;
+                    self.codec_id = tag_header.codec_id;
+
+                    return Ok(None);
+                }
+                avc_packet_type::AVC_NALU => {
+                    let data = self.hevc_processor.h265_mp4toannexb(&mut reader)?;
+
+                    let video_data = FlvDemuxerVideoData {
+                        codec_id: AvcCodecId::HEVC as u8,
+                        pts: timestamp as i64 + tag_header.composition_time as i64,
+                        dts: timestamp as i64,
+                        frame_type: tag_header.frame_type,
+                        data,
+                    };
+                    return Ok(Some(video_data));
+                }
+                _ => {}
+            }
         }
 
         Ok(None)
@@ -162,15 +197,22 @@ impl FlvVideoTagDemuxer {
 #[derive(Default)]
 pub struct FlvAudioTagDemuxer {
     aac_processor: Mpeg4AacProcessor,
+    /*an AAC sequence header is received*/
+    has_sequence_header: bool,
 }
 
 impl FlvAudioTagDemuxer {
     pub fn new() -> Self {
         Self {
             aac_processor: Mpeg4AacProcessor::new(),
+            has_sequence_header: false,
         }
     }
 
+    pub fn has_sequence_header(&self) -> bool {
+        self.has_sequence_header
+    }
+
     pub fn demux(
         &mut self,
         timestamp: u32,
@@ -187,6 +229,7 @@ impl FlvAudioTagDemuxer {
                 aac_packet_type::AAC_SEQHDR => {
                     if self.aac_processor.bytes_reader.len() >= 2 {
                         self.aac_processor.audio_specific_config_load()?;
+                        self.has_sequence_header = true;
                     }
 
                     return Ok(FlvDemuxerAudioData::new());
@@ -229,6 +272,11 @@ impl FlvDemuxer {
         Ok(())
     }
 
+    /*only the last previous tag size is left*/
+    pub fn is_end(&self) -> bool {
+        self.bytes_reader.len() <= 4
+    }
+
     pub fn read_flv_tag(&mut self) -> Result<Option<FlvData>, FlvDemuxerError> {
         /*previous_tag_size*/
         self.bytes_reader.read_u32::<BigEndian>()?;
@@ -262,6 +310,12 @@ impl FlvDemuxer {
                     data: body,
                 }));
             }
+            tag_type::SCRIPT_DATA_AMF => {
+                return Ok(Some(FlvData::MetaData {
+                    timestamp: dts,
+                    data: body,
+                }));
+            }
 
             _ => {}
         }
@@ -166,6 +166,37 @@ impl Unmarshal<&mut BytesReader, Result<Self, FlvDemuxerError>> for VideoTagHead
         let mut tag_header = VideoTagHeader::defalut();
 
         let flags = reader.read_u8()?;
+
+        /*Enhanced RTMP: IsExHeader(1 bit) | FrameType(3 bits) | PacketType(4 bits) | FourCC(4 bytes),
+        the HEVC packets are mapped to the legacy AVC packet types*/
+        if flags & 0x80 != 0 {
+            tag_header.frame_type = (flags >> 4) & 0x07;
+            let fourcc = reader.read_bytes(4)?;
+            if &fourcc[..] != define::FOURCC_HEVC {
+                return Ok(tag_header);
+            }
+
+            match flags & 0x0f {
+                define::ex_video_packet_type::SEQUENCE_START => {
+                    tag_header.avc_packet_type = define::avc_packet_type::AVC_SEQHDR;
+                }
+                define::ex_video_packet_type::CODED_FRAMES => {
+                    tag_header.avc_packet_type = define::avc_packet_type::AVC_NALU;
+                    tag_header.composition_time = read_composition_time(reader)?;
+                }
+                define::ex_video_packet_type::CODED_FRAMES_X => {
+                    tag_header.avc_packet_type = define::avc_packet_type::AVC_NALU;
+                }
+                define::ex_video_packet_type::SEQUENCE_END => {
+                    tag_header.avc_packet_type = define::avc_packet_type::AVC_EOS;
+                }
+                /*the metadata and the other packets are not supported*/
+                _ => return Ok(tag_header),
+            }
+            tag_header.codec_id = define::AvcCodecId::HEVC as u8;
+            return Ok(tag_header);
+        }
+
         tag_header.frame_type = flags >> 4;
         tag_header.codec_id = flags & 0x0f;
 
@@ -173,27 +204,31 @@ impl Unmarshal<&mut BytesReader, Result<Self, FlvDemuxerError>> for VideoTagHead
             || tag_header.codec_id == define::AvcCodecId::HEVC as u8
         {
             tag_header.avc_packet_type = reader.read_u8()?;
-            tag_header.composition_time = 0;
-
-            //bigend 3bytes
-            for _ in 0..3 {
-                let time = reader.read_u8()?;
-                //print!("==time0=={}\n", time);
-                //print!("==time1=={}\n", self.tag.composition_time);
-                tag_header.composition_time = (tag_header.composition_time << 8) + time as i32;
-            }
-            //transfer to signed i24
-            if tag_header.composition_time & (1 << 23) != 0 {
-                let sign_extend_mask = 0xff_ff << 23;
-                // Sign extend the value
-                tag_header.composition_time |= sign_extend_mask
-            }
+            tag_header.composition_time = read_composition_time(reader)?;
         }
 
         Ok(tag_header)
     }
 }
 
+fn read_composition_time(reader: &mut BytesReader) -> Result<i32, FlvDemuxerError> {
+    let mut composition_time: i32 = 0;
+
+    //bigend 3bytes
+    for _ in 0..3 {
+        let time = reader.read_u8()?;
+        composition_time = (composition_time << 8) + time as i32;
+    }
+    //transfer to signed i24
+    if composition_time & (1 << 23) != 0 {
+        let sign_extend_mask = 0xff_ff << 23;
+        // Sign extend the value
+        composition_time |= sign_extend_mask
+    }
+
+    Ok(composition_time)
+}
+
 impl Marshal<Result<BytesMut, FlvMuxerError>> for VideoTagHeader {
     fn marshal(&self) -> Result<BytesMut, FlvMuxerError> {
         let mut writer = BytesWriter::default();
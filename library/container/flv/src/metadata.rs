@@ -0,0 +1,107 @@
+use {
+    super::amf0::{
+        amf0_reader::Amf0Reader, amf0_writer::Amf0Writer, Amf0ReadError, Amf0ValueType,
+        Amf0WriteError,
+    },
+    bytes::BytesMut,
+    bytesio::bytes_reader::BytesReader,
+    indexmap::IndexMap,
+};
+
+/*The properties of an onMetaData script data, the "@setDataFrame" of the
+RTMP metadata is skipped.*/
+pub fn read_on_meta_data(data: BytesMut) -> Result<IndexMap<String, Amf0ValueType>, Amf0ReadError> {
+    let values = Amf0Reader::new(BytesReader::new(data)).read_all()?;
+    let properties = values.into_iter().find_map(|value| match value {
+        Amf0ValueType::Object(properties) | Amf0ValueType::EcmaArray(properties) => {
+            Some(properties)
+        }
+        _ => None,
+    });
+    Ok(properties.unwrap_or_default())
+}
+
+/*The onMetaData of a recorded file with its duration in seconds, its size
+and the keyframes index(the times in seconds and the file positions of the
+key frame tags) which makes it seekable. The size of the data only depends on
+the properties and the number of key frames, so it can be written before the
+positions are known.*/
+pub fn write_on_meta_data(
+    mut properties: IndexMap<String, Amf0ValueType>,
+    duration: f64,
+    filesize: u64,
+    keyframes: &[(f64, u64)],
+) -> Result<BytesMut, Amf0WriteError> {
+    let times = keyframes
+        .iter()
+        .map(|(time, _)| Amf0ValueType::Number(*time))
+        .collect();
+    let filepositions = keyframes
+        .iter()
+        .map(|(_, position)| Amf0ValueType::Number(*position as f64))
+        .collect();
+    let mut index = IndexMap::new();
+    index.insert(String::from("times"), Amf0ValueType::StrictArray(times));
+    index.insert(
+        String::from("filepositions"),
+        Amf0ValueType::StrictArray(filepositions),
+    );
+
+    properties.insert(String::from("duration"), Amf0ValueType::Number(duration));
+    properties.insert(
+        String::from("filesize"),
+        Amf0ValueType::Number(filesize as f64),
+    );
+    properties.insert(
+        String::from("hasKeyframes"),
+        Amf0ValueType::Boolean(!keyframes.is_empty()),
+    );
+    properties.insert(String::from("keyframes"), Amf0ValueType::Object(index));
+
+    let mut writer = Amf0Writer::new();
+    writer.write_string(&String::from("onMetaData"))?;
+    writer.write_eacm_array(&properties)?;
+    Ok(writer.extract_current_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_on_meta_data, write_on_meta_data};
+    use crate::amf0::{amf0_writer::Amf0Writer, Amf0ValueType};
+    use indexmap::IndexMap;
+
+    #[test]
+    fn test_on_meta_data() {
+        let mut properties = IndexMap::new();
+        properties.insert(String::from("width"), Amf0ValueType::Number(1280.0));
+        properties.insert(String::from("duration"), Amf0ValueType::Number(0.0));
+
+        let mut writer = Amf0Writer::new();
+        writer.write_string(&String::from("@setDataFrame")).unwrap();
+        writer.write_string(&String::from("onMetaData")).unwrap();
+        writer.write_eacm_array(&properties).unwrap();
+        let properties = read_on_meta_data(writer.extract_current_bytes()).unwrap();
+        assert_eq!(properties["width"], Amf0ValueType::Number(1280.0));
+
+        let keyframes = [(0.0, 100), (2.0, 5000)];
+        let data = write_on_meta_data(properties.clone(), 4.0, 9000, &keyframes).unwrap();
+        /*the size does not depend on the values*/
+        let placeholder = write_on_meta_data(properties, 0.0, 0, &[(0.0, 0); 2]).unwrap();
+        assert_eq!(data.len(), placeholder.len());
+
+        let properties = read_on_meta_data(data).unwrap();
+        assert_eq!(properties["duration"], Amf0ValueType::Number(4.0));
+        assert_eq!(properties["filesize"], Amf0ValueType::Number(9000.0));
+        let index = match &properties["keyframes"] {
+            Amf0ValueType::Object(index) => index,
+            _ => panic!("no keyframes index"),
+        };
+        assert_eq!(
+            index["filepositions"],
+            Amf0ValueType::StrictArray(vec![
+                Amf0ValueType::Number(100.0),
+                Amf0ValueType::Number(5000.0)
+            ])
+        );
+    }
+}
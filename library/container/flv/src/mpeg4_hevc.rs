@@ -1,4 +1,13 @@
-use {super::errors::Mpeg4AvcHevcError, byteorder::BigEndian, bytesio::bytes_reader::BytesReader};
+use {
+    super::{define::hevc_nal_type, errors::Mpeg4AvcHevcError},
+    byteorder::BigEndian,
+    bytes::BytesMut,
+    bytesio::{bits_reader::BitsReader, bytes_reader::BytesReader, bytes_writer::BytesWriter},
+    h264_decoder::{errors::H264Error, utils::read_uev},
+};
+
+const H265_START_CODE: [u8; 4] = [0x00, 0x00, 0x00, 0x01];
+
 #[allow(dead_code)]
 #[derive(Default)]
 
@@ -20,6 +29,13 @@ pub struct Mpeg4Hevc {
     num_temporal_layers: u8,   // 3bit,[0,7]
     temporal_id_nested: u8,    // 1bit,[0,1]
     length_size_minus_one: u8, // 2bit,[0,3]
+
+    /*the resolution parsed from the SPS, cropped by the conformance window*/
+    pub width: u32,
+    pub height: u32,
+    /*the VPS/SPS/PPS nalus of the hvcC arrays*/
+    pub nalus: Vec<BytesMut>,
+    pub parameter_sets_annexb_data: BytesWriter, // pice together all the vps/sps/pps data
 }
 
 #[derive(Default)]
@@ -28,6 +44,11 @@ pub struct Mpeg4HevcProcessor {
 }
 
 impl Mpeg4HevcProcessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /*HEVCDecoderConfigurationRecord, ISO/IEC 14496-15 8.3.3.1*/
     pub fn decoder_configuration_record_load(
         &mut self,
         bytes_reader: &mut BytesReader,
@@ -47,7 +68,251 @@ impl Mpeg4HevcProcessor {
         self.mpeg4_hevc.parallelism_type = bytes_reader.read_u8()? & 0x03;
         self.mpeg4_hevc.chroma_format = bytes_reader.read_u8()? & 0x03;
         self.mpeg4_hevc.bit_depth_luma_minus8 = bytes_reader.read_u8()? & 0x07;
+        self.mpeg4_hevc.bit_depth_chroma_minus8 = bytes_reader.read_u8()? & 0x07;
+        self.mpeg4_hevc.avg_frame_rate = bytes_reader.read_u16::<BigEndian>()?;
+
+        let byte_22 = bytes_reader.read_u8()?;
+        self.mpeg4_hevc.constant_frame_rate = (byte_22 >> 6) & 0x03;
+        self.mpeg4_hevc.num_temporal_layers = (byte_22 >> 3) & 0x07;
+        self.mpeg4_hevc.temporal_id_nested = (byte_22 >> 2) & 0x01;
+        self.mpeg4_hevc.length_size_minus_one = byte_22 & 0x03;
+
+        self.mpeg4_hevc.nalus.clear();
+        self.mpeg4_hevc.parameter_sets_annexb_data.clear();
+
+        let num_of_arrays = bytes_reader.read_u8()?;
+        for _ in 0..num_of_arrays {
+            /*array_completeness(1 bit) reserved(1 bit) NAL_unit_type(6 bits)*/
+            let nal_type = bytes_reader.read_u8()? & 0x3F;
+            let num_nalus = bytes_reader.read_u16::<BigEndian>()?;
+            for _ in 0..num_nalus {
+                let nalu_length = bytes_reader.read_u16::<BigEndian>()?;
+                let nalu = bytes_reader.read_bytes(nalu_length as usize)?;
+
+                if nal_type == hevc_nal_type::HEVC_NAL_SPS {
+                    (self.mpeg4_hevc.width, self.mpeg4_hevc.height) = parse_sps_resolution(&nalu)?;
+                }
+                if matches!(
+                    nal_type,
+                    hevc_nal_type::HEVC_NAL_VPS
+                        | hevc_nal_type::HEVC_NAL_SPS
+                        | hevc_nal_type::HEVC_NAL_PPS
+                ) {
+                    self.mpeg4_hevc
+                        .parameter_sets_annexb_data
+                        .write(&H265_START_CODE)?;
+                    self.mpeg4_hevc
+                        .parameter_sets_annexb_data
+                        .write(&nalu[..])?;
+                }
+                self.mpeg4_hevc.nalus.push(nalu);
+            }
+        }
+
+        log::info!(
+            "mpeg4 hevc profile: {} level: {} resolution: {}x{}",
+            self.mpeg4_hevc.general_profile_idc,
+            self.mpeg4_hevc.general_level_idc,
+            self.mpeg4_hevc.width,
+            self.mpeg4_hevc.height
+        );
+        /*clear the left bytes*/
+        bytes_reader.extract_remaining_bytes();
 
         Ok(self)
     }
+
+    /*The length prefixed nalus are converted to the Annex-B byte stream, the
+    VPS/SPS/PPS are put before an IRAP picture if the frame does not carry them.*/
+    pub fn h265_mp4toannexb(
+        &mut self,
+        bytes_reader: &mut BytesReader,
+    ) -> Result<BytesMut, Mpeg4AvcHevcError> {
+        let mut bytes_writer = BytesWriter::new();
+
+        let mut parameter_sets_flag = false;
+        while !bytes_reader.is_empty() {
+            let size = self.read_nalu_size(bytes_reader)?;
+            let nalu_type = (bytes_reader.advance_u8()? >> 1) & 0x3F;
+
+            match nalu_type {
+                hevc_nal_type::HEVC_NAL_VPS
+                | hevc_nal_type::HEVC_NAL_SPS
+                | hevc_nal_type::HEVC_NAL_PPS => {
+                    parameter_sets_flag = true;
+                }
+                hevc_nal_type::HEVC_NAL_BLA_W_LP..=hevc_nal_type::HEVC_NAL_RSV_IRAP_23
+                    if !parameter_sets_flag =>
+                {
+                    parameter_sets_flag = true;
+
+                    bytes_writer.prepend(
+                        &self
+                            .mpeg4_hevc
+                            .parameter_sets_annexb_data
+                            .get_current_bytes()[..],
+                    )?;
+                }
+                _ => {}
+            }
+
+            bytes_writer.write(&H265_START_CODE)?;
+            let data = bytes_reader.read_bytes(size as usize)?;
+            bytes_writer.write(&data[..])?;
+        }
+
+        Ok(bytes_writer.extract_current_bytes())
+    }
+
+    pub fn read_nalu_size(
+        &mut self,
+        bytes_reader: &mut BytesReader,
+    ) -> Result<u32, Mpeg4AvcHevcError> {
+        let mut size: u32 = 0;
+
+        for _ in 0..self.mpeg4_hevc.length_size_minus_one + 1 {
+            size = bytes_reader.read_u8()? as u32 + (size << 8);
+        }
+        Ok(size)
+    }
+}
+
+/*the emulation prevention bytes(0x03 after two zero bytes) are removed from a nalu*/
+fn rbsp(nalu: &[u8]) -> BytesMut {
+    let mut data = BytesMut::with_capacity(nalu.len());
+    let mut zeros = 0;
+    for &byte in nalu {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        data.extend_from_slice(&[byte]);
+    }
+    data
+}
+
+/*seq_parameter_set_rbsp up to the conformance window, ITU-T H.265 7.3.2.2*/
+fn parse_sps_resolution(nalu: &[u8]) -> Result<(u32, u32), H264Error> {
+    let mut reader = BitsReader::new(BytesReader::new(rbsp(nalu)));
+    /*nal unit header(16 bits), sps_video_parameter_set_id(4 bits)*/
+    reader.read_n_bits(20)?;
+    let max_sub_layers_minus1 = reader.read_n_bits(3)? as usize;
+    /*sps_temporal_id_nesting_flag*/
+    reader.read_bit()?;
+
+    /*profile_tier_level: the general profile(88 bits) and level(8 bits)*/
+    reader.read_n_bits(48)?;
+    reader.read_n_bits(48)?;
+    let mut sub_layers = Vec::new();
+    for _ in 0..max_sub_layers_minus1 {
+        let profile_present = reader.read_bit()? == 1;
+        let level_present = reader.read_bit()? == 1;
+        sub_layers.push((profile_present, level_present));
+    }
+    if max_sub_layers_minus1 > 0 {
+        reader.read_n_bits(2 * (8 - max_sub_layers_minus1))?;
+    }
+    for (profile_present, level_present) in sub_layers {
+        if profile_present {
+            reader.read_n_bits(44)?;
+            reader.read_n_bits(44)?;
+        }
+        if level_present {
+            reader.read_n_bits(8)?;
+        }
+    }
+
+    /*sps_seq_parameter_set_id*/
+    read_uev(&mut reader)?;
+    let chroma_format_idc = read_uev(&mut reader)?;
+    if chroma_format_idc == 3 {
+        /*separate_colour_plane_flag*/
+        reader.read_bit()?;
+    }
+    let mut width = read_uev(&mut reader)?;
+    let mut height = read_uev(&mut reader)?;
+
+    /*conformance_window_flag, the offsets are in chroma samples*/
+    if reader.read_bit()? == 1 {
+        let (sub_width, sub_height) = match chroma_format_idc {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        let left = read_uev(&mut reader)?;
+        let right = read_uev(&mut reader)?;
+        let top = read_uev(&mut reader)?;
+        let bottom = read_uev(&mut reader)?;
+        width = width.saturating_sub(sub_width * (left + right));
+        height = height.saturating_sub(sub_height * (top + bottom));
+    }
+
+    Ok((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_sps_resolution, Mpeg4HevcProcessor};
+    use bytes::BytesMut;
+    use bytesio::bytes_reader::BytesReader;
+
+    /*the SPS of a 1920x1080 Main profile stream, with emulation prevention bytes*/
+    const SPS_1080P: [u8; 42] = [
+        0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00,
+        0x03, 0x00, 0x78, 0xA0, 0x03, 0xC0, 0x80, 0x10, 0xE5, 0x96, 0x66, 0x69, 0x24, 0xCA, 0xE0,
+        0x10, 0x00, 0x00, 0x03, 0x00, 0x10, 0x00, 0x00, 0x03, 0x01, 0xE0, 0x80,
+    ];
+
+    #[test]
+    fn test_parse_sps_resolution() {
+        assert_eq!(parse_sps_resolution(&SPS_1080P).unwrap(), (1920, 1080));
+        assert!(parse_sps_resolution(&SPS_1080P[..10]).is_err());
+    }
+
+    #[test]
+    fn test_h265_mp4toannexb() {
+        let mut hvcc = BytesMut::new();
+        hvcc.extend_from_slice(&[
+            0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5D, 0xF0,
+            0x00, 0xFC, 0xFD, 0xF8, 0xF8, 0x00, 0x00, 0x0F,
+        ]);
+        /*3 arrays of one nalu: VPS, SPS and PPS*/
+        hvcc.extend_from_slice(&[0x03]);
+        hvcc.extend_from_slice(&[0xA0, 0x00, 0x01, 0x00, 0x02, 0x40, 0x01]);
+        hvcc.extend_from_slice(&[0xA1, 0x00, 0x01, 0x00, SPS_1080P.len() as u8]);
+        hvcc.extend_from_slice(&SPS_1080P);
+        hvcc.extend_from_slice(&[0xA2, 0x00, 0x01, 0x00, 0x02, 0x44, 0x01]);
+
+        let mut processor = Mpeg4HevcProcessor::new();
+        processor
+            .decoder_configuration_record_load(&mut BytesReader::new(hvcc))
+            .unwrap();
+        assert_eq!(processor.mpeg4_hevc.nalus.len(), 3);
+        assert_eq!(
+            (processor.mpeg4_hevc.width, processor.mpeg4_hevc.height),
+            (1920, 1080)
+        );
+
+        /*an IDR_W_RADL picture gets the parameter sets*/
+        let mut frame = BytesMut::new();
+        frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x03, 0x26, 0x01, 0xAF]);
+        let annexb = processor
+            .h265_mp4toannexb(&mut BytesReader::new(frame))
+            .unwrap();
+        let mut expected = vec![0x00, 0x00, 0x00, 0x01, 0x40, 0x01, 0x00, 0x00, 0x00, 0x01];
+        expected.extend_from_slice(&SPS_1080P);
+        expected.extend_from_slice(&[
+            0x00, 0x00, 0x00, 0x01, 0x44, 0x01, 0x00, 0x00, 0x00, 0x01, 0x26, 0x01, 0xAF,
+        ]);
+        assert_eq!(&annexb[..], &expected[..]);
+
+        /*a TRAIL_R picture is left as it is*/
+        let mut frame = BytesMut::new();
+        frame.extend_from_slice(&[0x00, 0x00, 0x00, 0x03, 0x02, 0x01, 0xD0]);
+        let annexb = processor
+            .h265_mp4toannexb(&mut BytesReader::new(frame))
+            .unwrap();
+        assert_eq!(&annexb[..], &[0x00, 0x00, 0x00, 0x01, 0x02, 0x01, 0xD0]);
+    }
 }
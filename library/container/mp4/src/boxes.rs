@@ -0,0 +1,715 @@
+use {
+    super::{
+        define::{
+            sample_flags, trun_flags, AudioTrack, SampleTable, VideoCodec, VideoTrack,
+            AUDIO_TRACK_ID, MOVIE_TIMESCALE, TFHD_DEFAULT_BASE_IS_MOOF, VIDEO_TIMESCALE,
+            VIDEO_TRACK_ID,
+        },
+        errors::{Mp4Error, Mp4ErrorValue},
+    },
+    byteorder::BigEndian,
+    bytesio::bytes_writer::BytesWriter,
+};
+
+/*
+ ** Box Struct **
+ +---------------------------------------------------+
+ | size(4 bytes) | type(4 bytes) | payload           |
+ +---------------------------------------------------+
+
+ A full box has a version(1 byte) and flags(3 bytes) at the beginning of the
+ payload. The size is unknown before the payload is written, so a placeholder
+ is written first and patched afterwards.
+
+ reference: ISO/IEC 14496-12, ISO/IEC 14496-15, ISO/IEC 23000-19(CMAF)
+*/
+
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+pub fn write_box<F>(
+    writer: &mut BytesWriter,
+    box_type: &[u8; 4],
+    payload: F,
+) -> Result<(), Mp4Error>
+where
+    F: FnOnce(&mut BytesWriter) -> Result<(), Mp4Error>,
+{
+    let start = writer.len();
+    writer.write_u32::<BigEndian>(0)?;
+    writer.write(box_type)?;
+    payload(writer)?;
+
+    let size = writer.len() - start;
+    if size > u32::MAX as usize {
+        return Err(Mp4Error {
+            value: Mp4ErrorValue::BoxTooLarge,
+        });
+    }
+    write_u32_at(writer, start, size as u32)
+}
+
+pub fn write_full_box<F>(
+    writer: &mut BytesWriter,
+    box_type: &[u8; 4],
+    version: u8,
+    flags: u32,
+    payload: F,
+) -> Result<(), Mp4Error>
+where
+    F: FnOnce(&mut BytesWriter) -> Result<(), Mp4Error>,
+{
+    write_box(writer, box_type, |w| {
+        w.write_u8(version)?;
+        w.write_u24::<BigEndian>(flags)?;
+        payload(w)
+    })
+}
+
+pub fn write_u32_at(writer: &mut BytesWriter, position: usize, value: u32) -> Result<(), Mp4Error> {
+    for (idx, byte) in value.to_be_bytes().iter().enumerate() {
+        writer.write_u8_at(position + idx, *byte)?;
+    }
+    Ok(())
+}
+
+pub fn write_ftyp(writer: &mut BytesWriter) -> Result<(), Mp4Error> {
+    write_brands(writer, b"iso6", &[b"iso6", b"cmfc", b"isom", b"mp41"])
+}
+
+/*the brands of a progressive file, it is playable by the players without the
+support of the fragments. It has the size of the fragmented one so that it
+replaces it in place.*/
+pub fn write_progressive_ftyp(writer: &mut BytesWriter) -> Result<(), Mp4Error> {
+    write_brands(writer, b"isom", &[b"isom", b"iso2", b"mp41", b"mp42"])
+}
+
+fn write_brands(
+    writer: &mut BytesWriter,
+    major_brand: &[u8; 4],
+    compatible_brands: &[&[u8; 4]],
+) -> Result<(), Mp4Error> {
+    write_box(writer, b"ftyp", |w| {
+        /*major brand and minor version*/
+        w.write(major_brand)?;
+        w.write_u32::<BigEndian>(0)?;
+        for brand in compatible_brands {
+            w.write(*brand)?;
+        }
+        Ok(())
+    })
+}
+
+pub fn write_moov(
+    writer: &mut BytesWriter,
+    video: Option<&VideoTrack>,
+    audio: Option<&AudioTrack>,
+) -> Result<(), Mp4Error> {
+    write_box(writer, b"moov", |w| {
+        write_mvhd(w, 0)?;
+        if let Some(video) = video {
+            write_video_trak(w, video, None, 0)?;
+        }
+        if let Some(audio) = audio {
+            write_audio_trak(w, audio, None, 0)?;
+        }
+        write_box(w, b"mvex", |w| {
+            if video.is_some() {
+                write_trex(w, VIDEO_TRACK_ID)?;
+            }
+            if audio.is_some() {
+                write_trex(w, AUDIO_TRACK_ID)?;
+            }
+            Ok(())
+        })
+    })
+}
+
+/*The moov box of a progressive file, the samples are described by the sample
+tables and the chunk offsets are relative to data_offset, the position of the
+first fragment in the file.*/
+pub fn write_progressive_moov(
+    writer: &mut BytesWriter,
+    video: Option<(&VideoTrack, &SampleTable)>,
+    audio: Option<(&AudioTrack, &SampleTable)>,
+    data_offset: u64,
+) -> Result<(), Mp4Error> {
+    let video_duration = video.map_or(0, |(_, table)| {
+        to_movie_timescale(table.start + table.duration(), VIDEO_TIMESCALE)
+    });
+    let audio_duration = audio.map_or(0, |(track, table)| {
+        to_movie_timescale(table.start + table.duration(), track.sample_rate)
+    });
+
+    write_box(writer, b"moov", |w| {
+        write_mvhd(w, std::cmp::max(video_duration, audio_duration))?;
+        if let Some((track, table)) = video {
+            write_video_trak(w, track, Some(table), data_offset)?;
+        }
+        if let Some((track, table)) = audio {
+            write_audio_trak(w, track, Some(table), data_offset)?;
+        }
+        Ok(())
+    })
+}
+
+fn to_movie_timescale(duration: u64, timescale: u32) -> u64 {
+    duration * MOVIE_TIMESCALE as u64 / std::cmp::max(timescale, 1) as u64
+}
+
+/*version 1 of mvhd, tkhd and mdhd has 64 bits times and durations*/
+fn header_version(duration: u64) -> u8 {
+    if duration > u32::MAX as u64 {
+        1
+    } else {
+        0
+    }
+}
+
+fn write_time(writer: &mut BytesWriter, version: u8, value: u64) -> Result<(), Mp4Error> {
+    if version == 1 {
+        writer.write_u64::<BigEndian>(value)?;
+    } else {
+        writer.write_u32::<BigEndian>(value as u32)?;
+    }
+    Ok(())
+}
+
+fn write_matrix(writer: &mut BytesWriter) -> Result<(), Mp4Error> {
+    for value in MATRIX {
+        writer.write_u32::<BigEndian>(value)?;
+    }
+    Ok(())
+}
+
+/*the duration is unknown(0) for a fragmented file*/
+fn write_mvhd(writer: &mut BytesWriter, duration: u64) -> Result<(), Mp4Error> {
+    let version = header_version(duration);
+    write_full_box(writer, b"mvhd", version, 0, |w| {
+        /*creation and modification time*/
+        write_time(w, version, 0)?;
+        write_time(w, version, 0)?;
+        w.write_u32::<BigEndian>(MOVIE_TIMESCALE)?;
+        write_time(w, version, duration)?;
+        /*rate 1.0 and volume 1.0*/
+        w.write_u32::<BigEndian>(0x0001_0000)?;
+        w.write_u16::<BigEndian>(0x0100)?;
+        /*reserved*/
+        w.write(&[0; 10])?;
+        write_matrix(w)?;
+        /*pre_defined*/
+        w.write(&[0; 24])?;
+        /*next track id*/
+        w.write_u32::<BigEndian>(AUDIO_TRACK_ID + 1)?;
+        Ok(())
+    })
+}
+
+fn write_tkhd(
+    writer: &mut BytesWriter,
+    track_id: u32,
+    is_audio: bool,
+    width: u32,
+    height: u32,
+    duration: u64,
+) -> Result<(), Mp4Error> {
+    let version = header_version(duration);
+    /*flags: track enabled and in movie*/
+    write_full_box(writer, b"tkhd", version, 0x03, |w| {
+        write_time(w, version, 0)?;
+        write_time(w, version, 0)?;
+        w.write_u32::<BigEndian>(track_id)?;
+        /*reserved*/
+        w.write_u32::<BigEndian>(0)?;
+        /*duration in the movie timescale*/
+        write_time(w, version, duration)?;
+        /*reserved*/
+        w.write(&[0; 8])?;
+        /*layer and alternate group*/
+        w.write_u16::<BigEndian>(0)?;
+        w.write_u16::<BigEndian>(0)?;
+        w.write_u16::<BigEndian>(if is_audio { 0x0100 } else { 0 })?;
+        /*reserved*/
+        w.write_u16::<BigEndian>(0)?;
+        write_matrix(w)?;
+        /*16.16 fixed point*/
+        w.write_u32::<BigEndian>(width << 16)?;
+        w.write_u32::<BigEndian>(height << 16)?;
+        Ok(())
+    })
+}
+
+fn write_mdhd(writer: &mut BytesWriter, timescale: u32, duration: u64) -> Result<(), Mp4Error> {
+    let version = header_version(duration);
+    write_full_box(writer, b"mdhd", version, 0, |w| {
+        write_time(w, version, 0)?;
+        write_time(w, version, 0)?;
+        w.write_u32::<BigEndian>(timescale)?;
+        write_time(w, version, duration)?;
+        /*language "und", packed ISO-639-2/T code*/
+        w.write_u16::<BigEndian>(0x55c4)?;
+        /*pre_defined*/
+        w.write_u16::<BigEndian>(0)?;
+        Ok(())
+    })
+}
+
+fn write_hdlr(
+    writer: &mut BytesWriter,
+    handler_type: &[u8; 4],
+    name: &str,
+) -> Result<(), Mp4Error> {
+    write_full_box(writer, b"hdlr", 0, 0, |w| {
+        /*pre_defined*/
+        w.write_u32::<BigEndian>(0)?;
+        w.write(handler_type)?;
+        /*reserved*/
+        w.write(&[0; 12])?;
+        w.write(name.as_bytes())?;
+        w.write_u8(0)?;
+        Ok(())
+    })
+}
+
+/*An empty edit delays a track which starts after the start of the file, the
+media follows it from its first sample.*/
+fn write_edts(
+    writer: &mut BytesWriter,
+    table: &SampleTable,
+    timescale: u32,
+) -> Result<(), Mp4Error> {
+    let empty_duration = to_movie_timescale(table.start, timescale);
+    let media_duration = to_movie_timescale(table.duration(), timescale);
+    let version = header_version(std::cmp::max(empty_duration, media_duration));
+    write_box(writer, b"edts", |w| {
+        write_full_box(w, b"elst", version, 0, |w| {
+            w.write_u32::<BigEndian>(2)?;
+            /*segment duration, media time(-1 for an empty edit) and rate 1.0*/
+            for (duration, media_time) in [(empty_duration, -1), (media_duration, 0)] {
+                write_time(w, version, duration)?;
+                write_time(w, version, media_time as u64)?;
+                w.write_u32::<BigEndian>(0x0001_0000)?;
+            }
+            Ok(())
+        })
+    })
+}
+
+fn write_dinf(writer: &mut BytesWriter) -> Result<(), Mp4Error> {
+    write_box(writer, b"dinf", |w| {
+        write_full_box(w, b"dref", 0, 0, |w| {
+            /*entry count*/
+            w.write_u32::<BigEndian>(1)?;
+            /*flags 1: the media data is in the same file*/
+            write_full_box(w, b"url ", 0, 1, |_| Ok(()))
+        })
+    })
+}
+
+/*the sample tables are empty for a fragmented file, the samples are described
+by the fragments*/
+fn write_stbl<F>(
+    writer: &mut BytesWriter,
+    sample_entry: F,
+    table: Option<&SampleTable>,
+    data_offset: u64,
+) -> Result<(), Mp4Error>
+where
+    F: FnOnce(&mut BytesWriter) -> Result<(), Mp4Error>,
+{
+    write_box(writer, b"stbl", |w| {
+        write_full_box(w, b"stsd", 0, 0, |w| {
+            w.write_u32::<BigEndian>(1)?;
+            sample_entry(w)
+        })?;
+        if let Some(table) = table {
+            return write_sample_table(w, table, data_offset);
+        }
+        for box_type in [b"stts", b"stsc", b"stco"] {
+            write_full_box(w, box_type, 0, 0, |w| {
+                w.write_u32::<BigEndian>(0)?;
+                Ok(())
+            })?;
+        }
+        write_full_box(w, b"stsz", 0, 0, |w| {
+            /*sample size and sample count*/
+            w.write_u32::<BigEndian>(0)?;
+            w.write_u32::<BigEndian>(0)?;
+            Ok(())
+        })
+    })
+}
+
+/*(first, count) of the runs of the equal values*/
+fn run_lengths<T: PartialEq + Copy>(values: impl Iterator<Item = T>) -> Vec<(T, u32)> {
+    let mut runs: Vec<(T, u32)> = Vec::new();
+    for value in values {
+        match runs.last_mut() {
+            Some((last, count)) if *last == value => *count += 1,
+            _ => runs.push((value, 1)),
+        }
+    }
+    runs
+}
+
+fn write_sample_table(
+    writer: &mut BytesWriter,
+    table: &SampleTable,
+    data_offset: u64,
+) -> Result<(), Mp4Error> {
+    let durations = run_lengths(table.samples.iter().map(|s| s.0));
+    write_full_box(writer, b"stts", 0, 0, |w| {
+        w.write_u32::<BigEndian>(durations.len() as u32)?;
+        for (duration, count) in &durations {
+            w.write_u32::<BigEndian>(*count)?;
+            w.write_u32::<BigEndian>(*duration)?;
+        }
+        Ok(())
+    })?;
+
+    /*version 1: signed composition time offsets, only if there are B frames*/
+    if table.samples.iter().any(|s| s.3 != 0) {
+        let offsets = run_lengths(table.samples.iter().map(|s| s.3));
+        write_full_box(writer, b"ctts", 1, 0, |w| {
+            w.write_u32::<BigEndian>(offsets.len() as u32)?;
+            for (offset, count) in &offsets {
+                w.write_u32::<BigEndian>(*count)?;
+                w.write_u32::<BigEndian>(*offset as u32)?;
+            }
+            Ok(())
+        })?;
+    }
+
+    /*all the samples are sync samples without the stss box*/
+    if table.samples.iter().any(|s| !s.2) {
+        let sync_samples: Vec<u32> = (1..)
+            .zip(&table.samples)
+            .filter(|(_, s)| s.2)
+            .map(|(number, _)| number)
+            .collect();
+        write_full_box(writer, b"stss", 0, 0, |w| {
+            w.write_u32::<BigEndian>(sync_samples.len() as u32)?;
+            for number in &sync_samples {
+                w.write_u32::<BigEndian>(*number)?;
+            }
+            Ok(())
+        })?;
+    }
+
+    /*(first chunk, samples per chunk), the chunk numbers start from 1*/
+    let mut chunk_runs = Vec::new();
+    let mut first_chunk = 1;
+    for (count, chunks) in run_lengths(table.chunks.iter().map(|c| c.1)) {
+        chunk_runs.push((first_chunk, count));
+        first_chunk += chunks;
+    }
+    write_full_box(writer, b"stsc", 0, 0, |w| {
+        w.write_u32::<BigEndian>(chunk_runs.len() as u32)?;
+        for (first_chunk, count) in &chunk_runs {
+            w.write_u32::<BigEndian>(*first_chunk)?;
+            w.write_u32::<BigEndian>(*count)?;
+            /*sample description index*/
+            w.write_u32::<BigEndian>(1)?;
+        }
+        Ok(())
+    })?;
+
+    write_full_box(writer, b"stsz", 0, 0, |w| {
+        w.write_u32::<BigEndian>(0)?;
+        w.write_u32::<BigEndian>(table.samples.len() as u32)?;
+        for sample in &table.samples {
+            w.write_u32::<BigEndian>(sample.1)?;
+        }
+        Ok(())
+    })?;
+
+    /*the 64 bits offsets are only used for the files larger than 4GB*/
+    let last_offset = table.chunks.last().map_or(0, |c| c.0) + data_offset;
+    if last_offset > u32::MAX as u64 {
+        write_full_box(writer, b"co64", 0, 0, |w| {
+            w.write_u32::<BigEndian>(table.chunks.len() as u32)?;
+            for (offset, _) in &table.chunks {
+                w.write_u64::<BigEndian>(data_offset + offset)?;
+            }
+            Ok(())
+        })
+    } else {
+        write_full_box(writer, b"stco", 0, 0, |w| {
+            w.write_u32::<BigEndian>(table.chunks.len() as u32)?;
+            for (offset, _) in &table.chunks {
+                w.write_u32::<BigEndian>((data_offset + offset) as u32)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+fn write_video_trak(
+    writer: &mut BytesWriter,
+    track: &VideoTrack,
+    table: Option<&SampleTable>,
+    data_offset: u64,
+) -> Result<(), Mp4Error> {
+    let duration = table.map_or(0, |t| t.duration());
+    let start = table.map_or(0, |t| t.start);
+    write_box(writer, b"trak", |w| {
+        write_tkhd(
+            w,
+            VIDEO_TRACK_ID,
+            false,
+            track.width,
+            track.height,
+            to_movie_timescale(start + duration, VIDEO_TIMESCALE),
+        )?;
+        if let Some(table) = table.filter(|t| t.start > 0) {
+            write_edts(w, table, VIDEO_TIMESCALE)?;
+        }
+        write_box(w, b"mdia", |w| {
+            write_mdhd(w, VIDEO_TIMESCALE, duration)?;
+            write_hdlr(w, b"vide", "VideoHandler")?;
+            write_box(w, b"minf", |w| {
+                /*flags 1, graphics mode and opcolor*/
+                write_full_box(w, b"vmhd", 0, 1, |w| {
+                    w.write(&[0; 8])?;
+                    Ok(())
+                })?;
+                write_dinf(w)?;
+                write_stbl(
+                    w,
+                    |w| write_visual_sample_entry(w, track),
+                    table,
+                    data_offset,
+                )
+            })
+        })
+    })
+}
+
+fn write_visual_sample_entry(writer: &mut BytesWriter, track: &VideoTrack) -> Result<(), Mp4Error> {
+    let (entry_type, config_type) = match track.codec {
+        VideoCodec::H264 => (b"avc1", b"avcC"),
+        VideoCodec::H265 => (b"hvc1", b"hvcC"),
+    };
+
+    write_box(writer, entry_type, |w| {
+        /*reserved*/
+        w.write(&[0; 6])?;
+        /*data reference index*/
+        w.write_u16::<BigEndian>(1)?;
+        /*pre_defined and reserved*/
+        w.write(&[0; 16])?;
+        w.write_u16::<BigEndian>(track.width as u16)?;
+        w.write_u16::<BigEndian>(track.height as u16)?;
+        /*72 dpi*/
+        w.write_u32::<BigEndian>(0x0048_0000)?;
+        w.write_u32::<BigEndian>(0x0048_0000)?;
+        /*reserved*/
+        w.write_u32::<BigEndian>(0)?;
+        /*frame count*/
+        w.write_u16::<BigEndian>(1)?;
+        /*compressor name*/
+        w.write(&[0; 32])?;
+        /*depth*/
+        w.write_u16::<BigEndian>(0x0018)?;
+        /*pre_defined*/
+        w.write_u16::<BigEndian>(0xffff)?;
+        write_box(w, config_type, |w| {
+            w.write(&track.config[..])?;
+            Ok(())
+        })
+    })
+}
+
+fn write_audio_trak(
+    writer: &mut BytesWriter,
+    track: &AudioTrack,
+    table: Option<&SampleTable>,
+    data_offset: u64,
+) -> Result<(), Mp4Error> {
+    let duration = table.map_or(0, |t| t.duration());
+    let start = table.map_or(0, |t| t.start);
+    write_box(writer, b"trak", |w| {
+        write_tkhd(
+            w,
+            AUDIO_TRACK_ID,
+            true,
+            0,
+            0,
+            to_movie_timescale(start + duration, track.sample_rate),
+        )?;
+        if let Some(table) = table.filter(|t| t.start > 0) {
+            write_edts(w, table, track.sample_rate)?;
+        }
+        write_box(w, b"mdia", |w| {
+            write_mdhd(w, track.sample_rate, duration)?;
+            write_hdlr(w, b"soun", "SoundHandler")?;
+            write_box(w, b"minf", |w| {
+                /*balance and reserved*/
+                write_full_box(w, b"smhd", 0, 0, |w| {
+                    w.write_u32::<BigEndian>(0)?;
+                    Ok(())
+                })?;
+                write_dinf(w)?;
+                write_stbl(w, |w| write_mp4a(w, track), table, data_offset)
+            })
+        })
+    })
+}
+
+fn write_mp4a(writer: &mut BytesWriter, track: &AudioTrack) -> Result<(), Mp4Error> {
+    write_box(writer, b"mp4a", |w| {
+        /*reserved*/
+        w.write(&[0; 6])?;
+        /*data reference index*/
+        w.write_u16::<BigEndian>(1)?;
+        /*reserved*/
+        w.write(&[0; 8])?;
+        w.write_u16::<BigEndian>(track.channels)?;
+        /*sample size*/
+        w.write_u16::<BigEndian>(16)?;
+        /*pre_defined and reserved*/
+        w.write_u32::<BigEndian>(0)?;
+        /*16.16 fixed point*/
+        w.write_u32::<BigEndian>(track.sample_rate << 16)?;
+        write_esds(w, track)
+    })
+}
+
+/*the length of a descriptor is coded with 7 bits per byte*/
+fn write_descriptor<F>(writer: &mut BytesWriter, tag: u8, payload: F) -> Result<(), Mp4Error>
+where
+    F: FnOnce(&mut BytesWriter) -> Result<(), Mp4Error>,
+{
+    let mut content = BytesWriter::new();
+    payload(&mut content)?;
+
+    writer.write_u8(tag)?;
+    let len = content.len();
+    for shift in [21, 14, 7] {
+        if len >> shift > 0 {
+            writer.write_u8(0x80 | ((len >> shift) & 0x7f) as u8)?;
+        }
+    }
+    writer.write_u8((len & 0x7f) as u8)?;
+    writer.append(&mut content);
+    Ok(())
+}
+
+fn write_esds(writer: &mut BytesWriter, track: &AudioTrack) -> Result<(), Mp4Error> {
+    write_full_box(writer, b"esds", 0, 0, |w| {
+        /*ES_Descriptor*/
+        write_descriptor(w, 0x03, |w| {
+            /*ES_ID and flags*/
+            w.write_u16::<BigEndian>(0)?;
+            w.write_u8(0)?;
+            /*DecoderConfigDescriptor*/
+            write_descriptor(w, 0x04, |w| {
+                /*object type: MPEG-4 audio*/
+                w.write_u8(0x40)?;
+                /*stream type: audio stream, upstream 0, reserved 1*/
+                w.write_u8(0x15)?;
+                /*buffer size, max bitrate and average bitrate*/
+                w.write_u24::<BigEndian>(0)?;
+                w.write_u32::<BigEndian>(0)?;
+                w.write_u32::<BigEndian>(0)?;
+                /*DecoderSpecificInfo*/
+                write_descriptor(w, 0x05, |w| {
+                    w.write(&track.config[..])?;
+                    Ok(())
+                })
+            })?;
+            /*SLConfigDescriptor, predefined 2: reserved for use in mp4 files*/
+            write_descriptor(w, 0x06, |w| {
+                w.write_u8(0x02)?;
+                Ok(())
+            })
+        })
+    })
+}
+
+fn write_trex(writer: &mut BytesWriter, track_id: u32) -> Result<(), Mp4Error> {
+    write_full_box(writer, b"trex", 0, 0, |w| {
+        w.write_u32::<BigEndian>(track_id)?;
+        /*default sample description index*/
+        w.write_u32::<BigEndian>(1)?;
+        /*default sample duration, size and flags*/
+        w.write_u32::<BigEndian>(0)?;
+        w.write_u32::<BigEndian>(0)?;
+        w.write_u32::<BigEndian>(0)?;
+        Ok(())
+    })
+}
+
+/*the samples of one track in a fragment, the durations and the composition time
+offsets are in the timescale of the track*/
+pub struct TrackRun {
+    pub track_id: u32,
+    pub base_media_decode_time: u64,
+    /*(duration, size, is_sync, composition time offset)*/
+    pub samples: Vec<(u32, u32, bool, i32)>,
+}
+
+impl TrackRun {
+    pub fn data_size(&self) -> usize {
+        self.samples.iter().map(|s| s.1 as usize).sum()
+    }
+
+    pub fn duration(&self) -> u64 {
+        self.samples.iter().map(|s| s.0 as u64).sum()
+    }
+}
+
+/*write the moof box, the positions of the data offsets of the track runs are
+returned and patched after the size of the moof box is known*/
+pub fn write_moof(
+    writer: &mut BytesWriter,
+    sequence_number: u32,
+    runs: &[TrackRun],
+) -> Result<Vec<usize>, Mp4Error> {
+    let mut data_offset_positions = Vec::new();
+
+    write_box(writer, b"moof", |w| {
+        write_full_box(w, b"mfhd", 0, 0, |w| {
+            w.write_u32::<BigEndian>(sequence_number)?;
+            Ok(())
+        })?;
+
+        for run in runs {
+            write_box(w, b"traf", |w| {
+                write_full_box(w, b"tfhd", 0, TFHD_DEFAULT_BASE_IS_MOOF, |w| {
+                    w.write_u32::<BigEndian>(run.track_id)?;
+                    Ok(())
+                })?;
+                write_full_box(w, b"tfdt", 1, 0, |w| {
+                    w.write_u64::<BigEndian>(run.base_media_decode_time)?;
+                    Ok(())
+                })?;
+
+                let flags = trun_flags::DATA_OFFSET
+                    | trun_flags::SAMPLE_DURATION
+                    | trun_flags::SAMPLE_SIZE
+                    | trun_flags::SAMPLE_FLAGS
+                    | trun_flags::SAMPLE_COMPOSITION_TIME_OFFSET;
+                /*version 1: signed composition time offsets*/
+                write_full_box(w, b"trun", 1, flags, |w| {
+                    w.write_u32::<BigEndian>(run.samples.len() as u32)?;
+                    data_offset_positions.push(w.len());
+                    w.write_u32::<BigEndian>(0)?;
+                    for (duration, size, is_sync, cts) in &run.samples {
+                        w.write_u32::<BigEndian>(*duration)?;
+                        w.write_u32::<BigEndian>(*size)?;
+                        w.write_u32::<BigEndian>(if *is_sync {
+                            sample_flags::SYNC
+                        } else {
+                            sample_flags::NON_SYNC
+                        })?;
+                        w.write_u32::<BigEndian>(*cts as u32)?;
+                    }
+                    Ok(())
+                })
+            })?;
+        }
+        Ok(())
+    })?;
+
+    Ok(data_offset_positions)
+}
@@ -0,0 +1,177 @@
+use bytes::BytesMut;
+
+pub const VIDEO_TRACK_ID: u32 = 1;
+pub const AUDIO_TRACK_ID: u32 = 2;
+
+/*the timescale of the movie header, the samples are timed with the timescale of their track*/
+pub const MOVIE_TIMESCALE: u32 = 1000;
+pub const VIDEO_TIMESCALE: u32 = 90000;
+/*samples per AAC frame*/
+pub const AAC_FRAME_SIZE: u32 = 1024;
+
+pub mod sample_flags {
+    /*sample_depends_on = 2, an I frame or an audio frame*/
+    pub const SYNC: u32 = 0x0200_0000;
+    /*sample_depends_on = 1 and sample_is_non_sync_sample = 1*/
+    pub const NON_SYNC: u32 = 0x0101_0000;
+}
+
+pub mod trun_flags {
+    pub const DATA_OFFSET: u32 = 0x0001;
+    pub const FIRST_SAMPLE_FLAGS: u32 = 0x0004;
+    pub const SAMPLE_DURATION: u32 = 0x0100;
+    pub const SAMPLE_SIZE: u32 = 0x0200;
+    pub const SAMPLE_FLAGS: u32 = 0x0400;
+    pub const SAMPLE_COMPOSITION_TIME_OFFSET: u32 = 0x0800;
+}
+
+/*tfhd flag, the data offsets of the track runs are relative to the moof box*/
+pub const TFHD_DEFAULT_BASE_IS_MOOF: u32 = 0x02_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    H264,
+    H265,
+}
+
+#[derive(Debug, Clone)]
+pub struct VideoTrack {
+    pub codec: VideoCodec,
+    pub width: u32,
+    pub height: u32,
+    /*AVCDecoderConfigurationRecord(avcC) or HEVCDecoderConfigurationRecord(hvcC),
+    the samples must use the nalu length size declared by the record*/
+    pub config: BytesMut,
+}
+
+impl VideoTrack {
+    /*the RFC 6381 codecs parameter, e.g. "avc1.64001f" or "hvc1.1.6.L93.B0"*/
+    pub fn codec_string(&self) -> String {
+        let config = &self.config;
+        match self.codec {
+            VideoCodec::H264 if config.len() >= 4 => {
+                format!("avc1.{:02x}{:02x}{:02x}", config[1], config[2], config[3])
+            }
+            VideoCodec::H264 => String::from("avc1"),
+            VideoCodec::H265 if config.len() >= 13 => {
+                let profile_space = ["", "A", "B", "C"][(config[1] >> 6) as usize];
+                let tier = if (config[1] >> 5) & 0x01 == 0 {
+                    "L"
+                } else {
+                    "H"
+                };
+                let profile_idc = config[1] & 0x1f;
+                /*the compatibility flags in reverse bit order*/
+                let compatibility =
+                    u32::from_be_bytes([config[2], config[3], config[4], config[5]]).reverse_bits();
+                let mut codec = format!(
+                    "hvc1.{}{}.{:X}.{}{}",
+                    profile_space, profile_idc, compatibility, tier, config[12]
+                );
+                /*the constraint flags with the trailing zero bytes omitted*/
+                let constraints = &config[6..12];
+                let len = constraints
+                    .iter()
+                    .rposition(|b| *b != 0)
+                    .map_or(0, |p| p + 1);
+                for byte in &constraints[..len] {
+                    codec += format!(".{byte:X}").as_str();
+                }
+                codec
+            }
+            VideoCodec::H265 => String::from("hvc1"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioTrack {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /*AAC AudioSpecificConfig*/
+    pub config: BytesMut,
+}
+
+impl AudioTrack {
+    /*"mp4a.40.{audio object type}", e.g. "mp4a.40.2" for AAC-LC*/
+    pub fn codec_string(&self) -> String {
+        let object_type = self.config.first().map_or(2, |b| b >> 3);
+        format!("mp4a.40.{object_type}")
+    }
+}
+
+/*the fragment of a single track*/
+pub struct TrackFragment {
+    /*moof and mdat*/
+    pub data: BytesMut,
+    /*the decode time of the first sample and the duration of the fragment,
+    in the timescale of the track*/
+    pub base_media_decode_time: u64,
+    pub duration: u64,
+}
+
+/*The samples of a track in a progressive file. Every track run of the
+fragments becomes a chunk, the chunks are in the order of the fragments.*/
+#[derive(Debug, Clone, Default)]
+pub struct SampleTable {
+    /*(duration, size, is_sync, composition time offset) in the timescale of the track*/
+    pub samples: Vec<(u32, u32, bool, i32)>,
+    /*(offset from the first fragment, sample count)*/
+    pub chunks: Vec<(u64, u32)>,
+    /*the decode time of the first sample in the timescale of the track, the
+    track starts after an empty edit if it starts after the other one*/
+    pub start: u64,
+}
+
+impl SampleTable {
+    pub fn duration(&self) -> u64 {
+        self.samples.iter().map(|s| s.0 as u64).sum()
+    }
+}
+
+/*a video frame(length prefixed nalus) or a raw AAC frame(without the ADTS header)*/
+#[derive(Debug, Clone, Default)]
+pub struct Sample {
+    /*milliseconds*/
+    pub dts: i64,
+    pub pts: i64,
+    pub is_sync: bool,
+    pub data: BytesMut,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AudioTrack, VideoCodec, VideoTrack};
+    use bytes::BytesMut;
+
+    #[test]
+    fn test_codec_string() {
+        let avc = VideoTrack {
+            codec: VideoCodec::H264,
+            width: 0,
+            height: 0,
+            config: BytesMut::from(&[0x01, 0x64, 0x00, 0x1f, 0xff][..]),
+        };
+        assert_eq!(avc.codec_string(), "avc1.64001f");
+
+        /*Main profile, level 3.1, progressive source flag set*/
+        let hevc = VideoTrack {
+            codec: VideoCodec::H265,
+            width: 0,
+            height: 0,
+            config: BytesMut::from(
+                &[
+                    0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 93,
+                ][..],
+            ),
+        };
+        assert_eq!(hevc.codec_string(), "hvc1.1.6.L93.90");
+
+        let aac = AudioTrack {
+            sample_rate: 44100,
+            channels: 2,
+            config: BytesMut::from(&[0x12, 0x10][..]),
+        };
+        assert_eq!(aac.codec_string(), "mp4a.40.2");
+    }
+}
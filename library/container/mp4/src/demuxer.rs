@@ -0,0 +1,829 @@
+use {
+    super::{
+        define::{trun_flags, AudioTrack, VideoCodec, VideoTrack},
+        errors::{Mp4Error, Mp4ErrorValue},
+    },
+    byteorder::BigEndian,
+    bytes::BytesMut,
+    bytesio::bytes_reader::BytesReader,
+    std::io::{Read, Seek, SeekFrom},
+};
+
+/*the sample_is_non_sync_sample bit of the sample flags in the fragments*/
+const SAMPLE_IS_NON_SYNC: u32 = 0x0001_0000;
+
+mod tfhd_flags {
+    pub const BASE_DATA_OFFSET: u32 = 0x01;
+    pub const SAMPLE_DESCRIPTION_INDEX: u32 = 0x02;
+    pub const DEFAULT_SAMPLE_DURATION: u32 = 0x08;
+    pub const DEFAULT_SAMPLE_SIZE: u32 = 0x10;
+    pub const DEFAULT_SAMPLE_FLAGS: u32 = 0x20;
+}
+
+/*A sample of a file, the times are milliseconds. The media data is not read
+by the demuxer, it is at the offset of the file.*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleInfo {
+    pub is_video: bool,
+    pub dts: i64,
+    pub pts: i64,
+    pub is_sync: bool,
+    pub offset: u64,
+    pub size: u32,
+}
+
+enum TrackKind {
+    Video,
+    Audio,
+}
+
+struct TrackInfo {
+    id: u32,
+    kind: TrackKind,
+    timescale: u32,
+    /*the decode time of the next sample of the fragments*/
+    next_decode_time: u64,
+    /*the defaults of the track extends box*/
+    default_duration: u32,
+    default_size: u32,
+    default_flags: u32,
+}
+
+impl TrackInfo {
+    fn to_milliseconds(&self, time: i64) -> i64 {
+        time * 1000 / self.timescale as i64
+    }
+}
+
+/*The fields of a track fragment header, the defaults are the ones of the track
+extends box if they are not present.*/
+struct TrackFragmentHeader {
+    track_id: u32,
+    base_data_offset: Option<u64>,
+    default_duration: Option<u32>,
+    default_size: Option<u32>,
+    default_flags: Option<u32>,
+}
+
+/*
+ The samples of a progressive mp4 file are described by the sample tables of
+ the moov box:
+
+ stts: the durations      ctts: the composition time offsets
+ stss: the sync samples   stsz: the sizes
+ stsc: the samples of the chunks
+ stco/co64: the file offsets of the chunks
+
+ The samples of a fragmented mp4 file(a file recorded without finalizing it)
+ are described by the track runs of the moof boxes. Only the first video track
+ and the first audio track of a file are demuxed, the other tracks and the
+ unsupported codecs are skipped.
+*/
+#[derive(Default)]
+pub struct Mp4Demuxer {
+    pub video_track: Option<VideoTrack>,
+    pub audio_track: Option<AudioTrack>,
+    /*the samples of both tracks in decode order*/
+    pub samples: Vec<SampleInfo>,
+    tracks: Vec<TrackInfo>,
+}
+
+impl Mp4Demuxer {
+    /*The boxes are read one by one, only the moov and the moof boxes are loaded.
+    The last box is ignored if it is truncated, e.g. the fragment being written
+    of a file which is still recorded.*/
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self, Mp4Error> {
+        let mut demuxer = Self::default();
+        let file_size = reader.seek(SeekFrom::End(0))?;
+
+        let mut position = 0;
+        while position + 8 <= file_size {
+            reader.seek(SeekFrom::Start(position))?;
+            let mut header = [0; 8];
+            reader.read_exact(&mut header)?;
+
+            let mut header_size = 8;
+            let size = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+                /*the box extends to the end of the file*/
+                0 => file_size - position,
+                1 => {
+                    let mut large_size = [0; 8];
+                    reader.read_exact(&mut large_size)?;
+                    header_size = 16;
+                    u64::from_be_bytes(large_size)
+                }
+                size => size as u64,
+            };
+            if size < header_size || position + size > file_size {
+                break;
+            }
+
+            let box_type = &header[4..8];
+            if box_type == b"moov" || box_type == b"moof" {
+                let mut payload = vec![0; (size - header_size) as usize];
+                reader.read_exact(&mut payload)?;
+                let payload = BytesMut::from(&payload[..]);
+                if box_type == b"moov" {
+                    demuxer.read_moov(payload)?;
+                } else {
+                    demuxer.read_moof(payload, position)?;
+                }
+            }
+            position += size;
+        }
+
+        if demuxer.tracks.is_empty() {
+            return Err(Mp4Error {
+                value: Mp4ErrorValue::NoTrack,
+            });
+        }
+        /*the media data of a truncated fragment may be missing*/
+        demuxer
+            .samples
+            .retain(|sample| sample.offset + sample.size as u64 <= file_size);
+        /*a stable sort, the samples with the same time keep their track order*/
+        demuxer.samples.sort_by_key(|sample| sample.dts);
+        Ok(demuxer)
+    }
+
+    fn read_moov(&mut self, data: BytesMut) -> Result<(), Mp4Error> {
+        let boxes = read_boxes(data)?;
+        let mut mvhd = BytesReader::new(find_box(&boxes, b"mvhd").ok_or_else(invalid_box)?);
+        let version = read_full_box_header(&mut mvhd)?.0;
+        mvhd.read_bytes(if version == 1 { 16 } else { 8 })?;
+        let movie_timescale = mvhd.read_u32::<BigEndian>()?;
+
+        for (box_type, payload) in &boxes {
+            if box_type == b"trak" {
+                self.read_trak(payload.clone(), movie_timescale)?;
+            }
+        }
+
+        if let Some(mvex) = find_box(&boxes, b"mvex") {
+            for (box_type, payload) in read_boxes(mvex)? {
+                if &box_type != b"trex" {
+                    continue;
+                }
+                let mut reader = BytesReader::new(payload);
+                read_full_box_header(&mut reader)?;
+                let track_id = reader.read_u32::<BigEndian>()?;
+                /*default sample description index*/
+                reader.read_u32::<BigEndian>()?;
+                let default_duration = reader.read_u32::<BigEndian>()?;
+                let default_size = reader.read_u32::<BigEndian>()?;
+                let default_flags = reader.read_u32::<BigEndian>()?;
+                if let Some(track) = self.tracks.iter_mut().find(|t| t.id == track_id) {
+                    track.default_duration = default_duration;
+                    track.default_size = default_size;
+                    track.default_flags = default_flags;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_trak(&mut self, data: BytesMut, movie_timescale: u32) -> Result<(), Mp4Error> {
+        let boxes = read_boxes(data)?;
+
+        let mut tkhd = BytesReader::new(find_box(&boxes, b"tkhd").ok_or_else(invalid_box)?);
+        /*the creation time and the modification time*/
+        let version = read_full_box_header(&mut tkhd)?.0;
+        tkhd.read_bytes(if version == 1 { 16 } else { 8 })?;
+        let track_id = tkhd.read_u32::<BigEndian>()?;
+
+        let mdia = read_boxes(find_box(&boxes, b"mdia").ok_or_else(invalid_box)?)?;
+        let mut mdhd = BytesReader::new(find_box(&mdia, b"mdhd").ok_or_else(invalid_box)?);
+        let version = read_full_box_header(&mut mdhd)?.0;
+        mdhd.read_bytes(if version == 1 { 16 } else { 8 })?;
+        let timescale = mdhd.read_u32::<BigEndian>()?;
+
+        let mut hdlr = BytesReader::new(find_box(&mdia, b"hdlr").ok_or_else(invalid_box)?);
+        read_full_box_header(&mut hdlr)?;
+        /*pre_defined*/
+        hdlr.read_u32::<BigEndian>()?;
+        let handler_type = hdlr.read_bytes(4)?;
+
+        let minf = read_boxes(find_box(&mdia, b"minf").ok_or_else(invalid_box)?)?;
+        let stbl = read_boxes(find_box(&minf, b"stbl").ok_or_else(invalid_box)?)?;
+        let mut stsd = BytesReader::new(find_box(&stbl, b"stsd").ok_or_else(invalid_box)?);
+        read_full_box_header(&mut stsd)?;
+        /*entry count, only the first sample entry is used*/
+        stsd.read_u32::<BigEndian>()?;
+        let (entry_type, entry) = match read_boxes(stsd.extract_remaining_bytes())?
+            .into_iter()
+            .next()
+        {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+
+        let kind = match &handler_type[..] {
+            b"vide" if self.video_track.is_none() => {
+                match read_visual_sample_entry(&entry_type, entry)? {
+                    Some(track) => {
+                        self.video_track = Some(track);
+                        TrackKind::Video
+                    }
+                    None => return Ok(()),
+                }
+            }
+            b"soun" if self.audio_track.is_none() && &entry_type == b"mp4a" => {
+                match read_mp4a(entry, timescale)? {
+                    Some(track) => {
+                        self.audio_track = Some(track);
+                        TrackKind::Audio
+                    }
+                    None => return Ok(()),
+                }
+            }
+            _ => return Ok(()),
+        };
+
+        if timescale == 0 || movie_timescale == 0 {
+            return Err(invalid_box());
+        }
+        let start = match find_box(&boxes, b"edts") {
+            Some(edts) => read_empty_edit(edts)? * timescale as u64 / movie_timescale as u64,
+            None => 0,
+        };
+        let track = TrackInfo {
+            id: track_id,
+            kind,
+            timescale,
+            next_decode_time: start,
+            default_duration: 0,
+            default_size: 0,
+            default_flags: 0,
+        };
+        self.read_sample_table(&track, &stbl)?;
+        self.tracks.push(track);
+        Ok(())
+    }
+
+    /*the sample tables of a fragmented file are empty*/
+    fn read_sample_table(
+        &mut self,
+        track: &TrackInfo,
+        stbl: &[([u8; 4], BytesMut)],
+    ) -> Result<(), Mp4Error> {
+        let mut sizes = Vec::new();
+        if let Some(stsz) = find_box(stbl, b"stsz") {
+            let mut reader = BytesReader::new(stsz);
+            read_full_box_header(&mut reader)?;
+            let sample_size = reader.read_u32::<BigEndian>()?;
+            let count = reader.read_u32::<BigEndian>()?;
+            for _ in 0..count {
+                sizes.push(if sample_size == 0 {
+                    reader.read_u32::<BigEndian>()?
+                } else {
+                    sample_size
+                });
+            }
+        }
+        if sizes.is_empty() {
+            return Ok(());
+        }
+
+        let durations = read_entries(stbl, b"stts", |r| {
+            Ok((r.read_u32::<BigEndian>()?, r.read_u32::<BigEndian>()?))
+        })?;
+        /*the offsets of the version 0 box are unsigned but they never exceed i32*/
+        let composition_offsets = read_entries(stbl, b"ctts", |r| {
+            Ok((
+                r.read_u32::<BigEndian>()?,
+                r.read_u32::<BigEndian>()? as i32,
+            ))
+        })?;
+        let sync_samples = read_entries(stbl, b"stss", |r| r.read_u32::<BigEndian>())?;
+        let chunk_runs = read_entries(stbl, b"stsc", |r| {
+            let first_chunk = r.read_u32::<BigEndian>()?;
+            let samples_per_chunk = r.read_u32::<BigEndian>()?;
+            /*sample description index*/
+            r.read_u32::<BigEndian>()?;
+            Ok((first_chunk, samples_per_chunk))
+        })?;
+        let mut chunk_offsets =
+            read_entries(stbl, b"stco", |r| Ok(r.read_u32::<BigEndian>()? as u64))?;
+        if chunk_offsets.is_empty() {
+            chunk_offsets = read_entries(stbl, b"co64", |r| r.read_u64::<BigEndian>())?;
+        }
+
+        let mut durations = expand_runs(&durations);
+        let mut composition_offsets = expand_runs(&composition_offsets);
+        let mut decode_time = track.next_decode_time as i64;
+        let mut sample_number = 0;
+
+        for (chunk_index, chunk_offset) in chunk_offsets.iter().enumerate() {
+            /*the chunk numbers start from 1*/
+            let samples_per_chunk = chunk_runs
+                .iter()
+                .rev()
+                .find(|(first_chunk, _)| *first_chunk as usize <= chunk_index + 1)
+                .map_or(0, |(_, count)| *count);
+
+            let mut offset = *chunk_offset;
+            for _ in 0..samples_per_chunk {
+                let size = match sizes.get(sample_number) {
+                    Some(size) => *size,
+                    None => return Ok(()),
+                };
+                sample_number += 1;
+                let composition_offset = composition_offsets.next().unwrap_or(0) as i64;
+                self.samples.push(SampleInfo {
+                    is_video: matches!(track.kind, TrackKind::Video),
+                    dts: track.to_milliseconds(decode_time),
+                    pts: track.to_milliseconds(decode_time + composition_offset),
+                    is_sync: sync_samples.is_empty()
+                        || sync_samples.contains(&(sample_number as u32)),
+                    offset,
+                    size,
+                });
+                offset += size as u64;
+                decode_time += durations.next().unwrap_or(0) as i64;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_moof(&mut self, data: BytesMut, moof_offset: u64) -> Result<(), Mp4Error> {
+        for (box_type, traf) in read_boxes(data)? {
+            if &box_type == b"traf" {
+                self.read_traf(traf, moof_offset)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_traf(&mut self, data: BytesMut, moof_offset: u64) -> Result<(), Mp4Error> {
+        let boxes = read_boxes(data)?;
+        let header = read_tfhd(find_box(&boxes, b"tfhd").ok_or_else(invalid_box)?)?;
+        let track = match self.tracks.iter_mut().find(|t| t.id == header.track_id) {
+            Some(track) => track,
+            None => return Ok(()),
+        };
+
+        if let Some(tfdt) = find_box(&boxes, b"tfdt") {
+            let mut reader = BytesReader::new(tfdt);
+            track.next_decode_time = if read_full_box_header(&mut reader)?.0 == 1 {
+                reader.read_u64::<BigEndian>()?
+            } else {
+                reader.read_u32::<BigEndian>()? as u64
+            };
+        }
+
+        /*the data of a run without a data offset follows the previous run*/
+        let base_data_offset = header.base_data_offset.unwrap_or(moof_offset);
+        let mut offset = base_data_offset;
+        for (box_type, trun) in &boxes {
+            if box_type != b"trun" {
+                continue;
+            }
+            let mut reader = BytesReader::new(trun.clone());
+            let flags = read_full_box_header(&mut reader)?.1;
+            let sample_count = reader.read_u32::<BigEndian>()?;
+            if flags & trun_flags::DATA_OFFSET != 0 {
+                let data_offset = reader.read_u32::<BigEndian>()? as i32;
+                offset = (base_data_offset as i64 + data_offset as i64) as u64;
+            }
+            let first_sample_flags = if flags & trun_flags::FIRST_SAMPLE_FLAGS != 0 {
+                Some(reader.read_u32::<BigEndian>()?)
+            } else {
+                None
+            };
+
+            for index in 0..sample_count {
+                let mut read_field = |flag: u32, default: u32| -> Result<u32, Mp4Error> {
+                    if flags & flag != 0 {
+                        Ok(reader.read_u32::<BigEndian>()?)
+                    } else {
+                        Ok(default)
+                    }
+                };
+                let duration = read_field(
+                    trun_flags::SAMPLE_DURATION,
+                    header.default_duration.unwrap_or(track.default_duration),
+                )?;
+                let size = read_field(
+                    trun_flags::SAMPLE_SIZE,
+                    header.default_size.unwrap_or(track.default_size),
+                )?;
+                let mut sample_flags = read_field(
+                    trun_flags::SAMPLE_FLAGS,
+                    header.default_flags.unwrap_or(track.default_flags),
+                )?;
+                let composition_offset =
+                    read_field(trun_flags::SAMPLE_COMPOSITION_TIME_OFFSET, 0)? as i32;
+                if let (0, Some(first_sample_flags)) = (index, first_sample_flags) {
+                    sample_flags = first_sample_flags;
+                }
+
+                let decode_time = track.next_decode_time as i64;
+                let is_video = matches!(track.kind, TrackKind::Video);
+                self.samples.push(SampleInfo {
+                    is_video,
+                    dts: track.to_milliseconds(decode_time),
+                    pts: track.to_milliseconds(decode_time + composition_offset as i64),
+                    is_sync: !is_video || sample_flags & SAMPLE_IS_NON_SYNC == 0,
+                    offset,
+                    size,
+                });
+                offset += size as u64;
+                track.next_decode_time += duration as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
+/*the duration(movie timescale) of the empty edit at the start of a track, the
+other edits are not supported*/
+fn read_empty_edit(edts: BytesMut) -> Result<u64, Mp4Error> {
+    let boxes = read_boxes(edts)?;
+    let mut reader = match find_box(&boxes, b"elst") {
+        Some(elst) => BytesReader::new(elst),
+        None => return Ok(0),
+    };
+    let version = read_full_box_header(&mut reader)?.0;
+    if reader.read_u32::<BigEndian>()? == 0 {
+        return Ok(0);
+    }
+    let (duration, media_time) = if version == 1 {
+        (
+            reader.read_u64::<BigEndian>()?,
+            reader.read_u64::<BigEndian>()? as i64,
+        )
+    } else {
+        (
+            reader.read_u32::<BigEndian>()? as u64,
+            reader.read_u32::<BigEndian>()? as i32 as i64,
+        )
+    };
+    Ok(if media_time == -1 { duration } else { 0 })
+}
+
+fn invalid_box() -> Mp4Error {
+    Mp4Error {
+        value: Mp4ErrorValue::InvalidBox,
+    }
+}
+
+/*(type, payload) of the boxes in data*/
+fn read_boxes(data: BytesMut) -> Result<Vec<([u8; 4], BytesMut)>, Mp4Error> {
+    let mut reader = BytesReader::new(data);
+    let mut boxes = Vec::new();
+    while reader.len() >= 8 {
+        let size = reader.read_u32::<BigEndian>()? as u64;
+        let box_type = reader.read_bytes(4)?;
+        let payload_size = match size {
+            0 => reader.len() as u64,
+            1 => reader
+                .read_u64::<BigEndian>()?
+                .checked_sub(16)
+                .ok_or_else(invalid_box)?,
+            size => size.checked_sub(8).ok_or_else(invalid_box)?,
+        };
+        let payload = reader.read_bytes(payload_size as usize)?;
+        boxes.push((
+            [box_type[0], box_type[1], box_type[2], box_type[3]],
+            payload,
+        ));
+    }
+    Ok(boxes)
+}
+
+fn find_box(boxes: &[([u8; 4], BytesMut)], box_type: &[u8; 4]) -> Option<BytesMut> {
+    boxes
+        .iter()
+        .find(|(t, _)| t == box_type)
+        .map(|(_, payload)| payload.clone())
+}
+
+/*(version, flags)*/
+fn read_full_box_header(reader: &mut BytesReader) -> Result<(u8, u32), Mp4Error> {
+    let version = reader.read_u8()?;
+    let flags = reader.read_u24::<BigEndian>()?;
+    Ok((version, flags))
+}
+
+/*the entries of a full box with an entry count, empty if the box is absent*/
+fn read_entries<T, F>(
+    boxes: &[([u8; 4], BytesMut)],
+    box_type: &[u8; 4],
+    mut read_entry: F,
+) -> Result<Vec<T>, Mp4Error>
+where
+    F: FnMut(&mut BytesReader) -> Result<T, bytesio::bytes_errors::BytesReadError>,
+{
+    let mut entries = Vec::new();
+    if let Some(payload) = find_box(boxes, box_type) {
+        let mut reader = BytesReader::new(payload);
+        read_full_box_header(&mut reader)?;
+        let count = reader.read_u32::<BigEndian>()?;
+        for _ in 0..count {
+            entries.push(read_entry(&mut reader)?);
+        }
+    }
+    Ok(entries)
+}
+
+/*the values of the (count, value) runs of stts and ctts*/
+fn expand_runs<T: Copy>(runs: &[(u32, T)]) -> impl Iterator<Item = T> + '_ {
+    runs.iter()
+        .flat_map(|(count, value)| std::iter::repeat_n(*value, *count as usize))
+}
+
+fn read_tfhd(data: BytesMut) -> Result<TrackFragmentHeader, Mp4Error> {
+    let mut reader = BytesReader::new(data);
+    let flags = read_full_box_header(&mut reader)?.1;
+    let track_id = reader.read_u32::<BigEndian>()?;
+
+    let base_data_offset = if flags & tfhd_flags::BASE_DATA_OFFSET != 0 {
+        Some(reader.read_u64::<BigEndian>()?)
+    } else {
+        None
+    };
+    if flags & tfhd_flags::SAMPLE_DESCRIPTION_INDEX != 0 {
+        reader.read_u32::<BigEndian>()?;
+    }
+    let mut read_default = |flag: u32| -> Result<Option<u32>, Mp4Error> {
+        if flags & flag != 0 {
+            Ok(Some(reader.read_u32::<BigEndian>()?))
+        } else {
+            Ok(None)
+        }
+    };
+
+    Ok(TrackFragmentHeader {
+        track_id,
+        base_data_offset,
+        default_duration: read_default(tfhd_flags::DEFAULT_SAMPLE_DURATION)?,
+        default_size: read_default(tfhd_flags::DEFAULT_SAMPLE_SIZE)?,
+        default_flags: read_default(tfhd_flags::DEFAULT_SAMPLE_FLAGS)?,
+    })
+}
+
+/*None if the codec is not supported*/
+fn read_visual_sample_entry(
+    entry_type: &[u8; 4],
+    data: BytesMut,
+) -> Result<Option<VideoTrack>, Mp4Error> {
+    let (codec, config_type) = match entry_type {
+        b"avc1" | b"avc3" => (VideoCodec::H264, b"avcC"),
+        b"hvc1" | b"hev1" => (VideoCodec::H265, b"hvcC"),
+        _ => return Ok(None),
+    };
+
+    let mut reader = BytesReader::new(data);
+    /*reserved, data reference index, pre_defined and reserved*/
+    reader.read_bytes(24)?;
+    let width = reader.read_u16::<BigEndian>()? as u32;
+    let height = reader.read_u16::<BigEndian>()? as u32;
+    /*resolutions, reserved, frame count, compressor name, depth and pre_defined*/
+    reader.read_bytes(50)?;
+
+    let boxes = read_boxes(reader.extract_remaining_bytes())?;
+    Ok(find_box(&boxes, config_type).map(|config| VideoTrack {
+        codec,
+        width,
+        height,
+        config,
+    }))
+}
+
+/*None if there is no AudioSpecificConfig, it is not AAC*/
+fn read_mp4a(data: BytesMut, timescale: u32) -> Result<Option<AudioTrack>, Mp4Error> {
+    let mut reader = BytesReader::new(data);
+    /*reserved, data reference index and reserved*/
+    reader.read_bytes(16)?;
+    let channels = reader.read_u16::<BigEndian>()?;
+    /*sample size, pre_defined and reserved*/
+    reader.read_bytes(6)?;
+    /*16.16 fixed point, the timescale is the sample rate if it does not fit*/
+    let sample_rate = match reader.read_u32::<BigEndian>()? >> 16 {
+        0 => timescale,
+        sample_rate => sample_rate,
+    };
+
+    let boxes = read_boxes(reader.extract_remaining_bytes())?;
+    let esds = match find_box(&boxes, b"esds") {
+        Some(esds) => esds,
+        None => return Ok(None),
+    };
+    let mut reader = BytesReader::new(esds);
+    read_full_box_header(&mut reader)?;
+    Ok(
+        find_decoder_specific_info(reader.extract_remaining_bytes())?.map(|config| AudioTrack {
+            sample_rate,
+            channels,
+            config,
+        }),
+    )
+}
+
+/*the DecoderSpecificInfo in the DecoderConfigDescriptor of the ES_Descriptor*/
+fn find_decoder_specific_info(data: BytesMut) -> Result<Option<BytesMut>, Mp4Error> {
+    let mut reader = BytesReader::new(data);
+    while !reader.is_empty() {
+        let tag = reader.read_u8()?;
+        /*the length is coded with 7 bits per byte*/
+        let mut len = 0;
+        for _ in 0..4 {
+            let byte = reader.read_u8()?;
+            len = (len << 7) | (byte & 0x7f) as usize;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut payload = BytesReader::new(reader.read_bytes(len)?);
+
+        match tag {
+            /*ES_Descriptor*/
+            0x03 => {
+                /*ES_ID*/
+                payload.read_u16::<BigEndian>()?;
+                let flags = payload.read_u8()?;
+                if flags & 0x80 != 0 {
+                    /*dependsOn_ES_ID*/
+                    payload.read_u16::<BigEndian>()?;
+                }
+                if flags & 0x40 != 0 {
+                    let url_len = payload.read_u8()?;
+                    payload.read_bytes(url_len as usize)?;
+                }
+                if flags & 0x20 != 0 {
+                    /*OCR_ES_Id*/
+                    payload.read_u16::<BigEndian>()?;
+                }
+                return find_decoder_specific_info(payload.extract_remaining_bytes());
+            }
+            /*DecoderConfigDescriptor*/
+            0x04 => {
+                /*object type, stream type, buffer size and the bitrates*/
+                payload.read_bytes(13)?;
+                return find_decoder_specific_info(payload.extract_remaining_bytes());
+            }
+            0x05 => return Ok(Some(payload.extract_remaining_bytes())),
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mp4Demuxer, SampleInfo};
+    use crate::{
+        define::{AudioTrack, Sample, VideoCodec, VideoTrack},
+        fmp4::Fmp4Muxer,
+    };
+    use bytes::BytesMut;
+    use std::{convert::TryInto, io::Cursor};
+
+    fn muxer() -> Fmp4Muxer {
+        let mut muxer = Fmp4Muxer::new();
+        muxer.set_video_track(VideoTrack {
+            codec: VideoCodec::H264,
+            width: 1280,
+            height: 720,
+            config: BytesMut::from(&[0x01, 0x64, 0x00, 0x1f, 0xff, 0xe0, 0x00][..]),
+        });
+        muxer.set_audio_track(AudioTrack {
+            sample_rate: 44100,
+            channels: 2,
+            config: BytesMut::from(&[0x12, 0x10][..]),
+        });
+        muxer
+    }
+
+    /*two fragments of two video frames and two audio frames*/
+    fn record(muxer: &mut Fmp4Muxer) -> Vec<u8> {
+        let mut file = muxer.init_segment().unwrap().to_vec();
+        for (idx, dts) in [0, 40, 80, 120].iter().copied().enumerate() {
+            muxer.write_video(Sample {
+                dts,
+                pts: dts + 40,
+                is_sync: idx % 2 == 0,
+                data: BytesMut::from(&[0, 0, 0, 1, idx as u8][..]),
+            });
+            muxer.write_audio(Sample {
+                dts,
+                pts: dts,
+                is_sync: true,
+                data: BytesMut::from(&[0x21, idx as u8][..]),
+            });
+            if idx % 2 == 1 {
+                let fragment = muxer.flush_fragment(dts + 40).unwrap();
+                file.extend_from_slice(&fragment);
+            }
+        }
+        file
+    }
+
+    /*the progressive moov is appended and the boxes of the fragmented file are
+    turned into free boxes, as a finalized recording*/
+    fn finalize(muxer: &Fmp4Muxer, mut file: Vec<u8>) -> Vec<u8> {
+        let init_size = muxer.init_segment().unwrap().len();
+        let mut offset = 0;
+        while offset < file.len() {
+            let size = u32::from_be_bytes(file[offset..offset + 4].try_into().unwrap()) as usize;
+            if &file[offset + 4..offset + 8] != b"mdat" && offset > 0 {
+                file[offset + 4..offset + 8].copy_from_slice(b"free");
+            }
+            offset += size;
+        }
+        file.extend_from_slice(&muxer.progressive_moov(init_size as u64).unwrap());
+        file
+    }
+
+    fn check_samples(data: &[u8]) {
+        let demuxer = Mp4Demuxer::read(&mut Cursor::new(data)).unwrap();
+        let video = demuxer.video_track.unwrap();
+        assert_eq!((video.width, video.height), (1280, 720));
+        assert_eq!(
+            &video.config[..],
+            &[0x01, 0x64, 0x00, 0x1f, 0xff, 0xe0, 0x00]
+        );
+        let audio = demuxer.audio_track.unwrap();
+        assert_eq!((audio.sample_rate, audio.channels), (44100, 2));
+        assert_eq!(&audio.config[..], &[0x12, 0x10]);
+
+        assert_eq!(demuxer.samples.len(), 8);
+        let video_samples: Vec<&SampleInfo> =
+            demuxer.samples.iter().filter(|s| s.is_video).collect();
+        assert_eq!(
+            video_samples.iter().map(|s| s.dts).collect::<Vec<_>>(),
+            vec![0, 40, 80, 120]
+        );
+        assert_eq!(video_samples[1].pts, 80);
+        assert_eq!(
+            video_samples.iter().map(|s| s.is_sync).collect::<Vec<_>>(),
+            vec![true, false, true, false]
+        );
+        for (idx, sample) in video_samples.iter().enumerate() {
+            let offset = sample.offset as usize;
+            assert_eq!(
+                &data[offset..offset + sample.size as usize],
+                &[0, 0, 0, 1, idx as u8]
+            );
+        }
+        /*the audio samples follow their timestamps*/
+        let audio_sample = demuxer.samples.iter().find(|s| !s.is_video && s.dts > 0);
+        assert_eq!(audio_sample.map(|s| s.dts), Some(40));
+    }
+
+    #[test]
+    fn test_fragmented_file() {
+        let mut file = record(&mut muxer());
+        check_samples(&file);
+
+        /*the fragment being written is ignored*/
+        file.extend_from_slice(&[0, 0, 1, 0, b'm', b'o', b'o', b'f', 0]);
+        assert_eq!(
+            Mp4Demuxer::read(&mut Cursor::new(&file))
+                .unwrap()
+                .samples
+                .len(),
+            8
+        );
+        assert!(Mp4Demuxer::read(&mut Cursor::new(&file[..16])).is_err());
+    }
+
+    #[test]
+    fn test_progressive_file() {
+        let mut muxer = muxer();
+        muxer.keep_sample_tables();
+        let file = record(&mut muxer);
+        check_samples(&finalize(&muxer, file));
+
+        /*the audio track starts after an empty edit*/
+        let mut muxer = self::muxer();
+        muxer.keep_sample_tables();
+        let mut file = muxer.init_segment().unwrap().to_vec();
+        for dts in [0, 40] {
+            muxer.write_video(Sample {
+                dts,
+                pts: dts,
+                is_sync: true,
+                data: BytesMut::from(&[0, 0, 0, 1, 0][..]),
+            });
+        }
+        for dts in [40, 80] {
+            muxer.write_audio(Sample {
+                dts,
+                pts: dts,
+                is_sync: true,
+                data: BytesMut::from(&[0x21, 0][..]),
+            });
+        }
+        file.extend_from_slice(&muxer.flush_fragment(120).unwrap());
+        let file = finalize(&muxer, file);
+        let demuxer = Mp4Demuxer::read(&mut Cursor::new(&file)).unwrap();
+        let audio_samples: Vec<i64> = demuxer
+            .samples
+            .iter()
+            .filter(|s| !s.is_video)
+            .map(|s| s.dts)
+            .collect();
+        assert_eq!(audio_samples, vec![40, 80]);
+    }
+}
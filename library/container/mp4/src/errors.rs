@@ -0,0 +1,75 @@
+#![allow(non_local_definitions)]
+
+use {
+    bytesio::bytes_errors::{BytesReadError, BytesWriteError},
+    failure::{Backtrace, Fail},
+    std::{fmt, io::Error},
+};
+
+#[derive(Debug, Fail)]
+pub enum Mp4ErrorValue {
+    #[fail(display = "bytes write error")]
+    BytesWriteError(BytesWriteError),
+
+    #[fail(display = "bytes read error")]
+    BytesReadError(BytesReadError),
+
+    #[fail(display = "io error: {}", _0)]
+    IOError(Error),
+
+    #[fail(display = "no track is configured")]
+    NoTrack,
+
+    #[fail(display = "box is too large")]
+    BoxTooLarge,
+
+    #[fail(display = "the sample tables are not kept")]
+    NoSampleTable,
+
+    #[fail(display = "invalid box")]
+    InvalidBox,
+}
+#[derive(Debug)]
+pub struct Mp4Error {
+    pub value: Mp4ErrorValue,
+}
+
+impl From<BytesWriteError> for Mp4Error {
+    fn from(error: BytesWriteError) -> Self {
+        Mp4Error {
+            value: Mp4ErrorValue::BytesWriteError(error),
+        }
+    }
+}
+
+impl From<BytesReadError> for Mp4Error {
+    fn from(error: BytesReadError) -> Self {
+        Mp4Error {
+            value: Mp4ErrorValue::BytesReadError(error),
+        }
+    }
+}
+
+impl From<Error> for Mp4Error {
+    fn from(error: Error) -> Self {
+        Mp4Error {
+            value: Mp4ErrorValue::IOError(error),
+        }
+    }
+}
+
+impl fmt::Display for Mp4Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.value, f)
+    }
+}
+
+impl Fail for Mp4Error {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.value.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.value.backtrace()
+    }
+}
@@ -0,0 +1,619 @@
+use {
+    super::{
+        boxes::{self, TrackRun},
+        define::{
+            AudioTrack, Sample, SampleTable, TrackFragment, VideoTrack, AAC_FRAME_SIZE,
+            AUDIO_TRACK_ID, VIDEO_TIMESCALE, VIDEO_TRACK_ID,
+        },
+        errors::{Mp4Error, Mp4ErrorValue},
+    },
+    bytes::BytesMut,
+    bytesio::bytes_writer::BytesWriter,
+};
+
+/*used for the last video sample of a fragment when the next frame is unknown, 25fps*/
+const DEFAULT_VIDEO_SAMPLE_DURATION: u32 = VIDEO_TIMESCALE / 25;
+
+/*
+ ** Fragmented MP4 **
+ +------------------------------------------------------------------+
+ | init segment: ftyp | moov                                        |
+ +------------------------------------------------------------------+
+ | media segment: moof | mdat | moof | mdat | ...                   |
+ +------------------------------------------------------------------+
+
+ The samples are buffered until flush_fragment is called, every call
+ produces one moof/mdat pair(a CMAF chunk) containing the buffered samples
+ of all the tracks.
+
+ ** Progressive MP4 **
+ +------------------------------------------------------------------+
+ | ftyp | moov | free | mdat | free | mdat | ...                     |
+ +------------------------------------------------------------------+
+
+ If the sample tables are kept, the moov box returned by progressive_moov
+ describes the samples where they are in the written fragments. Written after
+ the ftyp box and followed by the fragments with the moof boxes turned into
+ free boxes, it makes a progressive file of the fragmented one.
+*/
+pub struct Fmp4Muxer {
+    video_track: Option<VideoTrack>,
+    audio_track: Option<AudioTrack>,
+
+    video_samples: Vec<Sample>,
+    audio_samples: Vec<Sample>,
+
+    sequence_number: u32,
+    last_video_duration: u32,
+    /*the decode time(audio timescale) following the last written audio sample*/
+    audio_decode_time: Option<u64>,
+
+    /*None if the sample tables are not kept*/
+    sample_tables: Option<(SampleTable, SampleTable)>,
+    /*the size of the written fragments*/
+    fragments_size: u64,
+}
+
+impl Default for Fmp4Muxer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fmp4Muxer {
+    pub fn new() -> Self {
+        Self {
+            video_track: None,
+            audio_track: None,
+            video_samples: Vec::new(),
+            audio_samples: Vec::new(),
+            sequence_number: 0,
+            last_video_duration: DEFAULT_VIDEO_SAMPLE_DURATION,
+            audio_decode_time: None,
+            sample_tables: None,
+            fragments_size: 0,
+        }
+    }
+
+    /*keep the samples of the written fragments to build a progressive file*/
+    pub fn keep_sample_tables(&mut self) {
+        self.sample_tables = Some(Default::default());
+    }
+
+    /*the number of the samples in the sample tables*/
+    pub fn sample_count(&self) -> usize {
+        self.sample_tables
+            .as_ref()
+            .map_or(0, |(video_table, audio_table)| {
+                video_table.samples.len() + audio_table.samples.len()
+            })
+    }
+
+    pub fn set_video_track(&mut self, track: VideoTrack) {
+        self.video_track = Some(track);
+    }
+
+    pub fn set_audio_track(&mut self, track: AudioTrack) {
+        self.audio_track = Some(track);
+    }
+
+    pub fn video_track(&self) -> Option<&VideoTrack> {
+        self.video_track.as_ref()
+    }
+
+    pub fn audio_track(&self) -> Option<&AudioTrack> {
+        self.audio_track.as_ref()
+    }
+
+    pub fn has_samples(&self) -> bool {
+        !self.video_samples.is_empty() || !self.audio_samples.is_empty()
+    }
+
+    pub fn init_segment(&self) -> Result<BytesMut, Mp4Error> {
+        Self::write_init_segment(self.video_track.as_ref(), self.audio_track.as_ref())
+    }
+
+    /*the init segment with the video track only*/
+    pub fn video_init_segment(&self) -> Result<BytesMut, Mp4Error> {
+        Self::write_init_segment(self.video_track.as_ref(), None)
+    }
+
+    pub fn audio_init_segment(&self) -> Result<BytesMut, Mp4Error> {
+        Self::write_init_segment(None, self.audio_track.as_ref())
+    }
+
+    fn write_init_segment(
+        video_track: Option<&VideoTrack>,
+        audio_track: Option<&AudioTrack>,
+    ) -> Result<BytesMut, Mp4Error> {
+        if video_track.is_none() && audio_track.is_none() {
+            return Err(Mp4Error {
+                value: Mp4ErrorValue::NoTrack,
+            });
+        }
+
+        let mut writer = BytesWriter::new();
+        boxes::write_ftyp(&mut writer)?;
+        boxes::write_moov(&mut writer, video_track, audio_track)?;
+        Ok(writer.extract_current_bytes())
+    }
+
+    /*the ftyp box of a progressive file, it has the size of the one of the init
+    segment*/
+    pub fn progressive_ftyp(&self) -> Result<BytesMut, Mp4Error> {
+        let mut writer = BytesWriter::new();
+        boxes::write_progressive_ftyp(&mut writer)?;
+        Ok(writer.extract_current_bytes())
+    }
+
+    /*The moov box of a progressive file, data_offset is the position of the
+    first fragment in the file. The tracks without samples are left out.*/
+    pub fn progressive_moov(&self, data_offset: u64) -> Result<BytesMut, Mp4Error> {
+        let (video_table, audio_table) = match &self.sample_tables {
+            Some(tables) => tables,
+            None => {
+                return Err(Mp4Error {
+                    value: Mp4ErrorValue::NoSampleTable,
+                })
+            }
+        };
+        let video = self
+            .video_track
+            .as_ref()
+            .zip(Some(video_table).filter(|t| !t.samples.is_empty()));
+        let audio = self
+            .audio_track
+            .as_ref()
+            .zip(Some(audio_table).filter(|t| !t.samples.is_empty()));
+        if video.is_none() && audio.is_none() {
+            return Err(Mp4Error {
+                value: Mp4ErrorValue::NoTrack,
+            });
+        }
+
+        let mut writer = BytesWriter::new();
+        boxes::write_progressive_moov(&mut writer, video, audio, data_offset)?;
+        Ok(writer.extract_current_bytes())
+    }
+
+    /*the samples received before the decoder configuration of their track are dropped*/
+    pub fn write_video(&mut self, sample: Sample) {
+        if self.video_track.is_some() {
+            self.video_samples.push(sample);
+        }
+    }
+
+    pub fn write_audio(&mut self, sample: Sample) {
+        if self.audio_track.is_some() {
+            self.audio_samples.push(sample);
+        }
+    }
+
+    /*Write the buffered samples as a moof/mdat pair, next_dts(milliseconds) is the
+    dts of the first frame after the fragment, it gives the duration of the last
+    video sample. An empty buffer is returned if there is no sample.*/
+    pub fn flush_fragment(&mut self, next_dts: i64) -> Result<BytesMut, Mp4Error> {
+        let video_samples = std::mem::take(&mut self.video_samples);
+        let audio_samples = std::mem::take(&mut self.audio_samples);
+
+        let mut runs = Vec::new();
+        if !video_samples.is_empty() {
+            runs.push(self.video_run(&video_samples, next_dts));
+        }
+        if self.audio_track.is_some() && !audio_samples.is_empty() {
+            runs.push(self.audio_run(&audio_samples));
+        }
+
+        self.write_fragment(&runs, &[&video_samples, &audio_samples])
+    }
+
+    /*Write the buffered video samples only, the audio samples are kept. It is used
+    when the tracks are delivered separately, e.g. the representations of DASH.*/
+    pub fn flush_video_fragment(
+        &mut self,
+        next_dts: i64,
+    ) -> Result<Option<TrackFragment>, Mp4Error> {
+        if self.video_samples.is_empty() {
+            return Ok(None);
+        }
+
+        let samples = std::mem::take(&mut self.video_samples);
+        let run = self.video_run(&samples, next_dts);
+        Ok(Some(TrackFragment {
+            base_media_decode_time: run.base_media_decode_time,
+            duration: run.duration(),
+            data: self.write_fragment(&[run], &[&samples])?,
+        }))
+    }
+
+    pub fn flush_audio_fragment(&mut self) -> Result<Option<TrackFragment>, Mp4Error> {
+        if self.audio_track.is_none() || self.audio_samples.is_empty() {
+            return Ok(None);
+        }
+
+        let samples = std::mem::take(&mut self.audio_samples);
+        let run = self.audio_run(&samples);
+        Ok(Some(TrackFragment {
+            base_media_decode_time: run.base_media_decode_time,
+            duration: run.duration(),
+            data: self.write_fragment(&[run], &[&samples])?,
+        }))
+    }
+
+    /*the samples of every run follow each other in the mdat box*/
+    fn write_fragment(
+        &mut self,
+        runs: &[TrackRun],
+        samples: &[&[Sample]],
+    ) -> Result<BytesMut, Mp4Error> {
+        if runs.is_empty() {
+            return Ok(BytesMut::new());
+        }
+
+        self.sequence_number += 1;
+        let mut writer = BytesWriter::new();
+        let positions = boxes::write_moof(&mut writer, self.sequence_number, runs)?;
+
+        /*the data of the runs follows the 8 bytes mdat header*/
+        let mut data_offset = writer.len() + 8;
+        for (run, position) in runs.iter().zip(positions) {
+            boxes::write_u32_at(&mut writer, position, data_offset as u32)?;
+            data_offset += run.data_size();
+        }
+
+        /*the chunk offsets are relative to the first fragment*/
+        let mut chunk_offset = self.fragments_size + writer.len() as u64 + 8;
+        for run in runs {
+            if let Some((video_table, audio_table)) = &mut self.sample_tables {
+                let table = if run.track_id == VIDEO_TRACK_ID {
+                    video_table
+                } else {
+                    audio_table
+                };
+                if table.samples.is_empty() {
+                    table.start = run.base_media_decode_time;
+                }
+                table.chunks.push((chunk_offset, run.samples.len() as u32));
+                table.samples.extend_from_slice(&run.samples);
+            }
+            chunk_offset += run.data_size() as u64;
+        }
+
+        boxes::write_box(&mut writer, b"mdat", |w| {
+            for sample in samples.iter().flat_map(|s| s.iter()) {
+                w.write(&sample.data[..])?;
+            }
+            Ok(())
+        })?;
+
+        self.fragments_size += writer.len() as u64;
+        Ok(writer.extract_current_bytes())
+    }
+
+    fn video_run(&mut self, samples: &[Sample], next_dts: i64) -> TrackRun {
+        let to_timescale = |ms: i64| ms * (VIDEO_TIMESCALE / 1000) as i64;
+
+        let mut run_samples = Vec::with_capacity(samples.len());
+        for (idx, sample) in samples.iter().enumerate() {
+            let next = samples.get(idx + 1).map(|s| s.dts).unwrap_or(next_dts);
+            let duration = if next > sample.dts {
+                to_timescale(next - sample.dts) as u32
+            } else {
+                self.last_video_duration
+            };
+            self.last_video_duration = duration;
+
+            run_samples.push((
+                duration,
+                sample.data.len() as u32,
+                sample.is_sync,
+                to_timescale(sample.pts - sample.dts) as i32,
+            ));
+        }
+
+        TrackRun {
+            track_id: VIDEO_TRACK_ID,
+            base_media_decode_time: to_timescale(samples[0].dts).max(0) as u64,
+            samples: run_samples,
+        }
+    }
+
+    /*The decode times follow the written durations, so the tfdt of a run is the
+    end of the previous one. A duration is the distance from the decode time of
+    the sample to the timestamp of the next one, it absorbs the rounding of the
+    millisecond timestamps and the gaps. The last sample of a run lasts an AAC
+    frame, the next run corrects it.*/
+    fn audio_run(&mut self, samples: &[Sample]) -> TrackRun {
+        let sample_rate = self
+            .audio_track
+            .as_ref()
+            .map(|track| track.sample_rate as u64)
+            .unwrap_or_default();
+        let to_timescale = |ms: i64| ms.max(0) as u64 * sample_rate / 1000;
+
+        /*restart from the timestamps if they jumped more than a frame away*/
+        let first_decode_time = to_timescale(samples[0].dts);
+        let base_media_decode_time = match self.audio_decode_time {
+            Some(decode_time)
+                if decode_time.abs_diff(first_decode_time) <= AAC_FRAME_SIZE as u64 =>
+            {
+                decode_time
+            }
+            _ => first_decode_time,
+        };
+
+        let mut decode_time = base_media_decode_time;
+        let mut run_samples = Vec::with_capacity(samples.len());
+        for (idx, sample) in samples.iter().enumerate() {
+            let duration = match samples.get(idx + 1) {
+                Some(next) if to_timescale(next.dts) > decode_time => {
+                    (to_timescale(next.dts) - decode_time) as u32
+                }
+                _ => AAC_FRAME_SIZE,
+            };
+            decode_time += duration as u64;
+            run_samples.push((duration, sample.data.len() as u32, true, 0));
+        }
+        self.audio_decode_time = Some(decode_time);
+
+        TrackRun {
+            track_id: AUDIO_TRACK_ID,
+            base_media_decode_time,
+            samples: run_samples,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fmp4Muxer;
+    use crate::define::{AudioTrack, Sample, VideoCodec, VideoTrack};
+    use bytes::BytesMut;
+    use std::convert::TryInto;
+
+    /*(type, offset, size) of the boxes at the top level of data*/
+    fn top_level_boxes(data: &[u8]) -> Vec<(String, usize, usize)> {
+        let mut boxes = Vec::new();
+        let mut offset = 0;
+        while offset + 8 <= data.len() {
+            let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+            let box_type = String::from_utf8_lossy(&data[offset + 4..offset + 8]).to_string();
+            boxes.push((box_type, offset, size));
+            offset += size;
+        }
+        assert_eq!(offset, data.len());
+        boxes
+    }
+
+    fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+        data.windows(pattern.len()).position(|w| w == pattern)
+    }
+
+    fn muxer() -> Fmp4Muxer {
+        let mut muxer = Fmp4Muxer::new();
+        muxer.set_video_track(VideoTrack {
+            codec: VideoCodec::H264,
+            width: 1280,
+            height: 720,
+            config: BytesMut::from(&[0x01, 0x64, 0x00, 0x1f, 0xff, 0xe0, 0x00][..]),
+        });
+        muxer.set_audio_track(AudioTrack {
+            sample_rate: 44100,
+            channels: 2,
+            config: BytesMut::from(&[0x12, 0x10][..]),
+        });
+        muxer
+    }
+
+    #[test]
+    fn test_init_segment() {
+        assert!(Fmp4Muxer::new().init_segment().is_err());
+
+        let data = muxer().init_segment().unwrap();
+        let boxes = top_level_boxes(&data);
+        assert_eq!(boxes[0].0, "ftyp");
+        assert_eq!(boxes[1].0, "moov");
+        /*the progressive ftyp replaces it in place*/
+        assert_eq!(muxer().progressive_ftyp().unwrap().len(), boxes[0].2);
+
+        for box_type in [&b"avc1"[..], b"avcC", b"mp4a", b"esds", b"mvex", b"trex"] {
+            assert!(find(&data, box_type).is_some());
+        }
+        /*the AudioSpecificConfig is carried by the DecoderSpecificInfo*/
+        assert!(find(&data, &[0x05, 0x02, 0x12, 0x10]).is_some());
+    }
+
+    #[test]
+    fn test_fragment() {
+        let mut muxer = muxer();
+        assert!(muxer.flush_fragment(0).unwrap().is_empty());
+
+        muxer.write_video(Sample {
+            dts: 0,
+            pts: 40,
+            is_sync: true,
+            data: BytesMut::from(&[0, 0, 0, 2, 0x65, 0x88][..]),
+        });
+        muxer.write_video(Sample {
+            dts: 40,
+            pts: 40,
+            is_sync: false,
+            data: BytesMut::from(&[0, 0, 0, 1, 0x41][..]),
+        });
+        muxer.write_audio(Sample {
+            dts: 10,
+            pts: 10,
+            is_sync: true,
+            data: BytesMut::from(&[0x21, 0x22, 0x23][..]),
+        });
+
+        let data = muxer.flush_fragment(80).unwrap();
+        let boxes = top_level_boxes(&data);
+        assert_eq!(boxes[0].0, "moof");
+        assert_eq!(boxes[1].0, "mdat");
+        assert!(!muxer.has_samples());
+
+        /*the data offset of the first run points at the first video sample*/
+        let trun = find(&data, b"trun").unwrap();
+        let data_offset =
+            u32::from_be_bytes(data[trun + 12..trun + 16].try_into().unwrap()) as usize;
+        assert_eq!(data_offset, boxes[1].1 + 8);
+        assert_eq!(
+            &data[data_offset..data_offset + 6],
+            &[0, 0, 0, 2, 0x65, 0x88]
+        );
+
+        /*the audio run follows the 11 bytes of video data*/
+        let audio_trun = trun + 1 + find(&data[trun + 1..], b"trun").unwrap();
+        let audio_offset =
+            u32::from_be_bytes(data[audio_trun + 12..audio_trun + 16].try_into().unwrap()) as usize;
+        assert_eq!(audio_offset, data_offset + 11);
+        assert_eq!(&data[audio_offset..], &[0x21, 0x22, 0x23]);
+    }
+
+    #[test]
+    fn test_track_fragment() {
+        let mut muxer = muxer();
+        assert!(muxer.flush_video_fragment(0).unwrap().is_none());
+
+        for dts in [0, 23, 46] {
+            muxer.write_audio(Sample {
+                dts,
+                pts: dts,
+                is_sync: true,
+                data: BytesMut::from(&[0x21][..]),
+            });
+        }
+        muxer.write_video(Sample {
+            dts: 1000,
+            pts: 1000,
+            is_sync: true,
+            data: BytesMut::from(&[0, 0, 0, 1, 0x65][..]),
+        });
+
+        let video = muxer.flush_video_fragment(1040).unwrap().unwrap();
+        assert_eq!(video.base_media_decode_time, 90000);
+        assert_eq!(video.duration, 3600);
+        /*the audio samples are kept*/
+        assert!(muxer.has_samples());
+
+        let audio = muxer.flush_audio_fragment().unwrap().unwrap();
+        assert_eq!(audio.base_media_decode_time, 0);
+        /*23ms is 1014 samples, the last one lasts an AAC frame*/
+        assert_eq!(audio.duration, 2 * 1014 + 1024);
+        assert!(!muxer.has_samples());
+
+        let audio_init = muxer.audio_init_segment().unwrap();
+        assert!(find(&audio_init, b"mp4a").is_some());
+        assert!(find(&audio_init, b"avc1").is_none());
+    }
+
+    #[test]
+    fn test_audio_decode_time() {
+        let mut muxer = muxer();
+
+        /*the timestamps are 23ms apart, 1014.3 samples instead of 1024*/
+        let mut dts = 0;
+        let mut end = None;
+        for _ in 0..10 {
+            for _ in 0..10 {
+                muxer.write_audio(Sample {
+                    dts,
+                    pts: dts,
+                    is_sync: true,
+                    data: BytesMut::from(&[0x21][..]),
+                });
+                dts += 23;
+            }
+            let audio = muxer.flush_audio_fragment().unwrap().unwrap();
+            /*every run starts where the previous one ended*/
+            if let Some(end) = end {
+                assert_eq!(audio.base_media_decode_time, end);
+            }
+            end = Some(audio.base_media_decode_time + audio.duration);
+        }
+
+        /*the decode time does not drift away from the timestamps*/
+        let expected = dts as u64 * 44100 / 1000;
+        assert!(end.unwrap().abs_diff(expected) <= 1024);
+
+        /*a gap longer than a frame restarts from the timestamps*/
+        muxer.write_audio(Sample {
+            dts: dts + 1000,
+            pts: dts + 1000,
+            is_sync: true,
+            data: BytesMut::from(&[0x21][..]),
+        });
+        let audio = muxer.flush_audio_fragment().unwrap().unwrap();
+        assert_eq!(
+            audio.base_media_decode_time,
+            (dts + 1000) as u64 * 44100 / 1000
+        );
+    }
+
+    #[test]
+    fn test_progressive_moov() {
+        let mut muxer = muxer();
+        assert!(muxer.progressive_moov(0).is_err());
+        muxer.keep_sample_tables();
+
+        let mut data = muxer.init_segment().unwrap().to_vec();
+        let data_offset = data.len() as u64;
+        for (idx, dts) in [0, 40, 80, 120].iter().copied().enumerate() {
+            muxer.write_video(Sample {
+                dts,
+                pts: dts,
+                is_sync: idx % 2 == 0,
+                data: BytesMut::from(&[0, 0, 0, 1, idx as u8][..]),
+            });
+            /*the audio starts 40ms after the video*/
+            muxer.write_audio(Sample {
+                dts: dts + 40,
+                pts: dts + 40,
+                is_sync: true,
+                data: BytesMut::from(&[0x21, idx as u8][..]),
+            });
+            /*a fragment for every two frames*/
+            if idx % 2 == 1 {
+                data.extend_from_slice(&muxer.flush_fragment(dts + 40).unwrap());
+            }
+        }
+        assert_eq!(muxer.sample_count(), 8);
+
+        let moov = muxer.progressive_moov(data_offset).unwrap();
+        assert!(find(&moov, b"mvex").is_none());
+        data.extend_from_slice(&moov);
+        let boxes = top_level_boxes(&data);
+        assert_eq!(
+            boxes.iter().map(|b| b.0.as_str()).collect::<Vec<_>>(),
+            vec!["ftyp", "moov", "moof", "mdat", "moof", "mdat", "moov"]
+        );
+        let moov_offset = boxes[6].1;
+
+        /*the entries follow the version, the flags and the entry count*/
+        let entries = |box_type: &[u8]| -> Vec<u32> {
+            let position = moov_offset + find(&data[moov_offset..], box_type).unwrap() + 8;
+            let read = |p: usize| u32::from_be_bytes(data[p..p + 4].try_into().unwrap());
+            (0..read(position) as usize)
+                .map(|i| read(position + 4 + i * 4))
+                .collect()
+        };
+
+        /*the first and the third video samples are the sync samples*/
+        assert_eq!(entries(b"stss"), vec![1, 3]);
+
+        /*the chunks of the video track are the video runs of the fragments*/
+        let offsets = entries(b"stco");
+        assert_eq!(offsets.len(), 2);
+        assert_eq!(offsets[0] as usize, boxes[3].1 + 8);
+        let offset = offsets[1] as usize;
+        assert_eq!(&data[offset..offset + 5], &[0, 0, 0, 1, 2]);
+
+        /*only the audio track has an empty edit of 40ms*/
+        let elst = find(&moov, b"elst").unwrap();
+        assert_eq!(find(&moov[elst + 4..], b"elst"), None);
+        assert_eq!(&moov[elst + 8..elst + 16], &[0, 0, 0, 2, 0, 0, 0, 40]);
+        assert_eq!(&moov[elst + 16..elst + 20], &[0xff; 4]);
+        assert!(find(&moov, b"soun").unwrap() > elst);
+    }
+}
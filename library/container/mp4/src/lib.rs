@@ -0,0 +1,5 @@
+pub mod boxes;
+pub mod define;
+pub mod demuxer;
+pub mod errors;
+pub mod fmp4;
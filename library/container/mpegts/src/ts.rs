@@ -401,6 +401,22 @@ impl TsMuxer {
         Ok(self.pid - 1)
     }
 
+    /*change the stream type of a stream, e.g. the video codec of the publisher
+    changes, the new PMT is written before the next packet*/
+    pub fn set_stream_type(&mut self, pid: u16, codecid: u8) -> Result<(), MpegTsError> {
+        self.find_stream(pid)?;
+
+        let pmt = &mut self.pat.pmt[self.cur_pmt_index];
+        let stream = &mut pmt.streams[self.cur_stream_index];
+        if stream.codec_id != codecid {
+            stream.codec_id = codecid;
+            pmt.version_number = (pmt.version_number + 1) % 32;
+            self.pat_period = 0;
+        }
+
+        Ok(())
+    }
+
     pub fn add_program(&mut self, program_number: u16, info: BytesMut) -> Result<(), MpegTsError> {
         for cur_pmt in self.pat.pmt.iter() {
             if cur_pmt.program_number == program_number {
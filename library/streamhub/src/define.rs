@@ -7,7 +7,8 @@ use crate::utils;
 
 use {
     super::errors::StreamHubError,
-    crate::statistics::StatisticsStream,
+    crate::failover::FailoverSubscriber,
+    crate::statistics::{StatisticsStream, Tracks},
     async_trait::async_trait,
     bytes::BytesMut,
     serde::ser::SerializeStruct,
@@ -29,6 +30,9 @@ pub enum SubscribeType {
     /* Remote client request playing hls stream.*/
     PlayerHls,
     GenerateHls,
+    GenerateDash,
+    /* Local recorder writes the stream to files.*/
+    Record,
     /* Local client *subscribe* from local rtmp session
     and *publish* (relay push) the stream to remote server.*/
     PublisherRtmp,
@@ -79,6 +83,8 @@ pub struct PublisherInfo {
     pub pub_type: PublishType,
     pub pub_data_type: PubDataType,
     pub notify_info: NotifyInfo,
+    /*the label of the per stream publish key used by the publisher*/
+    pub publish_key: Option<String>,
 }
 
 impl Serialize for PublisherInfo {
@@ -92,6 +98,9 @@ impl Serialize for PublisherInfo {
         state.serialize_field("id", &self.id.to_string())?;
         state.serialize_field("pub_type", &self.pub_type)?;
         state.serialize_field("notify_info", &self.notify_info)?;
+        if let Some(publish_key) = &self.publish_key {
+            state.serialize_field("publish_key", publish_key)?;
+        }
         state.end()
     }
 }
@@ -115,6 +124,8 @@ pub enum FrameData {
     Audio { timestamp: u32, data: BytesMut },
     MetaData { timestamp: u32, data: BytesMut },
     MediaInfo { media_info: MediaInfo },
+    /*the following frames are from a new publisher or source*/
+    Discontinuity,
 }
 
 //Used to pass rtp raw data.
@@ -162,6 +173,8 @@ pub type StatisticStreamReceiver = mpsc::UnboundedReceiver<StatisticsStream>;
 pub type StatisticApiResultSender = oneshot::Sender<Value>;
 pub type StatisticApiResultReceiver = oneshot::Receiver<Value>;
 
+pub type TracksResultSender = oneshot::Sender<Option<Tracks>>;
+
 pub type SubEventExecuteResultSender =
     oneshot::Sender<Result<(DataReceiver, Option<StatisticDataSender>), StreamHubError>>;
 pub type PubEventExecuteResultSender = oneshot::Sender<
@@ -175,6 +188,7 @@ pub type PubEventExecuteResultSender = oneshot::Sender<
     >,
 >;
 pub type TransceiverEventExecuteResultSender = oneshot::Sender<StatisticDataSender>;
+pub type FailoverReleaseResultSender = oneshot::Sender<Vec<FailoverSubscriber>>;
 
 #[async_trait]
 pub trait TStreamHandler: Send + Sync {
@@ -187,7 +201,14 @@ pub trait TStreamHandler: Send + Sync {
     async fn send_information(&self, sender: InformationSender);
 }
 
+impl fmt::Debug for dyn TStreamHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TStreamHandler")
+    }
+}
+
 //A publisher can publish one or two kinds of av stream at a time.
+#[derive(Debug)]
 pub struct DataReceiver {
     pub frame_receiver: Option<FrameDataReceiver>,
     pub packet_receiver: Option<PacketDataReceiver>,
@@ -213,6 +234,15 @@ pub enum PubDataType {
     Both,
 }
 
+/*a file recorded from a stream, the duration(milliseconds) and the size(bytes)
+are 0 until it is finished*/
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordInfo {
+    pub path: String,
+    pub duration: i64,
+    pub size: u64,
+}
+
 #[derive(Clone, Serialize)]
 pub enum StreamHubEventMessage {
     Subscribe {
@@ -235,6 +265,14 @@ pub enum StreamHubEventMessage {
         name: String,
         info: PublisherInfo,
     },
+    RecordStart {
+        name: String,
+        info: RecordInfo,
+    },
+    RecordStop {
+        name: String,
+        info: RecordInfo,
+    },
     NotSupport {},
 }
 
@@ -266,6 +304,15 @@ pub enum StreamHubEvent {
         name: String,
         info: PublisherInfo,
     },
+    /*a recorder started or finished a file, they are only notified*/
+    RecordStart {
+        name: String,
+        info: RecordInfo,
+    },
+    RecordStop {
+        name: String,
+        info: RecordInfo,
+    },
     #[serde(skip_serializing)]
     ApiStatistic {
         top_n: Option<usize>,
@@ -276,6 +323,16 @@ pub enum StreamHubEvent {
     },
     #[serde(skip_serializing)]
     ApiKickClient { id: Uuid },
+    /*kick the publisher of a stream which is authorized by the publish key(label)*/
+    #[serde(skip_serializing)]
+    ApiKickPublisher { name: String, publish_key: String },
+    /*the measured tracks of a published stream, None if it is not published*/
+    #[serde(skip_serializing)]
+    ApiTracks {
+        protocol: Protocol,
+        name: String,
+        result_sender: TracksResultSender,
+    },
 
     #[serde(skip_serializing)]
     Request {
@@ -300,6 +357,12 @@ impl StreamHubEvent {
             StreamHubEvent::UnPublish {protocol, name, info } => {
                 StreamHubEventMessage::UnPublish { protocol:protocol.clone(), name:name.clone(), info: info.clone() }
             }
+            StreamHubEvent::RecordStart { name, info } => {
+                StreamHubEventMessage::RecordStart { name: name.clone(), info: info.clone() }
+            }
+            StreamHubEvent::RecordStop { name, info } => {
+                StreamHubEventMessage::RecordStop { name: name.clone(), info: info.clone() }
+            }
             _ => {
                 StreamHubEventMessage::NotSupport {}
             }
@@ -318,6 +381,22 @@ pub enum TransceiverEvent {
         info: SubscriberInfo,
     },
     UnPublish {},
+    /*attach the subscribers of a failover group*/
+    Adopt {
+        subscribers: Vec<FailoverSubscriber>,
+        result_sender: TransceiverEventExecuteResultSender,
+    },
+    /*the publisher returned within the grace period*/
+    Resume {
+        receiver: DataReceiver,
+        stream_handler: Arc<dyn TStreamHandler>,
+        result_sender: TransceiverEventExecuteResultSender,
+    },
+    /*detach the subscribers of a failover group by its primary stream*/
+    Release {
+        primary: String,
+        result_sender: FailoverReleaseResultSender,
+    },
 
     Api {
         sender: StatisticStreamSender,
@@ -0,0 +1,510 @@
+use {
+    crate::{
+        define::{FrameData, FrameDataSender, StatisticData, StatisticDataSender, SubscribeType},
+        stream::Protocol,
+        utils::Uuid,
+    },
+    bytes::BytesMut,
+    bytesio::bytes_reader::BytesReader,
+    chrono::{DateTime, Local},
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU32, AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    },
+    tokio::sync::mpsc,
+    xflv::{
+        define::{aac_packet_type, avc_packet_type, frame_type},
+        flv_tag_header::{AudioTagHeader, VideoTagHeader},
+        Unmarshal,
+    },
+};
+
+/*milliseconds between the last frame of a source and the first frame of the next one*/
+const SWITCH_GAP: i64 = 40;
+
+/*
+ The sources of a stream: the primary stream first, then the backup streams
+ in order. The frame subscribers of the primary stream are attached to the
+ first source which is publishing and not stalled, they are switched at a key
+ frame and the timestamps continue from the previous source.
+*/
+pub struct FailoverGroup {
+    pub protocol: Protocol,
+    pub sources: Vec<String>,
+    pub stall_timeout: Duration,
+    /*the index of the source the subscribers are attached to*/
+    pub active: Option<usize>,
+    /*released from a source which unpublished, waiting for the next source*/
+    pub pending: Vec<FailoverSubscriber>,
+    pub statistics: Arc<Mutex<FailoverStatistics>>,
+    statistic_sender: Option<StatisticDataSender>,
+}
+
+impl FailoverGroup {
+    pub fn new(
+        protocol: Protocol,
+        primary: String,
+        backups: Vec<String>,
+        stall_timeout: Duration,
+    ) -> Self {
+        let mut sources = vec![primary];
+        sources.extend(backups);
+        Self {
+            protocol,
+            sources,
+            stall_timeout,
+            active: None,
+            pending: Vec::new(),
+            statistics: Arc::new(Mutex::new(FailoverStatistics::default())),
+            statistic_sender: None,
+        }
+    }
+
+    /*the sender given to the subscribers, their statistic data is relayed to
+    the active source*/
+    pub fn statistic_sender(&mut self) -> StatisticDataSender {
+        let statistics = self.statistics.clone();
+        self.statistic_sender
+            .get_or_insert_with(|| {
+                let (sender, mut receiver) = mpsc::unbounded_channel();
+                tokio::spawn(async move {
+                    while let Some(data) = receiver.recv().await {
+                        if let Ok(mut statistics) = statistics.lock() {
+                            statistics.forward(data);
+                        }
+                    }
+                });
+                sender
+            })
+            .clone()
+    }
+
+    pub fn primary(&self) -> &str {
+        &self.sources[0]
+    }
+
+    pub fn active_source(&self) -> Option<&str> {
+        self.active.map(|index| self.sources[index].as_str())
+    }
+
+    /*idle: the time since the last frame of a publishing source, None if it
+    is not published. When all the sources are stalled the active one is kept.*/
+    pub fn select<F>(&self, idle: F) -> Option<usize>
+    where
+        F: Fn(&str) -> Option<Duration>,
+    {
+        let is_published = |index: &usize| idle(&self.sources[*index]).is_some();
+        (0..self.sources.len())
+            .find(|index| idle(&self.sources[*index]).is_some_and(|idle| idle < self.stall_timeout))
+            .or_else(|| self.active.filter(is_published))
+            .or_else(|| (0..self.sources.len()).find(is_published))
+    }
+}
+
+/*the statistic data of the subscribers of a failover group, the subscribers
+are registered again to the source after a switch*/
+#[derive(Default)]
+pub struct FailoverStatistics {
+    target: Option<StatisticDataSender>,
+    subscribers: HashMap<Uuid, (String, SubscribeType, DateTime<Local>)>,
+}
+
+impl FailoverStatistics {
+    pub fn set_target(&mut self, target: StatisticDataSender) {
+        if self
+            .target
+            .as_ref()
+            .is_some_and(|sender| sender.same_channel(&target))
+        {
+            return;
+        }
+        for (id, (remote_addr, sub_type, start_time)) in &self.subscribers {
+            let statistic_subscriber = StatisticData::Subscriber {
+                id: *id,
+                remote_addr: remote_addr.clone(),
+                sub_type: sub_type.clone(),
+                start_time: *start_time,
+            };
+            if let Err(err) = target.send(statistic_subscriber) {
+                log::error!("send statistic_subscriber err: {}", err);
+            }
+        }
+        self.target = Some(target);
+    }
+
+    pub fn remove_subscriber(&mut self, id: &Uuid) {
+        self.subscribers.remove(id);
+    }
+
+    fn forward(&mut self, data: StatisticData) {
+        if let StatisticData::Subscriber {
+            id,
+            remote_addr,
+            sub_type,
+            start_time,
+        } = &data
+        {
+            self.subscribers
+                .insert(*id, (remote_addr.clone(), sub_type.clone(), *start_time));
+        }
+        /*the source may be unpublished before the next one is attached*/
+        if let Some(target) = &self.target {
+            let _ = target.send(data);
+        }
+    }
+}
+
+/*the time and the timestamp of the last frame received from a publisher*/
+pub struct StreamActivity {
+    start: Instant,
+    last: AtomicU64,
+    timestamp: AtomicU32,
+}
+
+impl Default for StreamActivity {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+            timestamp: AtomicU32::new(0),
+        }
+    }
+}
+
+impl StreamActivity {
+    pub fn touch(&self) {
+        self.last
+            .store(self.start.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_timestamp(&self, timestamp: u32) {
+        self.timestamp.store(timestamp, Ordering::Relaxed);
+    }
+
+    pub fn timestamp(&self) -> u32 {
+        self.timestamp.load(Ordering::Relaxed)
+    }
+
+    pub fn idle(&self) -> Duration {
+        self.start
+            .elapsed()
+            .saturating_sub(Duration::from_millis(self.last.load(Ordering::Relaxed)))
+    }
+}
+
+/*a frame subscriber of a failover group, which is moved between the sources*/
+#[derive(Debug)]
+pub struct FailoverSubscriber {
+    pub id: Uuid,
+    pub sub_type: SubscribeType,
+    /*the primary stream of the group*/
+    pub primary: String,
+    sender: FrameDataSender,
+    /*the largest timestamp sent to the subscriber*/
+    last_timestamp: Option<u32>,
+    /*added to the timestamps of the current source, it is set at the first key
+    frame, or at the first audio frame of a source without video*/
+    offset: Option<i64>,
+    /*the current source has sent a video frame*/
+    has_video: bool,
+}
+
+impl FailoverSubscriber {
+    pub fn new(
+        id: Uuid,
+        sub_type: SubscribeType,
+        primary: String,
+        sender: FrameDataSender,
+    ) -> Self {
+        Self {
+            id,
+            sub_type,
+            primary,
+            sender,
+            last_timestamp: None,
+            offset: None,
+            has_video: false,
+        }
+    }
+
+    /*attached to a new source, the frames are dropped until its first key frame*/
+    pub fn switch(&mut self) {
+        self.offset = None;
+        self.has_video = false;
+        if self.last_timestamp.is_some() {
+            let _ = self.sender.send(FrameData::Discontinuity);
+        }
+    }
+
+    /*a subscriber of a stream whose publisher returned, the timestamps
+    continue from the last frame of the stream*/
+    pub fn resume(&mut self, last_timestamp: u32) {
+        self.last_timestamp = Some(self.last_timestamp.unwrap_or_default().max(last_timestamp));
+        self.switch();
+    }
+
+    /*false if the subscriber is gone*/
+    pub fn send(&mut self, data: FrameData) -> bool {
+        let data = match data {
+            FrameData::Video { timestamp, data } => {
+                if self.offset.is_none() {
+                    self.has_video = true;
+                    if is_video_sequence_header(&data) {
+                        return self.send_held(FrameData::Video { timestamp, data });
+                    }
+                    if !is_key_frame(&data) {
+                        return true;
+                    }
+                    self.start(timestamp);
+                } else if !self.has_video {
+                    /*the video of a source which was started by its audio starts
+                    at a key frame*/
+                    if !is_key_frame(&data) {
+                        return true;
+                    }
+                    self.has_video = true;
+                }
+                FrameData::Video {
+                    timestamp: self.rewrite(timestamp),
+                    data,
+                }
+            }
+            FrameData::Audio { timestamp, data } => {
+                if self.offset.is_none() {
+                    if is_audio_sequence_header(&data) {
+                        return self.send_held(FrameData::Audio { timestamp, data });
+                    }
+                    /*a source without video starts at its first audio frame*/
+                    if self.has_video {
+                        return true;
+                    }
+                    self.start(timestamp);
+                }
+                FrameData::Audio {
+                    timestamp: self.rewrite(timestamp),
+                    data,
+                }
+            }
+            FrameData::MetaData { timestamp, data } => {
+                if self.offset.is_none() {
+                    return self.send_held(FrameData::MetaData { timestamp, data });
+                }
+                FrameData::MetaData {
+                    timestamp: self.rewrite(timestamp),
+                    data,
+                }
+            }
+            FrameData::MediaInfo { media_info } => FrameData::MediaInfo { media_info },
+            FrameData::Discontinuity => FrameData::Discontinuity,
+        };
+        self.sender.send(data).is_ok()
+    }
+
+    /*the first frame of the source follows the last frame sent*/
+    fn start(&mut self, timestamp: u32) {
+        self.offset = Some(match self.last_timestamp {
+            Some(last) => last as i64 + SWITCH_GAP - timestamp as i64,
+            None => 0,
+        });
+    }
+
+    /*the headers before the first key frame are sent at the last timestamp*/
+    fn send_held(&mut self, data: FrameData) -> bool {
+        let data = match (self.last_timestamp, data) {
+            (Some(last), FrameData::Video { data, .. }) => FrameData::Video {
+                timestamp: last,
+                data,
+            },
+            (Some(last), FrameData::Audio { data, .. }) => FrameData::Audio {
+                timestamp: last,
+                data,
+            },
+            (Some(last), FrameData::MetaData { data, .. }) => FrameData::MetaData {
+                timestamp: last,
+                data,
+            },
+            (_, data) => data,
+        };
+        self.sender.send(data).is_ok()
+    }
+
+    fn rewrite(&mut self, timestamp: u32) -> u32 {
+        let timestamp = (timestamp as i64 + self.offset.unwrap_or_default()).max(0) as u32;
+        self.last_timestamp = Some(self.last_timestamp.unwrap_or_default().max(timestamp));
+        timestamp
+    }
+}
+
+fn is_key_frame(data: &BytesMut) -> bool {
+    let mut reader = BytesReader::new(data.clone());
+    VideoTagHeader::unmarshal(&mut reader)
+        .is_ok_and(|header| header.frame_type == frame_type::KEY_FRAME)
+}
+
+fn is_video_sequence_header(data: &BytesMut) -> bool {
+    let mut reader = BytesReader::new(data.clone());
+    VideoTagHeader::unmarshal(&mut reader).is_ok_and(|header| {
+        header.frame_type == frame_type::KEY_FRAME
+            && header.avc_packet_type == avc_packet_type::AVC_SEQHDR
+    })
+}
+
+fn is_audio_sequence_header(data: &BytesMut) -> bool {
+    let mut reader = BytesReader::new(data.clone());
+    AudioTagHeader::unmarshal(&mut reader).is_ok_and(|header| {
+        header.sound_format == xflv::define::SoundFormat::AAC as u8
+            && header.aac_packet_type == aac_packet_type::AAC_SEQHDR
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FailoverGroup, FailoverSubscriber};
+    use crate::{define::FrameData, define::SubscribeType, stream::Protocol, utils::Uuid};
+    use bytes::BytesMut;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    fn video(timestamp: u32, flags: u8, packet_type: u8) -> FrameData {
+        FrameData::Video {
+            timestamp,
+            data: BytesMut::from(&[flags, packet_type, 0, 0, 0, 0xff][..]),
+        }
+    }
+
+    fn audio(timestamp: u32, packet_type: u8) -> FrameData {
+        FrameData::Audio {
+            timestamp,
+            data: BytesMut::from(&[0xaf, packet_type, 0x12][..]),
+        }
+    }
+
+    #[test]
+    fn test_select_source() {
+        let mut group = FailoverGroup::new(
+            Protocol::Rtmp,
+            String::from("live/show"),
+            vec![String::from("live/show_backup")],
+            Duration::from_secs(3),
+        );
+        let idle = |primary: Option<u64>, backup: Option<u64>| {
+            move |name: &str| match name {
+                "live/show" => primary.map(Duration::from_secs),
+                _ => backup.map(Duration::from_secs),
+            }
+        };
+
+        assert_eq!(group.select(idle(None, None)), None);
+        assert_eq!(group.select(idle(Some(0), Some(0))), Some(0));
+        assert_eq!(group.select(idle(Some(5), Some(0))), Some(1));
+        assert_eq!(group.select(idle(None, Some(0))), Some(1));
+        /*all stalled: the active source is kept*/
+        group.active = Some(1);
+        assert_eq!(group.select(idle(Some(5), Some(5))), Some(1));
+        assert_eq!(group.select(idle(Some(5), None)), Some(0));
+    }
+
+    #[test]
+    fn test_switch_at_key_frame() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut subscriber = FailoverSubscriber::new(
+            Uuid::default(),
+            SubscribeType::PlayerRtmp,
+            String::from("live/show"),
+            sender,
+        );
+        /*a discontinuity is received as None*/
+        let mut received = || {
+            let mut timestamps = Vec::new();
+            while let Ok(data) = receiver.try_recv() {
+                match data {
+                    FrameData::Video { timestamp, .. } | FrameData::Audio { timestamp, .. } => {
+                        timestamps.push(Some(timestamp))
+                    }
+                    FrameData::Discontinuity => timestamps.push(None),
+                    _ => {}
+                }
+            }
+            timestamps
+        };
+
+        /*the primary keeps its timestamps*/
+        assert!(subscriber.send(video(1000, 0x27, 1)));
+        subscriber.send(video(1000, 0x17, 1));
+        subscriber.send(audio(1010, 1));
+        subscriber.send(video(1040, 0x27, 1));
+        assert_eq!(received(), vec![Some(1000), Some(1010), Some(1040)]);
+
+        /*the backup starts at its key frame after the last frame*/
+        subscriber.switch();
+        subscriber.send(video(0, 0x17, 0));
+        subscriber.send(audio(0, 0));
+        subscriber.send(video(500, 0x27, 1));
+        subscriber.send(audio(510, 1));
+        subscriber.send(video(520, 0x17, 1));
+        subscriber.send(audio(530, 1));
+        assert_eq!(
+            received(),
+            vec![None, Some(1040), Some(1040), Some(1080), Some(1090)]
+        );
+
+        /*a returned publisher starts again from 0*/
+        subscriber.resume(1000);
+        subscriber.send(video(0, 0x17, 1));
+        assert_eq!(received(), vec![None, Some(1130)]);
+
+        drop(receiver);
+        assert!(!subscriber.send(video(560, 0x27, 1)));
+    }
+
+    #[test]
+    fn test_switch_to_audio_only() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut subscriber = FailoverSubscriber::new(
+            Uuid::default(),
+            SubscribeType::PlayerRtmp,
+            String::from("live/radio"),
+            sender,
+        );
+        let mut received = || {
+            let mut frames = Vec::new();
+            while let Ok(data) = receiver.try_recv() {
+                match data {
+                    FrameData::Video { timestamp, .. } => frames.push(('v', timestamp)),
+                    FrameData::Audio { timestamp, .. } => frames.push(('a', timestamp)),
+                    _ => {}
+                }
+            }
+            frames
+        };
+
+        /*an audio-only source starts after its sequence header*/
+        subscriber.send(audio(100, 0));
+        subscriber.send(audio(100, 1));
+        subscriber.send(audio(123, 1));
+        assert_eq!(received(), vec![('a', 100), ('a', 100), ('a', 123)]);
+
+        /*the next audio-only source follows it*/
+        subscriber.switch();
+        subscriber.send(audio(5000, 0));
+        subscriber.send(audio(5000, 1));
+        assert_eq!(received(), vec![('a', 123), ('a', 163)]);
+
+        /*the video of a source started by its audio waits for a key frame*/
+        subscriber.send(video(5010, 0x27, 1));
+        subscriber.send(video(5020, 0x17, 1));
+        assert_eq!(received(), vec![('v', 183)]);
+
+        /*the audio of a source with video waits for its key frame*/
+        subscriber.switch();
+        subscriber.send(video(0, 0x17, 0));
+        subscriber.send(audio(0, 1));
+        subscriber.send(video(40, 0x17, 1));
+        subscriber.send(audio(50, 1));
+        assert_eq!(received(), vec![('v', 183), ('v', 223), ('a', 233)]);
+    }
+}
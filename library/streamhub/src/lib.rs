@@ -4,18 +4,25 @@ use define::{
     StatisticDataSender,
 };
 use serde_json::{json, Value};
-use statistics::{StatisticSubscriber, StatisticsStream};
+use statistics::{StatisticSubscriber, StatisticsStream, Tracks};
 use tokio::sync::oneshot;
 use xflv::define::aac_packet_type;
 
 use crate::define::PacketData;
 
+/*the interval to check the grace periods and the stalled publishers of the
+failover groups*/
+const STREAM_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 pub mod define;
 pub mod errors;
+pub mod failover;
+pub mod lifecycle;
 pub mod notify;
 pub mod statistics;
 pub mod stream;
 pub mod utils;
+pub mod vod;
 
 use {
     crate::notify::Notifier,
@@ -26,8 +33,11 @@ use {
         TransceiverEventReceiver, TransceiverEventSender,
     },
     errors::{StreamHubError, StreamHubErrorValue},
+    failover::{FailoverGroup, FailoverSubscriber, StreamActivity},
+    lifecycle::{StreamLifecycle, StreamState},
     std::collections::HashMap,
     std::sync::Arc,
+    std::time::{Duration, Instant},
     tokio::sync::{broadcast, mpsc, mpsc::UnboundedReceiver, Mutex},
     utils::Uuid,
 };
@@ -43,6 +53,10 @@ pub struct StreamDataTransceiver {
     id_to_frame_sender: Arc<Mutex<HashMap<Uuid, FrameDataSender>>>,
     //used for sending audio/video packet data to players/subscribers
     id_to_packet_sender: Arc<Mutex<HashMap<Uuid, PacketDataSender>>>,
+    //used for sending audio/video frame data to the subscribers of failover groups
+    id_to_failover_subscriber: Arc<Mutex<HashMap<Uuid, FailoverSubscriber>>>,
+    //the time of the last data received from the publisher
+    activity: Arc<StreamActivity>,
     //publisher and subscribers use this sender to submit statistical data
     statistic_data_sender: StatisticDataSender,
     //used for receiving statistical data from publishers and subscribers
@@ -51,6 +65,8 @@ pub struct StreamDataTransceiver {
     statistic_data: Arc<Mutex<StatisticsStream>>,
     //a hander implement by protocols, such as rtmp, webrtc, http-flv, hls
     stream_handler: Arc<dyn TStreamHandler>,
+    //the stream name, the primary of the subscribers resumed after a reconnect
+    name: String,
 }
 
 impl StreamDataTransceiver {
@@ -69,16 +85,26 @@ impl StreamDataTransceiver {
             statistic_data_receiver,
             id_to_frame_sender: Arc::new(Mutex::new(HashMap::new())),
             id_to_packet_sender: Arc::new(Mutex::new(HashMap::new())),
+            id_to_failover_subscriber: Arc::new(Mutex::new(HashMap::new())),
+            activity: Arc::new(StreamActivity::default()),
             stream_handler: h,
-            statistic_data: Arc::new(Mutex::new(StatisticsStream::new(protocol, name))),
+            statistic_data: Arc::new(Mutex::new(StatisticsStream::new(protocol, name.clone()))),
+            name,
         }
     }
 
     async fn receive_frame_data(
         data: Option<FrameData>,
         frame_senders: &Arc<Mutex<HashMap<Uuid, FrameDataSender>>>,
+        failover_subscribers: &Arc<Mutex<HashMap<Uuid, FailoverSubscriber>>>,
     ) {
         if let Some(val) = data {
+            if !matches!(val, FrameData::MetaData { .. }) {
+                failover_subscribers
+                    .lock()
+                    .await
+                    .retain(|_, subscriber| subscriber.send(val.clone()));
+            }
             match val {
                 FrameData::MetaData {
                     timestamp: _,
@@ -111,6 +137,8 @@ impl StreamDataTransceiver {
                         }
                     }
                 }
+                /*only sent to the subscribers directly*/
+                FrameData::Discontinuity => {}
                 FrameData::MediaInfo {
                     media_info: info_value,
                 } => {
@@ -133,12 +161,23 @@ impl StreamDataTransceiver {
         mut exit: broadcast::Receiver<()>,
         mut receiver: FrameDataReceiver,
         frame_senders: Arc<Mutex<HashMap<Uuid, FrameDataSender>>>,
+        failover_subscribers: Arc<Mutex<HashMap<Uuid, FailoverSubscriber>>>,
+        activity: Arc<StreamActivity>,
     ) {
         tokio::spawn(async move {
             loop {
                 tokio::select! {
                     data = receiver.recv() => {
-                       Self::receive_frame_data(data, &frame_senders).await;
+                       match &data {
+                           Some(FrameData::Video { timestamp, .. } | FrameData::Audio { timestamp, .. }) => {
+                               activity.touch();
+                               activity.set_timestamp(*timestamp);
+                           }
+                           Some(_) => activity.touch(),
+                           /*the publisher is gone*/
+                           None => break,
+                       }
+                       Self::receive_frame_data(data, &frame_senders, &failover_subscribers).await;
                     }
                     _ = exit.recv()=>{
                         break;
@@ -189,11 +228,16 @@ impl StreamDataTransceiver {
         mut exit: broadcast::Receiver<()>,
         mut receiver: PacketDataReceiver,
         packet_senders: Arc<Mutex<HashMap<Uuid, PacketDataSender>>>,
+        activity: Arc<StreamActivity>,
     ) {
         tokio::spawn(async move {
             loop {
                 tokio::select! {
                     data = receiver.recv() => {
+                       if data.is_none() {
+                           break;
+                       }
+                       activity.touch();
                        Self::receive_packet_data(data, &packet_senders).await;
                     }
                     _ = exit.recv()=>{
@@ -359,14 +403,18 @@ impl StreamDataTransceiver {
         });
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn receive_event_loop(
-        stream_handler: Arc<dyn TStreamHandler>,
+        name: String,
+        mut stream_handler: Arc<dyn TStreamHandler>,
         exit: broadcast::Sender<()>,
         mut receiver: TransceiverEventReceiver,
         packet_senders: Arc<Mutex<HashMap<Uuid, PacketDataSender>>>,
         frame_senders: Arc<Mutex<HashMap<Uuid, FrameDataSender>>>,
+        failover_subscribers: Arc<Mutex<HashMap<Uuid, FailoverSubscriber>>>,
         statistic_sender: StatisticDataSender,
         statistics_data: Arc<Mutex<StatisticsStream>>,
+        activity: Arc<StreamActivity>,
     ) {
         tokio::spawn(async move {
             loop {
@@ -410,6 +458,7 @@ impl StreamDataTransceiver {
                         TransceiverEvent::UnSubscribe { info } => {
                             // Remove from frame_senders (assuming it's a HashMap or similar)
                             frame_senders.lock().await.remove(&info.id);
+                            failover_subscribers.lock().await.remove(&info.id);
 
                             // Lock and access statistics_data
                             let mut statistics_data = statistics_data.lock().await;
@@ -432,6 +481,117 @@ impl StreamDataTransceiver {
                             }
                             break;
                         }
+                        TransceiverEvent::Adopt {
+                            subscribers,
+                            result_sender,
+                        } => {
+                            /*the frames are not sent until the prior data is sent*/
+                            let mut failover_subscribers = failover_subscribers.lock().await;
+                            let count = subscribers.len();
+                            for mut subscriber in subscribers {
+                                subscriber.switch();
+                                let (sender, mut receiver) = mpsc::unbounded_channel();
+                                if let Err(err) = stream_handler
+                                    .send_prior_data(
+                                        DataSender::Frame { sender },
+                                        subscriber.sub_type.clone(),
+                                    )
+                                    .await
+                                {
+                                    log::error!("receive_event_loop send_prior_data err: {}", err);
+                                }
+                                while let Ok(data) = receiver.try_recv() {
+                                    subscriber.send(data);
+                                }
+                                failover_subscribers.insert(subscriber.id, subscriber);
+                            }
+
+                            if let Err(err) = result_sender.send(statistic_sender.clone()) {
+                                log::error!(
+                                    "receive_event_loop:send statistic send err :{:?} ",
+                                    err
+                                )
+                            }
+                            statistics_data.lock().await.subscriber_count += count;
+                        }
+                        TransceiverEvent::Resume {
+                            receiver,
+                            stream_handler: handler,
+                            result_sender,
+                        } => {
+                            stream_handler = handler;
+
+                            /*the subscribers continue from the last frame of the
+                            previous publisher at the first key frame*/
+                            let last_timestamp = activity.timestamp();
+                            let mut subscribers = failover_subscribers.lock().await;
+                            for subscriber in subscribers.values_mut() {
+                                subscriber.resume(last_timestamp);
+                            }
+                            let statistics_subscribers = statistics_data.lock().await.subscribers.clone();
+                            for (id, sender) in frame_senders.lock().await.drain() {
+                                let sub_type = statistics_subscribers
+                                    .iter()
+                                    .find(|s| s.id == id)
+                                    .map(|s| s.sub_type.clone())
+                                    .unwrap_or_default();
+                                let mut subscriber =
+                                    FailoverSubscriber::new(id, sub_type, name.clone(), sender);
+                                subscriber.resume(last_timestamp);
+                                subscribers.insert(id, subscriber);
+                            }
+                            drop(subscribers);
+
+                            if let Some(receiver) = receiver.frame_receiver {
+                                Self::receive_frame_data_loop(
+                                    exit.subscribe(),
+                                    receiver,
+                                    frame_senders.clone(),
+                                    failover_subscribers.clone(),
+                                    activity.clone(),
+                                )
+                                .await;
+                            }
+                            if let Some(receiver) = receiver.packet_receiver {
+                                Self::receive_packet_data_loop(
+                                    exit.subscribe(),
+                                    receiver,
+                                    packet_senders.clone(),
+                                    activity.clone(),
+                                )
+                                .await;
+                            }
+
+                            if let Err(err) = result_sender.send(statistic_sender.clone()) {
+                                log::error!(
+                                    "receive_event_loop:send statistic send err :{:?} ",
+                                    err
+                                )
+                            }
+                        }
+                        TransceiverEvent::Release {
+                            primary,
+                            result_sender,
+                        } => {
+                            let mut failover_subscribers = failover_subscribers.lock().await;
+                            let ids: Vec<Uuid> = failover_subscribers
+                                .values()
+                                .filter(|subscriber| subscriber.primary == primary)
+                                .map(|subscriber| subscriber.id)
+                                .collect();
+                            let subscribers = ids
+                                .iter()
+                                .filter_map(|id| failover_subscribers.remove(id))
+                                .collect();
+
+                            let mut statistics_data = statistics_data.lock().await;
+                            statistics_data.subscribers.retain(|s| !ids.contains(&s.id));
+                            statistics_data.subscriber_count =
+                                statistics_data.subscriber_count.saturating_sub(ids.len());
+                            if result_sender.send(subscribers).is_err() {
+                                log::error!("receive_event_loop release error: The receiver dropped.");
+                            }
+                        }
                         TransceiverEvent::Api { sender, uuid } => {
                             log::info!("api:  stream identifier: {:?}", uuid);
                             let statistic_data = if let Some(uid) = uuid {
@@ -462,6 +622,8 @@ impl StreamDataTransceiver {
                 tx.subscribe(),
                 receiver,
                 self.id_to_frame_sender.clone(),
+                self.id_to_failover_subscriber.clone(),
+                self.activity.clone(),
             )
             .await;
         }
@@ -471,6 +633,7 @@ impl StreamDataTransceiver {
                 tx.subscribe(),
                 receiver,
                 self.id_to_packet_sender.clone(),
+                self.activity.clone(),
             )
             .await;
         }
@@ -484,13 +647,16 @@ impl StreamDataTransceiver {
         .await;
 
         Self::receive_event_loop(
+            self.name,
             self.stream_handler,
             tx,
             self.event_receiver,
             self.id_to_packet_sender,
             self.id_to_frame_sender,
+            self.id_to_failover_subscriber,
             self.statistic_data_sender,
             self.statistic_data.clone(),
+            self.activity,
         )
         .await;
 
@@ -500,6 +666,10 @@ impl StreamDataTransceiver {
     pub fn get_statistics_data_sender(&self) -> StatisticDataSender {
         self.statistic_data_sender.clone()
     }
+
+    pub fn get_activity(&self) -> Arc<StreamActivity> {
+        self.activity.clone()
+    }
 }
 
 pub struct StreamsHub {
@@ -523,6 +693,14 @@ pub struct StreamsHub {
     hls_enabled: bool,
     //http notifier on sub/pub event
     notifier: Option<Arc<dyn Notifier>>,
+    //the backup sources of the streams, keyed by the primary stream
+    failover_groups: HashMap<(Protocol, String), FailoverGroup>,
+    //used for detecting the stalled publishers of the failover groups
+    activities: HashMap<(Protocol, String), Arc<StreamActivity>>,
+    //the states of the published streams
+    lifecycles: HashMap<(Protocol, String), StreamLifecycle>,
+    //the subscribers are kept for the period after the publisher is gone
+    grace_period: Duration,
 }
 
 impl StreamsHub {
@@ -541,6 +719,10 @@ impl StreamsHub {
             rtmp_remuxer_enabled: false,
             hls_enabled: false,
             notifier,
+            failover_groups: HashMap::new(),
+            activities: HashMap::new(),
+            lifecycles: HashMap::new(),
+            grace_period: Duration::ZERO,
         }
     }
     pub async fn run(&mut self) {
@@ -563,6 +745,23 @@ impl StreamsHub {
         self.hls_enabled = enabled;
     }
 
+    pub fn set_grace_period(&mut self, grace_period: Duration) {
+        self.grace_period = grace_period;
+    }
+
+    /*the frame subscribers of the primary stream are switched to the first
+    backup which is publishing when the primary stalls or unpublishes*/
+    pub fn add_failover_group(
+        &mut self,
+        protocol: Protocol,
+        primary: String,
+        backups: Vec<String>,
+        stall_timeout: Duration,
+    ) {
+        let group = FailoverGroup::new(protocol.clone(), primary.clone(), backups, stall_timeout);
+        self.failover_groups.insert((protocol, primary), group);
+    }
+
     pub fn get_hub_event_sender(&mut self) -> StreamHubEventSender {
         self.hub_event_sender.clone()
     }
@@ -572,7 +771,19 @@ impl StreamsHub {
     }
 
     pub async fn event_loop(&mut self) {
-        while let Some(event) = self.hub_event_receiver.recv().await {
+        let mut check_interval = tokio::time::interval(STREAM_CHECK_INTERVAL);
+        loop {
+            let event = tokio::select! {
+                event = self.hub_event_receiver.recv() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+                _ = check_interval.tick() => {
+                    self.check_lifecycles().await;
+                    self.check_failover().await;
+                    continue;
+                }
+            };
             let message = event.to_message();
             match event {
                 StreamHubEvent::Publish {
@@ -632,6 +843,7 @@ impl StreamsHub {
                             }
                             self.un_pub_sub_events
                                 .insert(info.id, StreamHubEvent::UnPublish { protocol, name, info });
+                            self.check_failover().await;
 
                             Ok((frame_sender, packet_sender, Some(statistic_data_sender)))
                         }
@@ -651,7 +863,7 @@ impl StreamsHub {
                     name,
                     info: _,
                 } => {
-                    if let Err(err) = self.unpublish(protocol.clone(), name.clone()) {
+                    if let Err(err) = self.unpublish(protocol.clone(), name.clone()).await {
                         log::error!(
                             "event_loop Unpublish err: {} with identifier: {}-{}",
                             err,
@@ -659,6 +871,7 @@ impl StreamsHub {
                             name
                         );
                     }
+                    self.check_failover().await;
 
                     if let Some(notifier) = &self.notifier {
                         notifier.on_unpublish_notify(&message).await;
@@ -746,12 +959,37 @@ impl StreamsHub {
                         log::error!("event_loop api error: {}", err);
                     }
                 }
+                StreamHubEvent::ApiTracks {
+                    protocol,
+                    name,
+                    result_sender,
+                } => {
+                    let tracks = self.api_tracks(protocol, name).await;
+                    if result_sender.send(tracks).is_err() {
+                        log::error!("event_loop api tracks error: The receiver dropped.");
+                    }
+                }
+                StreamHubEvent::RecordStart { .. } => {
+                    if let Some(notifier) = &self.notifier {
+                        notifier.on_record_start_notify(&message).await;
+                    }
+                }
+                StreamHubEvent::RecordStop { .. } => {
+                    if let Some(notifier) = &self.notifier {
+                        notifier.on_record_stop_notify(&message).await;
+                    }
+                }
                 StreamHubEvent::ApiKickClient { id } => {
                     println!("ERR {:?}", self.api_kick_off_client(id));
                     if let Err(err) =self.api_kick_off_client(id) {
                         log::error!("api_kick_off_client api error: {}", err);
                     }
                 }
+                StreamHubEvent::ApiKickPublisher { name, publish_key } => {
+                    if let Err(err) = self.api_kick_off_publisher(&name, &publish_key) {
+                        log::error!("api_kick_off_publisher api error: {}", err);
+                    }
+                }
                 StreamHubEvent::Request { protocol, name, sender  } => {
                     if let Err(err) = self.request(protocol,name, sender) {
                         log::error!("event_loop request error: {}", err);
@@ -841,6 +1079,21 @@ impl StreamsHub {
         Ok(serde_json::to_value(data)?)
     }
 
+    async fn api_tracks(&mut self, protocol: Protocol, name: String) -> Option<Tracks> {
+        let event_sender = self.streams.get(&(protocol, name))?;
+        let (stream_sender, mut stream_receiver) = mpsc::unbounded_channel();
+        event_sender
+            .send(TransceiverEvent::Api {
+                sender: stream_sender,
+                uuid: None,
+            })
+            .ok()?;
+        stream_receiver
+            .recv()
+            .await
+            .map(|stream_statistics| stream_statistics.publisher.tracks)
+    }
+
     fn api_kick_off_client(&mut self, uid: Uuid) -> Result<(), StreamHubError> {
         if let Some(event) = self.un_pub_sub_events.get(&uid) {
             match event {
@@ -885,6 +1138,34 @@ impl StreamsHub {
         });
     }
 
+    /*a revoked or rotated publish key no longer authorizes the publisher which
+    is already connected with it*/
+    fn api_kick_off_publisher(&mut self, stream_name: &str, label: &str) -> Result<(), StreamHubError> {
+        let event = self.un_pub_sub_events.values().find(|event| {
+            matches!(event, StreamHubEvent::UnPublish { name, info, .. }
+                if name == stream_name && info.publish_key.as_deref() == Some(label))
+        });
+
+        if let Some(StreamHubEvent::UnPublish {
+            protocol,
+            name,
+            info,
+        }) = event
+        {
+            log::info!("kick off the publisher of {} by the publish key {}", name, label);
+            self.hub_event_sender
+                .send(StreamHubEvent::UnPublish {
+                    protocol: protocol.clone(),
+                    name: name.clone(),
+                    info: info.clone(),
+                })
+                .map_err(|_| StreamHubError {
+                    value: StreamHubErrorValue::SendError,
+                })?;
+        }
+        Ok(())
+    }
+
     //player subscribe a stream
     pub async fn subscribe(
         &mut self,
@@ -893,6 +1174,31 @@ impl StreamsHub {
         sub_info: SubscriberInfo,
         sender: DataSender,
     ) -> Result<StatisticDataSender, StreamHubError> {
+        if let DataSender::Frame { sender: frame_sender } = &sender {
+            if let Some(source) = self.failover_source(&protocol, &name) {
+                if let Some(event_sender) = self.streams.get(&(protocol.clone(), source.clone())) {
+                    let (result_sender, result_receiver) = oneshot::channel();
+                    let subscriber = FailoverSubscriber::new(
+                        sub_info.id,
+                        sub_info.sub_type.clone(),
+                        name.clone(),
+                        frame_sender.clone(),
+                    );
+                    let event = TransceiverEvent::Adopt {
+                        subscribers: vec![subscriber],
+                        result_sender,
+                    };
+                    log::info!("subscribe:  stream identifier: {}-{} from {}", protocol, name, source);
+                    event_sender.send(event).map_err(|_| StreamHubError {
+                        value: StreamHubErrorValue::SendError,
+                    })?;
+
+                    let statistic_data_sender = result_receiver.await?;
+                    return Ok(self.relay_failover_statistics(&(protocol, name), statistic_data_sender));
+                }
+            }
+        }
+
         if let Some(event_sender) = self.streams.get_mut(&(protocol.clone(), name.clone())) {
             let (result_sender, result_receiver) = oneshot::channel();
             let event = TransceiverEvent::Subscribe {
@@ -935,6 +1241,18 @@ impl StreamsHub {
         name: String,
         sub_info: SubscriberInfo,
     ) -> Result<(), StreamHubError> {
+        /*the frame subscribers of a failover group are attached to the active source*/
+        let name = match sub_info.sub_data_type {
+            define::SubDataType::Frame => {
+                if let Some(group) = self.failover_groups.get(&(protocol.clone(), name.clone())) {
+                    if let Ok(mut statistics) = group.statistics.lock() {
+                        statistics.remove_subscriber(&sub_info.id);
+                    }
+                }
+                self.failover_source(&protocol, &name).unwrap_or(name)
+            }
+            define::SubDataType::Packet => name,
+        };
         match self.streams.get_mut(&(protocol.clone(), name.clone())) {
             Some(producer) => {
                 log::info!("unsubscribe....:{}-{}", protocol, name);
@@ -962,6 +1280,12 @@ impl StreamsHub {
         receiver: DataReceiver,
         handler: Arc<dyn TStreamHandler>,
     ) -> Result<StatisticDataSender, StreamHubError> {
+        if let Some(lifecycle) = self.lifecycles.get_mut(&(protocol.clone(), name.clone())) {
+            if lifecycle.on_publish() {
+                return self.resume(protocol, name, receiver, handler).await;
+            }
+        }
+
         if self.streams.get(&(protocol.clone(), name.clone())).is_some() {
             return Err(StreamHubError {
                 value: StreamHubErrorValue::Exists,
@@ -973,6 +1297,12 @@ impl StreamsHub {
             StreamDataTransceiver::new(protocol.clone(), name.clone(), receiver, event_receiver, handler);
 
         let statistic_data_sender = transceiver.get_statistics_data_sender();
+        self.activities
+            .insert((protocol.clone(), name.clone()), transceiver.get_activity());
+        self.lifecycles.insert(
+            (protocol.clone(), name.clone()),
+            StreamLifecycle::new(self.grace_period),
+        );
 
 
         if let Err(err) = transceiver.run().await {
@@ -988,6 +1318,51 @@ impl StreamsHub {
 
         self.streams.insert((protocol.clone(),name.clone()), event_sender);
 
+        /*the subscribers of a failover group are still attached to a backup
+        and they will be switched back*/
+        let is_failover_active = self
+            .failover_groups
+            .get(&(protocol.clone(), name.clone()))
+            .is_some_and(|group| group.active.is_some());
+        if !is_failover_active {
+            self.broadcast_publish(protocol, name)?;
+        }
+
+        Ok(statistic_data_sender)
+    }
+
+    /*the publisher returned within the grace period, the subscribers are still
+    attached and the publish is not broadcasted again*/
+    async fn resume(
+        &mut self,
+        protocol: Protocol,
+        name: String,
+        receiver: DataReceiver,
+        handler: Arc<dyn TStreamHandler>,
+    ) -> Result<StatisticDataSender, StreamHubError> {
+        let event_sender = match self.streams.get(&(protocol.clone(), name.clone())) {
+            Some(event_sender) => event_sender,
+            None => {
+                return Err(StreamHubError {
+                    value: StreamHubErrorValue::NoAppOrStreamName,
+                })
+            }
+        };
+        let (result_sender, result_receiver) = oneshot::channel();
+        let event = TransceiverEvent::Resume {
+            receiver,
+            stream_handler: handler,
+            result_sender,
+        };
+        event_sender.send(event).map_err(|_| StreamHubError {
+            value: StreamHubErrorValue::SendError,
+        })?;
+        log::info!("resume stream, stream identifier: {}-{}", protocol, name);
+
+        Ok(result_receiver.await?)
+    }
+
+    fn broadcast_publish(&mut self, protocol: Protocol, name: String) -> Result<(), StreamHubError> {
         if self.rtmp_push_enabled || self.hls_enabled || self.rtmp_remuxer_enabled {
             let client_event = BroadcastEvent::Publish { protocol, name };
 
@@ -998,11 +1373,75 @@ impl StreamsHub {
                     value: StreamHubErrorValue::SendError,
                 })?;
         }
+        Ok(())
+    }
 
-        Ok(statistic_data_sender)
+    async fn unpublish(&mut self, protocol: Protocol, name: String) -> Result<(), StreamHubError> {
+        let state = match self.lifecycles.get_mut(&(protocol.clone(), name.clone())) {
+            Some(lifecycle) => lifecycle.on_unpublish(Instant::now()),
+            None => StreamState::Ended,
+        };
+        if state == StreamState::Reconnecting {
+            log::info!(
+                "the publisher is gone, wait {:?} for it to reconnect, stream identifier: {}-{}",
+                self.grace_period,
+                protocol,
+                name
+            );
+            return Ok(());
+        }
+        self.end_stream(protocol, name).await
     }
 
-    fn unpublish(&mut self, protocol: Protocol, name: String) -> Result<(), StreamHubError> {
+    /*ends the streams whose grace period expired*/
+    async fn check_lifecycles(&mut self) {
+        let now = Instant::now();
+        let ended: Vec<(Protocol, String)> = self
+            .lifecycles
+            .iter_mut()
+            .filter(|(_, lifecycle)| lifecycle.state() == StreamState::Reconnecting)
+            .filter_map(|(key, lifecycle)| {
+                (lifecycle.check(now) == StreamState::Ended).then(|| key.clone())
+            })
+            .collect();
+        for (protocol, name) in ended {
+            log::info!("the publisher did not reconnect, stream identifier: {}-{}", protocol, name);
+            if let Err(err) = self.end_stream(protocol, name).await {
+                log::error!("end stream error: {}", err);
+            }
+        }
+    }
+
+    async fn end_stream(&mut self, protocol: Protocol, name: String) -> Result<(), StreamHubError> {
+        /*keep the subscribers of the failover groups which are attached to the
+        stream, they are switched to the next source*/
+        let primaries: Vec<String> = self
+            .failover_groups
+            .values()
+            .filter(|group| group.protocol == protocol && group.active_source() == Some(&name))
+            .map(|group| group.primary().to_string())
+            .collect();
+        for primary in primaries {
+            /*the stream is removed even if its subscribers are lost*/
+            match self.release_failover_subscribers(&protocol, &name, &primary).await {
+                Ok(subscribers) => {
+                    if let Some(group) = self.failover_groups.get_mut(&(protocol.clone(), primary)) {
+                        group.pending = subscribers;
+                    }
+                }
+                Err(err) => {
+                    log::error!(
+                        "release the failover subscribers of {}-{} error: {}",
+                        protocol,
+                        primary,
+                        err
+                    );
+                }
+            }
+        }
+        self.activities.remove(&(protocol.clone(), name.clone()));
+        self.lifecycles.remove(&(protocol.clone(), name.clone()));
+
         match self.streams.get_mut(&(protocol.clone(), name.clone())) {
             Some(producer) => {
                 let event = TransceiverEvent::UnPublish {};
@@ -1021,4 +1460,351 @@ impl StreamsHub {
 
         Ok(())
     }
+
+    /*the source which the frame subscribers of a failover group are attached to*/
+    fn failover_source(&self, protocol: &Protocol, name: &str) -> Option<String> {
+        self.failover_groups
+            .get(&(protocol.clone(), name.to_string()))
+            .and_then(|group| group.active_source())
+            .map(|source| source.to_string())
+    }
+
+    fn relay_failover_statistics(
+        &mut self,
+        key: &(Protocol, String),
+        statistic_data_sender: StatisticDataSender,
+    ) -> StatisticDataSender {
+        match self.failover_groups.get_mut(key) {
+            Some(group) => {
+                if let Ok(mut statistics) = group.statistics.lock() {
+                    statistics.set_target(statistic_data_sender);
+                }
+                group.statistic_sender()
+            }
+            None => statistic_data_sender,
+        }
+    }
+
+    async fn release_failover_subscribers(
+        &mut self,
+        protocol: &Protocol,
+        source: &str,
+        primary: &str,
+    ) -> Result<Vec<FailoverSubscriber>, StreamHubError> {
+        let event_sender = match self.streams.get(&(protocol.clone(), source.to_string())) {
+            Some(event_sender) => event_sender,
+            None => return Ok(Vec::new()),
+        };
+        let (result_sender, result_receiver) = oneshot::channel();
+        let event = TransceiverEvent::Release {
+            primary: primary.to_string(),
+            result_sender,
+        };
+        event_sender.send(event).map_err(|_| StreamHubError {
+            value: StreamHubErrorValue::SendError,
+        })?;
+        Ok(result_receiver.await?)
+    }
+
+    async fn check_failover(&mut self) {
+        let keys: Vec<(Protocol, String)> = self.failover_groups.keys().cloned().collect();
+        for key in keys {
+            if let Err(err) = self.switch_failover_source(&key).await {
+                log::error!("failover of {}-{} error: {}", key.0, key.1, err);
+            }
+        }
+    }
+
+    /*move the subscribers of a failover group to the selected source*/
+    async fn switch_failover_source(
+        &mut self,
+        key: &(Protocol, String),
+    ) -> Result<(), StreamHubError> {
+        let (protocol, primary) = key;
+        let (selected, active) = match self.failover_groups.get(key) {
+            Some(group) => {
+                /*a reconnecting source is kept if no other source is publishing*/
+                let selected = group.select(|name| {
+                    let key = (protocol.clone(), name.to_string());
+                    match self.lifecycles.get(&key).map(|lifecycle| lifecycle.state()) {
+                        Some(StreamState::Reconnecting) => Some(Duration::MAX),
+                        _ => self.activities.get(&key).map(|activity| activity.idle()),
+                    }
+                });
+                (selected, group.active)
+            }
+            None => return Ok(()),
+        };
+        if selected == active {
+            return Ok(());
+        }
+
+        let mut subscribers = match self.failover_groups[key].active_source() {
+            Some(source) => {
+                let source = source.to_string();
+                self.release_failover_subscribers(protocol, &source, primary)
+                    .await?
+            }
+            None => Vec::new(),
+        };
+
+        let group = match self.failover_groups.get_mut(key) {
+            Some(group) => group,
+            None => return Ok(()),
+        };
+        subscribers.append(&mut group.pending);
+        group.active = selected;
+
+        let source = match selected {
+            Some(index) => group.sources[index].clone(),
+            None => {
+                log::warn!(
+                    "failover: no source of {}-{} is publishing, {} subscribers are dropped",
+                    protocol,
+                    primary,
+                    subscribers.len()
+                );
+                return Ok(());
+            }
+        };
+        log::info!("failover: switch {}-{} to {}", protocol, primary, source);
+
+        if let Some(event_sender) = self.streams.get(&(protocol.clone(), source.clone())) {
+            let (result_sender, result_receiver) = oneshot::channel();
+            let event = TransceiverEvent::Adopt {
+                subscribers,
+                result_sender,
+            };
+            event_sender.send(event).map_err(|_| StreamHubError {
+                value: StreamHubErrorValue::SendError,
+            })?;
+            let statistic_data_sender = result_receiver.await?;
+            self.relay_failover_statistics(key, statistic_data_sender);
+        }
+
+        /*the publish of the primary stream is broadcasted once when the group
+        becomes active from a backup*/
+        if active.is_none() && selected != Some(0) {
+            self.broadcast_publish(protocol.clone(), primary.clone())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamsHub;
+    use crate::{
+        define::{
+            DataSender, FrameData, FrameDataReceiver, FrameDataSender, InformationSender,
+            NotifyInfo, PubDataType, PublishType, PublisherInfo, StreamHubEvent,
+            StreamHubEventSender, SubDataType, SubscribeType, SubscriberInfo, TStreamHandler,
+        },
+        errors::StreamHubError,
+        statistics::StatisticsStream,
+        stream::Protocol,
+        utils::{RandomDigitCount, Uuid},
+    };
+    use async_trait::async_trait;
+    use bytes::BytesMut;
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::oneshot;
+
+    struct StreamHandler;
+
+    #[async_trait]
+    impl TStreamHandler for StreamHandler {
+        async fn send_prior_data(
+            &self,
+            _sender: DataSender,
+            _sub_type: SubscribeType,
+        ) -> Result<(), StreamHubError> {
+            Ok(())
+        }
+        async fn get_statistic_data(&self) -> Option<StatisticsStream> {
+            None
+        }
+        async fn send_information(&self, _sender: InformationSender) {}
+    }
+
+    fn notify_info() -> NotifyInfo {
+        NotifyInfo {
+            request_url: String::from(""),
+            remote_addr: String::from(""),
+        }
+    }
+
+    async fn try_publish(
+        event_producer: &StreamHubEventSender,
+        name: &str,
+        publish_key: Option<&str>,
+    ) -> Result<(FrameDataSender, PublisherInfo), StreamHubError> {
+        let info = PublisherInfo {
+            id: Uuid::new(RandomDigitCount::Four),
+            pub_type: PublishType::PushRtmp,
+            pub_data_type: PubDataType::Frame,
+            notify_info: notify_info(),
+            publish_key: publish_key.map(String::from),
+        };
+        let (result_sender, result_receiver) = oneshot::channel();
+        let event = StreamHubEvent::Publish {
+            protocol: Protocol::Rtmp,
+            name: name.to_string(),
+            info: info.clone(),
+            result_sender,
+            stream_handler: Arc::new(StreamHandler),
+        };
+        assert!(event_producer.send(event).is_ok());
+        let (frame_sender, _, _) = result_receiver.await.unwrap()?;
+        Ok((frame_sender.unwrap(), info))
+    }
+
+    async fn publish(
+        event_producer: &StreamHubEventSender,
+        name: &str,
+    ) -> (FrameDataSender, PublisherInfo) {
+        try_publish(event_producer, name, None).await.unwrap()
+    }
+
+    fn unpublish(event_producer: &StreamHubEventSender, name: &str, info: PublisherInfo) {
+        let event = StreamHubEvent::UnPublish {
+            protocol: Protocol::Rtmp,
+            name: name.to_string(),
+            info,
+        };
+        assert!(event_producer.send(event).is_ok());
+    }
+
+    async fn subscribe(event_producer: &StreamHubEventSender, name: &str) -> FrameDataReceiver {
+        let (result_sender, result_receiver) = oneshot::channel();
+        let event = StreamHubEvent::Subscribe {
+            protocol: Protocol::Rtmp,
+            name: name.to_string(),
+            info: SubscriberInfo {
+                id: Uuid::new(RandomDigitCount::Four),
+                sub_type: SubscribeType::PlayerRtmp,
+                sub_data_type: SubDataType::Frame,
+                notify_info: notify_info(),
+            },
+            result_sender,
+        };
+        assert!(event_producer.send(event).is_ok());
+        result_receiver
+            .await
+            .unwrap()
+            .unwrap()
+            .0
+            .frame_receiver
+            .unwrap()
+    }
+
+    fn key_frame(timestamp: u32) -> FrameData {
+        FrameData::Video {
+            timestamp,
+            data: BytesMut::from(&[0x17, 0x01, 0, 0, 0, 0xff][..]),
+        }
+    }
+
+    /*the timestamps of the video frames received, None for a discontinuity*/
+    async fn received(receiver: &mut FrameDataReceiver) -> Vec<Option<u32>> {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut timestamps = Vec::new();
+        while let Ok(data) = receiver.try_recv() {
+            match data {
+                FrameData::Video { timestamp, .. } => timestamps.push(Some(timestamp)),
+                FrameData::Discontinuity => timestamps.push(None),
+                _ => {}
+            }
+        }
+        timestamps
+    }
+
+    #[tokio::test]
+    async fn test_failover_to_backup() {
+        let mut stream_hub = StreamsHub::new(None);
+        stream_hub.add_failover_group(
+            Protocol::Rtmp,
+            String::from("live/show"),
+            vec![String::from("live/show_backup")],
+            Duration::from_secs(10),
+        );
+        let event_producer = stream_hub.get_hub_event_sender();
+        tokio::spawn(async move { stream_hub.run().await });
+
+        let (primary, primary_info) = publish(&event_producer, "live/show").await;
+        let (backup, _) = publish(&event_producer, "live/show_backup").await;
+        let mut receiver = subscribe(&event_producer, "live/show").await;
+
+        assert!(primary.send(key_frame(1000)).is_ok());
+        assert!(backup.send(key_frame(7000)).is_ok());
+        assert_eq!(received(&mut receiver).await, vec![Some(1000)]);
+
+        /*the subscriber is moved to the backup, its timestamps continue*/
+        unpublish(&event_producer, "live/show", primary_info);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(backup.send(key_frame(7040)).is_ok());
+        assert!(backup.send(key_frame(7080)).is_ok());
+        assert_eq!(
+            received(&mut receiver).await,
+            vec![None, Some(1040), Some(1080)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resume_within_grace_period() {
+        let mut stream_hub = StreamsHub::new(None);
+        stream_hub.set_grace_period(Duration::from_secs(10));
+        let event_producer = stream_hub.get_hub_event_sender();
+        tokio::spawn(async move { stream_hub.run().await });
+
+        let (publisher, publisher_info) = publish(&event_producer, "live/show").await;
+        let mut receiver = subscribe(&event_producer, "live/show").await;
+        assert!(publisher.send(key_frame(1000)).is_ok());
+        assert_eq!(received(&mut receiver).await, vec![Some(1000)]);
+
+        /*the encoder reconnects and restarts its timestamps, the subscriber
+        stays attached and its timestamps continue*/
+        unpublish(&event_producer, "live/show", publisher_info);
+        drop(publisher);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (publisher, _) = publish(&event_producer, "live/show").await;
+        assert!(publisher.send(key_frame(0)).is_ok());
+        assert!(publisher.send(key_frame(40)).is_ok());
+        assert_eq!(
+            received(&mut receiver).await,
+            vec![None, Some(1040), Some(1080)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kick_publisher_by_key() {
+        let mut stream_hub = StreamsHub::new(None);
+        let event_producer = stream_hub.get_hub_event_sender();
+        tokio::spawn(async move { stream_hub.run().await });
+
+        let kick = |publish_key: &str| {
+            let event = StreamHubEvent::ApiKickPublisher {
+                name: String::from("live/show"),
+                publish_key: publish_key.to_string(),
+            };
+            assert!(event_producer.send(event).is_ok());
+        };
+
+        let _publisher = try_publish(&event_producer, "live/show", Some("encoder-a"))
+            .await
+            .unwrap();
+
+        /*the key of another encoder does not kick it*/
+        kick("encoder-b");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(try_publish(&event_producer, "live/show", Some("encoder-b"))
+            .await
+            .is_err());
+
+        kick("encoder-a");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(try_publish(&event_producer, "live/show", Some("encoder-b"))
+            .await
+            .is_ok());
+    }
 }
@@ -0,0 +1,104 @@
+use {
+    serde::Serialize,
+    std::time::{Duration, Instant},
+};
+
+/*
+ The state of a published stream. When the publisher is gone the stream is
+ reconnecting for the grace period, its subscribers stay attached and they
+ are resumed if the publisher returns. The stream ends when the grace period
+ expires, or at once without a grace period.
+
+ Publishing -> Reconnecting -> Publishing
+                            -> Ended
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum StreamState {
+    Publishing,
+    Reconnecting,
+    Ended,
+}
+
+pub struct StreamLifecycle {
+    state: StreamState,
+    grace_period: Duration,
+    /*the end of the grace period while reconnecting*/
+    deadline: Option<Instant>,
+}
+
+impl StreamLifecycle {
+    pub fn new(grace_period: Duration) -> Self {
+        Self {
+            state: StreamState::Publishing,
+            grace_period,
+            deadline: None,
+        }
+    }
+
+    pub fn state(&self) -> StreamState {
+        self.state
+    }
+
+    pub fn on_unpublish(&mut self, now: Instant) -> StreamState {
+        if self.state == StreamState::Publishing {
+            self.state = if self.grace_period.is_zero() {
+                StreamState::Ended
+            } else {
+                self.deadline = Some(now + self.grace_period);
+                StreamState::Reconnecting
+            };
+        }
+        self.state
+    }
+
+    /*true if the stream is resumed by the returned publisher*/
+    pub fn on_publish(&mut self) -> bool {
+        if self.state != StreamState::Reconnecting {
+            return false;
+        }
+        self.state = StreamState::Publishing;
+        self.deadline = None;
+        true
+    }
+
+    /*ends the stream when the grace period expires*/
+    pub fn check(&mut self, now: Instant) -> StreamState {
+        if self.deadline.is_some_and(|deadline| now >= deadline) {
+            self.state = StreamState::Ended;
+            self.deadline = None;
+        }
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StreamLifecycle, StreamState};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_lifecycle() {
+        let now = Instant::now();
+        let mut lifecycle = StreamLifecycle::new(Duration::from_secs(5));
+        assert_eq!(lifecycle.state(), StreamState::Publishing);
+        assert!(!lifecycle.on_publish());
+
+        assert_eq!(lifecycle.on_unpublish(now), StreamState::Reconnecting);
+        assert_eq!(
+            lifecycle.check(now + Duration::from_secs(4)),
+            StreamState::Reconnecting
+        );
+        assert!(lifecycle.on_publish());
+        assert_eq!(lifecycle.state(), StreamState::Publishing);
+
+        assert_eq!(lifecycle.on_unpublish(now), StreamState::Reconnecting);
+        assert_eq!(
+            lifecycle.check(now + Duration::from_secs(5)),
+            StreamState::Ended
+        );
+        assert!(!lifecycle.on_publish());
+
+        let mut lifecycle = StreamLifecycle::new(Duration::ZERO);
+        assert_eq!(lifecycle.on_unpublish(now), StreamState::Ended);
+    }
+}
@@ -23,6 +23,8 @@ pub struct HttpNotifier {
     on_unpublish_url: Option<String>,
     on_play_url: Option<String>,
     on_stop_url: Option<String>,
+    on_record_start_url: Option<String>,
+    on_record_stop_url: Option<String>,
 }
 
 impl HttpNotifier {
@@ -31,6 +33,8 @@ impl HttpNotifier {
         on_unpublish_url: Option<String>,
         on_play_url: Option<String>,
         on_stop_url: Option<String>,
+        on_record_start_url: Option<String>,
+        on_record_stop_url: Option<String>,
     ) -> Self {
         Self {
             request_client: Client::new(),
@@ -38,6 +42,8 @@ impl HttpNotifier {
             on_unpublish_url,
             on_play_url,
             on_stop_url,
+            on_record_start_url,
+            on_record_stop_url,
         }
     }
 }
@@ -119,4 +125,42 @@ impl Notifier for HttpNotifier {
             }
         }
     }
+
+    async fn on_record_start_notify(&self, event: &StreamHubEventMessage) {
+        if let Some(on_record_start_url) = &self.on_record_start_url {
+            match self
+                .request_client
+                .post(on_record_start_url)
+                .body(serialize_event!(event))
+                .send()
+                .await
+            {
+                Err(err) => {
+                    log::error!("on_record_start error: {}", err);
+                }
+                Ok(response) => {
+                    log::info!("on_record_start success: {:?}", response);
+                }
+            }
+        }
+    }
+
+    async fn on_record_stop_notify(&self, event: &StreamHubEventMessage) {
+        if let Some(on_record_stop_url) = &self.on_record_stop_url {
+            match self
+                .request_client
+                .post(on_record_stop_url)
+                .body(serialize_event!(event))
+                .send()
+                .await
+            {
+                Err(err) => {
+                    log::error!("on_record_stop error: {}", err);
+                }
+                Ok(response) => {
+                    log::info!("on_record_stop success: {:?}", response);
+                }
+            }
+        }
+    }
 }
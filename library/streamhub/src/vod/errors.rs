@@ -0,0 +1,67 @@
+#![allow(non_local_definitions)]
+
+use {
+    failure::{Backtrace, Fail},
+    std::{fmt, io::Error},
+    xflv::amf0::Amf0WriteError,
+    xmp4::errors::Mp4Error,
+};
+
+#[derive(Debug, Fail)]
+pub enum VodErrorValue {
+    #[fail(display = "io error: {}", _0)]
+    IOError(Error),
+    #[fail(display = "mp4 error: {}", _0)]
+    Mp4Error(Mp4Error),
+    #[fail(display = "amf0 write error: {}", _0)]
+    Amf0WriteError(Amf0WriteError),
+    #[fail(display = "not a flv file")]
+    NotFlv,
+    #[fail(display = "unsupported file format")]
+    UnsupportedFormat,
+}
+
+#[derive(Debug)]
+pub struct VodError {
+    pub value: VodErrorValue,
+}
+
+impl From<Error> for VodError {
+    fn from(error: Error) -> Self {
+        VodError {
+            value: VodErrorValue::IOError(error),
+        }
+    }
+}
+
+impl From<Mp4Error> for VodError {
+    fn from(error: Mp4Error) -> Self {
+        VodError {
+            value: VodErrorValue::Mp4Error(error),
+        }
+    }
+}
+
+impl From<Amf0WriteError> for VodError {
+    fn from(error: Amf0WriteError) -> Self {
+        VodError {
+            value: VodErrorValue::Amf0WriteError(error),
+        }
+    }
+}
+
+impl fmt::Display for VodError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.value, f)
+    }
+}
+
+impl Fail for VodError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.value.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.value.backtrace()
+    }
+}
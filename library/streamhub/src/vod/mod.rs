@@ -0,0 +1,543 @@
+pub mod errors;
+
+use {
+    crate::define::FrameData,
+    bytes::BytesMut,
+    errors::{VodError, VodErrorValue},
+    indexmap::IndexMap,
+    std::{
+        fs::File,
+        io::{BufReader, ErrorKind, Read, Seek, SeekFrom},
+        path::Path,
+    },
+    xflv::{
+        amf0::{amf0_writer::Amf0Writer, Amf0ValueType},
+        define::{aac_packet_type, avc_packet_type, ex_video_packet_type, frame_type, tag_type},
+    },
+    xmp4::{define::VideoCodec, demuxer::Mp4Demuxer},
+};
+
+/*the flv codec ids of the mp4 tracks*/
+const FLV_CODEC_H264: u8 = 7;
+const FLV_CODEC_HEVC: u8 = 12;
+const FLV_SOUND_FORMAT_AAC: u8 = 10;
+/*AAC, 44kHz, 16 bits and stereo, the real values are in the AudioSpecificConfig*/
+const FLV_AAC_AUDIO_HEADER: u8 = 0xaf;
+
+enum FrameSource {
+    /*a flv tag body in the file*/
+    Tag {
+        position: u64,
+        size: u32,
+    },
+    /*a mp4 sample, the flv video or audio tag header is added when it is read*/
+    Sample {
+        position: u64,
+        size: u32,
+        composition_time: i32,
+    },
+    /*the metadata and the sequence headers built from a mp4 file*/
+    Data(BytesMut),
+}
+
+struct VodFrame {
+    tag_type: u8,
+    /*milliseconds*/
+    timestamp: u32,
+    /*a video key frame or an audio frame*/
+    is_key: bool,
+    /*the metadata or a sequence header, the ones in effect at a seek position
+    are sent again before the frames*/
+    is_header: bool,
+    source: FrameSource,
+}
+
+/*
+ A recorded flv or mp4 file played by the vod players. The frames are indexed
+ when the file is opened and read one by one as flv tags, the timestamps are
+ the ones of the file. A playback is started at a key frame, the metadata and
+ the sequence headers in effect there are read before it.
+*/
+pub struct VodFile {
+    reader: BufReader<File>,
+    frames: Vec<VodFrame>,
+    has_video: bool,
+    has_audio: bool,
+    /*the flv codec id of the video samples of a mp4 file*/
+    video_codec_id: u8,
+}
+
+impl VodFile {
+    pub fn open(path: &Path) -> Result<Self, VodError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let (frames, video_codec_id) = match path.extension().and_then(|e| e.to_str()) {
+            Some("flv") => (Self::index_flv(&mut reader)?, 0),
+            Some("mp4") => Self::index_mp4(&mut reader)?,
+            _ => {
+                return Err(VodError {
+                    value: VodErrorValue::UnsupportedFormat,
+                })
+            }
+        };
+
+        let has_frames = |tag_type| {
+            frames
+                .iter()
+                .any(|f| f.tag_type == tag_type && !f.is_header)
+        };
+        Ok(Self {
+            has_video: has_frames(tag_type::VIDEO),
+            has_audio: has_frames(tag_type::AUDIO),
+            reader,
+            frames,
+            video_codec_id,
+        })
+    }
+
+    /*The tag headers are read through the file, only the bodies of the metadata
+    and the sequence headers are kept. A truncated last tag is ignored, e.g. the
+    one being written by the recorder.*/
+    fn index_flv(reader: &mut BufReader<File>) -> Result<Vec<VodFrame>, VodError> {
+        let file_size = reader.get_ref().metadata()?.len();
+        let mut header = [0; 9];
+        reader.read_exact(&mut header)?;
+        if &header[..3] != b"FLV" {
+            return Err(VodError {
+                value: VodErrorValue::NotFlv,
+            });
+        }
+        /*the header size and the first previous tag size*/
+        let mut position = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as u64;
+        position += 4;
+        reader.seek(SeekFrom::Start(position))?;
+
+        let mut frames = Vec::new();
+        let mut tag_header = [0; 11];
+        loop {
+            match reader.read_exact(&mut tag_header) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            }
+            let size = u32::from_be_bytes([0, tag_header[1], tag_header[2], tag_header[3]]);
+            let timestamp =
+                u32::from_be_bytes([tag_header[7], tag_header[4], tag_header[5], tag_header[6]]);
+            let body_position = position + 11;
+            if body_position + size as u64 > file_size {
+                break;
+            }
+
+            /*the frame type and the packet type*/
+            let mut prefix = [0; 2];
+            let prefix_len = prefix.len().min(size as usize);
+            reader.read_exact(&mut prefix[..prefix_len])?;
+
+            let tag_type = tag_header[0] & 0x1f;
+            let (is_key, is_header) = match tag_type {
+                tag_type::VIDEO => {
+                    /*the extended video tag header of Enhanced RTMP*/
+                    let is_header = if prefix[0] & 0x80 != 0 {
+                        prefix[0] & 0x0f == ex_video_packet_type::SEQUENCE_START
+                    } else {
+                        prefix[1] == avc_packet_type::AVC_SEQHDR
+                    };
+                    let is_key = (prefix[0] >> 4) & 0x07 == frame_type::KEY_FRAME;
+                    (is_key && !is_header, is_header)
+                }
+                tag_type::AUDIO => {
+                    let is_header = prefix[0] >> 4 == FLV_SOUND_FORMAT_AAC
+                        && prefix[1] == aac_packet_type::AAC_SEQHDR;
+                    (!is_header, is_header)
+                }
+                tag_type::SCRIPT_DATA_AMF => (false, true),
+                _ => (false, false),
+            };
+
+            if is_key || is_header || tag_type == tag_type::VIDEO {
+                frames.push(VodFrame {
+                    tag_type,
+                    timestamp,
+                    is_key,
+                    is_header,
+                    source: FrameSource::Tag {
+                        position: body_position,
+                        size,
+                    },
+                });
+            }
+
+            position = body_position + size as u64 + 4;
+            reader.seek_relative((size as usize - prefix_len) as i64 + 4)?;
+        }
+        Ok(frames)
+    }
+
+    /*the metadata and the sequence headers are built from the tracks*/
+    fn index_mp4(reader: &mut BufReader<File>) -> Result<(Vec<VodFrame>, u8), VodError> {
+        let demuxer = Mp4Demuxer::read(reader)?;
+        let duration = demuxer.samples.last().map_or(0, |s| s.dts.max(0));
+
+        let mut properties = IndexMap::new();
+        properties.insert(
+            String::from("duration"),
+            Amf0ValueType::Number(duration as f64 / 1000.0),
+        );
+        let mut frames = Vec::new();
+        let mut video_codec_id = FLV_CODEC_H264;
+
+        if let Some(track) = &demuxer.video_track {
+            if track.codec == VideoCodec::H265 {
+                video_codec_id = FLV_CODEC_HEVC;
+            }
+            properties.insert(
+                String::from("width"),
+                Amf0ValueType::Number(track.width as f64),
+            );
+            properties.insert(
+                String::from("height"),
+                Amf0ValueType::Number(track.height as f64),
+            );
+            properties.insert(
+                String::from("videocodecid"),
+                Amf0ValueType::Number(video_codec_id as f64),
+            );
+
+            let mut data = BytesMut::from(
+                &[
+                    (frame_type::KEY_FRAME << 4) | video_codec_id,
+                    avc_packet_type::AVC_SEQHDR,
+                    0,
+                    0,
+                    0,
+                ][..],
+            );
+            data.extend_from_slice(&track.config[..]);
+            frames.push(VodFrame {
+                tag_type: tag_type::VIDEO,
+                timestamp: 0,
+                is_key: false,
+                is_header: true,
+                source: FrameSource::Data(data),
+            });
+        }
+
+        if let Some(track) = &demuxer.audio_track {
+            properties.insert(
+                String::from("audiocodecid"),
+                Amf0ValueType::Number(FLV_SOUND_FORMAT_AAC as f64),
+            );
+            properties.insert(
+                String::from("audiosamplerate"),
+                Amf0ValueType::Number(track.sample_rate as f64),
+            );
+
+            let mut data = BytesMut::from(&[FLV_AAC_AUDIO_HEADER, aac_packet_type::AAC_SEQHDR][..]);
+            data.extend_from_slice(&track.config[..]);
+            frames.push(VodFrame {
+                tag_type: tag_type::AUDIO,
+                timestamp: 0,
+                is_key: false,
+                is_header: true,
+                source: FrameSource::Data(data),
+            });
+        }
+
+        let mut writer = Amf0Writer::new();
+        writer.write_string(&String::from("onMetaData"))?;
+        writer.write_eacm_array(&properties)?;
+        frames.insert(
+            0,
+            VodFrame {
+                tag_type: tag_type::SCRIPT_DATA_AMF,
+                timestamp: 0,
+                is_key: false,
+                is_header: true,
+                source: FrameSource::Data(writer.extract_current_bytes()),
+            },
+        );
+
+        for sample in &demuxer.samples {
+            frames.push(VodFrame {
+                tag_type: if sample.is_video {
+                    tag_type::VIDEO
+                } else {
+                    tag_type::AUDIO
+                },
+                timestamp: sample.dts.max(0) as u32,
+                is_key: sample.is_sync,
+                is_header: false,
+                source: FrameSource::Sample {
+                    position: sample.offset,
+                    size: sample.size,
+                    composition_time: (sample.pts - sample.dts) as i32,
+                },
+            });
+        }
+        Ok((frames, video_codec_id))
+    }
+
+    pub fn has_video(&self) -> bool {
+        self.has_video
+    }
+
+    pub fn has_audio(&self) -> bool {
+        self.has_audio
+    }
+
+    /*milliseconds, the timestamp of the last frame*/
+    pub fn duration(&self) -> u32 {
+        self.frames.iter().map(|f| f.timestamp).max().unwrap_or(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn timestamp(&self, index: usize) -> Option<u32> {
+        self.frames.get(index).map(|f| f.timestamp)
+    }
+
+    /*The index of the last video key frame at or before the timestamp, or of
+    the first one if there is none. The audio frames are the seek points of an
+    audio-only file. The length is returned if there is no seek point.*/
+    pub fn seek(&self, timestamp: u32) -> usize {
+        let mut seek_points = self
+            .frames
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.is_key && (f.tag_type == tag_type::VIDEO || !self.has_video));
+
+        let mut index = match seek_points.next() {
+            Some((index, _)) => index,
+            None => return self.frames.len(),
+        };
+        for (next_index, frame) in seek_points {
+            if frame.timestamp > timestamp {
+                break;
+            }
+            index = next_index;
+        }
+        index
+    }
+
+    /*the metadata and the sequence headers before the frame, with the timestamp*/
+    pub fn read_headers(
+        &mut self,
+        index: usize,
+        timestamp: u32,
+    ) -> Result<Vec<FrameData>, VodError> {
+        let mut header_indexes = Vec::new();
+        for tag_type in [tag_type::SCRIPT_DATA_AMF, tag_type::VIDEO, tag_type::AUDIO] {
+            let header_index = self.frames[..index.min(self.frames.len())]
+                .iter()
+                .rposition(|f| f.is_header && f.tag_type == tag_type);
+            header_indexes.extend(header_index);
+        }
+
+        let mut headers = Vec::new();
+        for header_index in header_indexes {
+            let data = self.read_data(header_index)?;
+            headers.push(Self::frame_data(
+                self.frames[header_index].tag_type,
+                timestamp,
+                data,
+            ));
+        }
+        Ok(headers)
+    }
+
+    /*None after the last frame*/
+    pub fn read_frame(&mut self, index: usize) -> Result<Option<FrameData>, VodError> {
+        if index >= self.frames.len() {
+            return Ok(None);
+        }
+        let data = self.read_data(index)?;
+        let frame = &self.frames[index];
+        Ok(Some(Self::frame_data(
+            frame.tag_type,
+            frame.timestamp,
+            data,
+        )))
+    }
+
+    fn frame_data(tag_type: u8, timestamp: u32, data: BytesMut) -> FrameData {
+        match tag_type {
+            tag_type::VIDEO => FrameData::Video { timestamp, data },
+            tag_type::AUDIO => FrameData::Audio { timestamp, data },
+            _ => FrameData::MetaData { timestamp, data },
+        }
+    }
+
+    /*the flv tag body of a frame*/
+    fn read_data(&mut self, index: usize) -> Result<BytesMut, VodError> {
+        let frame = &self.frames[index];
+        let (position, size, mut data) = match &frame.source {
+            FrameSource::Data(data) => return Ok(data.clone()),
+            FrameSource::Tag { position, size } => (*position, *size, BytesMut::new()),
+            FrameSource::Sample {
+                position,
+                size,
+                composition_time,
+            } => {
+                let header = if frame.tag_type == tag_type::VIDEO {
+                    let frame_type = if frame.is_key {
+                        frame_type::KEY_FRAME
+                    } else {
+                        frame_type::INTER_FRAME
+                    };
+                    let time = composition_time.to_be_bytes();
+                    vec![
+                        (frame_type << 4) | self.video_codec_id,
+                        avc_packet_type::AVC_NALU,
+                        time[1],
+                        time[2],
+                        time[3],
+                    ]
+                } else {
+                    vec![FLV_AAC_AUDIO_HEADER, aac_packet_type::AAC_RAW]
+                };
+                (*position, *size, BytesMut::from(&header[..]))
+            }
+        };
+
+        let header_len = data.len();
+        data.resize(header_len + size as usize, 0);
+        self.reader.seek(SeekFrom::Start(position))?;
+        self.reader.read_exact(&mut data[header_len..])?;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VodFile;
+    use crate::define::FrameData;
+    use bytes::BytesMut;
+    use std::{io::Write, path::PathBuf};
+    use xmp4::{
+        define::{AudioTrack, Sample, VideoCodec, VideoTrack},
+        fmp4::Fmp4Muxer,
+    };
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("xiu_vod_{}_{}", std::process::id(), name))
+    }
+
+    fn flv_tag(tag_type: u8, timestamp: u32, body: &[u8]) -> Vec<u8> {
+        let mut tag = vec![tag_type];
+        tag.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        tag.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+        tag.push((timestamp >> 24) as u8);
+        tag.extend_from_slice(&[0, 0, 0]);
+        tag.extend_from_slice(body);
+        tag.extend_from_slice(&(body.len() as u32 + 11).to_be_bytes());
+        tag
+    }
+
+    fn timestamps(frames: &[FrameData]) -> Vec<(char, u32)> {
+        frames
+            .iter()
+            .map(|frame| match frame {
+                FrameData::Video { timestamp, .. } => ('v', *timestamp),
+                FrameData::Audio { timestamp, .. } => ('a', *timestamp),
+                FrameData::MetaData { timestamp, .. } => ('m', *timestamp),
+                FrameData::MediaInfo { .. } | FrameData::Discontinuity => ('i', 0),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_flv_file() {
+        let mut data = vec![b'F', b'L', b'V', 1, 5, 0, 0, 0, 9, 0, 0, 0, 0];
+        data.extend(flv_tag(18, 0, &[0x02, 0x00, 0x01, b'a']));
+        data.extend(flv_tag(9, 0, &[0x17, 0x00, 0, 0, 0, 0x01]));
+        data.extend(flv_tag(8, 0, &[0xaf, 0x00, 0x12, 0x10]));
+        for timestamp in (0..4000).step_by(500) {
+            let frame_type = if timestamp % 2000 == 0 { 0x17 } else { 0x27 };
+            data.extend(flv_tag(9, timestamp, &[frame_type, 0x01, 0, 0, 0, 0xff]));
+            data.extend(flv_tag(8, timestamp, &[0xaf, 0x01, 0x21]));
+        }
+        /*the tag being written*/
+        data.extend_from_slice(&[9, 0, 1, 0]);
+
+        let path = temp_path("test.flv");
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+        let mut file = VodFile::open(&path).unwrap();
+        assert!(file.has_video() && file.has_audio());
+        assert_eq!(file.duration(), 3500);
+
+        /*the key frames are at 0 and 2000*/
+        let index = file.seek(3000);
+        assert_eq!(file.timestamp(index), Some(2000));
+        assert_eq!(file.timestamp(file.seek(0)), Some(0));
+        let headers = file.read_headers(index, 3000).unwrap();
+        assert_eq!(
+            timestamps(&headers),
+            vec![('m', 3000), ('v', 3000), ('a', 3000)]
+        );
+        match file.read_frame(index).unwrap() {
+            Some(FrameData::Video { data, .. }) => {
+                assert_eq!(&data[..], &[0x17, 0x01, 0, 0, 0, 0xff])
+            }
+            _ => panic!("not a video frame"),
+        }
+        assert!(file.read_frame(file.len()).unwrap().is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_mp4_file() {
+        let mut muxer = Fmp4Muxer::new();
+        muxer.set_video_track(VideoTrack {
+            codec: VideoCodec::H264,
+            width: 1280,
+            height: 720,
+            config: BytesMut::from(&[0x01, 0x64, 0x00, 0x1f, 0xff, 0xe0, 0x00][..]),
+        });
+        muxer.set_audio_track(AudioTrack {
+            sample_rate: 48000,
+            channels: 2,
+            config: BytesMut::from(&[0x11, 0x90][..]),
+        });
+        let mut data = muxer.init_segment().unwrap().to_vec();
+        for (idx, dts) in [0, 40, 80, 120].iter().copied().enumerate() {
+            muxer.write_video(Sample {
+                dts,
+                pts: dts + 80,
+                is_sync: idx == 2,
+                data: BytesMut::from(&[0, 0, 0, 1, idx as u8][..]),
+            });
+        }
+        data.extend_from_slice(&muxer.flush_fragment(160).unwrap());
+
+        let path = temp_path("test.mp4");
+        std::fs::write(&path, &data).unwrap();
+        let mut file = VodFile::open(&path).unwrap();
+        assert!(file.has_video() && !file.has_audio());
+
+        let index = file.seek(100);
+        assert_eq!(file.timestamp(index), Some(80));
+        let headers = file.read_headers(index, 80).unwrap();
+        match &headers[1] {
+            FrameData::Video { data, .. } => {
+                assert_eq!(&data[..7], &[0x17, 0x00, 0, 0, 0, 0x01, 0x64])
+            }
+            _ => panic!("no video sequence header"),
+        }
+        /*the flv video tag header with the composition time*/
+        match file.read_frame(index).unwrap() {
+            Some(FrameData::Video { data, .. }) => {
+                assert_eq!(&data[..], &[0x17, 0x01, 0, 0, 80, 0, 0, 0, 1, 2])
+            }
+            _ => panic!("not a video frame"),
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
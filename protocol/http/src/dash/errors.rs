@@ -0,0 +1,77 @@
+#![allow(non_local_definitions)]
+
+use {
+    crate::hls::errors::MediaError,
+    failure::{Backtrace, Fail},
+    std::fmt,
+    streamhub::errors::StreamHubError,
+    tokio::sync::broadcast::error::RecvError,
+    tokio::sync::oneshot::error::RecvError as OneshotRecvError,
+};
+
+#[derive(Debug)]
+pub struct DashError {
+    pub value: DashErrorValue,
+}
+
+#[derive(Debug, Fail)]
+pub enum DashErrorValue {
+    #[fail(display = "channel error:{}", _0)]
+    ChannelError(#[cause] StreamHubError),
+    #[fail(display = "media error:{}", _0)]
+    MediaError(#[cause] MediaError),
+    #[fail(display = "receive error:{}", _0)]
+    RecvError(#[cause] RecvError),
+    #[fail(display = "tokio: oneshot receiver err: {}", _0)]
+    OneshotRecvError(#[cause] OneshotRecvError),
+    #[fail(display = "stream hub event send error")]
+    StreamHubEventSendErr,
+}
+
+impl From<RecvError> for DashError {
+    fn from(error: RecvError) -> Self {
+        DashError {
+            value: DashErrorValue::RecvError(error),
+        }
+    }
+}
+
+impl From<MediaError> for DashError {
+    fn from(error: MediaError) -> Self {
+        DashError {
+            value: DashErrorValue::MediaError(error),
+        }
+    }
+}
+
+impl From<StreamHubError> for DashError {
+    fn from(error: StreamHubError) -> Self {
+        DashError {
+            value: DashErrorValue::ChannelError(error),
+        }
+    }
+}
+
+impl From<OneshotRecvError> for DashError {
+    fn from(error: OneshotRecvError) -> Self {
+        DashError {
+            value: DashErrorValue::OneshotRecvError(error),
+        }
+    }
+}
+
+impl fmt::Display for DashError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.value, f)
+    }
+}
+
+impl Fail for DashError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.value.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.value.backtrace()
+    }
+}
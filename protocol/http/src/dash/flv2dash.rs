@@ -0,0 +1,106 @@
+use {
+    super::mpd::Mpd,
+    crate::hls::{
+        errors::MediaError,
+        fmp4::{Flv2Fmp4, Fmp4Sample},
+    },
+    xflv::define::FlvData,
+};
+
+/*The audio and the video are delivered as separate representations, both are
+cut at the same time so that their segments are aligned.*/
+pub struct Flv2DashRemuxer {
+    fmp4: Flv2Fmp4,
+    mpd: Mpd,
+
+    /*milliseconds*/
+    segment_duration: i64,
+    segment_start_dts: i64,
+    last_dts: i64,
+}
+
+impl Flv2DashRemuxer {
+    pub fn new(
+        app_name: String,
+        stream_name: String,
+        segment_duration: i64,
+        time_shift_buffer_depth: i64,
+    ) -> Self {
+        Self {
+            fmp4: Flv2Fmp4::new(),
+            mpd: Mpd::new(
+                app_name,
+                stream_name,
+                segment_duration,
+                time_shift_buffer_depth,
+            ),
+            segment_duration,
+            segment_start_dts: 0,
+            last_dts: 0,
+        }
+    }
+
+    pub fn process_flv_data(&mut self, data: FlvData) -> Result<(), MediaError> {
+        let sample = match self.fmp4.demux(data)? {
+            Some(sample) => sample,
+            None => return Ok(()),
+        };
+
+        let (dts, is_key_frame, is_video) = match &sample {
+            Fmp4Sample::Video(video) => (video.dts, video.is_sync, true),
+            Fmp4Sample::Audio(audio) => (audio.dts, false, false),
+        };
+        self.mpd.start(dts);
+
+        /*a segment starts with a key frame, or any frame of an audio only stream*/
+        let has_video = self.fmp4.muxer().video_track().is_some();
+        let can_cut = if has_video { is_key_frame } else { !is_video };
+        if can_cut
+            && dts - self.segment_start_dts >= self.segment_duration
+            && self.fmp4.muxer().has_samples()
+        {
+            self.flush_segment(dts)?;
+            self.segment_start_dts = dts;
+        }
+
+        self.last_dts = dts;
+        self.fmp4.write(sample);
+
+        Ok(())
+    }
+
+    fn flush_segment(&mut self, next_dts: i64) -> Result<(), MediaError> {
+        if self.fmp4.take_init_changed() {
+            let muxer = self.fmp4.muxer();
+            if let Some(track) = muxer.video_track() {
+                self.mpd
+                    .set_video_init(track, muxer.video_init_segment()?)?;
+            }
+            if let Some(track) = muxer.audio_track() {
+                self.mpd
+                    .set_audio_init(track, muxer.audio_init_segment()?)?;
+            }
+        }
+
+        if let Some(fragment) = self.fmp4.muxer().flush_video_fragment(next_dts)? {
+            self.mpd.add_video_segment(fragment)?;
+        }
+        if let Some(fragment) = self.fmp4.muxer().flush_audio_fragment()? {
+            self.mpd.add_audio_segment(fragment)?;
+        }
+        self.mpd.refresh()?;
+
+        Ok(())
+    }
+
+    pub fn flush_remaining_data(&mut self) -> Result<(), MediaError> {
+        if self.fmp4.muxer().has_samples() {
+            self.flush_segment(self.last_dts)?;
+        }
+        Ok(())
+    }
+
+    pub fn clear_files(&mut self) -> Result<(), MediaError> {
+        self.mpd.clear()
+    }
+}
@@ -0,0 +1,150 @@
+use {
+    super::{
+        errors::{DashError, DashErrorValue},
+        flv2dash::Flv2DashRemuxer,
+    },
+    std::time::Duration,
+    streamhub::{
+        define::{
+            FrameData, FrameDataReceiver, NotifyInfo, StreamHubEvent, StreamHubEventSender,
+            SubDataType, SubscribeType, SubscriberInfo,
+        },
+        stream::Protocol,
+        utils::{RandomDigitCount, Uuid},
+    },
+    tokio::{
+        sync::{mpsc, oneshot},
+        time::sleep,
+    },
+    xflv::define::FlvData,
+};
+
+pub struct FlvDataReceiver {
+    app_name: String,
+    stream_name: String,
+    event_producer: StreamHubEventSender,
+    data_consumer: FrameDataReceiver,
+    media_processor: Flv2DashRemuxer,
+    subscriber_id: Uuid,
+}
+
+impl FlvDataReceiver {
+    pub fn new(
+        app_name: String,
+        stream_name: String,
+        event_producer: StreamHubEventSender,
+        segment_duration: i64,
+        time_shift_buffer_depth: i64,
+    ) -> Self {
+        let (_, data_consumer) = mpsc::unbounded_channel();
+        let subscriber_id = Uuid::new(RandomDigitCount::Four);
+
+        Self {
+            app_name: app_name.clone(),
+            stream_name: stream_name.clone(),
+            data_consumer,
+            event_producer,
+            media_processor: Flv2DashRemuxer::new(
+                app_name,
+                stream_name,
+                segment_duration,
+                time_shift_buffer_depth,
+            ),
+            subscriber_id,
+        }
+    }
+
+    pub async fn run(&mut self) -> Result<(), DashError> {
+        self.subscribe_from_rtmp_channels().await?;
+        self.receive_flv_data().await?;
+
+        Ok(())
+    }
+
+    /*the live files are dropped and the channel is unsubscribed whatever
+    the packaging ends with*/
+    pub async fn receive_flv_data(&mut self) -> Result<(), DashError> {
+        let result = self.remux_flv_data().await;
+        if let Err(err) = self.media_processor.clear_files() {
+            log::error!(
+                "clear the dash files of {}/{} err: {}",
+                self.app_name,
+                self.stream_name,
+                err
+            );
+        }
+        self.unsubscribe_from_rtmp_channels();
+        result
+    }
+
+    async fn remux_flv_data(&mut self) -> Result<(), DashError> {
+        let mut retry_count = 0;
+
+        loop {
+            if let Some(data) = self.data_consumer.recv().await {
+                let flv_data: FlvData = match data {
+                    FrameData::Audio { timestamp, data } => FlvData::Audio { timestamp, data },
+                    FrameData::Video { timestamp, data } => FlvData::Video { timestamp, data },
+                    _ => continue,
+                };
+                retry_count = 0;
+                self.media_processor.process_flv_data(flv_data)?;
+            } else {
+                sleep(Duration::from_millis(100)).await;
+                retry_count += 1;
+            }
+            //the same as hls, the stream is regarded as finished after 10 retries
+            if retry_count > 10 {
+                self.media_processor.flush_remaining_data()?;
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn subscriber_info(&self) -> SubscriberInfo {
+        SubscriberInfo {
+            id: self.subscriber_id,
+            sub_type: SubscribeType::GenerateDash,
+            sub_data_type: SubDataType::Frame,
+            notify_info: NotifyInfo {
+                request_url: String::from(""),
+                remote_addr: String::from(""),
+            },
+        }
+    }
+
+    pub async fn subscribe_from_rtmp_channels(&mut self) -> Result<(), DashError> {
+        let (event_result_sender, event_result_receiver) = oneshot::channel();
+
+        let subscribe_event = StreamHubEvent::Subscribe {
+            protocol: Protocol::Rtmp,
+            name: format!("{}/{}", self.app_name, self.stream_name),
+            info: self.subscriber_info(),
+            result_sender: event_result_sender,
+        };
+
+        if self.event_producer.send(subscribe_event).is_err() {
+            return Err(DashError {
+                value: DashErrorValue::StreamHubEventSendErr,
+            });
+        }
+
+        let receiver = event_result_receiver.await??.0.frame_receiver.unwrap();
+        self.data_consumer = receiver;
+
+        Ok(())
+    }
+
+    pub fn unsubscribe_from_rtmp_channels(&mut self) {
+        let unsubscribe_event = StreamHubEvent::UnSubscribe {
+            protocol: Protocol::Rtmp,
+            name: format!("{}/{}", self.app_name, self.stream_name),
+            info: self.subscriber_info(),
+        };
+        if let Err(err) = self.event_producer.send(unsubscribe_event) {
+            log::error!("unsubscribe_from_channels err {}", err);
+        }
+    }
+}
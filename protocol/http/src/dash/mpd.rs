@@ -0,0 +1,349 @@
+use {
+    crate::hls::errors::MediaError,
+    bytes::BytesMut,
+    chrono::{DateTime, Duration, SecondsFormat, Utc},
+    std::{collections::VecDeque, fs, fs::File, io::Write},
+    xmp4::define::{AudioTrack, TrackFragment, VideoTrack, VIDEO_TIMESCALE},
+};
+
+pub struct SegmentEntry {
+    pub number: u64,
+    /*the start time and the duration in the timescale of the representation*/
+    pub time: u64,
+    pub duration: u64,
+    pub size: usize,
+}
+
+enum MediaInfo {
+    Video { width: u32, height: u32 },
+    Audio { sample_rate: u32, channels: u16 },
+}
+
+/*Every track is a representation of its own adaptation set, the init segment
+is "init-{id}.mp4" and the segments are "{id}-{number}.m4s".*/
+pub struct Representation {
+    id: &'static str,
+    codecs: String,
+    timescale: u32,
+    info: MediaInfo,
+    next_number: u64,
+    segments: VecDeque<SegmentEntry>,
+}
+
+impl Representation {
+    fn video(track: &VideoTrack) -> Self {
+        Self {
+            id: "video",
+            codecs: track.codec_string(),
+            timescale: VIDEO_TIMESCALE,
+            info: MediaInfo::Video {
+                width: track.width,
+                height: track.height,
+            },
+            next_number: 0,
+            segments: VecDeque::new(),
+        }
+    }
+
+    fn audio(track: &AudioTrack) -> Self {
+        Self {
+            id: "audio",
+            codecs: track.codec_string(),
+            timescale: track.sample_rate,
+            info: MediaInfo::Audio {
+                sample_rate: track.sample_rate,
+                channels: track.channels,
+            },
+            next_number: 0,
+            segments: VecDeque::new(),
+        }
+    }
+
+    /*a changed decoder configuration keeps the segment numbers*/
+    fn update(&mut self, other: Representation) {
+        self.codecs = other.codecs;
+        self.timescale = other.timescale;
+        self.info = other.info;
+    }
+
+    /*bits per second of the segments in the manifest*/
+    fn bandwidth(&self) -> u64 {
+        let size: usize = self.segments.iter().map(|s| s.size).sum();
+        let duration: u64 = self.segments.iter().map(|s| s.duration).sum();
+        if duration == 0 {
+            return 1;
+        }
+        std::cmp::max(size as u64 * 8 * self.timescale as u64 / duration, 1)
+    }
+
+    fn generate(&self, index: usize) -> String {
+        let (content_type, attributes, children) = match self.info {
+            MediaInfo::Video { width, height } => (
+                "video",
+                format!(" width=\"{width}\" height=\"{height}\""),
+                String::new(),
+            ),
+            MediaInfo::Audio {
+                sample_rate,
+                channels,
+            } => (
+                "audio",
+                format!(" audioSamplingRate=\"{sample_rate}\""),
+                format!(
+                    "        <AudioChannelConfiguration schemeIdUri=\"urn:mpeg:dash:23003:3:audio_channel_configuration:2011\" value=\"{channels}\"/>\n"
+                ),
+            ),
+        };
+
+        let start_number = self.segments.front().map_or(0, |s| s.number);
+
+        let mut content = format!(
+            "    <AdaptationSet id=\"{index}\" contentType=\"{content_type}\" mimeType=\"{content_type}/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\">\n"
+        );
+        content += format!(
+            "      <Representation id=\"{}\" codecs=\"{}\" bandwidth=\"{}\"{attributes}>\n",
+            self.id,
+            self.codecs,
+            self.bandwidth()
+        )
+        .as_str();
+        content += children.as_str();
+        content += format!(
+            "        <SegmentTemplate timescale=\"{}\" initialization=\"dash/init-$RepresentationID$.mp4\" media=\"dash/$RepresentationID$-$Number$.m4s\" startNumber=\"{start_number}\">\n",
+            self.timescale
+        )
+        .as_str();
+        content += "          <SegmentTimeline>\n";
+        for segment in &self.segments {
+            content += format!(
+                "            <S t=\"{}\" d=\"{}\"/>\n",
+                segment.time, segment.duration
+            )
+            .as_str();
+        }
+        content += "          </SegmentTimeline>\n";
+        content += "        </SegmentTemplate>\n";
+        content += "      </Representation>\n";
+        content += "    </AdaptationSet>\n";
+        content
+    }
+}
+
+fn write_file(folder: &str, name: &str, data: &[u8]) -> Result<(), MediaError> {
+    let mut file_handler = File::create(format!("{folder}/{name}"))?;
+    file_handler.write_all(data)?;
+    Ok(())
+}
+
+fn iso_duration(milliseconds: i64) -> String {
+    format!("PT{:.3}S", milliseconds as f64 / 1000.0)
+}
+
+pub struct Mpd {
+    folder: String,
+    /*milliseconds*/
+    segment_duration: i64,
+    time_shift_buffer_depth: i64,
+    /*the wall clock time of the media time 0*/
+    availability_start_time: Option<DateTime<Utc>>,
+    video: Option<Representation>,
+    audio: Option<Representation>,
+}
+
+impl Mpd {
+    pub fn new(
+        app_name: String,
+        stream_name: String,
+        segment_duration: i64,
+        time_shift_buffer_depth: i64,
+    ) -> Self {
+        let folder = format!("./dash/{app_name}/{stream_name}");
+        fs::create_dir_all(folder.clone()).unwrap();
+
+        Self {
+            folder,
+            segment_duration,
+            time_shift_buffer_depth,
+            availability_start_time: None,
+            video: None,
+            audio: None,
+        }
+    }
+
+    /*called with the dts(milliseconds) of the first frame*/
+    pub fn start(&mut self, dts: i64) {
+        if self.availability_start_time.is_none() {
+            self.availability_start_time = Some(Utc::now() - Duration::milliseconds(dts));
+        }
+    }
+
+    pub fn set_video_init(&mut self, track: &VideoTrack, data: BytesMut) -> Result<(), MediaError> {
+        let representation = Representation::video(track);
+        write_file(
+            &self.folder,
+            &format!("init-{}.mp4", representation.id),
+            &data,
+        )?;
+        match &mut self.video {
+            Some(video) => video.update(representation),
+            None => self.video = Some(representation),
+        }
+        Ok(())
+    }
+
+    pub fn set_audio_init(&mut self, track: &AudioTrack, data: BytesMut) -> Result<(), MediaError> {
+        let representation = Representation::audio(track);
+        write_file(
+            &self.folder,
+            &format!("init-{}.mp4", representation.id),
+            &data,
+        )?;
+        match &mut self.audio {
+            Some(audio) => audio.update(representation),
+            None => self.audio = Some(representation),
+        }
+        Ok(())
+    }
+
+    pub fn add_video_segment(&mut self, fragment: TrackFragment) -> Result<(), MediaError> {
+        match &mut self.video {
+            Some(video) => {
+                Self::add_segment(&self.folder, self.time_shift_buffer_depth, video, fragment)
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn add_audio_segment(&mut self, fragment: TrackFragment) -> Result<(), MediaError> {
+        match &mut self.audio {
+            Some(audio) => {
+                Self::add_segment(&self.folder, self.time_shift_buffer_depth, audio, fragment)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /*the segments out of the time shift buffer are removed from the manifest and deleted*/
+    fn add_segment(
+        folder: &str,
+        time_shift_buffer_depth: i64,
+        representation: &mut Representation,
+        fragment: TrackFragment,
+    ) -> Result<(), MediaError> {
+        let number = representation.next_number;
+        write_file(
+            folder,
+            &format!("{}-{}.m4s", representation.id, number),
+            &fragment.data,
+        )?;
+        representation.next_number += 1;
+        representation.segments.push_back(SegmentEntry {
+            number,
+            time: fragment.base_media_decode_time,
+            duration: fragment.duration,
+            size: fragment.data.len(),
+        });
+
+        let end = fragment.base_media_decode_time + fragment.duration;
+        let depth = time_shift_buffer_depth as u64 * representation.timescale as u64 / 1000;
+        while let Some(front) = representation.segments.front() {
+            if representation.segments.len() <= 1 || front.time + front.duration + depth >= end {
+                break;
+            }
+            let path = format!("{}/{}-{}.m4s", folder, representation.id, front.number);
+            if let Err(err) = fs::remove_file(&path) {
+                log::warn!("delete dash segment {} error: {}", path, err);
+            }
+            representation.segments.pop_front();
+        }
+
+        Ok(())
+    }
+
+    pub fn generate(&self) -> String {
+        let availability_start_time = self
+            .availability_start_time
+            .unwrap_or_else(Utc::now)
+            .to_rfc3339_opts(SecondsFormat::Millis, true);
+        let publish_time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+
+        let mut mpd = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        mpd += format!(
+            "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" profiles=\"urn:mpeg:dash:profile:isoff-live:2011\" type=\"dynamic\" availabilityStartTime=\"{}\" publishTime=\"{}\" minimumUpdatePeriod=\"{}\" minBufferTime=\"{}\" timeShiftBufferDepth=\"{}\" suggestedPresentationDelay=\"{}\">\n",
+            availability_start_time,
+            publish_time,
+            iso_duration(self.segment_duration),
+            iso_duration(self.segment_duration),
+            iso_duration(self.time_shift_buffer_depth),
+            iso_duration(self.segment_duration * 3),
+        )
+        .as_str();
+        mpd += "  <Period id=\"0\" start=\"PT0S\">\n";
+        for (index, representation) in [&self.video, &self.audio]
+            .into_iter()
+            .flatten()
+            .filter(|r| !r.segments.is_empty())
+            .enumerate()
+        {
+            mpd += representation.generate(index).as_str();
+        }
+        mpd += "  </Period>\n</MPD>\n";
+        mpd
+    }
+
+    pub fn refresh(&mut self) -> Result<String, MediaError> {
+        let mpd = self.generate();
+        write_file(&self.folder, "index.mpd", mpd.as_bytes())?;
+        Ok(mpd)
+    }
+
+    pub fn clear(&mut self) -> Result<(), MediaError> {
+        for representation in [&self.video, &self.audio].into_iter().flatten() {
+            for segment in &representation.segments {
+                let _ = fs::remove_file(format!(
+                    "{}/{}-{}.m4s",
+                    self.folder, representation.id, segment.number
+                ));
+            }
+            let _ = fs::remove_file(format!("{}/init-{}.mp4", self.folder, representation.id));
+        }
+        fs::remove_file(format!("{}/index.mpd", self.folder))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Representation, SegmentEntry};
+    use bytes::BytesMut;
+    use xmp4::define::{VideoCodec, VideoTrack};
+
+    #[test]
+    fn test_representation() {
+        let mut video = Representation::video(&VideoTrack {
+            codec: VideoCodec::H264,
+            width: 1280,
+            height: 720,
+            config: BytesMut::from(&[0x01, 0x64, 0x00, 0x1f][..]),
+        });
+        for number in 5..7 {
+            video.segments.push_back(SegmentEntry {
+                number,
+                time: number * 180000,
+                duration: 180000,
+                size: 250000,
+            });
+        }
+
+        /*250000 bytes every 2 seconds*/
+        assert_eq!(video.bandwidth(), 1000000);
+
+        let adaptation_set = video.generate(0);
+        assert!(adaptation_set.contains(
+            "codecs=\"avc1.64001f\" bandwidth=\"1000000\" width=\"1280\" height=\"720\""
+        ));
+        assert!(adaptation_set.contains("startNumber=\"5\""));
+        assert!(adaptation_set.contains("<S t=\"900000\" d=\"180000\"/>"));
+        assert!(adaptation_set.contains("<S t=\"1080000\" d=\"180000\"/>"));
+    }
+}
@@ -0,0 +1,67 @@
+use {
+    super::{errors::DashError, flv_data_receiver::FlvDataReceiver},
+    streamhub::{
+        define::{BroadcastEvent, BroadcastEventReceiver, StreamHubEventSender},
+        stream::Protocol,
+    },
+};
+
+pub struct DashRemuxer {
+    client_event_consumer: BroadcastEventReceiver,
+    event_producer: StreamHubEventSender,
+    /*milliseconds*/
+    segment_duration: i64,
+    time_shift_buffer_depth: i64,
+}
+
+impl DashRemuxer {
+    pub fn new(
+        consumer: BroadcastEventReceiver,
+        event_producer: StreamHubEventSender,
+        segment_duration: i64,
+        time_shift_buffer_depth: i64,
+    ) -> Self {
+        Self {
+            client_event_consumer: consumer,
+            event_producer,
+            segment_duration,
+            time_shift_buffer_depth,
+        }
+    }
+
+    pub async fn run(&mut self) -> Result<(), DashError> {
+        loop {
+            let val = self.client_event_consumer.recv().await?;
+            match val {
+                BroadcastEvent::Publish {
+                    protocol: Protocol::Rtmp,
+                    name,
+                } => {
+                    let (app_name, stream_name) = match name.split_once('/') {
+                        Some((app_name, stream_name)) => {
+                            (app_name.to_string(), stream_name.to_string())
+                        }
+                        None => continue,
+                    };
+
+                    let mut rtmp_subscriber = FlvDataReceiver::new(
+                        app_name,
+                        stream_name,
+                        self.event_producer.clone(),
+                        self.segment_duration,
+                        self.time_shift_buffer_depth,
+                    );
+
+                    tokio::spawn(async move {
+                        if let Err(err) = rtmp_subscriber.run().await {
+                            log::error!("dash handler run error {}", err);
+                        }
+                    });
+                }
+                _ => {
+                    log::trace!("other infos...");
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use streamhub::errors::StreamHubError;
+use streamhub::{errors::StreamHubError, vod::errors::VodError};
 
 use {
     failure::Fail, futures::channel::mpsc::SendError, std::fmt,
@@ -39,6 +39,8 @@ pub enum HttpFLvErrorValue {
     ChannelRecvError,
     #[fail(display = "send frame data error")]
     SendFrameDataErr,
+    #[fail(display = "vod file error: {}", _0)]
+    VodError(VodError),
 }
 
 impl From<FlvMuxerError> for HttpFLvError {
@@ -65,6 +67,14 @@ impl From<Amf0WriteError> for HttpFLvError {
     }
 }
 
+impl From<VodError> for HttpFLvError {
+    fn from(error: VodError) -> Self {
+        HttpFLvError {
+            value: HttpFLvErrorValue::VodError(error),
+        }
+    }
+}
+
 impl From<StreamHubError> for HttpFLvError {
     fn from(error: StreamHubError) -> Self {
         HttpFLvError {
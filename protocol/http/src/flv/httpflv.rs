@@ -6,7 +6,7 @@ use {
         errors::{HttpFLvError, HttpFLvErrorValue},
     },
     bytes::BytesMut,
-    std::net::SocketAddr,
+    std::{net::SocketAddr, time::Duration},
     streamhub::define::{
         FrameData, FrameDataReceiver, NotifyInfo, StreamHubEvent, StreamHubEventSender,
         SubDataType, SubscribeType, SubscriberInfo,
@@ -15,7 +15,7 @@ use {
         utils::{RandomDigitCount, Uuid},
         stream::Protocol
     },
-    tokio::sync::mpsc,
+    tokio::{sync::mpsc, time::Instant},
     xflv::amf0::amf0_writer::Amf0Writer,
     xflv::muxer::{FlvMuxer, HEADER_LENGTH},
 };
@@ -38,6 +38,8 @@ pub struct HttpFlv {
     subscriber_id: Uuid,
     request_url: String,
     remote_addr: SocketAddr,
+    /*the session duration allowed by the play token*/
+    deadline: Option<Instant>,
 }
 
 impl HttpFlv {
@@ -48,6 +50,7 @@ impl HttpFlv {
         http_response_data_producer: HttpResponseDataProducer,
         request_url: String,
         remote_addr: SocketAddr,
+        max_duration: Option<Duration>,
     ) -> Self {
         let (_, data_receiver) = mpsc::unbounded_channel();
         let subscriber_id = Uuid::new(RandomDigitCount::Four);
@@ -66,6 +69,7 @@ impl HttpFlv {
             subscriber_id,
             request_url,
             remote_addr,
+            deadline: max_duration.map(|d| Instant::now() + d),
         }
     }
 
@@ -84,7 +88,22 @@ impl HttpFlv {
         let mut cached_frames = Vec::new();
         //write flv body
         loop {
-            if let Some(data) = self.data_receiver.recv().await {
+            /*the deadline is also reached while the stream is idle*/
+            let data = match self.deadline {
+                Some(deadline) => tokio::select! {
+                    data = self.data_receiver.recv() => data,
+                    _ = tokio::time::sleep_until(deadline) => {
+                        log::info!(
+                            "http-flv session duration reached, app_name: {} stream_name: {}",
+                            self.app_name,
+                            self.stream_name
+                        );
+                        break;
+                    }
+                },
+                None => self.data_receiver.recv().await,
+            };
+            if let Some(data) = data {
                 if !self.has_send_header {
                     max_av_frame_num_to_guess_av += 1;
 
@@ -192,6 +211,8 @@ impl HttpFlv {
 
                 (BytesMut::from(right), timestamp, tag_type::SCRIPT_DATA_AMF)
             }
+            //the timestamps of the new publisher are already continuous
+            FrameData::Discontinuity => return Ok(()),
             _ => {
                 log::error!("should not be here!!!");
                 (BytesMut::new(), 0, 0)
@@ -1,3 +1,4 @@
 pub mod define;
 pub mod errors;
 pub mod httpflv;
+pub mod vod;
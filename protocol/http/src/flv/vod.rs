@@ -0,0 +1,181 @@
+use {
+    super::{define::tag_type, errors::HttpFLvError},
+    bytes::BytesMut,
+    futures::stream::{self, Stream},
+    std::io,
+    streamhub::{define::FrameData, vod::VodFile},
+    xflv::muxer::{FlvMuxer, HEADER_LENGTH},
+};
+
+/*the size of the body chunks, the frames are read on the blocking threads
+when the body is polled*/
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/*
+ Remuxes a recorded file to a http-flv stream from a start position. The
+ playback starts at the key frame before the start position and keeps the
+ timestamps of the file. It is sent as fast as the client reads it.
+*/
+pub struct HttpFlvVod {
+    file: VodFile,
+    muxer: FlvMuxer,
+    /*the next frame to be written*/
+    index: usize,
+    has_send_header: bool,
+}
+
+impl HttpFlvVod {
+    /*start: milliseconds*/
+    pub fn new(file: VodFile, start: u32) -> Self {
+        let index = file.seek(start);
+        Self {
+            file,
+            muxer: FlvMuxer::new(),
+            index,
+            has_send_header: false,
+        }
+    }
+
+    fn write_flv_tag(&mut self, frame: FrameData) -> Result<(), HttpFLvError> {
+        let (data, timestamp, tag_type) = match frame {
+            FrameData::Video { timestamp, data } => (data, timestamp, tag_type::VIDEO),
+            FrameData::Audio { timestamp, data } => (data, timestamp, tag_type::AUDIO),
+            FrameData::MetaData { timestamp, data } => (data, timestamp, tag_type::SCRIPT_DATA_AMF),
+            FrameData::MediaInfo { .. } | FrameData::Discontinuity => return Ok(()),
+        };
+
+        let data_len = data.len() as u32;
+        self.muxer
+            .write_flv_tag_header(tag_type, data_len, timestamp)?;
+        self.muxer.write_flv_tag_body(data)?;
+        self.muxer
+            .write_previous_tag_size(data_len + HEADER_LENGTH)?;
+        Ok(())
+    }
+
+    /*the next chunk of the body, None after the last frame*/
+    fn read_chunk(&mut self) -> Result<Option<BytesMut>, HttpFLvError> {
+        if !self.has_send_header {
+            self.has_send_header = true;
+            self.muxer
+                .write_flv_header(self.file.has_audio(), self.file.has_video())?;
+            self.muxer.write_previous_tag_size(0)?;
+
+            let timestamp = self.file.timestamp(self.index).unwrap_or_default();
+            for header in self.file.read_headers(self.index, timestamp)? {
+                self.write_flv_tag(header)?;
+            }
+        }
+
+        while self.muxer.writer.len() < CHUNK_SIZE {
+            match self.file.read_frame(self.index)? {
+                Some(frame) => {
+                    self.index += 1;
+                    self.write_flv_tag(frame)?;
+                }
+                None => break,
+            }
+        }
+
+        let data = self.muxer.writer.extract_current_bytes();
+        Ok(if data.is_empty() { None } else { Some(data) })
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = io::Result<BytesMut>> {
+        stream::unfold(Some(self), |vod| async move {
+            let mut vod = vod?;
+            /*the file is read on the blocking threads*/
+            let (vod, result) = match tokio::task::spawn_blocking(move || {
+                let result = vod.read_chunk();
+                (vod, result)
+            })
+            .await
+            {
+                Ok(read) => read,
+                Err(err) => {
+                    log::error!("http-flv vod read error: {}", err);
+                    return Some((Err(io::Error::other(err)), None));
+                }
+            };
+            match result {
+                Ok(Some(data)) => Some((Ok(data), Some(vod))),
+                Ok(None) => None,
+                Err(err) => {
+                    log::error!("http-flv vod read error: {}", err);
+                    Some((Err(io::Error::other(err.to_string())), None))
+                }
+            }
+        })
+    }
+}
+
+/*The byte range of a "Range: bytes=..." header, the first and the last
+positions are inclusive. Only a single range is supported, None if the range
+cannot be satisfied.*/
+pub fn parse_range(value: &str, size: u64) -> Option<(u64, u64)> {
+    let (first, last) = value.strip_prefix("bytes=")?.trim().split_once('-')?;
+    let (first, last) = if first.is_empty() {
+        /*the suffix range: the last bytes of the file*/
+        let len = last.parse::<u64>().ok()?.min(size);
+        (size.checked_sub(len)?, size.checked_sub(1)?)
+    } else {
+        let first = first.parse::<u64>().ok()?;
+        let last = match last {
+            "" => size.checked_sub(1)?,
+            _ => last.parse::<u64>().ok()?.min(size.checked_sub(1)?),
+        };
+        (first, last)
+    };
+    if first > last || first >= size {
+        return None;
+    }
+    Some((first, last))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_range, HttpFlvVod};
+    use futures::StreamExt;
+    use streamhub::vod::VodFile;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(parse_range("bytes=900-", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=500-2000", 1000), Some((500, 999)));
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=-0", 1000), None);
+        assert_eq!(parse_range("bytes=99-0", 1000), None);
+        assert_eq!(parse_range("items=0-99", 1000), None);
+    }
+
+    #[tokio::test]
+    async fn test_remux_from_start() {
+        let tag = |tag_type: u8, timestamp: u32, body: &[u8]| {
+            let mut tag = vec![tag_type, 0, 0, body.len() as u8, 0];
+            tag.extend_from_slice(&(timestamp as u16).to_be_bytes());
+            tag.extend_from_slice(&[0, 0, 0, 0]);
+            tag.extend_from_slice(body);
+            tag.extend_from_slice(&(body.len() as u32 + 11).to_be_bytes());
+            tag
+        };
+        let mut data = vec![b'F', b'L', b'V', 1, 1, 0, 0, 0, 9, 0, 0, 0, 0];
+        data.extend(tag(9, 0, &[0x17, 0x00, 0, 0, 0, 0x01]));
+        for timestamp in (0..4000).step_by(1000) {
+            data.extend(tag(9, timestamp, &[0x17, 0x01, 0, 0, 0, 0xff]));
+        }
+        let path = std::env::temp_dir().join(format!("xiu_http_vod_{}.flv", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+
+        let vod = HttpFlvVod::new(VodFile::open(&path).unwrap(), 2500);
+        let body: Vec<_> = vod.into_stream().collect().await;
+        let body = body.into_iter().next().unwrap().unwrap();
+        /*the header, the sequence header at 2000 and the frames at 2000 and 3000*/
+        assert_eq!(&body[..13], &data[..13]);
+        assert_eq!(body.len(), 13 + 3 * (11 + 6 + 4));
+        assert_eq!(&body[13 + 4..13 + 7], &[0x00, 0x07, 0xd0]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
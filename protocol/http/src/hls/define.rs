@@ -1,4 +1,10 @@
-use xflv::demuxer::{FlvDemuxerAudioData, FlvDemuxerVideoData};
+use {
+    commonlib::{
+        config::{HlsAppConfig, HlsEncryptionConfig, HlsSegmentFormat},
+        record::RecordSettings,
+    },
+    xflv::demuxer::{FlvDemuxerAudioData, FlvDemuxerVideoData},
+};
 
 pub const HLS_DURATION: u8 = 10;
 
@@ -7,3 +13,156 @@ pub enum FlvDemuxerData {
     Audio { data: FlvDemuxerAudioData },
     None,
 }
+
+/*The hls settings of a stream, the defaults overridden by the settings of its
+app and then by the ones of the stream. The durations are in milliseconds.*/
+#[derive(Debug, Clone)]
+pub struct HlsSettings {
+    pub target_duration: i64,
+    pub playlist_length: usize,
+    pub delete_after: usize,
+    pub discontinuity_threshold: i64,
+    pub output_root: String,
+    pub segment_name: String,
+    pub segment_format: HlsSegmentFormat,
+    /*the stream is recorded under output_root if no record root is set, None
+    if it is not recorded*/
+    pub record: Option<RecordSettings>,
+    /*LL-HLS part target duration, None disables LL-HLS*/
+    pub part_duration: Option<i64>,
+    /*the stream is a variant of a rendition group, its segments are cut on the
+    multiples of the target duration so that they line up with the other variants*/
+    pub align_segments: bool,
+    /*AES-128 encryption, None if it is not enabled*/
+    pub encryption: Option<HlsEncryptionConfig>,
+    /*the media retained for the time-shift playlists, 0 disables the DVR window*/
+    pub dvr_window: i64,
+    /*the stream is packaged on demand and its packaging stops after this long
+    without playlist requests, None if it is always packaged*/
+    pub idle_timeout: Option<i64>,
+    /*the segments of an audio-only stream are packed audio*/
+    pub packed_audio: bool,
+}
+
+impl HlsSettings {
+    pub fn new(part_duration: Option<i64>) -> Self {
+        Self {
+            target_duration: 5 * 1000,
+            playlist_length: 6,
+            delete_after: 0,
+            discontinuity_threshold: 15 * 1000,
+            output_root: String::from("./hls"),
+            segment_name: String::from("{seq}"),
+            segment_format: HlsSegmentFormat::Ts,
+            record: None,
+            part_duration,
+            align_segments: false,
+            encryption: None,
+            dvr_window: 0,
+            idle_timeout: None,
+            packed_audio: false,
+        }
+    }
+
+    pub fn resolve(mut self, hls_apps: &[HlsAppConfig], app_name: &str, stream_name: &str) -> Self {
+        let app = hls_apps
+            .iter()
+            .find(|c| c.app == app_name && c.stream.is_none());
+        let stream = hls_apps
+            .iter()
+            .find(|c| c.app == app_name && c.stream.as_deref() == Some(stream_name));
+        for config in [app, stream].into_iter().flatten() {
+            self.apply(config);
+        }
+        self
+    }
+
+    fn apply(&mut self, config: &HlsAppConfig) {
+        if let Some(target_duration) = config.target_duration {
+            self.target_duration = std::cmp::max(target_duration, 1) as i64 * 1000;
+        }
+        if let Some(playlist_length) = config.playlist_length {
+            self.playlist_length = std::cmp::max(playlist_length, 1);
+        }
+        if let Some(delete_after) = config.delete_after {
+            self.delete_after = delete_after;
+        }
+        if let Some(threshold) = config.discontinuity_threshold {
+            self.discontinuity_threshold = threshold as i64 * 1000;
+        }
+        if let Some(output_root) = &config.output_root {
+            self.output_root = output_root.trim_end_matches('/').to_string();
+        }
+        if let Some(segment_name) = &config.segment_name {
+            /*the names must be unique and be served from the stream path*/
+            if segment_name.contains("{seq}") && !segment_name.contains('/') {
+                self.segment_name = segment_name.clone();
+            } else {
+                log::warn!("invalid hls segment name template: {}", segment_name);
+            }
+        }
+        if let Some(segment_format) = config.segment_format {
+            self.segment_format = segment_format;
+        }
+        if let Some(dvr_window) = config.dvr_window {
+            self.dvr_window = dvr_window as i64 * 60 * 1000;
+        }
+        if let Some(packed_audio) = config.packed_audio {
+            self.packed_audio = packed_audio;
+        }
+        if let Some(encryption) = &config.encryption {
+            self.encryption = Some(encryption.clone()).filter(|e| e.enabled);
+        }
+    }
+
+    pub fn segment_file_name(&self, app_name: &str, stream_name: &str, seq: u32) -> String {
+        self.segment_name
+            .replace("{app}", app_name)
+            .replace("{stream}", stream_name)
+            .replace("{seq}", &seq.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HlsSettings;
+    use commonlib::config::{HlsAppConfig, HlsSegmentFormat};
+
+    #[test]
+    fn test_resolve_settings() {
+        let hls_apps = vec![
+            HlsAppConfig {
+                app: String::from("live"),
+                segment_format: Some(HlsSegmentFormat::Fmp4),
+                target_duration: Some(2),
+                playlist_length: Some(10),
+                segment_name: Some(String::from("{stream}-{seq}")),
+                ..Default::default()
+            },
+            HlsAppConfig {
+                app: String::from("live"),
+                stream: Some(String::from("news")),
+                target_duration: Some(4),
+                segment_name: Some(String::from("../{seq}")),
+                ..Default::default()
+            },
+        ];
+
+        let settings = HlsSettings::new(None).resolve(&hls_apps, "live", "sports");
+        assert_eq!(settings.target_duration, 2000);
+        assert_eq!(settings.playlist_length, 10);
+        assert_eq!(settings.segment_format, HlsSegmentFormat::Fmp4);
+        assert_eq!(settings.segment_file_name("live", "sports", 7), "sports-7");
+
+        /*the stream settings win, the invalid template is ignored*/
+        let settings = HlsSettings::new(None).resolve(&hls_apps, "live", "news");
+        assert_eq!(settings.target_duration, 4000);
+        assert_eq!(settings.playlist_length, 10);
+        assert_eq!(settings.segment_file_name("live", "news", 7), "news-7");
+
+        let settings = HlsSettings::new(None).resolve(&hls_apps, "other", "news");
+        assert_eq!(settings.target_duration, 5000);
+        assert_eq!(settings.discontinuity_threshold, 15000);
+        assert_eq!(settings.segment_file_name("other", "news", 7), "7");
+    }
+}
@@ -0,0 +1,223 @@
+use {
+    chrono::{DateTime, Duration, SecondsFormat, Utc},
+    std::collections::VecDeque,
+};
+
+#[derive(Debug, Clone)]
+pub struct DvrSegment {
+    /*media sequence number*/
+    pub sequence: u64,
+    /*milliseconds*/
+    pub duration: i64,
+    pub name: String,
+    pub program_date_time: DateTime<Utc>,
+    pub discontinuity: bool,
+    pub map: Option<String>,
+    pub key: Option<String>,
+}
+
+impl DvrSegment {
+    fn end_time(&self) -> DateTime<Utc> {
+        self.program_date_time + Duration::milliseconds(self.duration)
+    }
+}
+
+pub fn program_date_time_tag(time: &DateTime<Utc>) -> String {
+    format!(
+        "#EXT-X-PROGRAM-DATE-TIME:{}\n",
+        time.to_rfc3339_opts(SecondsFormat::Millis, true)
+    )
+}
+
+/*The segments retained for the DVR window of a stream, shared by the playlist
+writer and the http server which generates the time-shift playlists from them.
+At least the segments of the live playlist are retained.*/
+pub struct DvrIndex {
+    version: u16,
+    playlist_length: usize,
+    /*milliseconds*/
+    window: i64,
+    retained_duration: i64,
+    segments: VecDeque<DvrSegment>,
+    /*segments have left the window, so the playlists are no longer append-only*/
+    evicted: bool,
+}
+
+impl DvrIndex {
+    pub fn new(version: u16, playlist_length: usize, window: i64) -> Self {
+        Self {
+            version,
+            playlist_length,
+            window,
+            retained_duration: 0,
+            segments: VecDeque::new(),
+            evicted: false,
+        }
+    }
+
+    /*the names of the segments out of the window are returned to be deleted*/
+    pub fn push(&mut self, segment: DvrSegment) -> Vec<String> {
+        self.retained_duration += segment.duration;
+        self.segments.push_back(segment);
+
+        let mut evicted = Vec::new();
+        while let Some(front) = self.segments.front() {
+            if self.segments.len() <= self.playlist_length
+                || self.retained_duration - front.duration < self.window
+            {
+                break;
+            }
+            self.retained_duration -= front.duration;
+            evicted.push(self.segments.pop_front().unwrap().name);
+            self.evicted = true;
+        }
+        evicted
+    }
+
+    /*the sequence number of the oldest retained segment*/
+    pub fn first_sequence(&self) -> Option<u64> {
+        self.segments.front().map(|s| s.sequence)
+    }
+
+    /*A playlist from the segment containing the start time to the live edge,
+    it starts with the oldest retained segment if the time is out of the
+    window. It is an event playlist until the first segment leaves the window,
+    then segments are removed from its head like a live playlist.*/
+    pub fn event_playlist(&self, start: DateTime<Utc>) -> Option<String> {
+        let from = self
+            .segments
+            .iter()
+            .position(|s| s.end_time() > start)
+            .unwrap_or(self.segments.len().saturating_sub(1));
+        let playlist_type = Some("EVENT").filter(|_| !self.evicted);
+        self.generate(from, self.segments.len(), playlist_type)
+    }
+
+    /*A sliding playlist whose live edge is `offset` milliseconds behind the
+    live edge of the stream, the offset is limited to the retained media.*/
+    pub fn time_shift_playlist(&self, offset: i64) -> Option<String> {
+        let live_edge = self.segments.back()?.end_time();
+        let offset = offset
+            .unsigned_abs()
+            .min(self.retained_duration.max(0) as u64) as i64;
+        let shifted_edge = live_edge - Duration::milliseconds(offset);
+        let to = self
+            .segments
+            .iter()
+            .position(|s| s.end_time() > shifted_edge)
+            .map_or(self.segments.len(), |index| std::cmp::max(index, 1));
+        self.generate(to.saturating_sub(self.playlist_length), to, None)
+    }
+
+    fn generate(&self, from: usize, to: usize, playlist_type: Option<&str>) -> Option<String> {
+        if from >= to {
+            return None;
+        }
+        let segments = self.segments.range(from..to);
+        let target_duration = self
+            .segments
+            .range(from..to)
+            .map(|s| (s.duration + 999) / 1000)
+            .max()
+            .unwrap_or(1);
+
+        let mut content = String::from("#EXTM3U\n");
+        content += format!("#EXT-X-VERSION:{}\n", self.version).as_str();
+        content += format!("#EXT-X-TARGETDURATION:{target_duration}\n").as_str();
+        content += format!("#EXT-X-MEDIA-SEQUENCE:{}\n", self.segments[from].sequence).as_str();
+        if let Some(playlist_type) = playlist_type {
+            content += format!("#EXT-X-PLAYLIST-TYPE:{playlist_type}\n").as_str();
+        }
+
+        let mut map = None;
+        let mut key = None;
+        for segment in segments {
+            if segment.discontinuity {
+                content += "#EXT-X-DISCONTINUITY\n";
+            }
+            if segment.map.is_some() && segment.map != map {
+                map = segment.map.clone();
+                content += format!("#EXT-X-MAP:URI=\"{}\"\n", map.as_ref().unwrap()).as_str();
+            }
+            if segment.key.is_some() && segment.key != key {
+                key = segment.key.clone();
+                content += format!(
+                    "#EXT-X-KEY:METHOD=AES-128,URI=\"{}\"\n",
+                    key.as_ref().unwrap()
+                )
+                .as_str();
+            }
+            content += program_date_time_tag(&segment.program_date_time).as_str();
+            content += format!(
+                "#EXTINF:{:.3}\n{}\n",
+                segment.duration as f64 / 1000.0,
+                segment.name
+            )
+            .as_str();
+        }
+        Some(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DvrIndex, DvrSegment};
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn test_dvr_index() {
+        /*a window of 20 seconds, 2 segments in the live playlist*/
+        let mut index = DvrIndex::new(3, 2, 20 * 1000);
+        let start = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let mut evicted = Vec::new();
+        for sequence in 0..8 {
+            evicted.extend(index.push(DvrSegment {
+                sequence,
+                duration: 5000,
+                name: format!("{sequence}.ts"),
+                program_date_time: start + Duration::seconds(sequence as i64 * 5),
+                discontinuity: false,
+                map: None,
+                key: None,
+            }));
+        }
+        assert_eq!(evicted, vec!["0.ts", "1.ts", "2.ts", "3.ts"]);
+
+        /*from the segment containing 12:00:27 to the live edge, the head of
+        the playlist is removed as segments leave the window*/
+        let playlist = index.event_playlist(start + Duration::seconds(27)).unwrap();
+        assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:5\n#EXT-X-PROGRAM-DATE-TIME"));
+        assert!(!playlist.contains("#EXT-X-PLAYLIST-TYPE"));
+        assert!(playlist
+            .contains("#EXT-X-PROGRAM-DATE-TIME:2024-05-01T12:00:25.000Z\n#EXTINF:5.000\n5.ts\n"));
+        assert!(playlist.ends_with("7.ts\n"));
+
+        /*10 seconds behind the live edge*/
+        let playlist = index.time_shift_playlist(-10 * 1000).unwrap();
+        assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:4\n"));
+        assert!(playlist.ends_with(
+            "4.ts\n#EXT-X-PROGRAM-DATE-TIME:2024-05-01T12:00:25.000Z\n#EXTINF:5.000\n5.ts\n"
+        ));
+
+        /*out of the window*/
+        let playlist = index.time_shift_playlist(-3600 * 1000).unwrap();
+        assert!(playlist.ends_with("\n4.ts\n"));
+        for offset in [i64::MIN, i64::MAX] {
+            assert_eq!(index.time_shift_playlist(offset), Some(playlist.clone()));
+        }
+
+        /*an event playlist while no segment has left the window*/
+        let mut index = DvrIndex::new(3, 2, 20 * 1000);
+        index.push(DvrSegment {
+            sequence: 0,
+            duration: 5000,
+            name: String::from("0.ts"),
+            program_date_time: start,
+            discontinuity: false,
+            map: None,
+            key: None,
+        });
+        let playlist = index.event_playlist(start).unwrap();
+        assert!(playlist.contains("#EXT-X-PLAYLIST-TYPE:EVENT\n"));
+    }
+}
@@ -0,0 +1,154 @@
+use {
+    aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit},
+    rand::Rng,
+};
+
+type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+
+pub const KEY_SIZE: usize = 16;
+/*The keys of a stream are served at /{app}/{stream}/{key}.key, the default
+URI is relative to the playlist so it resolves to the same path.*/
+pub const DEFAULT_KEY_URI: &str = "{key}.key";
+
+/*the app, the stream and the key file name of a key request path*/
+pub fn parse_key_path(path: &str) -> Option<(&str, &str, &str)> {
+    let mut rv = path.strip_prefix('/')?.split('/');
+    match (rv.next(), rv.next(), rv.next(), rv.next()) {
+        (Some(app), Some(stream), Some(key), None) if key.ends_with(".key") => {
+            Some((app, stream, key))
+        }
+        _ => None,
+    }
+}
+
+pub struct HlsKey {
+    /*the key file name without the extension, "key_{index}"*/
+    pub name: String,
+    pub key: [u8; KEY_SIZE],
+    /*the URI of the EXT-X-KEY tag*/
+    pub uri: String,
+}
+
+/*Generates the AES-128 keys of a stream and rotates them every `rotation`
+segments, a rotation of 0 keeps the first key for the whole stream.*/
+pub struct HlsEncryptor {
+    rotation: u32,
+    /*{app}, {stream} and {key} are replaced*/
+    key_uri: String,
+    app_name: String,
+    stream_name: String,
+    key_count: u32,
+    /*how many segments are encrypted by the current key*/
+    key_segments: u32,
+    current: Option<HlsKey>,
+}
+
+impl HlsEncryptor {
+    pub fn new(rotation: u32, key_uri: String, app_name: String, stream_name: String) -> Self {
+        Self {
+            rotation,
+            key_uri,
+            app_name,
+            stream_name,
+            key_count: 0,
+            key_segments: 0,
+            current: None,
+        }
+    }
+
+    /*The key of the next segment, the new key is also returned if it is
+    generated for this segment.*/
+    pub fn next_key(&mut self) -> (&HlsKey, bool) {
+        let rotate = self.rotation > 0 && self.key_segments >= self.rotation;
+        let generated = self.current.is_none() || rotate;
+        if generated {
+            let name = format!("key_{}", self.key_count);
+            let uri = self
+                .key_uri
+                .replace("{app}", &self.app_name)
+                .replace("{stream}", &self.stream_name)
+                .replace("{key}", &name);
+            self.current = Some(HlsKey {
+                name,
+                key: rand::thread_rng().gen(),
+                uri,
+            });
+            self.key_count += 1;
+            self.key_segments = 0;
+        }
+        self.key_segments += 1;
+        (self.current.as_ref().unwrap(), generated)
+    }
+
+    /*the key of the last segment*/
+    pub fn current(&self) -> Option<&HlsKey> {
+        self.current.as_ref()
+    }
+}
+
+/*AES-128-CBC with PKCS7 padding over the whole segment, the IV is the media
+sequence number of the segment since the EXT-X-KEY tags carry no IV.*/
+pub fn encrypt_segment(key: &[u8; KEY_SIZE], sequence_number: u64, data: &[u8]) -> Vec<u8> {
+    let iv = (sequence_number as u128).to_be_bytes();
+    Aes128CbcEnc::new(key.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encrypt_segment, parse_key_path, HlsEncryptor};
+
+    #[test]
+    fn test_key_rotation() {
+        let mut encryptor = HlsEncryptor::new(
+            2,
+            String::from("/{app}/{stream}/{key}.key"),
+            String::from("live"),
+            String::from("paid"),
+        );
+
+        let (key, generated) = encryptor.next_key();
+        assert!(generated);
+        assert_eq!(key.uri, "/live/paid/key_0.key");
+        /*the absolute URI is a path of the key endpoint*/
+        assert_eq!(
+            parse_key_path(&key.uri),
+            Some(("live", "paid", "key_0.key"))
+        );
+        assert_eq!(parse_key_path("/keys/live/paid/key_0.key"), None);
+        let first = key.key;
+
+        let (key, generated) = encryptor.next_key();
+        assert!(!generated);
+        assert_eq!(key.key, first);
+
+        let (key, generated) = encryptor.next_key();
+        assert!(generated);
+        assert_eq!(key.name, "key_1");
+    }
+
+    #[test]
+    fn test_encrypt_segment() {
+        /*with the zero IV of sequence number 0 the first block is the one of
+        NIST SP 800-38A F.1.1 ECB-AES128.Encrypt*/
+        let key = [
+            0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf,
+            0x4f, 0x3c,
+        ];
+        let plain = [
+            0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93,
+            0x17, 0x2a,
+        ];
+        let encrypted = encrypt_segment(&key, 0, &plain);
+        assert_eq!(
+            &encrypted[..16],
+            &[
+                0x3a, 0xd7, 0x7b, 0xb4, 0x0d, 0x7a, 0x36, 0x60, 0xa8, 0x9e, 0xca, 0xf3, 0x24, 0x66,
+                0xef, 0x97
+            ]
+        );
+        /*a full block of padding*/
+        assert_eq!(encrypted.len(), 32);
+
+        assert_eq!(encrypt_segment(&key, 1, &[0x47; 188]).len(), 192);
+    }
+}
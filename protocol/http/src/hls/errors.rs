@@ -5,6 +5,7 @@ use {
     tokio::sync::broadcast::error::RecvError,
     tokio::sync::oneshot::error::RecvError as OneshotRecvError,
     xflv::errors::FlvDemuxerError,
+    xmp4::errors::Mp4Error,
     xmpegts::errors::MpegTsError,
 };
 
@@ -33,6 +34,8 @@ pub enum MediaErrorValue {
     FlvDemuxerError(#[cause] FlvDemuxerError),
     #[fail(display = "mpegts error:{}", _0)]
     MpegTsError(#[cause] MpegTsError),
+    #[fail(display = "mp4 error:{}", _0)]
+    Mp4Error(#[cause] Mp4Error),
     #[fail(display = "write file error:{}", _0)]
     IOError(#[cause] std::io::Error),
 }
@@ -53,6 +56,14 @@ impl From<MpegTsError> for MediaError {
     }
 }
 
+impl From<Mp4Error> for MediaError {
+    fn from(error: Mp4Error) -> Self {
+        MediaError {
+            value: MediaErrorValue::Mp4Error(error),
+        }
+    }
+}
+
 impl From<std::io::Error> for MediaError {
     fn from(error: std::io::Error) -> Self {
         MediaError {
@@ -1,8 +1,17 @@
 use {
-    super::{define::FlvDemuxerData, errors::MediaError, m3u8::M3u8},
+    super::{
+        define::{FlvDemuxerData, HlsSettings},
+        errors::MediaError,
+        fmp4::{Flv2Fmp4, Fmp4Sample},
+        m3u8::M3u8,
+        packed_audio::id3_timestamp_tag,
+        registry::HlsRegistry,
+    },
     bytes::BytesMut,
+    commonlib::config::HlsSegmentFormat,
+    std::sync::Arc,
     xflv::{
-        define::{frame_type, FlvData},
+        define::{frame_type, u8_2_avc_codec_id, AvcCodecId, FlvData},
         demuxer::{FlvAudioTagDemuxer, FlvVideoTagDemuxer},
     },
     xmpegts::{
@@ -11,11 +20,17 @@ use {
     },
 };
 
+/*milliseconds, an audio stream without a video sequence header is regarded as
+audio-only after it*/
+const TRACK_DETECTION_TIMEOUT: i64 = 500;
+
 pub struct Flv2HlsRemuxer {
     video_demuxer: FlvVideoTagDemuxer,
     audio_demuxer: FlvAudioTagDemuxer,
 
     ts_muxer: TsMuxer,
+    segment_format: HlsSegmentFormat,
+    fmp4: Flv2Fmp4,
 
     last_ts_dts: i64,
     last_ts_pts: i64,
@@ -23,30 +38,58 @@ pub struct Flv2HlsRemuxer {
     last_dts: i64,
     last_pts: i64,
 
+    /*the target duration of the segments in milliseconds*/
     duration: i64,
+    /*a longer gap(milliseconds) between two segments is a discontinuity*/
+    discontinuity_threshold: i64,
+    align_segments: bool,
     need_new_segment: bool,
-
-    video_pid: u16,
-    audio_pid: u16,
+    /*the publisher changed, the segment is cut at the next key frame*/
+    cut_discontinuity: bool,
+    /*the current segment starts after a discontinuity*/
+    segment_discontinuity: bool,
+
+    /*LL-HLS part target duration in milliseconds*/
+    part_duration: Option<i64>,
+    last_part_dts: i64,
+    /*the current part starts with a key frame*/
+    part_independent: bool,
+
+    /*the streams are added to the PMT when the sequence headers of the tracks
+    are received*/
+    video_pid: Option<u16>,
+    audio_pid: Option<u16>,
+    /*a video track is received, the segments are only cut on key frames then*/
+    has_video: bool,
+    /*the tracks are known, when both sequence headers are received, at the
+    first video frame or TRACK_DETECTION_TIMEOUT after the first audio frame*/
+    media_started: bool,
+    /*the audio frames received before the tracks are known*/
+    pending_audio: Vec<FlvDemuxerData>,
+    packed_audio_enabled: bool,
+    /*the frames of the current part or segment of a packed audio stream*/
+    packed_audio: Option<BytesMut>,
 
     m3u8_handler: M3u8,
 }
 
 impl Flv2HlsRemuxer {
-    pub fn new(duration: i64, app_name: String, stream_name: String, need_record: bool) -> Self {
-        let mut ts_muxer = TsMuxer::new();
-        let audio_pid = ts_muxer
-            .add_stream(epsi_stream_type::PSI_STREAM_AAC, BytesMut::new())
-            .unwrap();
-        let video_pid = ts_muxer
-            .add_stream(epsi_stream_type::PSI_STREAM_H264, BytesMut::new())
-            .unwrap();
+    pub fn new(
+        app_name: String,
+        stream_name: String,
+        settings: HlsSettings,
+        registry: Arc<HlsRegistry>,
+    ) -> Self {
+        let packed_audio_enabled =
+            settings.packed_audio && settings.segment_format == HlsSegmentFormat::Ts;
 
         Self {
             video_demuxer: FlvVideoTagDemuxer::new(),
             audio_demuxer: FlvAudioTagDemuxer::new(),
 
-            ts_muxer,
+            ts_muxer: TsMuxer::new(),
+            segment_format: settings.segment_format,
+            fmp4: Flv2Fmp4::new(),
 
             last_ts_dts: 0,
             last_ts_pts: 0,
@@ -54,24 +97,44 @@ impl Flv2HlsRemuxer {
             last_dts: 0,
             last_pts: 0,
 
-            duration,
+            duration: settings.target_duration,
+            discontinuity_threshold: settings.discontinuity_threshold,
+            align_segments: settings.align_segments,
             need_new_segment: false,
-
-            video_pid,
-            audio_pid,
-
-            m3u8_handler: M3u8::new(duration, 6, app_name, stream_name, need_record),
+            cut_discontinuity: false,
+            segment_discontinuity: false,
+
+            part_duration: settings.part_duration,
+            last_part_dts: 0,
+            part_independent: true,
+
+            video_pid: None,
+            audio_pid: None,
+            has_video: false,
+            media_started: false,
+            pending_audio: Vec::new(),
+            packed_audio_enabled,
+            packed_audio: None,
+
+            m3u8_handler: M3u8::new(app_name, stream_name, settings, registry),
         }
     }
 
     pub fn process_flv_data(&mut self, data: FlvData) -> Result<(), MediaError> {
+        if self.segment_format == HlsSegmentFormat::Fmp4 {
+            return self.process_fmp4_data(data);
+        }
+
         let flv_demux_data: FlvDemuxerData = match data {
             FlvData::Audio { timestamp, data } => {
                 let audio_data = self.audio_demuxer.demux(timestamp, data)?;
+                self.update_tracks()?;
                 FlvDemuxerData::Audio { data: audio_data }
             }
             FlvData::Video { timestamp, data } => {
-                if let Some(video_data) = self.video_demuxer.demux(timestamp, data)? {
+                let video_data = self.video_demuxer.demux(timestamp, data)?;
+                self.update_tracks()?;
+                if let Some(video_data) = video_data {
                     FlvDemuxerData::Video { data: video_data }
                 } else {
                     return Ok(());
@@ -80,15 +143,76 @@ impl Flv2HlsRemuxer {
             _ => return Ok(()),
         };
 
-        self.process_demux_data(&flv_demux_data)?;
+        self.process_demux_data(flv_demux_data)?;
+
+        Ok(())
+    }
+
+    /*The PMT lists the tracks whose sequence headers are received, the video
+    stream type follows the codec of the last sequence header.*/
+    fn update_tracks(&mut self) -> Result<(), MediaError> {
+        if self.audio_pid.is_none() && self.audio_demuxer.has_sequence_header() {
+            self.audio_pid = Some(
+                self.ts_muxer
+                    .add_stream(epsi_stream_type::PSI_STREAM_AAC, BytesMut::new())?,
+            );
+        }
 
+        let stream_type = match u8_2_avc_codec_id(self.video_demuxer.codec_id()) {
+            AvcCodecId::H264 => epsi_stream_type::PSI_STREAM_H264,
+            AvcCodecId::HEVC => epsi_stream_type::PSI_STREAM_H265,
+            AvcCodecId::UNKNOWN => return Ok(()),
+        };
+        match self.video_pid {
+            Some(video_pid) => self.ts_muxer.set_stream_type(video_pid, stream_type)?,
+            None => {
+                self.video_pid = Some(self.ts_muxer.add_stream(stream_type, BytesMut::new())?);
+            }
+        }
+        Ok(())
+    }
+
+    /*an audio-only stream is segmented as packed audio if it is enabled, the
+    video received later is dropped. The audio frames held back until the
+    tracks are known are muxed first.*/
+    fn on_media_started(&mut self) -> Result<(), MediaError> {
+        self.media_started = true;
+        if self.packed_audio_enabled && self.video_pid.is_none() && self.audio_pid.is_some() {
+            log::info!("audio-only hls stream, segmented as packed audio");
+            self.packed_audio = Some(BytesMut::new());
+            self.m3u8_handler.set_segment_extension("aac");
+        }
+        for data in std::mem::take(&mut self.pending_audio) {
+            self.process_demux_data(data)?;
+        }
         Ok(())
     }
 
+    /*the tracks are unknown until both sequence headers are received, a video
+    frame is received or the audio frames reach TRACK_DETECTION_TIMEOUT*/
+    fn tracks_known(&self, is_video: bool, dts: i64) -> bool {
+        let first_dts = match self.pending_audio.first() {
+            Some(FlvDemuxerData::Audio { data }) => data.dts,
+            _ => dts,
+        };
+        is_video || self.video_pid.is_some() || dts - first_dts >= TRACK_DETECTION_TIMEOUT
+    }
+
+    /*the following frames are from a new publisher, they start a new segment
+    with a discontinuity*/
+    pub fn on_discontinuity(&mut self) {
+        if self.media_started {
+            self.cut_discontinuity = true;
+        }
+    }
+
     pub fn flush_remaining_data(&mut self) -> Result<(), MediaError> {
-        let data = self.ts_muxer.get_data();
-        let mut discontinuity: bool = false;
-        if self.last_dts > self.last_ts_dts + 15 * 1000 {
+        if !self.media_started && !self.pending_audio.is_empty() {
+            self.on_media_started()?;
+        }
+        let data = self.flush_part(self.last_dts)?;
+        let mut discontinuity: bool = self.segment_discontinuity;
+        if self.last_dts > self.last_ts_dts + self.discontinuity_threshold {
             discontinuity = true;
         }
         self.m3u8_handler.add_segment(
@@ -102,30 +226,67 @@ impl Flv2HlsRemuxer {
         Ok(())
     }
 
-    pub fn process_demux_data(
-        &mut self,
-        flv_demux_data: &FlvDemuxerData,
-    ) -> Result<(), MediaError> {
-        self.need_new_segment = false;
+    fn process_fmp4_data(&mut self, data: FlvData) -> Result<(), MediaError> {
+        let sample = match self.fmp4.demux(data)? {
+            Some(sample) => sample,
+            None => return Ok(()),
+        };
+
+        let (dts, pts, is_key_frame, is_video) = match &sample {
+            Fmp4Sample::Video(video) => (video.dts, video.pts, video.is_sync, true),
+            Fmp4Sample::Audio(audio) => (audio.dts, audio.pts, false, false),
+        };
+        self.on_frame(dts, pts, is_key_frame, is_video)?;
+        self.fmp4.write(sample);
+
+        Ok(())
+    }
+
+    /*In the LL-HLS mode the buffered data becomes a part of the current segment
+    and an empty buffer is returned, otherwise the buffered data is returned.*/
+    fn flush_part(&mut self, dts: i64) -> Result<BytesMut, MediaError> {
+        let data = match self.segment_format {
+            HlsSegmentFormat::Ts => match &mut self.packed_audio {
+                Some(packed_audio) => packed_audio.split(),
+                None => self.ts_muxer.get_data(),
+            },
+            HlsSegmentFormat::Fmp4 => {
+                /*the init segment must be ready before the fragments referencing it*/
+                if let Some(init_segment) = self.fmp4.take_init_segment()? {
+                    self.m3u8_handler.set_init_segment(init_segment)?;
+                }
+                self.fmp4.flush_fragment(dts)?
+            }
+        };
+        if self.part_duration.is_none() {
+            return Ok(data);
+        }
+
+        self.m3u8_handler
+            .add_part(dts - self.last_part_dts, self.part_independent, data)?;
+        self.last_part_dts = dts;
+        Ok(BytesMut::new())
+    }
 
+    pub fn process_demux_data(&mut self, flv_demux_data: FlvDemuxerData) -> Result<(), MediaError> {
         let pid: u16;
         let pts: i64;
         let dts: i64;
         let mut flags: u16 = 0;
         let mut payload: BytesMut = BytesMut::new();
 
-        match flv_demux_data {
+        match &flv_demux_data {
             FlvDemuxerData::Video { data } => {
                 pts = data.pts;
                 dts = data.dts;
-                pid = self.video_pid;
+                pid = match self.video_pid {
+                    Some(video_pid) => video_pid,
+                    None => return Ok(()),
+                };
                 payload.extend_from_slice(&data.data[..]);
 
                 if data.frame_type == frame_type::KEY_FRAME {
                     flags = MPEG_FLAG_IDR_FRAME;
-                    if dts - self.last_ts_dts >= self.duration * 1000 {
-                        self.need_new_segment = true;
-                    }
                 }
             }
             FlvDemuxerData::Audio { data } => {
@@ -135,22 +296,77 @@ impl Flv2HlsRemuxer {
 
                 pts = data.pts;
                 dts = data.dts;
-                pid = self.audio_pid;
+                pid = match self.audio_pid {
+                    Some(audio_pid) => audio_pid,
+                    None => return Ok(()),
+                };
                 payload.extend_from_slice(&data.data[..]);
-
-                if dts - self.last_ts_dts >= self.duration * 1000 {
-                    self.need_new_segment = true;
-                }
             }
             _ => return Ok(()),
         }
 
+        let is_video = matches!(flv_demux_data, FlvDemuxerData::Video { .. });
+        if !self.media_started {
+            if !self.tracks_known(is_video, dts) {
+                self.pending_audio.push(flv_demux_data);
+                return Ok(());
+            }
+            self.on_media_started()?;
+        }
+        if is_video && self.packed_audio.is_some() {
+            return Ok(());
+        }
+        self.on_frame(dts, pts, flags == MPEG_FLAG_IDR_FRAME, is_video)?;
+
+        if let Some(packed_audio) = &mut self.packed_audio {
+            /*every part and segment starts with its timestamp*/
+            if packed_audio.is_empty() {
+                packed_audio.extend_from_slice(&id3_timestamp_tag(pts * 90));
+            }
+            packed_audio.extend_from_slice(&payload);
+            return Ok(());
+        }
+        self.ts_muxer
+            .write(pid, pts * 90, dts * 90, flags, payload)?;
+
+        Ok(())
+    }
+
+    /*Called before a frame is muxed, the segment is cut before a key frame or an
+    audio frame once the target duration is reached, and in the LL-HLS mode
+    the parts are cut inside the segment.*/
+    fn on_frame(
+        &mut self,
+        dts: i64,
+        pts: i64,
+        is_key_frame: bool,
+        is_video: bool,
+    ) -> Result<(), MediaError> {
+        /*the variants of a group with lined up key frames are cut at the same
+        timestamps whenever they are started*/
+        if is_video {
+            self.has_video = true;
+        }
+        /*the segments of a stream with video start with a key frame, the ones of
+        an audio-only stream are cut on the target duration*/
+        let can_cut = if self.has_video {
+            is_video && is_key_frame
+        } else {
+            true
+        };
+        let segment_end = if self.align_segments {
+            (self.last_ts_dts / self.duration + 1) * self.duration
+        } else {
+            self.last_ts_dts + self.duration
+        };
+        self.need_new_segment = can_cut && (dts >= segment_end || self.cut_discontinuity);
+
         if self.need_new_segment {
-            let mut discontinuity: bool = false;
-            if dts > self.last_ts_dts + 15 * 1000 {
+            let mut discontinuity: bool = self.segment_discontinuity;
+            if dts > self.last_ts_dts + self.discontinuity_threshold {
                 discontinuity = true;
             }
-            let data = self.ts_muxer.get_data();
+            let data = self.flush_part(dts)?;
 
             self.m3u8_handler
                 .add_segment(dts - self.last_ts_dts, discontinuity, false, data)?;
@@ -160,14 +376,22 @@ impl Flv2HlsRemuxer {
             self.last_ts_dts = dts;
             self.last_ts_pts = pts;
             self.need_new_segment = false;
+            self.segment_discontinuity = self.cut_discontinuity;
+            self.cut_discontinuity = false;
+            self.part_independent = true;
+        } else if let Some(part_duration) = self.part_duration {
+            /*the parts are cut inside the segment on any frame, a part starting
+            with a key frame is marked as independent*/
+            if dts - self.last_part_dts >= part_duration {
+                self.flush_part(dts)?;
+                self.m3u8_handler.refresh_playlist()?;
+                self.part_independent = is_key_frame;
+            }
         }
 
         self.last_dts = dts;
         self.last_pts = pts;
 
-        self.ts_muxer
-            .write(pid, pts * 90, dts * 90, flags, payload)?;
-
         Ok(())
     }
 
@@ -177,6 +401,98 @@ impl Flv2HlsRemuxer {
 }
 #[cfg(test)]
 mod tests {
+    use super::Flv2HlsRemuxer;
+    use crate::hls::{define::HlsSettings, registry::HlsRegistry};
+    use bytes::BytesMut;
+    use std::sync::Arc;
+    use xflv::define::FlvData;
+
+    fn remuxer() -> Flv2HlsRemuxer {
+        let mut settings = HlsSettings::new(None);
+        settings.packed_audio = true;
+        Flv2HlsRemuxer::new(
+            String::from("live"),
+            String::from("test"),
+            settings,
+            Arc::new(HlsRegistry::default()),
+        )
+    }
+
+    fn audio(timestamp: u32, data: &[u8]) -> FlvData {
+        FlvData::Audio {
+            timestamp,
+            data: BytesMut::from(data),
+        }
+    }
+
+    fn video(timestamp: u32, data: &[u8]) -> FlvData {
+        FlvData::Video {
+            timestamp,
+            data: BytesMut::from(data),
+        }
+    }
+
+    /*AAC LC 44.1kHz stereo, H.264 baseline without parameter sets*/
+    const AAC_SEQUENCE_HEADER: [u8; 4] = [0xaf, 0x00, 0x12, 0x10];
+    const AAC_FRAME: [u8; 4] = [0xaf, 0x01, 0x21, 0x00];
+    const AVC_SEQUENCE_HEADER: [u8; 12] = [
+        0x17, 0x00, 0, 0, 0, 0x01, 0x42, 0x00, 0x1f, 0xff, 0xe0, 0x00,
+    ];
+    const AVC_KEY_FRAME: [u8; 11] = [0x17, 0x01, 0, 0, 0, 0, 0, 0, 2, 0x65, 0x88];
+
+    #[test]
+    fn test_late_video_sequence_header() {
+        let mut remuxer = remuxer();
+        remuxer
+            .process_flv_data(audio(0, &AAC_SEQUENCE_HEADER))
+            .unwrap();
+        for timestamp in [0, 23, 46] {
+            remuxer
+                .process_flv_data(audio(timestamp, &AAC_FRAME))
+                .unwrap();
+        }
+        /*the audio frames are held back until the tracks are known*/
+        assert!(!remuxer.media_started);
+        assert_eq!(remuxer.pending_audio.len(), 3);
+
+        remuxer
+            .process_flv_data(video(50, &AVC_SEQUENCE_HEADER))
+            .unwrap();
+        remuxer.process_flv_data(video(60, &AVC_KEY_FRAME)).unwrap();
+        assert!(remuxer.media_started);
+        assert!(remuxer.pending_audio.is_empty());
+        assert!(remuxer.packed_audio.is_none());
+        assert!(remuxer.video_pid.is_some());
+    }
+
+    #[test]
+    fn test_audio_only_timeout() {
+        let mut remuxer = remuxer();
+        remuxer
+            .process_flv_data(audio(0, &AAC_SEQUENCE_HEADER))
+            .unwrap();
+        for timestamp in (0..super::TRACK_DETECTION_TIMEOUT as u32).step_by(23) {
+            remuxer
+                .process_flv_data(audio(timestamp, &AAC_FRAME))
+                .unwrap();
+        }
+        assert!(!remuxer.media_started);
+        let timestamp = 506;
+        remuxer
+            .process_flv_data(audio(timestamp, &AAC_FRAME))
+            .unwrap();
+        assert!(remuxer.media_started);
+        /*segmented as packed audio with the held back frames*/
+        assert!(remuxer.pending_audio.is_empty());
+        assert!(!remuxer.packed_audio.as_ref().unwrap().is_empty());
+
+        /*the video received later is dropped*/
+        remuxer
+            .process_flv_data(video(timestamp, &AVC_SEQUENCE_HEADER))
+            .unwrap();
+        assert!(remuxer.packed_audio.is_some());
+    }
+
     // use std::{
     //     env,
     //     fs::{self},
@@ -3,10 +3,15 @@ use tokio::sync::oneshot;
 
 use {
     super::{
+        define::HlsSettings,
         errors::{HlsError, HlsErrorValue},
         flv2hls::Flv2HlsRemuxer,
+        registry::HlsRegistry,
+    },
+    std::{
+        sync::Arc,
+        time::{Duration, Instant},
     },
-    std::time::Duration,
     streamhub::{
         define::{
             FrameData, FrameDataReceiver, NotifyInfo, StreamHubEvent, StreamHubEventSender,
@@ -27,6 +32,10 @@ pub struct FlvDataReceiver {
     data_consumer: FrameDataReceiver,
     media_processor: Flv2HlsRemuxer,
     subscriber_id: Uuid,
+    registry: Arc<HlsRegistry>,
+    /*milliseconds, the packaging of an on-demand stream stops when it is idle*/
+    idle_timeout: Option<i64>,
+    idle_checked: Instant,
 }
 
 impl FlvDataReceiver {
@@ -34,19 +43,23 @@ impl FlvDataReceiver {
         app_name: String,
         stream_name: String,
         event_producer: StreamHubEventSender,
-        duration: i64,
-        need_record: bool,
+        settings: HlsSettings,
+        registry: Arc<HlsRegistry>,
     ) -> Self {
         let (_, data_consumer) = mpsc::unbounded_channel();
         let subscriber_id = Uuid::new(RandomDigitCount::Four);
+        let idle_timeout = settings.idle_timeout;
 
         Self {
             app_name: app_name.clone(),
             stream_name: stream_name.clone(),
             data_consumer,
             event_producer,
-            media_processor: Flv2HlsRemuxer::new(duration, app_name, stream_name, need_record),
+            media_processor: Flv2HlsRemuxer::new(app_name, stream_name, settings, registry.clone()),
             subscriber_id,
+            registry,
+            idle_timeout,
+            idle_checked: Instant::now(),
         }
     }
 
@@ -58,7 +71,23 @@ impl FlvDataReceiver {
         Ok(())
     }
 
+    /*the live files and the keys of the stream are dropped whatever the
+    packaging ends with*/
     pub async fn receive_flv_data(&mut self) -> Result<(), HlsError> {
+        let result = self.remux_flv_data().await;
+        if let Err(err) = self.media_processor.clear_files() {
+            log::error!(
+                "clear the hls files of {}/{} err: {}",
+                self.app_name,
+                self.stream_name,
+                err
+            );
+        }
+        self.unsubscribe_from_rtmp_channels().await?;
+        result
+    }
+
+    async fn remux_flv_data(&mut self) -> Result<(), HlsError> {
         let mut retry_count = 0;
 
         loop {
@@ -66,10 +95,22 @@ impl FlvDataReceiver {
                 let flv_data: FlvData = match data {
                     FrameData::Audio { timestamp, data } => FlvData::Audio { timestamp, data },
                     FrameData::Video { timestamp, data } => FlvData::Video { timestamp, data },
+                    FrameData::Discontinuity => {
+                        self.media_processor.on_discontinuity();
+                        continue;
+                    }
                     _ => continue,
                 };
                 retry_count = 0;
                 self.media_processor.process_flv_data(flv_data)?;
+                if self.is_idle() {
+                    log::info!(
+                        "no hls viewers of {}/{}, stop packaging",
+                        self.app_name,
+                        self.stream_name
+                    );
+                    break;
+                }
             } else {
                 sleep(Duration::from_millis(100)).await;
                 retry_count += 1;
@@ -83,9 +124,26 @@ impl FlvDataReceiver {
                 break;
             }
         }
+        Ok(())
+    }
+
+    /*checked once a second, the stream is idle if its playlist is not
+    requested within the idle timeout*/
+    fn is_idle(&mut self) -> bool {
+        let idle_timeout = match self.idle_timeout {
+            Some(idle_timeout) => Duration::from_millis(idle_timeout as u64),
+            None => return false,
+        };
+        if self.idle_checked.elapsed() < Duration::from_secs(1) {
+            return false;
+        }
+        self.idle_checked = Instant::now();
 
-        self.media_processor.clear_files()?;
-        self.unsubscribe_from_rtmp_channels().await
+        let name = format!("{}/{}", self.app_name, self.stream_name);
+        match self.registry.idle_time(&name) {
+            Some(idle_time) => idle_time > idle_timeout,
+            None => true,
+        }
     }
 
     pub fn flush_response_data(&mut self) -> Result<(), HlsError> {
@@ -0,0 +1,240 @@
+use {
+    super::errors::MediaError,
+    bytes::BytesMut,
+    bytesio::bytes_reader::BytesReader,
+    xflv::{
+        define::{aac_packet_type, avc_packet_type, frame_type, AvcCodecId, FlvData, SoundFormat},
+        errors::FlvDemuxerError,
+        flv_tag_header::{AudioTagHeader, VideoTagHeader},
+        mpeg4_aac::Mpeg4AacProcessor,
+        mpeg4_avc::Mpeg4AvcProcessor,
+        mpeg4_hevc::Mpeg4HevcProcessor,
+        Unmarshal,
+    },
+    xmp4::{
+        define::{AudioTrack, Sample, VideoCodec, VideoTrack},
+        fmp4::Fmp4Muxer,
+    },
+};
+
+pub enum Fmp4Sample {
+    Video(Sample),
+    Audio(Sample),
+}
+
+/*The flv video payloads are already length prefixed nalus and the AAC payloads
+are raw frames, so unlike the ts path they are put into the fragments as they
+are, the sequence headers become the decoder configurations of the init segment.*/
+pub struct Flv2Fmp4 {
+    muxer: Fmp4Muxer,
+    avc_processor: Mpeg4AvcProcessor,
+    hevc_processor: Mpeg4HevcProcessor,
+    aac_processor: Mpeg4AacProcessor,
+    /*the sequence headers are resent by some encoders, the unchanged ones
+    do not produce a new init segment*/
+    video_config: BytesMut,
+    audio_config: BytesMut,
+    /*the decoder configurations changed since the last init segment*/
+    init_changed: bool,
+}
+
+impl Default for Flv2Fmp4 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Flv2Fmp4 {
+    pub fn new() -> Self {
+        Self {
+            muxer: Fmp4Muxer::new(),
+            avc_processor: Mpeg4AvcProcessor::new(),
+            hevc_processor: Mpeg4HevcProcessor::new(),
+            aac_processor: Mpeg4AacProcessor::new(),
+            video_config: BytesMut::new(),
+            audio_config: BytesMut::new(),
+            init_changed: false,
+        }
+    }
+
+    /*a sample is returned for the media frames, the sequence headers update the tracks*/
+    pub fn demux(&mut self, data: FlvData) -> Result<Option<Fmp4Sample>, MediaError> {
+        match data {
+            FlvData::Video { timestamp, data } => self.demux_video(timestamp, data),
+            FlvData::Audio { timestamp, data } => self.demux_audio(timestamp, data),
+            _ => Ok(None),
+        }
+    }
+
+    fn demux_video(
+        &mut self,
+        timestamp: u32,
+        data: BytesMut,
+    ) -> Result<Option<Fmp4Sample>, MediaError> {
+        let mut reader = BytesReader::new(data);
+        let tag_header = VideoTagHeader::unmarshal(&mut reader)?;
+
+        let codec = if tag_header.codec_id == AvcCodecId::H264 as u8 {
+            VideoCodec::H264
+        } else if tag_header.codec_id == AvcCodecId::HEVC as u8 {
+            VideoCodec::H265
+        } else {
+            return Ok(None);
+        };
+
+        match tag_header.avc_packet_type {
+            avc_packet_type::AVC_SEQHDR => {
+                let config = reader.extract_remaining_bytes();
+                if config == self.video_config {
+                    return Ok(None);
+                }
+                self.video_config = config.clone();
+                /*the resolution is parsed from the SPS in the avcC/hvcC*/
+                let (width, height) = if codec == VideoCodec::H264 {
+                    self.avc_processor
+                        .decoder_configuration_record_load(&mut BytesReader::new(config.clone()))
+                        .map_err(FlvDemuxerError::from)?;
+                    let avc = &self.avc_processor.mpeg4_avc;
+                    (avc.width, avc.height)
+                } else {
+                    self.hevc_processor
+                        .decoder_configuration_record_load(&mut BytesReader::new(config.clone()))
+                        .map_err(FlvDemuxerError::from)?;
+                    let hevc = &self.hevc_processor.mpeg4_hevc;
+                    (hevc.width, hevc.height)
+                };
+
+                self.muxer.set_video_track(VideoTrack {
+                    codec,
+                    width,
+                    height,
+                    config,
+                });
+                self.init_changed = true;
+                Ok(None)
+            }
+            avc_packet_type::AVC_NALU => Ok(Some(Fmp4Sample::Video(Sample {
+                dts: timestamp as i64,
+                pts: timestamp as i64 + tag_header.composition_time as i64,
+                is_sync: tag_header.frame_type == frame_type::KEY_FRAME,
+                data: reader.extract_remaining_bytes(),
+            }))),
+            _ => Ok(None),
+        }
+    }
+
+    fn demux_audio(
+        &mut self,
+        timestamp: u32,
+        data: BytesMut,
+    ) -> Result<Option<Fmp4Sample>, MediaError> {
+        let mut reader = BytesReader::new(data);
+        let tag_header = AudioTagHeader::unmarshal(&mut reader)?;
+        if tag_header.sound_format != SoundFormat::AAC as u8 {
+            return Ok(None);
+        }
+
+        match tag_header.aac_packet_type {
+            aac_packet_type::AAC_SEQHDR => {
+                let config = reader.extract_remaining_bytes();
+                if config.len() < 2 || config == self.audio_config {
+                    return Ok(None);
+                }
+                self.audio_config = config.clone();
+                self.aac_processor
+                    .extend_data(config.clone())
+                    .audio_specific_config_load()
+                    .map_err(FlvDemuxerError::from)?;
+
+                let aac = &self.aac_processor.mpeg4_aac;
+                self.muxer.set_audio_track(AudioTrack {
+                    sample_rate: aac.sampling_frequency,
+                    channels: aac.channels as u16,
+                    config,
+                });
+                self.init_changed = true;
+                Ok(None)
+            }
+            aac_packet_type::AAC_RAW => Ok(Some(Fmp4Sample::Audio(Sample {
+                dts: timestamp as i64,
+                pts: timestamp as i64,
+                is_sync: true,
+                data: reader.extract_remaining_bytes(),
+            }))),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn write(&mut self, sample: Fmp4Sample) {
+        match sample {
+            Fmp4Sample::Video(sample) => self.muxer.write_video(sample),
+            Fmp4Sample::Audio(sample) => self.muxer.write_audio(sample),
+        }
+    }
+
+    pub fn flush_fragment(&mut self, next_dts: i64) -> Result<BytesMut, MediaError> {
+        Ok(self.muxer.flush_fragment(next_dts)?)
+    }
+
+    pub fn muxer(&mut self) -> &mut Fmp4Muxer {
+        &mut self.muxer
+    }
+
+    /*the decoder configurations changed since the last call*/
+    pub fn take_init_changed(&mut self) -> bool {
+        std::mem::take(&mut self.init_changed)
+    }
+
+    /*the new init segment if the decoder configurations changed since the last call*/
+    pub fn take_init_segment(&mut self) -> Result<Option<BytesMut>, MediaError> {
+        if !self.take_init_changed() {
+            return Ok(None);
+        }
+        Ok(Some(self.muxer.init_segment()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Flv2Fmp4;
+    use bytes::BytesMut;
+    use xflv::define::FlvData;
+
+    fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+        data.windows(pattern.len()).position(|w| w == pattern)
+    }
+
+    #[test]
+    fn test_hevc_init_segment() {
+        /*an HEVC sequence header: the flv video tag header and the hvcC with
+        the VPS, the SPS of a 1920x1080 stream and the PPS*/
+        let mut data = BytesMut::from(&[0x1C, 0x00, 0x00, 0x00, 0x00][..]);
+        data.extend_from_slice(&[
+            0x01, 0x01, 0x60, 0x00, 0x00, 0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78, 0xF0,
+            0x00, 0xFC, 0xFD, 0xF8, 0xF8, 0x00, 0x00, 0x0F, 0x03,
+        ]);
+        data.extend_from_slice(&[0xA0, 0x00, 0x01, 0x00, 0x02, 0x40, 0x01]);
+        data.extend_from_slice(&[0xA1, 0x00, 0x01, 0x00, 0x2A]);
+        data.extend_from_slice(&[
+            0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00,
+            0x00, 0x03, 0x00, 0x78, 0xA0, 0x03, 0xC0, 0x80, 0x10, 0xE5, 0x96, 0x66, 0x69, 0x24,
+            0xCA, 0xE0, 0x10, 0x00, 0x00, 0x03, 0x00, 0x10, 0x00, 0x00, 0x03, 0x01, 0xE0, 0x80,
+        ]);
+        data.extend_from_slice(&[0xA2, 0x00, 0x01, 0x00, 0x02, 0x44, 0x01]);
+
+        let mut fmp4 = Flv2Fmp4::new();
+        assert!(fmp4
+            .demux(FlvData::Video { timestamp: 0, data })
+            .unwrap()
+            .is_none());
+        let init = fmp4.take_init_segment().unwrap().unwrap();
+
+        /*the width and the height of the visual sample entry follow its 24
+        reserved and pre-defined bytes*/
+        let hvc1 = find(&init, b"hvc1").unwrap() + 4;
+        assert_eq!(&init[hvc1 + 24..hvc1 + 28], &[0x07, 0x80, 0x04, 0x38]);
+        assert!(find(&init, b"hvcC").is_some());
+        /*tkhd carries them in 16.16 fixed point*/
+        assert!(find(&init, &[0x07, 0x80, 0x00, 0x00, 0x04, 0x38, 0x00, 0x00]).is_some());
+    }
+}
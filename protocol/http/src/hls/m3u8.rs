@@ -1,33 +1,73 @@
 use {
-    super::{errors::MediaError, ts::Ts},
+    super::{
+        define::HlsSettings,
+        dvr::{program_date_time_tag, DvrIndex, DvrSegment},
+        encryption::{encrypt_segment, HlsEncryptor, DEFAULT_KEY_URI},
+        errors::MediaError,
+        registry::{HlsRegistry, PlaylistState},
+        ts::Ts,
+    },
     bytes::BytesMut,
-    std::{collections::VecDeque, fs, fs::File, io::Write},
+    chrono::{DateTime, Duration, Utc},
+    commonlib::{
+        config::HlsSegmentFormat,
+        record::{RecordFile, RecordSettings},
+    },
+    std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex, MutexGuard},
+    },
 };
 
+//how many complete segments keep their parts listed in a LL-HLS playlist
+const LL_HLS_PART_SEGMENTS: usize = 2;
+
+/*LL-HLS partial segment*/
+pub struct Part {
+    /*part duration*/
+    pub duration: i64,
+    /*starts with a key frame*/
+    pub independent: bool,
+    pub name: String,
+}
+
+/*the state of the LL-HLS mode*/
+struct LowLatency {
+    /*the target duration of the parts in milliseconds*/
+    part_target: i64,
+    /*the complete parts of the segment being built*/
+    parts: Vec<Part>,
+    /*the data of the complete parts, the segment is the concatenation of its parts*/
+    segment_data: BytesMut,
+}
+
 pub struct Segment {
     /*ts duration*/
     pub duration: i64,
     pub discontinuity: bool,
     /*ts name*/
     pub name: String,
-    path: String,
     pub is_eof: bool,
+    pub parts: Vec<Part>,
+    /*the init segment of a fragmented mp4 segment*/
+    pub map: Option<String>,
+    /*the key URI of an encrypted segment*/
+    pub key: Option<String>,
+    /*the wall clock time of the start of the segment*/
+    pub program_date_time: Option<DateTime<Utc>>,
 }
 
 impl Segment {
-    pub fn new(
-        duration: i64,
-        discontinuity: bool,
-        name: String,
-        path: String,
-        is_eof: bool,
-    ) -> Self {
+    pub fn new(duration: i64, discontinuity: bool, name: String, is_eof: bool) -> Self {
         Self {
             duration,
             discontinuity,
             name,
-            path,
             is_eof,
+            parts: Vec::new(),
+            map: None,
+            key: None,
+            program_date_time: None,
         }
     }
 }
@@ -44,54 +84,199 @@ pub struct M3u8 {
     live_ts_count: usize,
 
     segments: VecDeque<Segment>,
-
-    m3u8_folder: String,
-    live_m3u8_name: String,
+    /*the segments removed from the playlist, they are deleted after
+    delete_after more segments for the clients still downloading them*/
+    expired_segments: VecDeque<Segment>,
+    delete_after: usize,
 
     ts_handler: Ts,
 
-    need_record: bool,
+    /*None if the stream is not recorded*/
+    record: Option<RecordSettings>,
+    /*the recording being written, a new one is started at the first segment
+    and when it is full*/
+    record_file: Option<RecordFile>,
+    /*the default root directory of the recordings*/
+    output_root: String,
     vod_m3u8_content: String,
     vod_m3u8_name: String,
+
+    low_latency: Option<LowLatency>,
+    /*the current init segment of the fmp4 format*/
+    map: Option<String>,
+    /*how many init segments are written*/
+    init_count: usize,
+    /*the last init segment listed in the vod playlist*/
+    vod_map: Option<String>,
+    encryptor: Option<HlsEncryptor>,
+    /*the sequence number of the first segment encrypted by every key in the
+    store, and the key file name*/
+    keys: VecDeque<(u64, String)>,
+    /*the last key listed in the vod playlist*/
+    vod_key: Option<String>,
+    /*the segments are retained for the DVR window after they leave the live
+    playlist, on disk under {output_root}/dvr, the live playlist carries
+    EXT-X-PROGRAM-DATE-TIME*/
+    dvr: Option<Arc<Mutex<DvrIndex>>>,
+    /*the wall clock time of the end of the last segment*/
+    next_program_date_time: Option<DateTime<Utc>>,
+    registry: Arc<HlsRegistry>,
+    /*app_name/stream_name*/
+    name: String,
 }
 
 impl M3u8 {
     pub fn new(
-        duration: i64,
-        live_ts_count: usize,
         app_name: String,
         stream_name: String,
-        need_record: bool,
+        settings: HlsSettings,
+        registry: Arc<HlsRegistry>,
     ) -> Self {
-        let m3u8_folder = format!("./hls/{app_name}/{stream_name}");
-        fs::create_dir_all(m3u8_folder.clone()).unwrap();
+        let name = format!("{app_name}/{stream_name}");
+        let vod_m3u8_name = format!("vod_{stream_name}.m3u8");
+        let record = settings.record.clone();
+        let output_root = settings.output_root.clone();
+
+        let low_latency = settings.part_duration.map(|part_target| LowLatency {
+            part_target,
+            parts: Vec::new(),
+            segment_data: BytesMut::new(),
+        });
+
+        let encryptor = match &settings.encryption {
+            Some(_)
+                if settings.segment_format == HlsSegmentFormat::Fmp4
+                    || settings.part_duration.is_some() =>
+            {
+                log::warn!("{}: hls encryption is not applied to fmp4 or LL-HLS", name);
+                None
+            }
+            Some(encryption) => Some(HlsEncryptor::new(
+                encryption.key_rotation.unwrap_or(0),
+                encryption
+                    .key_uri
+                    .clone()
+                    .unwrap_or_else(|| String::from(DEFAULT_KEY_URI)),
+                app_name.clone(),
+                stream_name.clone(),
+            )),
+            None => None,
+        };
 
-        let live_m3u8_name = "index.m3u8".to_string();
-        let vod_m3u8_name = if need_record {
-            format!("vod_{stream_name}.m3u8")
+        let (version, extension) = match settings.segment_format {
+            //EXT-X-MAP for fragmented mp4 segments
+            HlsSegmentFormat::Fmp4 => (7, "m4s"),
+            //EXT-X-PART requires version 6 at least
+            HlsSegmentFormat::Ts if low_latency.is_some() => (6, "ts"),
+            HlsSegmentFormat::Ts => (3, "ts"),
+        };
+
+        let dvr = if settings.dvr_window > 0 {
+            let index = Arc::new(Mutex::new(DvrIndex::new(
+                version,
+                settings.playlist_length,
+                settings.dvr_window,
+            )));
+            registry.set_dvr(&name, index.clone());
+            Some(index)
         } else {
-            String::default()
+            None
         };
+        let dvr_path = format!("{}/dvr/{name}", settings.output_root);
+        let (duration, live_ts_count, delete_after) = (
+            settings.target_duration,
+            settings.playlist_length,
+            settings.delete_after,
+        );
 
-        let mut m3u8 = Self {
-            version: 3,
+        let mut ts_handler = Ts::new(app_name, stream_name, extension, settings, registry.clone());
+        if dvr.is_some() {
+            /*the segments out of the live playlist are served from the directory
+            like the recorded ones*/
+            ts_handler.set_dvr_path(dvr_path.clone());
+            registry.add_record_path(&name, dvr_path);
+        }
+
+        Self {
+            version,
             sequence_no: 0,
             duration,
             live_ts_count,
             segments: VecDeque::new(),
-            m3u8_folder,
-            live_m3u8_name,
-            ts_handler: Ts::new(app_name, stream_name),
-            // record,
-            need_record,
+            expired_segments: VecDeque::new(),
+            delete_after,
+            ts_handler,
+            record,
+            record_file: None,
+            output_root,
             vod_m3u8_content: String::default(),
             vod_m3u8_name,
+            low_latency,
+            map: None,
+            init_count: 0,
+            vod_map: None,
+            encryptor,
+            keys: VecDeque::new(),
+            vod_key: None,
+            dvr,
+            next_program_date_time: None,
+            registry,
+            name,
+        }
+    }
+
+    /*Start a new recording before the segment is written if there is none or
+    the current one is full, the current init segment and key are recorded
+    with it so that it can be played on its own.*/
+    fn update_record_file(&mut self) -> Result<(), MediaError> {
+        let record = match &self.record {
+            Some(record) => record.clone(),
+            None => return Ok(()),
         };
+        match &self.record_file {
+            Some(record_file) if !record_file.is_full() => return Ok(()),
+            Some(_) => self.finish_record_file()?,
+            None => {}
+        }
+
+        let (app_name, stream_name) = self.name.split_once('/').unwrap_or_default();
+        let record_file = record.start(app_name, stream_name, &self.output_root);
+        self.ts_handler.set_record_path(record_file.path.clone())?;
+        self.registry
+            .add_record_path(&self.name, record_file.path.clone());
+        log::info!("{}: start recording to {}", self.name, record_file.path);
+        self.record_file = Some(record_file);
+
+        self.vod_m3u8_content = self.generate_m3u8_header(true);
+        self.vod_map = None;
+        self.vod_key = None;
+        if let Some(map) = &self.map {
+            self.ts_handler.record_live_file(map)?;
+        }
+        if let Some(key) = self.encryptor.as_ref().and_then(|e| e.current()) {
+            self.ts_handler
+                .record_live_file(&format!("{}.key", key.name))?;
+        }
+        Ok(())
+    }
 
-        if need_record {
-            m3u8.vod_m3u8_content = m3u8.generate_m3u8_header(true);
+    /*the vod playlist of the recording is written and it is added to the
+    recordings index*/
+    fn finish_record_file(&mut self) -> Result<(), MediaError> {
+        if let Some(mut record_file) = self.record_file.take() {
+            self.vod_m3u8_content += "#EXT-X-ENDLIST\n";
+            self.ts_handler
+                .record_playlist(&self.vod_m3u8_name, &self.vod_m3u8_content)?;
+            record_file.set_files(self.ts_handler.recorded_files());
+            let recording = record_file.finish();
+            log::info!(
+                "{}: recording {} is finished: {}",
+                self.name,
+                recording.id,
+                recording.path
+            );
         }
-        m3u8
+        Ok(())
     }
 
     pub fn add_segment(
@@ -105,67 +290,264 @@ impl M3u8 {
 
         if segment_count >= self.live_ts_count {
             let segment = self.segments.pop_front().unwrap();
-            if !self.need_record {
-                self.ts_handler.delete(segment.path);
+            if self.dvr.is_some() {
+                /*the segment is moved to disk and deleted when it leaves the
+                DVR window*/
+                for part in &segment.parts {
+                    self.ts_handler.delete(&part.name);
+                }
+                self.ts_handler.spill(&segment.name)?;
+            } else {
+                self.expired_segments.push_back(segment);
+            }
+            while self.expired_segments.len() > self.delete_after {
+                let segment = self.expired_segments.pop_front().unwrap();
+                for part in &segment.parts {
+                    self.ts_handler.delete(&part.name);
+                }
+                self.ts_handler.delete(&segment.name);
             }
 
             self.sequence_no += 1;
         }
         self.duration = std::cmp::max(duration, self.duration);
-        let (ts_name, ts_path) = self.ts_handler.write(ts_data)?;
-        let segment = Segment::new(duration, discontinuity, ts_name, ts_path, is_eof);
+        self.update_record_file()?;
 
-        if self.need_record {
+        let mut parts = Vec::new();
+        let ts_data = match &mut self.low_latency {
+            Some(low_latency) => {
+                parts = std::mem::take(&mut low_latency.parts);
+                let mut segment_data = std::mem::take(&mut low_latency.segment_data);
+                segment_data.extend_from_slice(&ts_data[..]);
+                segment_data
+            }
+            None => ts_data,
+        };
+
+        let mut key_uri = None;
+        let ts_data = match &mut self.encryptor {
+            Some(encryptor) => {
+                let (key, generated) = encryptor.next_key();
+                if generated {
+                    self.ts_handler.write_key(&key.name, &key.key)?;
+                    self.keys.push_back((
+                        self.ts_handler.current_number() as u64,
+                        format!("{}.key", key.name),
+                    ));
+                }
+                key_uri = Some(key.uri.clone());
+                let sequence_number = self.ts_handler.current_number() as u64;
+                BytesMut::from(&encrypt_segment(&key.key, sequence_number, &ts_data)[..])
+            }
+            None => ts_data,
+        };
+
+        let sequence = self.ts_handler.current_number() as u64;
+        let size = ts_data.len() as u64;
+        let ts_name = self.ts_handler.write(ts_data)?;
+        let mut segment = Segment::new(duration, discontinuity, ts_name, is_eof);
+        segment.parts = parts;
+        segment.map = self.map.clone();
+        segment.key = key_uri;
+
+        /*the segments are contiguous on the wall clock until a discontinuity*/
+        let program_date_time = match self.next_program_date_time {
+            Some(next) if !discontinuity => next,
+            _ => Utc::now() - Duration::milliseconds(duration),
+        };
+        self.next_program_date_time = Some(program_date_time + Duration::milliseconds(duration));
+        segment.program_date_time = Some(program_date_time);
+
+        if let Some(dvr) = &self.dvr {
+            let evicted = Self::lock_dvr(dvr).push(DvrSegment {
+                sequence,
+                duration,
+                name: segment.name.clone(),
+                program_date_time,
+                discontinuity,
+                map: segment.map.clone(),
+                key: segment.key.clone(),
+            });
+            for name in evicted {
+                self.ts_handler.delete(&name);
+            }
+        }
+
+        if let Some(record_file) = &mut self.record_file {
+            record_file.add(duration, size);
             self.update_vod_m3u8(&segment);
         }
 
         self.segments.push_back(segment);
+        self.delete_unused_keys();
 
         Ok(())
     }
 
-    pub fn clear(&mut self) -> Result<(), MediaError> {
-        if self.need_record {
-            let vod_m3u8_path = format!("hls/{}/{}", self.m3u8_folder, self.vod_m3u8_name);
-            let mut file_handler = File::create(vod_m3u8_path).unwrap();
-            self.vod_m3u8_content += "#EXT-X-ENDLIST\n";
-            file_handler.write_all(self.vod_m3u8_content.as_bytes())?;
-        } else {
-            for segment in &self.segments {
-                self.ts_handler.delete(segment.path.clone());
-            }
+    /*the index stays usable if a playlist request panicked while holding it*/
+    fn lock_dvr(dvr: &Mutex<DvrIndex>) -> MutexGuard<'_, DvrIndex> {
+        dvr.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /*a key is deleted from the store once the segments it encrypts are deleted*/
+    fn delete_unused_keys(&mut self) {
+        let oldest = match &self.dvr {
+            Some(dvr) => Self::lock_dvr(dvr).first_sequence(),
+            None => Some(self.sequence_no - self.expired_segments.len() as u64),
+        };
+        let Some(oldest) = oldest else {
+            return;
+        };
+        while self.keys.len() > 1 && self.keys[1].0 <= oldest {
+            let (_, key_file_name) = self.keys.pop_front().unwrap();
+            self.ts_handler.delete(&key_file_name);
+        }
+    }
+
+    /*the segments of an audio-only stream become packed audio once its tracks
+    are known, before the first segment is written*/
+    pub fn set_segment_extension(&mut self, extension: &'static str) {
+        self.ts_handler.set_extension(extension);
+    }
+
+    /*Write a new fmp4 init segment, it is referenced by the segments added
+    from now on.*/
+    pub fn set_init_segment(&mut self, data: BytesMut) -> Result<(), MediaError> {
+        let name = self.ts_handler.write_init(self.init_count, &data)?;
+        self.init_count += 1;
+        self.map = Some(name);
+        Ok(())
+    }
+
+    /*Add a LL-HLS partial segment to the segment being built, it is ignored
+    if the low latency mode is not enabled.*/
+    pub fn add_part(
+        &mut self,
+        duration: i64,
+        independent: bool,
+        data: BytesMut,
+    ) -> Result<(), MediaError> {
+        let part_index = match &self.low_latency {
+            Some(low_latency) => low_latency.parts.len(),
+            None => return Ok(()),
+        };
+        if data.is_empty() {
+            return Ok(());
         }
 
-        //clear live m3u8
-        let live_m3u8_path = format!("hls/{}/{}", self.m3u8_folder, self.live_m3u8_name);
-        fs::remove_file(live_m3u8_path)?;
+        let name = self.ts_handler.write_part(part_index, &data);
+        if let Some(low_latency) = &mut self.low_latency {
+            low_latency.segment_data.extend_from_slice(&data[..]);
+            low_latency.parts.push(Part {
+                duration,
+                independent,
+                name,
+            });
+        }
 
         Ok(())
     }
 
+    /*the live files are dropped with the stream, the current recording of a
+    recorded stream is finished*/
+    pub fn clear(&mut self) -> Result<(), MediaError> {
+        self.registry.remove(&self.name);
+        self.ts_handler.clear_spilled_files();
+        self.finish_record_file()
+    }
+
     pub fn generate_m3u8_header(&self, is_vod: bool) -> String {
         let mut m3u8_header = "#EXTM3U\n".to_string();
         m3u8_header += format!("#EXT-X-VERSION:{}\n", self.version).as_str();
         m3u8_header += format!("#EXT-X-TARGETDURATION:{}\n", (self.duration + 999) / 1000).as_str();
 
         if is_vod {
-            m3u8_header += "#EXT-X-MEDIA-SEQUENCE:0\n";
+            /*a recording starts at the sequence number of its first segment,
+            which is the IV of the encrypted segments*/
+            m3u8_header += format!(
+                "#EXT-X-MEDIA-SEQUENCE:{}\n",
+                self.ts_handler.current_number()
+            )
+            .as_str();
             m3u8_header += "#EXT-X-PLAYLIST-TYPE:VOD\n";
             m3u8_header += "#EXT-X-ALLOW-CACHE:YES\n";
         } else {
+            if let Some(low_latency) = &self.low_latency {
+                let part_target = low_latency.part_target as f64 / 1000.0;
+                /*the spec requires PART-HOLD-BACK to be at least 3 part target durations*/
+                m3u8_header += format!(
+                    "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK={:.3}\n",
+                    part_target * 3.0
+                )
+                .as_str();
+                m3u8_header += format!("#EXT-X-PART-INF:PART-TARGET={part_target:.3}\n").as_str();
+            }
             m3u8_header += format!("#EXT-X-MEDIA-SEQUENCE:{}\n", self.sequence_no).as_str();
         }
 
         m3u8_header
     }
 
+    /*EXT-X-MAP is written before the first segment and whenever the init segment changes*/
+    fn generate_map(current: &mut Option<String>, map: &Option<String>) -> String {
+        match map {
+            Some(uri) if current.as_ref() != Some(uri) => {
+                *current = Some(uri.clone());
+                format!("#EXT-X-MAP:URI=\"{uri}\"\n")
+            }
+            _ => String::new(),
+        }
+    }
+
+    /*EXT-X-KEY is written before the first encrypted segment and whenever the
+    key rotates*/
+    fn generate_key(current: &mut Option<String>, key: &Option<String>) -> String {
+        match key {
+            Some(uri) if current.as_ref() != Some(uri) => {
+                *current = Some(uri.clone());
+                format!("#EXT-X-KEY:METHOD=AES-128,URI=\"{uri}\"\n")
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn generate_parts(parts: &[Part]) -> String {
+        let mut content = String::new();
+        for part in parts {
+            content += format!(
+                "#EXT-X-PART:DURATION={:.3},URI=\"{}\"{}\n",
+                part.duration as f64 / 1000.0,
+                part.name,
+                if part.independent {
+                    ",INDEPENDENT=YES"
+                } else {
+                    ""
+                }
+            )
+            .as_str();
+        }
+        content
+    }
+
     pub fn refresh_playlist(&mut self) -> Result<String, MediaError> {
         let mut m3u8_content = self.generate_m3u8_header(false);
 
-        for segment in &self.segments {
+        let parts_from = self.segments.len().saturating_sub(LL_HLS_PART_SEGMENTS);
+        let mut is_eof = false;
+        let mut map = None;
+        let mut key = None;
+        for (index, segment) in self.segments.iter().enumerate() {
             if segment.discontinuity {
                 m3u8_content += "#EXT-X-DISCONTINUITY\n";
             }
+            m3u8_content += Self::generate_map(&mut map, &segment.map).as_str();
+            m3u8_content += Self::generate_key(&mut key, &segment.key).as_str();
+            if let (Some(_), Some(program_date_time)) = (&self.dvr, &segment.program_date_time) {
+                m3u8_content += program_date_time_tag(program_date_time).as_str();
+            }
+            if index >= parts_from {
+                m3u8_content += Self::generate_parts(&segment.parts).as_str();
+            }
             m3u8_content += format!(
                 "#EXTINF:{:.3}\n{}\n",
                 segment.duration as f64 / 1000.0,
@@ -175,14 +557,36 @@ impl M3u8 {
 
             if segment.is_eof {
                 m3u8_content += "#EXT-X-ENDLIST\n";
+                is_eof = true;
                 break;
             }
         }
 
-        let m3u8_path = format!("{}/{}", self.m3u8_folder, self.live_m3u8_name);
+        let mut parts = 0;
+        if let (Some(low_latency), false) = (&self.low_latency, is_eof) {
+            m3u8_content += Self::generate_map(&mut map, &self.map).as_str();
+            m3u8_content += Self::generate_parts(&low_latency.parts).as_str();
+            m3u8_content += format!(
+                "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"{}.{}.{}\"\n",
+                self.ts_handler.current_number(),
+                low_latency.parts.len(),
+                self.ts_handler.extension()
+            )
+            .as_str();
+            parts = low_latency.parts.len() as u64;
+        }
 
-        let mut file_handler = File::create(m3u8_path).unwrap();
-        file_handler.write_all(m3u8_content.as_bytes())?;
+        self.ts_handler.write_playlist(&m3u8_content);
+
+        /*notify the blocking requests after the playlist is written*/
+        self.registry.update(
+            &self.name,
+            PlaylistState {
+                msn: self.ts_handler.current_number() as u64,
+                parts,
+                target_duration: ((self.duration + 999) / 1000) as u64,
+            },
+        );
 
         Ok(m3u8_content)
     }
@@ -191,6 +595,8 @@ impl M3u8 {
         if segment.discontinuity {
             self.vod_m3u8_content += "#EXT-X-DISCONTINUITY\n";
         }
+        self.vod_m3u8_content += Self::generate_map(&mut self.vod_map, &segment.map).as_str();
+        self.vod_m3u8_content += Self::generate_key(&mut self.vod_key, &segment.key).as_str();
         self.vod_m3u8_content += format!(
             "#EXTINF:{:.3}\n{}\n",
             segment.duration as f64 / 1000.0,
@@ -199,3 +605,232 @@ impl M3u8 {
         .as_str();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::M3u8;
+    use crate::hls::{define::HlsSettings, registry::HlsRegistry};
+    use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+    use bytes::BytesMut;
+    use commonlib::{
+        config::{HlsEncryptionConfig, RecordFormat},
+        record::{RecordIndex, RecordSettings},
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_playlist_length_and_delete_after() {
+        let registry = Arc::new(HlsRegistry::default());
+        let mut settings = HlsSettings::new(None);
+        settings.playlist_length = 2;
+        settings.delete_after = 1;
+        settings.segment_name = String::from("{stream}-{seq}");
+
+        let mut m3u8 = M3u8::new(
+            String::from("live"),
+            String::from("test"),
+            settings,
+            registry.clone(),
+        );
+        for _ in 0..4 {
+            m3u8.add_segment(2000, false, false, BytesMut::from(&[0x47; 188][..]))
+                .unwrap();
+        }
+        let playlist = m3u8.refresh_playlist().unwrap();
+
+        assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:2\n"));
+        assert!(playlist.contains("#EXT-X-TARGETDURATION:5\n"));
+        assert!(!playlist.contains("test-1.ts"));
+        assert!(playlist.contains("test-2.ts\n") && playlist.contains("test-3.ts\n"));
+
+        /*one segment out of the playlist is kept*/
+        let store = registry.store();
+        assert!(!store.contains("live/test", "test-0.ts"));
+        assert!(store.contains("live/test", "test-1.ts"));
+        assert!(store.contains("live/test", "index.m3u8"));
+
+        m3u8.clear().unwrap();
+        assert!(!store.contains("live/test", "test-3.ts"));
+    }
+
+    #[test]
+    fn test_encrypted_segments() {
+        let registry = Arc::new(HlsRegistry::default());
+        let mut settings = HlsSettings::new(None);
+        settings.encryption = Some(HlsEncryptionConfig {
+            enabled: true,
+            key_rotation: Some(2),
+            key_uri: None,
+        });
+
+        let mut m3u8 = M3u8::new(
+            String::from("live"),
+            String::from("paid"),
+            settings,
+            registry.clone(),
+        );
+        for _ in 0..3 {
+            m3u8.add_segment(2000, false, false, BytesMut::from(&[0x47; 188][..]))
+                .unwrap();
+        }
+        let playlist = m3u8.refresh_playlist().unwrap();
+
+        assert!(playlist.contains(
+            "#EXT-X-KEY:METHOD=AES-128,URI=\"key_0.key\"\n#EXTINF:2.000\n0.ts\n#EXTINF:2.000\n1.ts\n"
+        ));
+        assert!(
+            playlist.contains("#EXT-X-KEY:METHOD=AES-128,URI=\"key_1.key\"\n#EXTINF:2.000\n2.ts\n")
+        );
+
+        let store = registry.store();
+        assert_eq!(store.get("live/paid", "key_1.key").unwrap().len(), 16);
+        /*padded to the AES block size*/
+        assert_eq!(store.get("live/paid", "0.ts").unwrap().len(), 192);
+
+        /*segments 0-2 leave the playlist, the first key is not used any more*/
+        for _ in 0..6 {
+            m3u8.add_segment(2000, false, false, BytesMut::from(&[0x47; 188][..]))
+                .unwrap();
+        }
+        assert!(!store.contains("live/paid", "key_0.key"));
+        assert!(store.contains("live/paid", "key_1.key"));
+
+        /*the keys are evicted with the stream*/
+        m3u8.clear().unwrap();
+        assert!(!store.contains("live/paid", "key_1.key"));
+    }
+
+    #[test]
+    fn test_record_paths() {
+        let root = std::env::temp_dir().join(format!("xiu_hls_record_{}", std::process::id()));
+        let index = std::env::temp_dir().join(format!("xiu_hls_index_{}.json", std::process::id()));
+        let registry = Arc::new(HlsRegistry::default());
+        let mut settings = HlsSettings::new(None);
+        settings.record = Some(RecordSettings {
+            format: RecordFormat::Hls,
+            root: Some(root.to_str().unwrap().to_string()),
+            path: String::from("{app}/{stream}"),
+            max_duration: None,
+            max_size: None,
+            finalize: false,
+            index: Arc::new(RecordIndex::load(index.to_str().unwrap())),
+        });
+
+        let mut m3u8 = M3u8::new(
+            String::from("live"),
+            String::from("test"),
+            settings,
+            registry.clone(),
+        );
+        m3u8.add_segment(2000, false, false, BytesMut::from(&[0x47; 188][..]))
+            .unwrap();
+
+        /*the recorded segments are served from the configured root*/
+        let record_paths = registry.record_paths("live/test");
+        assert_eq!(record_paths.len(), 1);
+        assert!(record_paths[0].starts_with(root.to_str().unwrap()));
+        assert!(std::path::Path::new(&format!("{}/0.ts", record_paths[0])).is_file());
+
+        m3u8.clear().unwrap();
+        assert!(registry.record_paths("live/test").is_empty());
+        std::fs::remove_dir_all(root).unwrap();
+        std::fs::remove_file(index).unwrap();
+    }
+
+    #[test]
+    fn test_split_encrypted_recording() {
+        let root = std::env::temp_dir().join(format!("xiu_hls_split_{}", std::process::id()));
+        let index = std::env::temp_dir().join(format!("xiu_hls_split_{}.json", std::process::id()));
+        let registry = Arc::new(HlsRegistry::default());
+        let mut settings = HlsSettings::new(None);
+        settings.encryption = Some(HlsEncryptionConfig {
+            enabled: true,
+            key_rotation: None,
+            key_uri: None,
+        });
+        let record_index = Arc::new(RecordIndex::load(index.to_str().unwrap()));
+        settings.record = Some(RecordSettings {
+            format: RecordFormat::Hls,
+            root: Some(root.to_str().unwrap().to_string()),
+            path: String::from("{app}/{stream}"),
+            max_duration: Some(4000),
+            max_size: None,
+            finalize: false,
+            index: record_index.clone(),
+        });
+
+        let mut m3u8 = M3u8::new(
+            String::from("live"),
+            String::from("split"),
+            settings,
+            registry,
+        );
+        for _ in 0..3 {
+            m3u8.add_segment(2000, false, false, BytesMut::from(&[0x47; 188][..]))
+                .unwrap();
+        }
+        m3u8.clear().unwrap();
+
+        let recordings = record_index.list(Some("live"), Some("split"));
+        assert_eq!(recordings.len(), 2);
+        let path = &recordings[1].path;
+        let playlist = std::fs::read_to_string(format!("{path}/vod_split.m3u8")).unwrap();
+        assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:2\n"));
+        assert!(
+            playlist.contains("#EXT-X-KEY:METHOD=AES-128,URI=\"key_0.key\"\n#EXTINF:2.000\n2.ts\n")
+        );
+
+        /*the IV of the segment is its media sequence number in the recording*/
+        let key = std::fs::read(format!("{path}/key_0.key")).unwrap();
+        let encrypted = std::fs::read(format!("{path}/2.ts")).unwrap();
+        let iv = 2u128.to_be_bytes();
+        let decrypted = cbc::Decryptor::<aes::Aes128>::new(key[..].into(), &iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(&encrypted)
+            .unwrap();
+        assert_eq!(decrypted, vec![0x47; 188]);
+
+        std::fs::remove_dir_all(root).unwrap();
+        std::fs::remove_file(index).unwrap();
+    }
+
+    #[test]
+    fn test_dvr_segments_on_disk() {
+        let root = std::env::temp_dir().join(format!("xiu_hls_dvr_{}", std::process::id()));
+        let registry = Arc::new(HlsRegistry::default());
+        let mut settings = HlsSettings::new(None);
+        settings.output_root = root.to_str().unwrap().to_string();
+        settings.playlist_length = 2;
+        settings.dvr_window = 10 * 1000;
+
+        let mut m3u8 = M3u8::new(
+            String::from("live"),
+            String::from("dvr"),
+            settings,
+            registry.clone(),
+        );
+        for _ in 0..8 {
+            m3u8.add_segment(2000, false, false, BytesMut::from(&[0x47; 188][..]))
+                .unwrap();
+        }
+
+        /*only the live playlist is in memory, the rest of the window is on disk*/
+        let store = registry.store();
+        let dvr_path = format!("{}/dvr/live/dvr", root.to_str().unwrap());
+        assert_eq!(registry.record_paths("live/dvr"), vec![dvr_path.clone()]);
+        for sequence in 6..8 {
+            assert!(store.contains("live/dvr", &format!("{sequence}.ts")));
+        }
+        for sequence in 3..6 {
+            assert!(!store.contains("live/dvr", &format!("{sequence}.ts")));
+            assert!(std::path::Path::new(&format!("{dvr_path}/{sequence}.ts")).is_file());
+        }
+        /*out of the window*/
+        for sequence in 0..3 {
+            assert!(!std::path::Path::new(&format!("{dvr_path}/{sequence}.ts")).exists());
+        }
+
+        m3u8.clear().unwrap();
+        assert!(!std::path::Path::new(&dvr_path).exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+use {
+    super::registry::HlsRegistry,
+    futures::future::join_all,
+    streamhub::{
+        define::{StreamHubEvent, StreamHubEventSender},
+        statistics::Tracks,
+        stream::Protocol,
+    },
+    tokio::sync::oneshot,
+    xflv::define::{AacProfile, AvcCodecId},
+};
+
+/*A variant stream of a master playlist, the attributes are taken from the
+measured statistics of the published stream.*/
+pub struct Variant {
+    pub uri: String,
+    /*bits per second*/
+    pub bandwidth: u64,
+    pub resolution: Option<(u32, u32)>,
+    pub frame_rate: Option<usize>,
+    pub codecs: Option<String>,
+}
+
+impl Variant {
+    /*None if the bitrate of the stream is not measured yet*/
+    pub fn new(uri: String, tracks: &Tracks) -> Option<Self> {
+        let bandwidth = (tracks.video.bitrate + tracks.audio.bitrate) as u64 * 1000;
+        if bandwidth == 0 {
+            return None;
+        }
+
+        let video = &tracks.video;
+        let resolution = if video.width > 0 && video.height > 0 {
+            Some((video.width, video.height))
+        } else {
+            None
+        };
+        let frame_rate = if video.frame_rate > 0 {
+            Some(video.frame_rate)
+        } else {
+            None
+        };
+
+        Some(Self {
+            uri,
+            bandwidth,
+            resolution,
+            frame_rate,
+            codecs: Self::codecs(tracks),
+        })
+    }
+
+    /*CODECS must list every codec of the variant, so it is left out if one of
+    them is not known*/
+    fn codecs(tracks: &Tracks) -> Option<String> {
+        let mut codecs = Vec::new();
+
+        let video = &tracks.video;
+        match video.codec {
+            AvcCodecId::H264 => {
+                let profile = video.profile.clone() as i32;
+                let level = video.level.clone() as i32;
+                if profile <= 0 || level <= 0 {
+                    return None;
+                }
+                codecs.push(format!("avc1.{profile:02x}00{level:02x}"));
+            }
+            AvcCodecId::HEVC => return None,
+            AvcCodecId::UNKNOWN => {}
+        }
+
+        let audio = &tracks.audio;
+        if audio.sample_rate > 0 {
+            match audio.profile {
+                AacProfile::UNKNOWN => return None,
+                _ => codecs.push(format!("mp4a.40.{}", audio.profile.clone() as i32)),
+            }
+        }
+
+        if codecs.is_empty() {
+            None
+        } else {
+            Some(codecs.join(","))
+        }
+    }
+
+    fn generate(&self) -> String {
+        let mut attributes = format!("BANDWIDTH={}", self.bandwidth);
+        if let Some((width, height)) = self.resolution {
+            attributes += format!(",RESOLUTION={width}x{height}").as_str();
+        }
+        if let Some(frame_rate) = self.frame_rate {
+            attributes += format!(",FRAME-RATE={frame_rate}.000").as_str();
+        }
+        if let Some(codecs) = &self.codecs {
+            attributes += format!(",CODECS=\"{codecs}\"").as_str();
+        }
+        format!("#EXT-X-STREAM-INF:{attributes}\n{}\n", self.uri)
+    }
+}
+
+pub fn generate_master_playlist(variants: &[Variant]) -> String {
+    let mut content = String::from("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+    for variant in variants {
+        content += variant.generate().as_str();
+    }
+    content
+}
+
+/*The master playlist of a rendition group, the variants which are not live or
+not measured yet are left out. None if no variant is listed.*/
+pub async fn master_playlist(
+    event_producer: &StreamHubEventSender,
+    registry: &HlsRegistry,
+    app_name: &str,
+    variant_names: Vec<String>,
+) -> Option<String> {
+    /*the on-demand variants are packaged before they are listed*/
+    if registry.is_on_demand() {
+        join_all(variant_names.iter().map(|variant_name| {
+            let name = format!("{app_name}/{variant_name}");
+            async move { registry.demand(&name).await }
+        }))
+        .await;
+    }
+
+    let mut variants = Vec::new();
+    for variant_name in variant_names {
+        let name = format!("{app_name}/{variant_name}");
+        if !registry.store().contains(&name, "index.m3u8") {
+            continue;
+        }
+
+        let (result_sender, result_receiver) = oneshot::channel();
+        let event = StreamHubEvent::ApiTracks {
+            protocol: Protocol::Rtmp,
+            name,
+            result_sender,
+        };
+        if event_producer.send(event).is_err() {
+            log::error!("send api tracks event error");
+            return None;
+        }
+
+        if let Ok(Some(tracks)) = result_receiver.await {
+            let uri = format!("/{app_name}/{variant_name}/index.m3u8");
+            if let Some(variant) = Variant::new(uri, &tracks) {
+                variants.push(variant);
+            }
+        }
+    }
+
+    if variants.is_empty() {
+        None
+    } else {
+        Some(generate_master_playlist(&variants))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_master_playlist, Variant};
+    use streamhub::statistics::Tracks;
+    use xflv::define::{AacProfile, AvcCodecId, AvcLevel, AvcProfile};
+
+    #[test]
+    fn test_master_playlist() {
+        let mut tracks = Tracks::default();
+        /*not measured yet*/
+        assert!(Variant::new(String::from("/live/show_720/index.m3u8"), &tracks).is_none());
+
+        tracks.video.codec = AvcCodecId::H264;
+        tracks.video.profile = AvcProfile::High;
+        tracks.video.level = AvcLevel::Level31;
+        tracks.video.width = 1280;
+        tracks.video.height = 720;
+        tracks.video.frame_rate = 30;
+        tracks.video.bitrate = 2500;
+        tracks.audio.profile = AacProfile::LC;
+        tracks.audio.sample_rate = 44100;
+        tracks.audio.bitrate = 128;
+
+        let variant = Variant::new(String::from("/live/show_720/index.m3u8"), &tracks).unwrap();
+        let playlist = generate_master_playlist(&[variant]);
+        assert!(playlist.contains(
+            "#EXT-X-STREAM-INF:BANDWIDTH=2628000,RESOLUTION=1280x720,FRAME-RATE=30.000,CODECS=\"avc1.64001f,mp4a.40.2\"\n/live/show_720/index.m3u8\n"
+        ));
+
+        tracks.video.codec = AvcCodecId::HEVC;
+        let variant = Variant::new(String::from("/live/show_720/index.m3u8"), &tracks).unwrap();
+        assert!(variant.codecs.is_none());
+    }
+}
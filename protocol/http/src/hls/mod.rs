@@ -1,7 +1,14 @@
 pub mod define;
+pub mod dvr;
+pub mod encryption;
 pub mod errors;
 pub mod flv2hls;
 pub mod flv_data_receiver;
+pub mod fmp4;
 pub mod m3u8;
+pub mod master;
+pub mod packed_audio;
+pub mod registry;
 pub mod remuxer;
+pub mod store;
 pub mod ts;
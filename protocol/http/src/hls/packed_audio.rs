@@ -0,0 +1,47 @@
+use bytes::{BufMut, BytesMut};
+
+const TIMESTAMP_OWNER: &[u8] = b"com.apple.streaming.transportStreamTimestamp\0";
+
+/*The ID3v2.4 tag with the PRIV frame which starts every packed audio segment
+and part, it carries the 33 bits MPEG-2 timestamp(90kHz) of the first frame.*/
+pub fn id3_timestamp_tag(pts: i64) -> BytesMut {
+    let frame_size = TIMESTAMP_OWNER.len() + 8;
+    let tag_size = 10 + frame_size;
+
+    let mut tag = BytesMut::with_capacity(10 + tag_size);
+    /*tag header: "ID3", version 4.0, no flags, syncsafe size*/
+    tag.put_slice(b"ID3");
+    tag.put_slice(&[0x04, 0x00, 0x00]);
+    tag.put_slice(&syncsafe(tag_size as u32));
+    /*frame header: "PRIV", syncsafe size, no flags*/
+    tag.put_slice(b"PRIV");
+    tag.put_slice(&syncsafe(frame_size as u32));
+    tag.put_slice(&[0x00, 0x00]);
+
+    tag.put_slice(TIMESTAMP_OWNER);
+    tag.put_u64((pts as u64) & 0x1_FFFF_FFFF);
+    tag
+}
+
+fn syncsafe(size: u32) -> [u8; 4] {
+    [
+        ((size >> 21) & 0x7F) as u8,
+        ((size >> 14) & 0x7F) as u8,
+        ((size >> 7) & 0x7F) as u8,
+        (size & 0x7F) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::id3_timestamp_tag;
+
+    #[test]
+    fn test_id3_timestamp_tag() {
+        let tag = id3_timestamp_tag(90000 * 10);
+        assert_eq!(tag.len(), 73);
+        assert_eq!(&tag[..10], b"ID3\x04\x00\x00\x00\x00\x00\x3F");
+        assert_eq!(&tag[10..20], b"PRIV\x00\x00\x00\x35\x00\x00");
+        assert_eq!(&tag[65..], &[0, 0, 0, 0, 0x00, 0x0D, 0xBB, 0xA0]);
+    }
+}
@@ -0,0 +1,337 @@
+use {
+    super::{dvr::DvrIndex, store::HlsStore},
+    commonlib::config::HlsGroupConfig,
+    std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+    tokio::sync::{mpsc, watch},
+};
+
+//how long a request of an on-demand stream waits for its first segment
+const FIRST_SEGMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/*The position of the live edge of a playlist.*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlaylistState {
+    //media sequence number of the segment being built
+    pub msn: u64,
+    //how many parts of that segment are complete
+    pub parts: u64,
+    //seconds
+    pub target_duration: u64,
+}
+
+impl PlaylistState {
+    /*the playlist contains segment msn, or part `part` of segment msn*/
+    pub fn contains(&self, msn: u64, part: Option<u64>) -> bool {
+        match part {
+            Some(part) => self.msn > msn || (self.msn == msn && self.parts > part),
+            None => self.msn > msn,
+        }
+    }
+}
+
+/*Shared by the hls remuxers and the http server, so that the server can hold
+LL-HLS blocking playlist reloads and preload hint requests until the remuxer
+has produced the requested segment or part, and serve the live files from the
+in-memory store. Keyed by "app_name/stream_name". It also holds the rendition
+groups served as master playlists, and passes the playlist requests of the
+on-demand streams to the remuxer.*/
+#[derive(Default)]
+pub struct HlsRegistry {
+    streams: Mutex<HashMap<String, watch::Sender<PlaylistState>>>,
+    store: HlsStore,
+    groups: Mutex<Vec<HlsGroupConfig>>,
+    dvr: Mutex<HashMap<String, Arc<Mutex<DvrIndex>>>>,
+    /*the stream names are sent to the remuxer to start packaging them, None if
+    the streams are always packaged*/
+    demand_sender: Mutex<Option<mpsc::UnboundedSender<String>>>,
+    /*the last playlist request of every stream*/
+    accessed: Mutex<HashMap<String, Instant>>,
+    /*the directories of the recordings of every stream, the recorded segments
+    out of the live window are served from them*/
+    record_paths: Mutex<HashMap<String, Vec<String>>>,
+}
+
+pub enum WaitResult {
+    Ready,
+    //the stream is not live or is not produced by this server
+    NotFound,
+    //the request is too far ahead of the live edge
+    BadRequest,
+    Timeout,
+}
+
+impl HlsRegistry {
+    pub fn update(&self, name: &str, state: PlaylistState) {
+        let mut streams = self.streams.lock().unwrap();
+        match streams.get(name) {
+            Some(sender) => {
+                sender.send_replace(state);
+            }
+            None => {
+                let (sender, _) = watch::channel(state);
+                streams.insert(name.to_string(), sender);
+            }
+        }
+    }
+
+    /*the stream is unpublished, its live files are dropped too*/
+    pub fn remove(&self, name: &str) {
+        self.streams.lock().unwrap().remove(name);
+        self.store.remove_stream(name);
+        self.dvr.lock().unwrap().remove(name);
+        self.accessed.lock().unwrap().remove(name);
+        self.record_paths.lock().unwrap().remove(name);
+    }
+
+    pub fn set_demand_sender(&self, sender: mpsc::UnboundedSender<String>) {
+        *self.demand_sender.lock().unwrap() = Some(sender);
+    }
+
+    pub fn is_on_demand(&self) -> bool {
+        self.demand_sender.lock().unwrap().is_some()
+    }
+
+    /*a playlist of the stream is requested*/
+    pub fn touch(&self, name: &str) {
+        self.accessed
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), Instant::now());
+    }
+
+    /*the time since the last playlist request, None if it is never requested*/
+    pub fn idle_time(&self, name: &str) -> Option<Duration> {
+        self.accessed
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|instant| instant.elapsed())
+    }
+
+    /*Ask the remuxer to package an on-demand stream if it is not packaged yet,
+    and wait until its first segment is ready. The remuxer removes the stream
+    if it is not published.*/
+    pub async fn demand(&self, name: &str) -> WaitResult {
+        self.touch(name);
+        let mut receiver = {
+            let mut streams = self.streams.lock().unwrap();
+            match streams.get(name) {
+                Some(sender) => sender.subscribe(),
+                None => {
+                    let demand_sender = self.demand_sender.lock().unwrap();
+                    let demand_sender = match demand_sender.as_ref() {
+                        Some(demand_sender) => demand_sender,
+                        None => return WaitResult::NotFound,
+                    };
+                    let (sender, receiver) = watch::channel(PlaylistState::default());
+                    streams.insert(name.to_string(), sender);
+                    if demand_sender.send(name.to_string()).is_err() {
+                        streams.remove(name);
+                        return WaitResult::NotFound;
+                    }
+                    receiver
+                }
+            }
+        };
+
+        let result =
+            tokio::time::timeout(FIRST_SEGMENT_TIMEOUT, receiver.wait_for(|s| s.msn > 0)).await;
+        match result {
+            Ok(Ok(_)) => WaitResult::Ready,
+            Ok(Err(_)) => WaitResult::NotFound,
+            Err(_) => WaitResult::Timeout,
+        }
+    }
+
+    pub fn store(&self) -> &HlsStore {
+        &self.store
+    }
+
+    /*the DVR window of a stream, registered by its playlist writer*/
+    pub fn set_dvr(&self, name: &str, index: Arc<Mutex<DvrIndex>>) {
+        self.dvr.lock().unwrap().insert(name.to_string(), index);
+    }
+
+    pub fn dvr(&self, name: &str) -> Option<Arc<Mutex<DvrIndex>>> {
+        self.dvr.lock().unwrap().get(name).cloned()
+    }
+
+    /*a new recording of the stream is started in this directory*/
+    pub fn add_record_path(&self, name: &str, path: String) {
+        self.record_paths
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .push(path);
+    }
+
+    /*the newest recording first*/
+    pub fn record_paths(&self, name: &str) -> Vec<String> {
+        self.record_paths
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|paths| paths.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn set_groups(&self, groups: Vec<HlsGroupConfig>) {
+        *self.groups.lock().unwrap() = groups;
+    }
+
+    /*the variant stream names of the group*/
+    pub fn group_variants(&self, app_name: &str, group_name: &str) -> Option<Vec<String>> {
+        self.groups
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|g| g.app == app_name && g.name == group_name)
+            .map(|g| g.variants.clone())
+    }
+
+    pub fn in_group(&self, app_name: &str, stream_name: &str) -> bool {
+        self.groups
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|g| g.app == app_name && g.variants.iter().any(|v| v == stream_name))
+    }
+
+    pub fn state(&self, name: &str) -> Option<PlaylistState> {
+        self.streams
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|sender| *sender.borrow())
+    }
+
+    /*Wait until the playlist of the stream contains the segment msn(and the part).
+    Following the LL-HLS spec, a request more than two segments ahead of the last
+    complete segment is rejected, and the wait lasts at most three target durations.*/
+    pub async fn wait_for(&self, name: &str, msn: u64, part: Option<u64>) -> WaitResult {
+        let mut receiver = match self.streams.lock().unwrap().get(name) {
+            Some(sender) => sender.subscribe(),
+            None => return WaitResult::NotFound,
+        };
+
+        let state = *receiver.borrow();
+        if msn > state.msn + 1 {
+            return WaitResult::BadRequest;
+        }
+
+        let timeout = Duration::from_secs(std::cmp::max(state.target_duration, 1) * 3);
+        let result =
+            tokio::time::timeout(timeout, receiver.wait_for(|s| s.contains(msn, part))).await;
+        match result {
+            Ok(Ok(_)) => WaitResult::Ready,
+            //the sender is dropped when the stream is unpublished
+            Ok(Err(_)) => WaitResult::NotFound,
+            Err(_) => WaitResult::Timeout,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HlsRegistry, PlaylistState, WaitResult};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_playlist_state_contains() {
+        let state = PlaylistState {
+            msn: 5,
+            parts: 2,
+            target_duration: 5,
+        };
+        assert!(state.contains(4, None));
+        assert!(!state.contains(5, None));
+        assert!(state.contains(5, Some(1)));
+        assert!(!state.contains(5, Some(2)));
+        assert!(state.contains(4, Some(7)));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for() {
+        let registry = Arc::new(HlsRegistry::default());
+        assert!(matches!(
+            registry.wait_for("live/test", 0, None).await,
+            WaitResult::NotFound
+        ));
+
+        registry.update(
+            "live/test",
+            PlaylistState {
+                msn: 3,
+                parts: 0,
+                target_duration: 1,
+            },
+        );
+        assert!(matches!(
+            registry.wait_for("live/test", 10, None).await,
+            WaitResult::BadRequest
+        ));
+
+        let updater = registry.clone();
+        tokio::spawn(async move {
+            updater.update(
+                "live/test",
+                PlaylistState {
+                    msn: 3,
+                    parts: 1,
+                    target_duration: 1,
+                },
+            );
+        });
+        assert!(matches!(
+            registry.wait_for("live/test", 3, Some(0)).await,
+            WaitResult::Ready
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_demand() {
+        let registry = Arc::new(HlsRegistry::default());
+        /*the streams are always packaged*/
+        assert!(matches!(
+            registry.demand("live/test").await,
+            WaitResult::NotFound
+        ));
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        registry.set_demand_sender(sender);
+        let remuxer = registry.clone();
+        tokio::spawn(async move {
+            while let Some(name) = receiver.recv().await {
+                if name == "live/test" {
+                    remuxer.update(
+                        &name,
+                        PlaylistState {
+                            msn: 1,
+                            parts: 0,
+                            target_duration: 5,
+                        },
+                    );
+                } else {
+                    /*not published*/
+                    remuxer.remove(&name);
+                }
+            }
+        });
+
+        assert!(matches!(
+            registry.demand("live/test").await,
+            WaitResult::Ready
+        ));
+        assert!(registry.idle_time("live/test").is_some());
+        assert!(matches!(
+            registry.demand("live/absent").await,
+            WaitResult::NotFound
+        ));
+        assert!(registry.state("live/absent").is_none());
+    }
+}
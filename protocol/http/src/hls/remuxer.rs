@@ -1,61 +1,172 @@
 use streamhub::stream::Protocol;
 use {
-    super::{errors::HlsError, flv_data_receiver::FlvDataReceiver},
+    super::{
+        define::HlsSettings,
+        errors::HlsError,
+        flv_data_receiver::FlvDataReceiver,
+        registry::{HlsRegistry, PlaylistState},
+    },
+    commonlib::{
+        config::{load_config, HlsAppConfig, RecordFormat},
+        record::{RecordService, RecordSettings},
+    },
+    std::sync::Arc,
     streamhub::{
         define::{BroadcastEvent, BroadcastEventReceiver, StreamHubEventSender},
     },
+    tokio::sync::mpsc,
 };
 
 pub struct HlsRemuxer {
     client_event_consumer: BroadcastEventReceiver,
     event_producer: StreamHubEventSender,
-    need_record: bool,
+    /*None if no stream is recorded*/
+    record_service: Option<Arc<RecordService>>,
+    /*LL-HLS part target duration in milliseconds, None disables LL-HLS*/
+    part_duration: Option<i64>,
+    /*the per app settings at startup, used if the config file cannot be loaded*/
+    hls_apps: Vec<HlsAppConfig>,
+    registry: Arc<HlsRegistry>,
+    /*the streams are packaged when their playlists are requested instead of
+    when they are published, and stop after this many milliseconds without
+    requests. None if the streams are always packaged*/
+    idle_timeout: Option<i64>,
+    /*the names of the requested on-demand streams*/
+    demand_receiver: Option<mpsc::UnboundedReceiver<String>>,
 }
 
 impl HlsRemuxer {
     pub fn new(
         consumer: BroadcastEventReceiver,
         event_producer: StreamHubEventSender,
-        need_record: bool,
+        record_service: Option<Arc<RecordService>>,
+        part_duration: Option<i64>,
+        hls_apps: Vec<HlsAppConfig>,
+        registry: Arc<HlsRegistry>,
+        on_demand_idle_timeout: Option<i64>,
     ) -> Self {
+        let idle_timeout = on_demand_idle_timeout;
+        let demand_receiver = if idle_timeout.is_some() {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            registry.set_demand_sender(sender);
+            Some(receiver)
+        } else {
+            None
+        };
+
         Self {
             client_event_consumer: consumer,
             event_producer,
-            need_record,
+            record_service,
+            part_duration,
+            hls_apps,
+            registry,
+            idle_timeout,
+            demand_receiver,
+        }
+    }
+
+    /*the settings and the rendition groups are loaded again for every new
+    stream, so that they can be changed without a restart*/
+    fn load_hls_apps(&self) -> Vec<HlsAppConfig> {
+        match load_config("config.json") {
+            Ok(config) => {
+                let http = config.http;
+                let groups = http.as_ref().and_then(|h| h.hls_groups.clone());
+                self.registry.set_groups(groups.unwrap_or_default());
+                http.and_then(|h| h.hls_apps).unwrap_or_default()
+            }
+            Err(err) => {
+                log::warn!("load hls settings from config.json error: {}", err);
+                self.hls_apps.clone()
+            }
         }
     }
 
     pub async fn run(&mut self) -> Result<(), HlsError> {
+        let mut demand_receiver = self.demand_receiver.take();
         loop {
-            let val = self.client_event_consumer.recv().await?;
-            match val {
-                BroadcastEvent::Publish { protocol: Protocol::Rtmp, name } => {
-                    let (app_name, stream_name) = {
-                        let mut iter = name.split('/');
-                        let app_name = iter.next().unwrap_or_default();
-                        let stream_name = iter.next().unwrap_or_default();
-                        (app_name.to_string(), stream_name.to_string())
-                    };
-                    {
-                        let mut rtmp_subscriber = FlvDataReceiver::new(
-                            app_name,
-                            stream_name,
-                            self.event_producer.clone(),
-                            5,
-                            self.need_record,
-                        );
-
-                        tokio::spawn(async move {
-                            if let Err(err) = rtmp_subscriber.run().await {
-                                println!("hls handler run error {err}");
+            tokio::select! {
+                val = self.client_event_consumer.recv() => {
+                    match val? {
+                        BroadcastEvent::Publish { protocol: Protocol::Rtmp, name } => {
+                            /*the recorded streams are always packaged*/
+                            if self.idle_timeout.is_none() || self.is_recorded(&name) {
+                                self.start_packaging(name);
                             }
-                        });
+                        }
+                        _ => {
+                            log::trace!("other infos...");
+                        }
                     }
                 }
-                _ => {
-                    log::trace!("other infos...");
+                Some(name) = async {
+                    match demand_receiver.as_mut() {
+                        Some(receiver) => receiver.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    log::info!("hls playlist of {} is requested, start packaging", name);
+                    self.start_packaging(name);
                 }
             }
         }
     }
+
+    fn split_name(name: &str) -> (String, String) {
+        let mut iter = name.split('/');
+        let app_name = iter.next().unwrap_or_default();
+        let stream_name = iter.next().unwrap_or_default();
+        (app_name.to_string(), stream_name.to_string())
+    }
+
+    /*the settings of the streams recorded as hls, the other formats are
+    recorded by the record remuxer*/
+    fn record_settings(&self, app_name: &str, stream_name: &str) -> Option<RecordSettings> {
+        self.record_service
+            .as_ref()
+            .and_then(|service| service.settings(app_name, stream_name))
+            .filter(|settings| settings.format == RecordFormat::Hls)
+    }
+
+    fn is_recorded(&self, name: &str) -> bool {
+        let (app_name, stream_name) = Self::split_name(name);
+        self.record_settings(&app_name, &stream_name).is_some()
+    }
+
+    fn start_packaging(&self, name: String) {
+        let (app_name, stream_name) = Self::split_name(&name);
+        let mut settings = HlsSettings::new(self.part_duration).resolve(
+            &self.load_hls_apps(),
+            &app_name,
+            &stream_name,
+        );
+        settings.align_segments = self.registry.in_group(&app_name, &stream_name);
+        settings.record = self.record_settings(&app_name, &stream_name);
+        /*a recorded stream is not stopped when it is idle, it is registered so
+        that its playlist requests wait for it instead of starting it again*/
+        if settings.record.is_none() {
+            settings.idle_timeout = self.idle_timeout;
+        } else if self.idle_timeout.is_some() {
+            self.registry.update(&name, PlaylistState::default());
+        }
+
+        let mut rtmp_subscriber = FlvDataReceiver::new(
+            app_name,
+            stream_name,
+            self.event_producer.clone(),
+            settings,
+            self.registry.clone(),
+        );
+        let registry = self.registry.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = rtmp_subscriber.run().await {
+                println!("hls handler run error {err}");
+                /*the requests waiting for an on-demand stream which is not
+                published are released*/
+                registry.remove(&name);
+            }
+        });
+    }
 }
@@ -0,0 +1,77 @@
+use {
+    bytes::Bytes,
+    std::{collections::HashMap, sync::RwLock},
+};
+
+/*The live playlists, segments, parts and init segments of the hls streams, they
+are served from memory and only written to disk when the stream is recorded.
+The files of a stream form a ring: the playlist removes the oldest segment(and
+its parts) whenever it adds a new one, so a stream holds at most the segments
+listed in its live playlist. Keyed by "app_name/stream_name" and the file name.*/
+#[derive(Default)]
+pub struct HlsStore {
+    streams: RwLock<HashMap<String, HashMap<String, Bytes>>>,
+}
+
+impl HlsStore {
+    pub fn put(&self, name: &str, file_name: &str, data: Bytes) {
+        self.streams
+            .write()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .insert(file_name.to_string(), data);
+    }
+
+    pub fn get(&self, name: &str, file_name: &str) -> Option<Bytes> {
+        self.streams
+            .read()
+            .unwrap()
+            .get(name)
+            .and_then(|files| files.get(file_name))
+            .cloned()
+    }
+
+    pub fn contains(&self, name: &str, file_name: &str) -> bool {
+        self.streams
+            .read()
+            .unwrap()
+            .get(name)
+            .is_some_and(|files| files.contains_key(file_name))
+    }
+
+    pub fn remove(&self, name: &str, file_name: &str) {
+        if let Some(files) = self.streams.write().unwrap().get_mut(name) {
+            files.remove(file_name);
+        }
+    }
+
+    pub fn remove_stream(&self, name: &str) {
+        self.streams.write().unwrap().remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HlsStore;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_hls_store() {
+        let store = HlsStore::default();
+        store.put("live/test", "index.m3u8", Bytes::from_static(b"#EXTM3U\n"));
+        store.put("live/test", "0.ts", Bytes::from_static(&[0x47; 188]));
+        store.put("live/other", "0.ts", Bytes::from_static(&[0x47; 376]));
+
+        assert_eq!(store.get("live/test", "0.ts").unwrap().len(), 188);
+        assert!(store.get("live/test", "1.ts").is_none());
+
+        store.remove("live/test", "0.ts");
+        assert!(!store.contains("live/test", "0.ts"));
+        assert!(store.contains("live/test", "index.m3u8"));
+
+        store.remove_stream("live/test");
+        assert!(store.get("live/test", "index.m3u8").is_none());
+        assert!(store.contains("live/other", "0.ts"));
+    }
+}
@@ -1,35 +1,236 @@
 use {
-    super::errors::MediaError,
-    bytes::BytesMut,
-    std::{fs, fs::File, io::Write},
+    super::{define::HlsSettings, errors::MediaError, registry::HlsRegistry},
+    bytes::{Bytes, BytesMut},
+    std::{
+        collections::HashSet,
+        fs,
+        fs::File,
+        io::{ErrorKind, Write},
+        sync::Arc,
+    },
 };
 
+/*The segments are kept in the in-memory store of the registry for the live
+playlist, the complete segments and the init segments are also written to
+disk under the record path when the stream is recorded. The segments retained
+for the DVR window are moved to disk once they leave the live playlist.*/
 pub struct Ts {
     ts_number: u32,
-    live_path: String,
+    app_name: String,
+    stream_name: String,
+    /*app_name/stream_name*/
+    name: String,
+    /*the directory of the current recording*/
+    record_path: Option<String>,
+    /*the files written in the directory of the current recording*/
+    recorded_files: HashSet<String>,
+    /*the directory of the DVR segments out of the live playlist*/
+    dvr_path: Option<String>,
+    spilled_files: HashSet<String>,
+    settings: HlsSettings,
+    /*"ts", "m4s" for the fragmented mp4 segments or "aac" for packed audio*/
+    extension: &'static str,
+    registry: Arc<HlsRegistry>,
 }
 
 impl Ts {
-    pub fn new(app_name: String, stream_name: String) -> Self {
-        let live_path = format!("./hls/{app_name}/{stream_name}");
-        fs::create_dir_all(live_path.clone()).unwrap();
-
+    pub fn new(
+        app_name: String,
+        stream_name: String,
+        extension: &'static str,
+        settings: HlsSettings,
+        registry: Arc<HlsRegistry>,
+    ) -> Self {
         Self {
             ts_number: 0,
-            live_path,
+            name: format!("{app_name}/{stream_name}"),
+            app_name,
+            stream_name,
+            record_path: None,
+            recorded_files: HashSet::new(),
+            dvr_path: None,
+            spilled_files: HashSet::new(),
+            settings,
+            extension,
+            registry,
+        }
+    }
+
+    /*the files written from now on are also recorded in this directory*/
+    pub fn set_record_path(&mut self, record_path: String) -> Result<(), MediaError> {
+        fs::create_dir_all(&record_path)?;
+        self.record_path = Some(record_path);
+        self.recorded_files.clear();
+        Ok(())
+    }
+
+    /*the DVR segments are moved to this directory, the files left by a
+    previous publish of the stream are removed*/
+    pub fn set_dvr_path(&mut self, dvr_path: String) {
+        self.dvr_path = Some(dvr_path);
+        self.clear_spilled_files();
+    }
+
+    /*Move a segment out of the live playlist from the store to the DVR
+    directory, a segment of the current recording is already on disk and
+    is served from there.*/
+    pub fn spill(&mut self, file_name: &str) -> Result<(), MediaError> {
+        let Some(dvr_path) = &self.dvr_path else {
+            return Ok(());
+        };
+        if !self.recorded_files.contains(file_name) {
+            if let Some(data) = self.registry.store().get(&self.name, file_name) {
+                fs::create_dir_all(dvr_path)?;
+                let mut file_handler = File::create(format!("{dvr_path}/{file_name}"))?;
+                file_handler.write_all(&data[..])?;
+                self.spilled_files.insert(file_name.to_string());
+            }
+        }
+        self.registry.store().remove(&self.name, file_name);
+        Ok(())
+    }
+
+    /*the DVR directory is removed with the stream*/
+    pub fn clear_spilled_files(&mut self) {
+        if let Some(dvr_path) = &self.dvr_path {
+            if let Err(err) = fs::remove_dir_all(dvr_path) {
+                if err.kind() != ErrorKind::NotFound {
+                    log::error!(
+                        "{}: remove dvr directory {} error: {}",
+                        self.name,
+                        dvr_path,
+                        err
+                    );
+                }
+            }
         }
+        self.spilled_files.clear();
     }
-    pub fn write(&mut self, data: BytesMut) -> Result<(String, String), MediaError> {
-        let ts_file_name = format!("{}.ts", self.ts_number);
-        let ts_file_path = format!("{}/{}", self.live_path, ts_file_name);
+
+    /*the files of the current recording, so that it can be deleted alone*/
+    pub fn recorded_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = self.recorded_files.iter().cloned().collect();
+        files.sort();
+        files
+    }
+
+    /*copy a live file, an init segment or a key, to the current recording*/
+    pub fn record_live_file(&mut self, file_name: &str) -> Result<(), MediaError> {
+        if let Some(data) = self.registry.store().get(&self.name, file_name) {
+            self.record(file_name, &data[..])?;
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, file_name: &str, data: &[u8]) -> Result<(), MediaError> {
+        if let Some(record_path) = &self.record_path {
+            let mut file_handler = File::create(format!("{record_path}/{file_name}"))?;
+            file_handler.write_all(data)?;
+            self.recorded_files.insert(file_name.to_string());
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, data: BytesMut) -> Result<String, MediaError> {
+        let ts_file_name = format!(
+            "{}.{}",
+            self.settings
+                .segment_file_name(&self.app_name, &self.stream_name, self.ts_number),
+            self.extension
+        );
         self.ts_number += 1;
 
-        let mut ts_file_handler = File::create(ts_file_path.clone())?;
-        ts_file_handler.write_all(&data[..])?;
+        self.record(&ts_file_name, &data[..])?;
+        self.registry
+            .store()
+            .put(&self.name, &ts_file_name, data.freeze());
+
+        Ok(ts_file_name)
+    }
+    /*LL-HLS partial segment of the segment being built, named "{segment}.{part}.ts"
+    or "{segment}.{part}.m4s" whatever the segment name template is, they are
+    never recorded*/
+    pub fn write_part(&mut self, part_index: usize, data: &BytesMut) -> String {
+        let part_file_name = format!("{}.{}.{}", self.ts_number, part_index, self.extension);
+        self.registry.store().put(
+            &self.name,
+            &part_file_name,
+            Bytes::copy_from_slice(&data[..]),
+        );
+
+        part_file_name
+    }
+
+    /*fragmented mp4 init segment, a new one is written when the decoder
+    configurations change, named "init_{index}.mp4"*/
+    pub fn write_init(&mut self, index: usize, data: &BytesMut) -> Result<String, MediaError> {
+        let init_file_name = format!("init_{index}.mp4");
+
+        self.record(&init_file_name, &data[..])?;
+        self.registry.store().put(
+            &self.name,
+            &init_file_name,
+            Bytes::copy_from_slice(&data[..]),
+        );
+
+        Ok(init_file_name)
+    }
+
+    /*AES-128 key of the encrypted segments, named "{key_name}.key", it is
+    recorded with the segments so that the recorded stream can be decrypted*/
+    pub fn write_key(&mut self, key_name: &str, key: &[u8]) -> Result<(), MediaError> {
+        let key_file_name = format!("{key_name}.key");
+
+        self.record(&key_file_name, key)?;
+        self.registry
+            .store()
+            .put(&self.name, &key_file_name, Bytes::copy_from_slice(key));
+
+        Ok(())
+    }
 
-        Ok((ts_file_name, ts_file_path))
+    /*the live playlist only lives in memory*/
+    pub fn write_playlist(&mut self, content: &str) {
+        self.registry.store().put(
+            &self.name,
+            "index.m3u8",
+            Bytes::copy_from_slice(content.as_bytes()),
+        );
     }
-    pub fn delete(&mut self, ts_file_name: String) {
-        fs::remove_file(ts_file_name).unwrap();
+
+    pub fn set_extension(&mut self, extension: &'static str) {
+        self.extension = extension;
+    }
+
+    pub fn extension(&self) -> &'static str {
+        self.extension
+    }
+
+    /*the number of the segment being built, it is also its media sequence number*/
+    pub fn current_number(&self) -> u32 {
+        self.ts_number
+    }
+
+    /*remove a segment, a part, an init segment or a key from the live files
+    and the DVR directory, the recorded files are kept*/
+    pub fn delete(&mut self, file_name: &str) {
+        self.registry.store().remove(&self.name, file_name);
+        if self.spilled_files.remove(file_name) {
+            if let Some(dvr_path) = &self.dvr_path {
+                if let Err(err) = fs::remove_file(format!("{dvr_path}/{file_name}")) {
+                    log::error!(
+                        "{}: remove dvr segment {} error: {}",
+                        self.name,
+                        file_name,
+                        err
+                    );
+                }
+            }
+        }
+    }
+
+    /*the vod playlist of a recorded stream*/
+    pub fn record_playlist(&mut self, file_name: &str, content: &str) -> Result<(), MediaError> {
+        self.record(file_name, content.as_bytes())
     }
 }
@@ -1,3 +1,5 @@
+pub mod dash;
 pub mod flv;
 pub mod hls;
+pub mod record;
 pub mod server;
\ No newline at end of file
@@ -0,0 +1,94 @@
+use {
+    commonlib::record::{ActiveRecording, Recording},
+    std::sync::{Arc, Mutex},
+    streamhub::define::{RecordInfo, StreamHubEvent, StreamHubEventSender},
+};
+
+/*the status of a recorder, shared with the record manager*/
+pub type RecordStatus = Arc<Mutex<ActiveRecording>>;
+
+/*milliseconds, a stream is regarded as audio-only if no video is received
+within the first second of its audio*/
+const AUDIO_ONLY_PROBE_DURATION: u32 = 1000;
+
+/*Where the files of a recording can start: on the video key frames once the
+stream has video, on any audio frame once it is confirmed audio-only.*/
+#[derive(Default)]
+pub struct CutPoints {
+    has_video: bool,
+    first_audio_timestamp: Option<u32>,
+}
+
+impl CutPoints {
+    pub fn on_video(&mut self) {
+        self.has_video = true;
+    }
+
+    pub fn can_cut_on_audio(&mut self, timestamp: u32) -> bool {
+        if self.has_video {
+            return false;
+        }
+        let first_audio_timestamp = *self.first_audio_timestamp.get_or_insert(timestamp);
+        timestamp.saturating_sub(first_audio_timestamp) >= AUDIO_ONLY_PROBE_DURATION
+    }
+}
+
+/*a recorder started a file*/
+pub fn notify_record_start(
+    event_producer: &StreamHubEventSender,
+    status: &RecordStatus,
+    path: &str,
+) {
+    let name = {
+        let mut status = status.lock().unwrap();
+        status.path = Some(path.to_string());
+        format!("{}/{}", status.app, status.stream)
+    };
+
+    let event = StreamHubEvent::RecordStart {
+        name,
+        info: RecordInfo {
+            path: path.to_string(),
+            duration: 0,
+            size: 0,
+        },
+    };
+    if let Err(err) = event_producer.send(event) {
+        log::error!("send record start event error: {}", err);
+    }
+}
+
+/*a recorder finished a file*/
+pub fn notify_record_stop(event_producer: &StreamHubEventSender, recording: &Recording) {
+    let event = StreamHubEvent::RecordStop {
+        name: format!("{}/{}", recording.app, recording.stream),
+        info: RecordInfo {
+            path: recording.path.clone(),
+            duration: recording.duration,
+            size: recording.size,
+        },
+    };
+    if let Err(err) = event_producer.send(event) {
+        log::error!("send record stop event error: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CutPoints;
+
+    #[test]
+    fn test_cut_points() {
+        /*not on the audio received before the video*/
+        let mut cut_points = CutPoints::default();
+        assert!(!cut_points.can_cut_on_audio(0));
+        cut_points.on_video();
+        assert!(!cut_points.can_cut_on_audio(2000));
+
+        /*an audio-only stream is confirmed after a second*/
+        let mut cut_points = CutPoints::default();
+        assert!(!cut_points.can_cut_on_audio(500));
+        assert!(!cut_points.can_cut_on_audio(1400));
+        assert!(cut_points.can_cut_on_audio(1500));
+    }
+}
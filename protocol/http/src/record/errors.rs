@@ -0,0 +1,136 @@
+#![allow(non_local_definitions)]
+
+use {
+    crate::hls::errors::MediaError,
+    failure::{Backtrace, Fail},
+    std::fmt,
+    streamhub::errors::StreamHubError,
+    tokio::sync::broadcast::error::RecvError,
+    tokio::sync::oneshot::error::RecvError as OneshotRecvError,
+    xflv::{
+        amf0::Amf0WriteError,
+        errors::{FlvDemuxerError, FlvMuxerError},
+    },
+    xmp4::errors::Mp4Error,
+};
+
+#[derive(Debug)]
+pub struct RecordError {
+    pub value: RecordErrorValue,
+}
+
+#[derive(Debug, Fail)]
+pub enum RecordErrorValue {
+    #[fail(display = "channel error:{}", _0)]
+    ChannelError(#[cause] StreamHubError),
+    #[fail(display = "receive error:{}", _0)]
+    RecvError(#[cause] RecvError),
+    #[fail(display = "tokio: oneshot receiver err: {}", _0)]
+    OneshotRecvError(#[cause] OneshotRecvError),
+    #[fail(display = "stream hub event send error")]
+    StreamHubEventSendErr,
+    #[fail(display = "io error:{}", _0)]
+    IOError(#[cause] std::io::Error),
+    #[fail(display = "flv muxer error:{}", _0)]
+    FlvMuxerError(#[cause] FlvMuxerError),
+    #[fail(display = "flv demuxer error:{}", _0)]
+    FlvDemuxerError(#[cause] FlvDemuxerError),
+    #[fail(display = "amf0 write error:{}", _0)]
+    Amf0WriteError(#[cause] Amf0WriteError),
+    #[fail(display = "mp4 error:{}", _0)]
+    Mp4Error(#[cause] Mp4Error),
+    #[fail(display = "media error:{}", _0)]
+    MediaError(#[cause] MediaError),
+    #[fail(display = "the stream is already being recorded")]
+    AlreadyRecording,
+    #[fail(display = "the hls recordings are made by the hls remuxer")]
+    UnsupportedFormat,
+}
+
+impl From<StreamHubError> for RecordError {
+    fn from(error: StreamHubError) -> Self {
+        RecordError {
+            value: RecordErrorValue::ChannelError(error),
+        }
+    }
+}
+
+impl From<RecvError> for RecordError {
+    fn from(error: RecvError) -> Self {
+        RecordError {
+            value: RecordErrorValue::RecvError(error),
+        }
+    }
+}
+
+impl From<OneshotRecvError> for RecordError {
+    fn from(error: OneshotRecvError) -> Self {
+        RecordError {
+            value: RecordErrorValue::OneshotRecvError(error),
+        }
+    }
+}
+
+impl From<std::io::Error> for RecordError {
+    fn from(error: std::io::Error) -> Self {
+        RecordError {
+            value: RecordErrorValue::IOError(error),
+        }
+    }
+}
+
+impl From<FlvMuxerError> for RecordError {
+    fn from(error: FlvMuxerError) -> Self {
+        RecordError {
+            value: RecordErrorValue::FlvMuxerError(error),
+        }
+    }
+}
+
+impl From<FlvDemuxerError> for RecordError {
+    fn from(error: FlvDemuxerError) -> Self {
+        RecordError {
+            value: RecordErrorValue::FlvDemuxerError(error),
+        }
+    }
+}
+
+impl From<Amf0WriteError> for RecordError {
+    fn from(error: Amf0WriteError) -> Self {
+        RecordError {
+            value: RecordErrorValue::Amf0WriteError(error),
+        }
+    }
+}
+
+impl From<Mp4Error> for RecordError {
+    fn from(error: Mp4Error) -> Self {
+        RecordError {
+            value: RecordErrorValue::Mp4Error(error),
+        }
+    }
+}
+
+impl From<MediaError> for RecordError {
+    fn from(error: MediaError) -> Self {
+        RecordError {
+            value: RecordErrorValue::MediaError(error),
+        }
+    }
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.value, f)
+    }
+}
+
+impl Fail for RecordError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.value.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.value.backtrace()
+    }
+}
@@ -0,0 +1,257 @@
+use {
+    super::errors::RecordError,
+    bytes::BytesMut,
+    commonlib::record::{RecordFile, Recording},
+    indexmap::IndexMap,
+    std::{
+        fs::{self, File},
+        io::{BufWriter, Seek, SeekFrom, Write},
+        path::Path,
+    },
+    xflv::{
+        amf0::Amf0ValueType,
+        define::tag_type,
+        metadata::{read_on_meta_data, write_on_meta_data},
+        muxer::{FlvMuxer, HEADER_LENGTH},
+    },
+};
+
+/*the entries of the keyframes index reserved in the onMetaData*/
+const KEYFRAME_SLOTS: usize = 512;
+
+/*A recorded flv file. The tags are written as they are received with the
+timestamps starting from 0. The onMetaData is written with room for the
+duration, the file size and the keyframes index, they are filled in place
+with the header flags when the file is closed.*/
+pub struct FlvFile {
+    writer: BufWriter<File>,
+    muxer: FlvMuxer,
+    record_file: RecordFile,
+    /*the properties of the onMetaData of the stream*/
+    properties: IndexMap<String, Amf0ValueType>,
+    /*the position of the first tag after the header and the onMetaData*/
+    body_start: u64,
+    position: u64,
+    base_timestamp: Option<u32>,
+    /*milliseconds from the first tag*/
+    last_timestamp: u32,
+    has_audio: bool,
+    has_video: bool,
+    /*the times in seconds and the file positions of the key frames*/
+    keyframes: Vec<(f64, u64)>,
+}
+
+impl FlvFile {
+    pub fn create(
+        record_file: RecordFile,
+        metadata: Option<BytesMut>,
+    ) -> Result<Self, RecordError> {
+        if let Some(parent) = Path::new(&record_file.path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let writer = BufWriter::new(File::create(&record_file.path)?);
+
+        let properties = match metadata.map(read_on_meta_data) {
+            Some(Ok(properties)) => properties,
+            Some(Err(err)) => {
+                log::warn!("{}: read onMetaData error: {}", record_file.path, err);
+                Default::default()
+            }
+            None => Default::default(),
+        };
+
+        let mut flv_file = Self {
+            writer,
+            muxer: FlvMuxer::new(),
+            record_file,
+            properties,
+            body_start: 0,
+            position: 0,
+            base_timestamp: None,
+            last_timestamp: 0,
+            has_audio: false,
+            has_video: false,
+            keyframes: Vec::new(),
+        };
+
+        /*the size of the onMetaData only depends on the properties and the
+        number of key frames*/
+        let placeholder = write_on_meta_data(
+            flv_file.properties.clone(),
+            0.0,
+            0,
+            &[(0.0, 0); KEYFRAME_SLOTS],
+        )?;
+        flv_file.muxer.write_flv_header(true, true)?;
+        flv_file.muxer.write_previous_tag_size(0)?;
+        flv_file.write_raw_tag(tag_type::SCRIPT_DATA_AMF, 0, placeholder)?;
+        flv_file.flush()?;
+        flv_file.body_start = flv_file.position;
+
+        Ok(flv_file)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.record_file.path
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.record_file.is_full()
+    }
+
+    pub fn write_tag(
+        &mut self,
+        tag_type: u8,
+        timestamp: u32,
+        data: BytesMut,
+        is_key_frame: bool,
+    ) -> Result<(), RecordError> {
+        let base_timestamp = *self.base_timestamp.get_or_insert(timestamp);
+        let timestamp = timestamp.saturating_sub(base_timestamp);
+
+        match tag_type {
+            tag_type::AUDIO => self.has_audio = true,
+            tag_type::VIDEO => self.has_video = true,
+            _ => {}
+        }
+        if is_key_frame {
+            self.keyframes
+                .push((timestamp as f64 / 1000.0, self.position));
+        }
+
+        let size = data.len() as u64 + HEADER_LENGTH as u64 + 4;
+        let duration = timestamp.saturating_sub(self.last_timestamp);
+        self.last_timestamp = std::cmp::max(timestamp, self.last_timestamp);
+        self.record_file.add(duration as i64, size);
+
+        self.write_raw_tag(tag_type, timestamp, data)?;
+        self.flush()
+    }
+
+    fn write_raw_tag(
+        &mut self,
+        tag_type: u8,
+        timestamp: u32,
+        data: BytesMut,
+    ) -> Result<(), RecordError> {
+        let data_size = data.len() as u32;
+        self.muxer
+            .write_flv_tag_header(tag_type, data_size, timestamp)?;
+        self.muxer.write_flv_tag_body(data)?;
+        self.muxer
+            .write_previous_tag_size(data_size + HEADER_LENGTH)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), RecordError> {
+        let data = self.muxer.writer.extract_current_bytes();
+        self.writer.write_all(&data[..])?;
+        self.position += data.len() as u64;
+        Ok(())
+    }
+
+    /*The header flags of the tracks and the onMetaData with the keyframes
+    index are written over the reserved ones, and the file is added to the
+    recordings index.*/
+    pub fn finish(mut self) -> Result<Recording, RecordError> {
+        let keyframes = self.keyframe_slots();
+        let metadata = write_on_meta_data(
+            std::mem::take(&mut self.properties),
+            self.last_timestamp as f64 / 1000.0,
+            self.position,
+            &keyframes,
+        )?;
+
+        self.muxer
+            .write_flv_header(self.has_audio, self.has_video)?;
+        self.muxer.write_previous_tag_size(0)?;
+        self.write_raw_tag(tag_type::SCRIPT_DATA_AMF, 0, metadata)?;
+        let header = self.muxer.writer.extract_current_bytes();
+        debug_assert_eq!(header.len() as u64, self.body_start);
+
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&header[..])?;
+        self.writer.flush()?;
+
+        self.record_file.set_size(self.position);
+        Ok(self.record_file.finish())
+    }
+
+    /*The key frames in the reserved entries, one of every few key frames is
+    indexed if there are more, the unused entries repeat the last one.*/
+    fn keyframe_slots(&self) -> Vec<(f64, u64)> {
+        let step = std::cmp::max(self.keyframes.len().div_ceil(KEYFRAME_SLOTS), 1);
+        let mut slots: Vec<(f64, u64)> = self.keyframes.iter().step_by(step).copied().collect();
+        let last = slots.last().copied().unwrap_or((0.0, self.body_start));
+        slots.resize(KEYFRAME_SLOTS, last);
+        slots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlvFile, KEYFRAME_SLOTS};
+    use bytes::BytesMut;
+    use commonlib::{
+        config::{RecordConfig, RecordFormat},
+        record::RecordService,
+    };
+    use xflv::{amf0::Amf0ValueType, define::tag_type, metadata::read_on_meta_data};
+
+    #[test]
+    fn test_flv_file_keyframes() {
+        let root = std::env::temp_dir().join(format!("xiu_flv_record_{}", std::process::id()));
+        let root = root.to_str().unwrap();
+        let service = RecordService::new(RecordConfig {
+            enabled: true,
+            format: Some(RecordFormat::Flv),
+            root: Some(root.to_string()),
+            path: Some(String::from("{app}/{stream}")),
+            index: Some(format!("{root}/recordings.json")),
+            ..Default::default()
+        });
+        let settings = service.settings("live", "test").unwrap();
+
+        let mut flv_file = FlvFile::create(settings.start("live", "test", root), None).unwrap();
+        for (index, timestamp) in [1000, 1040, 2000, 2040].into_iter().enumerate() {
+            let is_key_frame = index % 2 == 0;
+            let data = BytesMut::from(&[if is_key_frame { 0x17 } else { 0x27 }, 1, 0, 0, 0][..]);
+            flv_file
+                .write_tag(tag_type::VIDEO, timestamp, data, is_key_frame)
+                .unwrap();
+        }
+        let recording = flv_file.finish().unwrap();
+        assert_eq!(recording.duration, 1040);
+
+        let data = std::fs::read(&recording.path).unwrap();
+        assert_eq!(data.len() as u64, recording.size);
+        /*a video only file*/
+        assert_eq!(data[4], 0x01);
+
+        let metadata_size = u32::from_be_bytes([0, data[14], data[15], data[16]]) as usize;
+        let properties = read_on_meta_data(BytesMut::from(&data[24..24 + metadata_size])).unwrap();
+        assert_eq!(properties["duration"], Amf0ValueType::Number(1.04));
+        let positions = match &properties["keyframes"] {
+            Amf0ValueType::Object(index) => index["filepositions"].clone(),
+            _ => panic!("no keyframes index"),
+        };
+        let positions = match positions {
+            Amf0ValueType::StrictArray(positions) => positions,
+            _ => panic!("no file positions"),
+        };
+        assert_eq!(positions.len(), KEYFRAME_SLOTS);
+        /*the unused entries repeat the last key frame*/
+        assert_ne!(positions[0], positions[1]);
+        assert_eq!(positions[1], positions[KEYFRAME_SLOTS - 1]);
+        for position in positions {
+            if let Amf0ValueType::Number(position) = position {
+                /*a key frame video tag*/
+                assert_eq!(data[position as usize], tag_type::VIDEO);
+                assert_eq!(data[position as usize + 11], 0x17);
+            }
+        }
+
+        assert_eq!(service.index().list(Some("live"), None).len(), 1);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
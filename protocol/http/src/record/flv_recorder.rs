@@ -0,0 +1,203 @@
+use {
+    super::{
+        define::{notify_record_start, notify_record_stop, CutPoints, RecordStatus},
+        errors::RecordError,
+        flv_file::FlvFile,
+        subscriber::RecordSubscriber,
+    },
+    bytes::BytesMut,
+    bytesio::bytes_reader::BytesReader,
+    commonlib::record::{RecordSettings, DEFAULT_RECORD_ROOT},
+    streamhub::define::{FrameData, StreamHubEventSender},
+    tokio::sync::oneshot,
+    xflv::{
+        amf0::amf0_writer::Amf0Writer,
+        define::{aac_packet_type, avc_packet_type, frame_type, tag_type, AvcCodecId, SoundFormat},
+        flv_tag_header::{AudioTagHeader, VideoTagHeader},
+        Unmarshal,
+    },
+};
+
+/*Records a stream to flv files. A file starts with the onMetaData and the
+sequence headers of the stream and its first tag is a key frame, a new file is
+started at the first key frame after the file reaches its max duration or
+size.*/
+pub struct FlvRecorder {
+    app_name: String,
+    stream_name: String,
+    event_producer: StreamHubEventSender,
+    subscriber: RecordSubscriber,
+    settings: RecordSettings,
+    status: RecordStatus,
+    file: Option<FlvFile>,
+    /*the onMetaData of the stream, without "@setDataFrame"*/
+    metadata: Option<BytesMut>,
+    audio_sequence_header: Option<BytesMut>,
+    video_sequence_header: Option<BytesMut>,
+    cut_points: CutPoints,
+}
+
+impl FlvRecorder {
+    pub fn new(
+        app_name: String,
+        stream_name: String,
+        event_producer: StreamHubEventSender,
+        settings: RecordSettings,
+        status: RecordStatus,
+        stop_receiver: oneshot::Receiver<()>,
+    ) -> Self {
+        let subscriber = RecordSubscriber::new(
+            app_name.clone(),
+            stream_name.clone(),
+            event_producer.clone(),
+            stop_receiver,
+        );
+
+        Self {
+            app_name,
+            stream_name,
+            event_producer,
+            subscriber,
+            settings,
+            status,
+            file: None,
+            metadata: None,
+            audio_sequence_header: None,
+            video_sequence_header: None,
+            cut_points: CutPoints::default(),
+        }
+    }
+
+    /*the current file is finished when the stream ends or the recording is
+    stopped*/
+    pub async fn receive_frame_data(&mut self) -> Result<(), RecordError> {
+        while let Some(data) = self.subscriber.recv().await {
+            if let Err(err) = self.process_frame_data(data) {
+                log::error!(
+                    "record {}/{} error: {}",
+                    self.app_name,
+                    self.stream_name,
+                    err
+                );
+                break;
+            }
+        }
+
+        let result = self.finish_file();
+        self.subscriber.unsubscribe_from_rtmp_channels();
+        result
+    }
+
+    pub async fn subscribe_from_rtmp_channels(&mut self) -> Result<(), RecordError> {
+        self.subscriber.subscribe_from_rtmp_channels().await
+    }
+
+    fn process_frame_data(&mut self, data: FrameData) -> Result<(), RecordError> {
+        match data {
+            FrameData::MetaData { timestamp: _, data } => {
+                self.metadata = Some(remove_set_data_frame(data));
+                Ok(())
+            }
+            FrameData::Audio { timestamp, data } => {
+                let header = AudioTagHeader::unmarshal(&mut BytesReader::new(data.clone()))?;
+                let is_sequence_header = header.sound_format == SoundFormat::AAC as u8
+                    && header.aac_packet_type == aac_packet_type::AAC_SEQHDR;
+                if is_sequence_header {
+                    self.audio_sequence_header = Some(data.clone());
+                }
+                /*the files of an audio-only stream are cut on any audio frame*/
+                let can_cut = self.cut_points.can_cut_on_audio(timestamp) && !is_sequence_header;
+                self.write_tag(tag_type::AUDIO, timestamp, data, can_cut, false)
+            }
+            FrameData::Video { timestamp, data } => {
+                let header = VideoTagHeader::unmarshal(&mut BytesReader::new(data.clone()))?;
+                self.cut_points.on_video();
+                let is_avc = header.codec_id == AvcCodecId::H264 as u8
+                    || header.codec_id == AvcCodecId::HEVC as u8;
+                if is_avc && header.avc_packet_type == avc_packet_type::AVC_SEQHDR {
+                    self.video_sequence_header = Some(data.clone());
+                    return self.write_tag(tag_type::VIDEO, timestamp, data, false, false);
+                }
+                let is_key_frame = header.frame_type == frame_type::KEY_FRAME
+                    && (!is_avc || header.avc_packet_type == avc_packet_type::AVC_NALU);
+                self.write_tag(tag_type::VIDEO, timestamp, data, is_key_frame, is_key_frame)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /*the tags before the first file is started are dropped, the sequence
+    headers are written at the start of every file*/
+    fn write_tag(
+        &mut self,
+        tag_type: u8,
+        timestamp: u32,
+        data: BytesMut,
+        can_cut: bool,
+        is_key_frame: bool,
+    ) -> Result<(), RecordError> {
+        let is_full = self.file.as_ref().is_some_and(|file| file.is_full());
+        if can_cut && (self.file.is_none() || is_full) {
+            self.finish_file()?;
+            self.start_file(timestamp)?;
+        }
+
+        if let Some(file) = &mut self.file {
+            file.write_tag(tag_type, timestamp, data, is_key_frame)?;
+        }
+        Ok(())
+    }
+
+    fn start_file(&mut self, timestamp: u32) -> Result<(), RecordError> {
+        let record_file =
+            self.settings
+                .start(&self.app_name, &self.stream_name, DEFAULT_RECORD_ROOT);
+        let mut file = FlvFile::create(record_file, self.metadata.clone())?;
+        log::info!(
+            "{}/{}: start recording to {}",
+            self.app_name,
+            self.stream_name,
+            file.path()
+        );
+        notify_record_start(&self.event_producer, &self.status, file.path());
+
+        if let Some(data) = &self.audio_sequence_header {
+            file.write_tag(tag_type::AUDIO, timestamp, data.clone(), false)?;
+        }
+        if let Some(data) = &self.video_sequence_header {
+            file.write_tag(tag_type::VIDEO, timestamp, data.clone(), false)?;
+        }
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn finish_file(&mut self) -> Result<(), RecordError> {
+        if let Some(file) = self.file.take() {
+            let recording = file.finish()?;
+            log::info!(
+                "{}/{}: recording {} is finished: {}",
+                self.app_name,
+                self.stream_name,
+                recording.id,
+                recording.path
+            );
+            notify_record_stop(&self.event_producer, &recording);
+        }
+        Ok(())
+    }
+}
+
+/*the RTMP metadata starts with "@setDataFrame" which is not in the flv files*/
+fn remove_set_data_frame(mut data: BytesMut) -> BytesMut {
+    let mut amf_writer = Amf0Writer::new();
+    if amf_writer
+        .write_string(&String::from("@setDataFrame"))
+        .is_ok()
+    {
+        let prefix = amf_writer.extract_current_bytes();
+        if data.starts_with(&prefix[..]) {
+            return data.split_off(prefix.len());
+        }
+    }
+    data
+}
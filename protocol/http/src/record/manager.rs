@@ -0,0 +1,258 @@
+use {
+    super::{
+        define::RecordStatus,
+        errors::{RecordError, RecordErrorValue},
+        flv_recorder::FlvRecorder,
+        mp4_recorder::Mp4Recorder,
+    },
+    chrono::Local,
+    commonlib::{
+        config::RecordFormat,
+        record::{ActiveRecording, RecordService, RecordSettings},
+    },
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    },
+    streamhub::define::StreamHubEventSender,
+    tokio::sync::oneshot,
+};
+
+enum FileRecorder {
+    Flv(Box<FlvRecorder>),
+    Mp4(Box<Mp4Recorder>),
+}
+
+impl FileRecorder {
+    async fn subscribe_from_rtmp_channels(&mut self) -> Result<(), RecordError> {
+        match self {
+            FileRecorder::Flv(recorder) => recorder.subscribe_from_rtmp_channels().await,
+            FileRecorder::Mp4(recorder) => recorder.subscribe_from_rtmp_channels().await,
+        }
+    }
+
+    async fn receive_frame_data(&mut self) -> Result<(), RecordError> {
+        match self {
+            FileRecorder::Flv(recorder) => recorder.receive_frame_data().await,
+            FileRecorder::Mp4(recorder) => recorder.receive_frame_data().await,
+        }
+    }
+}
+
+struct Recorder {
+    /*a stream has one recorder, the id tells a recorder from the one which
+    replaced it*/
+    id: u64,
+    status: RecordStatus,
+    stop_sender: oneshot::Sender<()>,
+}
+
+/*Runs the recorders of the streams recorded to files, they are started by the
+record config when the streams are published or on demand by the api. The
+streams recorded as hls are recorded by the hls remuxer.*/
+pub struct RecordManager {
+    event_producer: StreamHubEventSender,
+    record_service: Arc<RecordService>,
+    /*app/stream*/
+    recorders: Mutex<HashMap<String, Recorder>>,
+    next_id: AtomicU64,
+}
+
+impl RecordManager {
+    pub fn new(event_producer: StreamHubEventSender, record_service: Arc<RecordService>) -> Self {
+        Self {
+            event_producer,
+            record_service,
+            recorders: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_service(&self) -> &Arc<RecordService> {
+        &self.record_service
+    }
+
+    /*The stream is recorded if it is enabled by the record config. The recorder
+    of the last publishing is replaced, it may not have noticed that the stream
+    ended yet.*/
+    pub async fn on_publish(self: &Arc<Self>, app: &str, stream: &str) -> Result<(), RecordError> {
+        match self.record_service.settings(app, stream) {
+            Some(settings) if settings.format != RecordFormat::Hls => {
+                self.start_recorder(app, stream, settings, false).await?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /*Start recording a live stream with its record settings, whether or not it
+    is enabled. The format of the settings is used if no format is given, or mp4
+    if it is hls.*/
+    pub async fn start(
+        self: &Arc<Self>,
+        app: &str,
+        stream: &str,
+        format: Option<RecordFormat>,
+    ) -> Result<ActiveRecording, RecordError> {
+        let mut settings = self.record_service.on_demand_settings(app, stream);
+        settings.format = match (format, settings.format) {
+            (Some(RecordFormat::Hls), _) => {
+                return Err(RecordError {
+                    value: RecordErrorValue::UnsupportedFormat,
+                })
+            }
+            (Some(format), _) => format,
+            (None, RecordFormat::Hls) => RecordFormat::Mp4,
+            (None, format) => format,
+        };
+        self.start_recorder(app, stream, settings, true).await
+    }
+
+    async fn start_recorder(
+        self: &Arc<Self>,
+        app: &str,
+        stream: &str,
+        settings: RecordSettings,
+        on_demand: bool,
+    ) -> Result<ActiveRecording, RecordError> {
+        let name = format!("{app}/{stream}");
+        let status = Arc::new(Mutex::new(ActiveRecording {
+            app: app.to_string(),
+            stream: stream.to_string(),
+            format: settings.format,
+            path: None,
+            start_time: Local::now().timestamp(),
+            on_demand,
+        }));
+        let (stop_sender, stop_receiver) = oneshot::channel();
+
+        let (app_name, stream_name) = (app.to_string(), stream.to_string());
+        let event_producer = self.event_producer.clone();
+        let mut recorder = match settings.format {
+            RecordFormat::Flv => FileRecorder::Flv(Box::new(FlvRecorder::new(
+                app_name,
+                stream_name,
+                event_producer,
+                settings,
+                status.clone(),
+                stop_receiver,
+            ))),
+            RecordFormat::Mp4 => FileRecorder::Mp4(Box::new(Mp4Recorder::new(
+                app_name,
+                stream_name,
+                event_producer,
+                settings,
+                status.clone(),
+                stop_receiver,
+            ))),
+            RecordFormat::Hls => {
+                return Err(RecordError {
+                    value: RecordErrorValue::UnsupportedFormat,
+                })
+            }
+        };
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut recorders = self.recorders.lock().unwrap();
+            if on_demand && recorders.contains_key(&name) {
+                return Err(RecordError {
+                    value: RecordErrorValue::AlreadyRecording,
+                });
+            }
+            let recorder = Recorder {
+                id,
+                status: status.clone(),
+                stop_sender,
+            };
+            if let Some(replaced) = recorders.insert(name.clone(), recorder) {
+                let _ = replaced.stop_sender.send(());
+            }
+        }
+
+        /*the stream must be live*/
+        if let Err(err) = recorder.subscribe_from_rtmp_channels().await {
+            self.remove(&name, id);
+            return Err(err);
+        }
+
+        let manager = self.clone();
+        tokio::spawn(async move {
+            if let Err(err) = recorder.receive_frame_data().await {
+                log::error!("record {} error: {}", name, err);
+            }
+            manager.remove(&name, id);
+        });
+
+        let recording = status.lock().unwrap().clone();
+        Ok(recording)
+    }
+
+    fn remove(&self, name: &str, id: u64) {
+        let mut recorders = self.recorders.lock().unwrap();
+        if recorders
+            .get(name)
+            .is_some_and(|recorder| recorder.id == id)
+        {
+            recorders.remove(name);
+        }
+    }
+
+    /*the current file is finished, None if the stream is not being recorded*/
+    pub fn stop(&self, app: &str, stream: &str) -> Option<ActiveRecording> {
+        let recorder = self
+            .recorders
+            .lock()
+            .unwrap()
+            .remove(&format!("{app}/{stream}"))?;
+        let _ = recorder.stop_sender.send(());
+
+        let recording = recorder.status.lock().unwrap().clone();
+        Some(recording)
+    }
+
+    pub fn active(&self, app: Option<&str>, stream: Option<&str>) -> Vec<ActiveRecording> {
+        self.recorders
+            .lock()
+            .unwrap()
+            .values()
+            .map(|recorder| recorder.status.lock().unwrap().clone())
+            .filter(|r| app.is_none_or(|app| r.app == app))
+            .filter(|r| stream.is_none_or(|stream| r.stream == stream))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RecordManager;
+    use commonlib::{
+        config::{RecordConfig, RecordFormat},
+        record::RecordService,
+    };
+    use std::sync::Arc;
+    use streamhub::StreamsHub;
+
+    #[tokio::test]
+    async fn test_record_offline_stream() {
+        let mut stream_hub = StreamsHub::new(None);
+        let record_service = Arc::new(RecordService::new(RecordConfig::default()));
+        let manager = Arc::new(RecordManager::new(
+            stream_hub.get_hub_event_sender(),
+            record_service,
+        ));
+        tokio::spawn(async move { stream_hub.run().await });
+
+        assert!(manager
+            .start("live", "test", Some(RecordFormat::Hls))
+            .await
+            .is_err());
+        /*the stream is not published*/
+        assert!(manager.start("live", "test", None).await.is_err());
+        assert!(manager.active(None, None).is_empty());
+        assert!(manager.stop("live", "test").is_none());
+    }
+}
@@ -0,0 +1,9 @@
+pub mod define;
+pub mod errors;
+pub mod flv_file;
+pub mod flv_recorder;
+pub mod manager;
+pub mod mp4_file;
+pub mod mp4_recorder;
+pub mod remuxer;
+pub mod subscriber;
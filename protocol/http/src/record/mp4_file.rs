@@ -0,0 +1,312 @@
+use {
+    super::errors::RecordError,
+    crate::hls::fmp4::Fmp4Sample,
+    commonlib::record::{RecordFile, Recording},
+    std::{
+        fs::{self, File},
+        io::{self, BufWriter, Seek, SeekFrom, Write},
+        path::Path,
+    },
+    xmp4::{
+        define::{AudioTrack, VideoTrack},
+        fmp4::Fmp4Muxer,
+    },
+};
+
+/*milliseconds, the longest fragment, it is the most media lost if the server
+crashes while recording*/
+const MAX_FRAGMENT_DURATION: i64 = 1000;
+
+/*the most samples kept for the moov box of a finalized file, about 16MB of
+memory, a new file is started after it(about 4 hours of 25fps video with
+44.1kHz audio)*/
+const MAX_PROGRESSIVE_SAMPLES: usize = 1 << 20;
+
+/*A recorded mp4 file. It is a fragmented mp4 while it is recorded, every
+fragment is written as soon as it is complete so that the file is playable up
+to the last fragment after a crash. The timestamps start from 0. If it is
+finalized, it is rewritten into a progressive mp4 when it is closed, so that
+it can be played before it is fully downloaded: the moov box with the sample
+tables follows the ftyp box, then the fragments with the moof boxes turned
+into free boxes.*/
+pub struct Mp4File {
+    writer: BufWriter<File>,
+    muxer: Fmp4Muxer,
+    record_file: RecordFile,
+    finalize: bool,
+    position: u64,
+    /*the size of the init segment, the position of the first fragment*/
+    init_size: u64,
+    /*the positions of the moof boxes*/
+    fragment_positions: Vec<u64>,
+    base_timestamp: Option<i64>,
+    /*milliseconds from the first sample*/
+    fragment_start: i64,
+    last_dts: i64,
+}
+
+impl Mp4File {
+    pub fn create(
+        record_file: RecordFile,
+        video_track: Option<VideoTrack>,
+        audio_track: Option<AudioTrack>,
+        finalize: bool,
+    ) -> Result<Self, RecordError> {
+        let mut muxer = Fmp4Muxer::new();
+        if let Some(track) = video_track {
+            muxer.set_video_track(track);
+        }
+        if let Some(track) = audio_track {
+            muxer.set_audio_track(track);
+        }
+        if finalize {
+            muxer.keep_sample_tables();
+        }
+        let init_segment = muxer.init_segment()?;
+
+        if let Some(parent) = Path::new(&record_file.path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let writer = BufWriter::new(File::create(&record_file.path)?);
+
+        let mut mp4_file = Self {
+            writer,
+            muxer,
+            record_file,
+            finalize,
+            position: 0,
+            init_size: init_segment.len() as u64,
+            fragment_positions: Vec::new(),
+            base_timestamp: None,
+            fragment_start: 0,
+            last_dts: 0,
+        };
+        mp4_file.write_data(&init_segment[..])?;
+
+        Ok(mp4_file)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.record_file.path
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.record_file.is_full() || self.muxer.sample_count() >= MAX_PROGRESSIVE_SAMPLES
+    }
+
+    /*A fragment is written before a video key frame or when it reaches the max
+    duration. The fragments of a stream with video are only cut before a video
+    frame, so that the durations of the video samples are exact.*/
+    pub fn write_sample(&mut self, sample: Fmp4Sample) -> Result<(), RecordError> {
+        let has_video = self.muxer.video_track().is_some();
+        let (mut sample, is_video) = match sample {
+            Fmp4Sample::Video(sample) => (sample, true),
+            Fmp4Sample::Audio(sample) => (sample, false),
+        };
+
+        let base_timestamp = *self.base_timestamp.get_or_insert(sample.dts);
+        sample.dts -= base_timestamp;
+        sample.pts -= base_timestamp;
+
+        if is_video == has_video
+            && ((is_video && sample.is_sync)
+                || sample.dts - self.fragment_start >= MAX_FRAGMENT_DURATION)
+        {
+            self.flush_fragment(sample.dts)?;
+        }
+
+        let duration = sample.dts - self.last_dts;
+        if duration > 0 {
+            self.record_file.add(duration, 0);
+            self.last_dts = sample.dts;
+        }
+
+        if is_video {
+            self.muxer.write_video(sample);
+        } else {
+            self.muxer.write_audio(sample);
+        }
+        Ok(())
+    }
+
+    fn flush_fragment(&mut self, next_dts: i64) -> Result<(), RecordError> {
+        let fragment = self.muxer.flush_fragment(next_dts)?;
+        if fragment.is_empty() {
+            return Ok(());
+        }
+
+        self.fragment_positions.push(self.position);
+        self.fragment_start = next_dts;
+        self.write_data(&fragment[..])
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> Result<(), RecordError> {
+        self.writer.write_all(data)?;
+        self.writer.flush()?;
+        self.position += data.len() as u64;
+        self.record_file.add(0, data.len() as u64);
+        Ok(())
+    }
+
+    /*The last fragment is written and the file is added to the recordings
+    index, it is finalized first if it is configured. The fragmented file is
+    kept if it cannot be finalized.*/
+    pub fn finish(mut self) -> Result<Recording, RecordError> {
+        self.flush_fragment(self.last_dts)?;
+
+        if self.finalize && !self.fragment_positions.is_empty() {
+            let temp_path = format!("{}.tmp", self.path());
+            if let Err(err) = self.write_progressive(&temp_path) {
+                log::warn!("{}: finalize mp4 error: {}", self.path(), err);
+                let _ = fs::remove_file(&temp_path);
+            }
+        }
+
+        Ok(self.record_file.finish())
+    }
+
+    /*The progressive file is written to temp_path and moved over the
+    fragmented one, which is complete until then.*/
+    fn write_progressive(&mut self, temp_path: &str) -> Result<(), RecordError> {
+        let ftyp = self.muxer.progressive_ftyp()?;
+        /*the fragments follow the moov box, its size depends on the chunk
+        offsets only when they no longer fit in 32 bits*/
+        let mut moov = self.muxer.progressive_moov(ftyp.len() as u64)?;
+        loop {
+            let next = self
+                .muxer
+                .progressive_moov((ftyp.len() + moov.len()) as u64)?;
+            let stable = next.len() == moov.len();
+            moov = next;
+            if stable {
+                break;
+            }
+        }
+        let data_offset = (ftyp.len() + moov.len()) as u64;
+
+        let mut fragments = File::open(self.path())?;
+        fragments.seek(SeekFrom::Start(self.init_size))?;
+        let mut writer = BufWriter::new(File::create(temp_path)?);
+        writer.write_all(&ftyp[..])?;
+        writer.write_all(&moov[..])?;
+        io::copy(&mut fragments, &mut writer)?;
+
+        for position in &self.fragment_positions {
+            /*the type follows the 4 bytes size*/
+            writer.seek(SeekFrom::Start(position - self.init_size + data_offset + 4))?;
+            writer.write_all(b"free")?;
+        }
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(temp_path, self.path())?;
+
+        self.position = self.position - self.init_size + data_offset;
+        self.record_file.set_size(self.position);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mp4File;
+    use crate::hls::fmp4::Fmp4Sample;
+    use bytes::BytesMut;
+    use commonlib::{
+        config::{RecordConfig, RecordFormat},
+        record::RecordService,
+    };
+    use std::{convert::TryInto, io::Cursor};
+    use xmp4::{
+        define::{Sample, VideoCodec, VideoTrack},
+        demuxer::Mp4Demuxer,
+    };
+
+    /*the types of the boxes at the top level of data*/
+    fn top_level_boxes(data: &[u8]) -> Vec<String> {
+        let mut boxes = Vec::new();
+        let mut offset = 0;
+        while offset + 8 <= data.len() {
+            let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+            boxes.push(String::from_utf8_lossy(&data[offset + 4..offset + 8]).to_string());
+            offset += size;
+        }
+        assert_eq!(offset, data.len());
+        boxes
+    }
+
+    fn record(root: &str, finalize: bool) -> (Vec<u8>, i64) {
+        let service = RecordService::new(RecordConfig {
+            enabled: true,
+            format: Some(RecordFormat::Mp4),
+            root: Some(root.to_string()),
+            path: Some(format!("{{app}}/{{stream}}_{finalize}")),
+            finalize: Some(finalize),
+            index: Some(format!("{root}/recordings.json")),
+            ..Default::default()
+        });
+        let settings = service.settings("live", "test").unwrap();
+
+        let track = VideoTrack {
+            codec: VideoCodec::H264,
+            width: 1280,
+            height: 720,
+            config: BytesMut::from(&[0x01, 0x64, 0x00, 0x1f, 0xff, 0xe0, 0x00][..]),
+        };
+        let mut mp4_file = Mp4File::create(
+            settings.start("live", "test", root),
+            Some(track),
+            None,
+            settings.finalize,
+        )
+        .unwrap();
+        for (index, timestamp) in [1000, 1040, 1080, 1120].into_iter().enumerate() {
+            mp4_file
+                .write_sample(Fmp4Sample::Video(Sample {
+                    dts: timestamp,
+                    pts: timestamp,
+                    is_sync: index % 2 == 0,
+                    data: BytesMut::from(&[0, 0, 0, 1, index as u8][..]),
+                }))
+                .unwrap();
+        }
+        let recording = mp4_file.finish().unwrap();
+        assert!(recording.path.ends_with(".mp4"));
+        assert!(!std::path::Path::new(&format!("{}.tmp", recording.path)).exists());
+
+        let data = std::fs::read(&recording.path).unwrap();
+        assert_eq!(data.len() as u64, recording.size);
+        (data, recording.duration)
+    }
+
+    #[test]
+    fn test_mp4_file() {
+        let root = std::env::temp_dir().join(format!("xiu_mp4_record_{}", std::process::id()));
+        let root = root.to_str().unwrap();
+
+        /*a fragment for every key frame*/
+        let (data, duration) = record(root, false);
+        assert_eq!(duration, 120);
+        assert_eq!(
+            top_level_boxes(&data),
+            vec!["ftyp", "moov", "moof", "mdat", "moof", "mdat"]
+        );
+
+        /*finalized, the moov box is at the front*/
+        let (data, _) = record(root, true);
+        assert_eq!(
+            top_level_boxes(&data),
+            vec!["ftyp", "moov", "free", "mdat", "free", "mdat"]
+        );
+        assert_eq!(&data[8..12], b"isom");
+        let demuxer = Mp4Demuxer::read(&mut Cursor::new(&data)).unwrap();
+        for (index, sample) in demuxer.samples.iter().enumerate() {
+            let offset = sample.offset as usize;
+            assert_eq!(&data[offset..offset + 5], &[0, 0, 0, 1, index as u8]);
+        }
+        assert_eq!(demuxer.samples.len(), 4);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
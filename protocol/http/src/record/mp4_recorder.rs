@@ -0,0 +1,161 @@
+use {
+    super::{
+        define::{notify_record_start, notify_record_stop, CutPoints, RecordStatus},
+        errors::RecordError,
+        mp4_file::Mp4File,
+        subscriber::RecordSubscriber,
+    },
+    crate::hls::fmp4::{Flv2Fmp4, Fmp4Sample},
+    commonlib::record::{RecordSettings, DEFAULT_RECORD_ROOT},
+    streamhub::define::{FrameData, StreamHubEventSender},
+    tokio::sync::oneshot,
+    xflv::define::FlvData,
+};
+
+/*Records a stream to mp4 files. The tracks of a file are built from the
+decoder configurations of the sequence headers received before it is started,
+its first sample is a key frame. A new file is started at the first key frame
+after the file reaches its max duration or size, or after the decoder
+configurations change.*/
+pub struct Mp4Recorder {
+    app_name: String,
+    stream_name: String,
+    event_producer: StreamHubEventSender,
+    subscriber: RecordSubscriber,
+    settings: RecordSettings,
+    status: RecordStatus,
+    fmp4: Flv2Fmp4,
+    file: Option<Mp4File>,
+    /*the decoder configurations changed since the file was started*/
+    tracks_changed: bool,
+    cut_points: CutPoints,
+}
+
+impl Mp4Recorder {
+    pub fn new(
+        app_name: String,
+        stream_name: String,
+        event_producer: StreamHubEventSender,
+        settings: RecordSettings,
+        status: RecordStatus,
+        stop_receiver: oneshot::Receiver<()>,
+    ) -> Self {
+        let subscriber = RecordSubscriber::new(
+            app_name.clone(),
+            stream_name.clone(),
+            event_producer.clone(),
+            stop_receiver,
+        );
+
+        Self {
+            app_name,
+            stream_name,
+            event_producer,
+            subscriber,
+            settings,
+            status,
+            fmp4: Flv2Fmp4::new(),
+            file: None,
+            tracks_changed: false,
+            cut_points: CutPoints::default(),
+        }
+    }
+
+    /*the current file is finished when the stream ends or the recording is
+    stopped*/
+    pub async fn receive_frame_data(&mut self) -> Result<(), RecordError> {
+        while let Some(data) = self.subscriber.recv().await {
+            if let Err(err) = self.process_frame_data(data) {
+                log::error!(
+                    "record {}/{} error: {}",
+                    self.app_name,
+                    self.stream_name,
+                    err
+                );
+                break;
+            }
+        }
+
+        let result = self.finish_file();
+        self.subscriber.unsubscribe_from_rtmp_channels();
+        result
+    }
+
+    pub async fn subscribe_from_rtmp_channels(&mut self) -> Result<(), RecordError> {
+        self.subscriber.subscribe_from_rtmp_channels().await
+    }
+
+    fn process_frame_data(&mut self, data: FrameData) -> Result<(), RecordError> {
+        let flv_data = match data {
+            FrameData::Audio { timestamp, data } => FlvData::Audio { timestamp, data },
+            FrameData::Video { timestamp, data } => {
+                self.cut_points.on_video();
+                FlvData::Video { timestamp, data }
+            }
+            _ => return Ok(()),
+        };
+
+        let sample = self.fmp4.demux(flv_data)?;
+        self.tracks_changed |= self.fmp4.take_init_changed();
+        let sample = match sample {
+            Some(sample) => sample,
+            None => return Ok(()),
+        };
+
+        /*the files of an audio-only stream are cut on any audio frame*/
+        let can_cut = match &sample {
+            Fmp4Sample::Video(video) => video.is_sync,
+            Fmp4Sample::Audio(audio) => self.cut_points.can_cut_on_audio(audio.dts as u32),
+        };
+        let is_full = self.file.as_ref().is_some_and(|file| file.is_full());
+        if can_cut && (self.file.is_none() || is_full || self.tracks_changed) {
+            self.finish_file()?;
+            self.start_file()?;
+        }
+
+        /*the samples before the first file is started are dropped*/
+        if let Some(file) = &mut self.file {
+            file.write_sample(sample)?;
+        }
+        Ok(())
+    }
+
+    fn start_file(&mut self) -> Result<(), RecordError> {
+        let record_file =
+            self.settings
+                .start(&self.app_name, &self.stream_name, DEFAULT_RECORD_ROOT);
+        let muxer = self.fmp4.muxer();
+        let file = Mp4File::create(
+            record_file,
+            muxer.video_track().cloned(),
+            muxer.audio_track().cloned(),
+            self.settings.finalize,
+        )?;
+        log::info!(
+            "{}/{}: start recording to {}",
+            self.app_name,
+            self.stream_name,
+            file.path()
+        );
+        notify_record_start(&self.event_producer, &self.status, file.path());
+
+        self.file = Some(file);
+        self.tracks_changed = false;
+        Ok(())
+    }
+
+    fn finish_file(&mut self) -> Result<(), RecordError> {
+        if let Some(file) = self.file.take() {
+            let recording = file.finish()?;
+            log::info!(
+                "{}/{}: recording {} is finished: {}",
+                self.app_name,
+                self.stream_name,
+                recording.id,
+                recording.path
+            );
+            notify_record_stop(&self.event_producer, &recording);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use {
+    super::{errors::RecordError, manager::RecordManager},
+    std::sync::Arc,
+    streamhub::{
+        define::{BroadcastEvent, BroadcastEventReceiver},
+        stream::Protocol,
+    },
+};
+
+/*Starts the recorders of the published streams which are recorded to files,
+the streams recorded as hls are recorded by the hls remuxer.*/
+pub struct RecordRemuxer {
+    client_event_consumer: BroadcastEventReceiver,
+    record_manager: Arc<RecordManager>,
+}
+
+impl RecordRemuxer {
+    pub fn new(consumer: BroadcastEventReceiver, record_manager: Arc<RecordManager>) -> Self {
+        Self {
+            client_event_consumer: consumer,
+            record_manager,
+        }
+    }
+
+    pub async fn run(&mut self) -> Result<(), RecordError> {
+        loop {
+            let val = self.client_event_consumer.recv().await?;
+            match val {
+                BroadcastEvent::Publish {
+                    protocol: Protocol::Rtmp,
+                    name,
+                } => {
+                    let (app_name, stream_name) = match name.split_once('/') {
+                        Some((app_name, stream_name)) => {
+                            (app_name.to_string(), stream_name.to_string())
+                        }
+                        None => continue,
+                    };
+
+                    let record_manager = self.record_manager.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = record_manager.on_publish(&app_name, &stream_name).await {
+                            log::error!("start recording {} error: {}", name, err);
+                        }
+                    });
+                }
+                _ => {
+                    log::trace!("other infos...");
+                }
+            }
+        }
+    }
+}
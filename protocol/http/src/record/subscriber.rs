@@ -0,0 +1,118 @@
+use {
+    super::errors::{RecordError, RecordErrorValue},
+    std::time::Duration,
+    streamhub::{
+        define::{
+            FrameData, FrameDataReceiver, NotifyInfo, StreamHubEvent, StreamHubEventSender,
+            SubDataType, SubscribeType, SubscriberInfo,
+        },
+        stream::Protocol,
+        utils::{RandomDigitCount, Uuid},
+    },
+    tokio::{
+        sync::{mpsc, oneshot},
+        time::sleep,
+    },
+};
+
+/*The subscription of a recorder to the frames of a stream, shared by the flv
+and the mp4 recorders.*/
+pub struct RecordSubscriber {
+    app_name: String,
+    stream_name: String,
+    event_producer: StreamHubEventSender,
+    data_consumer: FrameDataReceiver,
+    subscriber_id: Uuid,
+    /*the recording is stopped by the api*/
+    stop_receiver: oneshot::Receiver<()>,
+}
+
+impl RecordSubscriber {
+    pub fn new(
+        app_name: String,
+        stream_name: String,
+        event_producer: StreamHubEventSender,
+        stop_receiver: oneshot::Receiver<()>,
+    ) -> Self {
+        let (_, data_consumer) = mpsc::unbounded_channel();
+
+        Self {
+            app_name,
+            stream_name,
+            event_producer,
+            data_consumer,
+            subscriber_id: Uuid::new(RandomDigitCount::Four),
+            stop_receiver,
+        }
+    }
+
+    /*None when the stream ends or the recording is stopped*/
+    pub async fn recv(&mut self) -> Option<FrameData> {
+        let mut retry_count = 0;
+
+        loop {
+            tokio::select! {
+                data = self.data_consumer.recv() => {
+                    if data.is_some() {
+                        return data;
+                    }
+                    sleep(Duration::from_millis(100)).await;
+                    retry_count += 1;
+                }
+                _ = &mut self.stop_receiver => {
+                    log::info!("{}/{}: recording is stopped", self.app_name, self.stream_name);
+                    return None;
+                }
+            }
+            //the same as hls, the stream is regarded as finished after 10 retries
+            if retry_count > 10 {
+                return None;
+            }
+        }
+    }
+
+    fn subscriber_info(&self) -> SubscriberInfo {
+        SubscriberInfo {
+            id: self.subscriber_id,
+            sub_type: SubscribeType::Record,
+            sub_data_type: SubDataType::Frame,
+            notify_info: NotifyInfo {
+                request_url: String::from(""),
+                remote_addr: String::from(""),
+            },
+        }
+    }
+
+    pub async fn subscribe_from_rtmp_channels(&mut self) -> Result<(), RecordError> {
+        let (event_result_sender, event_result_receiver) = oneshot::channel();
+
+        let subscribe_event = StreamHubEvent::Subscribe {
+            protocol: Protocol::Rtmp,
+            name: format!("{}/{}", self.app_name, self.stream_name),
+            info: self.subscriber_info(),
+            result_sender: event_result_sender,
+        };
+
+        if self.event_producer.send(subscribe_event).is_err() {
+            return Err(RecordError {
+                value: RecordErrorValue::StreamHubEventSendErr,
+            });
+        }
+
+        let receiver = event_result_receiver.await??.0.frame_receiver.unwrap();
+        self.data_consumer = receiver;
+
+        Ok(())
+    }
+
+    pub fn unsubscribe_from_rtmp_channels(&mut self) {
+        let unsubscribe_event = StreamHubEvent::UnSubscribe {
+            protocol: Protocol::Rtmp,
+            name: format!("{}/{}", self.app_name, self.stream_name),
+            info: self.subscriber_info(),
+        };
+        if let Err(err) = self.event_producer.send(unsubscribe_event) {
+            log::error!("unsubscribe_from_channels err {}", err);
+        }
+    }
+}
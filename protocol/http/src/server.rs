@@ -5,24 +5,103 @@ use {
         body::Body,
         extract::{Request, State},
         handler::Handler,
-        http::StatusCode,
+        http::{header, StatusCode},
         response::Response,
     },
-    commonlib::auth::{Auth, SecretCarrier},
-    std::net::SocketAddr,
-    tokio::{fs::File, net::TcpListener},
+    bytesio::proxy_protocol,
+    commonlib::{
+        access::AccessControl,
+        auth::{parse_query, Auth, SecretCarrier},
+        jwt::JwtClaims,
+        vod::VodService,
+    },
+    hyper::body::Incoming,
+    hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto::Builder,
+    },
+    std::{net::SocketAddr, sync::Arc, time::Duration},
+    tokio::{
+        fs::File,
+        io::{AsyncReadExt, AsyncSeekExt},
+        net::TcpListener,
+    },
     tokio_util::codec::{BytesCodec, FramedRead},
+    tower::ServiceExt,
 };
+use chrono::DateTime;
 use streamhub::define::StreamHubEventSender;
 use crate::flv::httpflv::HttpFlv;
+use crate::flv::vod::{parse_range, HttpFlvVod};
+use streamhub::vod::VodFile;
+use crate::hls::{
+    encryption::parse_key_path,
+    master::master_playlist,
+    registry::{HlsRegistry, WaitResult},
+};
 
 type GenericError = Box<dyn std::error::Error + Send + Sync>;
 type Result<T> = std::result::Result<T, GenericError>;
 static NOTFOUND: &[u8] = b"Not Found";
 static UNAUTHORIZED: &[u8] = b"Unauthorized";
+static FORBIDDEN: &[u8] = b"Forbidden";
+//mpegts segments, fragmented mp4 segments and init segments
+static SEGMENT_EXTENSIONS: [&str; 4] = [".ts", ".m4s", ".mp4", ".aac"];
+
+type ServerState = (
+    StreamHubEventSender,
+    Option<Auth>,
+    Option<Arc<AccessControl>>,
+    Arc<HlsRegistry>,
+    Option<Arc<VodService>>,
+);
+
+/*check the per app ip allow/deny lists and the play token. The jwt claims are
+returned if any, otherwise the status code of the rejection.
+opens_session: an auth failure is counted for the temporary ip ban only on the
+requests which open a play session(the playlist and the flv connect), a player
+whose token expires would otherwise ban itself by its segment requests.*/
+fn check_play_permission(
+    auth: &Option<Auth>,
+    access: &Option<Arc<AccessControl>>,
+    remote_addr: &SocketAddr,
+    app_name: &str,
+    stream_name: &String,
+    secret: Option<SecretCarrier>,
+    opens_session: bool,
+) -> std::result::Result<Option<JwtClaims>, StatusCode> {
+    if let Some(access_val) = access {
+        if access_val
+            .check(app_name, &remote_addr.ip(), true)
+            .is_err()
+        {
+            return Err(StatusCode::FORBIDDEN);
+        }
+    }
+
+    if let Some(auth_val) = auth {
+        match auth_val.authenticate(
+            app_name,
+            stream_name,
+            &secret,
+            true,
+            Some(remote_addr.ip()),
+        ) {
+            Ok(claims) => return Ok(claims),
+            Err(_) => {
+                if let Some(access_val) = access.as_ref().filter(|_| opens_session) {
+                    access_val.on_auth_failure(&remote_addr.ip());
+                }
+                return Err(StatusCode::UNAUTHORIZED);
+            }
+        }
+    }
+
+    Ok(None)
+}
 
 async fn handle_connection(
-    State((event_producer, auth)): State<(StreamHubEventSender, Option<Auth>)>, // event_producer: ChannelEventProducer
+    State((event_producer, auth, access, hls_registry, vod)): State<ServerState>, // event_producer: ChannelEventProducer
     ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
     req: Request<Body>,
 ) -> Response<Body> {
@@ -31,6 +110,44 @@ async fn handle_connection(
     let query_string: Option<String> = req.uri().query().map(|s| s.to_string());
     let mut file_path: String = String::from("");
 
+    //http://127.0.0.1/vod/2024/show.flv, the path of a recorded file under the vod root
+    if let Some((vod, stream_name)) = vod.as_ref().and_then(|vod| {
+        let (app_name, stream_name) = path.trim_start_matches('/').split_once('/')?;
+        vod.is_vod_app(app_name).then_some((vod, stream_name))
+    }) {
+        let secret = match req.headers().get(header::AUTHORIZATION) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .map(|v| SecretCarrier::Bearer(v.to_string())),
+            None => query_string.clone().map(SecretCarrier::Query),
+        };
+        if let Err(status) = check_play_permission(
+            &auth,
+            &access,
+            &remote_addr,
+            vod.app(),
+            &stream_name.to_string(),
+            secret,
+            //a ranged request continues a download which is already opened
+            req.headers().get(header::RANGE).is_none(),
+        ) {
+            return rejected(status);
+        }
+
+        let start = query_string
+            .as_deref()
+            .map(parse_query)
+            .unwrap_or_default()
+            .get("start")
+            .cloned();
+        let range = req
+            .headers()
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok());
+        return vod_send(vod, stream_name, start, range).await;
+    }
+
     if path.ends_with(".m3u8") {
         //http://127.0.0.1/app_name/stream_name/index.m3u8
         let m3u8_index = path.find(".m3u8").unwrap();
@@ -42,38 +159,217 @@ async fn handle_connection(
             let app_name = String::from(rv[1]);
             let stream_name = String::from(rv[2]);
 
-            if let Some(auth_val) = auth {
-                if auth_val
-                    .authenticate(
-                        &stream_name,
-                        &query_string.map(|q| SecretCarrier::Query(q)),
-                        true,
-                    )
-                    .is_err()
-                {
-                    return Response::builder()
-                        .status(StatusCode::UNAUTHORIZED)
-                        .body(UNAUTHORIZED.into())
-                        .unwrap();
+            if let Err(status) = check_play_permission(
+                &auth,
+                &access,
+                &remote_addr,
+                &app_name,
+                &stream_name,
+                query_string.clone().map(SecretCarrier::Query),
+                true,
+            ) {
+                return rejected(status);
+            }
+
+            let name = format!("{app_name}/{stream_name}");
+            let group_variants = hls_registry.group_variants(&app_name, &stream_name);
+            //an on-demand stream is packaged from its first playlist request
+            if hls_registry.is_on_demand() && group_variants.is_none() {
+                match hls_registry.demand(&name).await {
+                    WaitResult::Ready => {}
+                    WaitResult::Timeout => return rejected(StatusCode::SERVICE_UNAVAILABLE),
+                    _ => return not_found(),
                 }
             }
 
-            file_path = format!("./hls/{app_name}/{stream_name}/index.m3u8");
+            let query_pairs = query_string
+                .as_deref()
+                .map(parse_query)
+                .unwrap_or_default();
+            //LL-HLS blocking playlist reload
+            if let Some(msn) = query_pairs.get("_HLS_msn") {
+                let msn = msn.parse::<u64>().ok();
+                let part = query_pairs.get("_HLS_part").map(|p| p.parse::<u64>().ok());
+                match (msn, part) {
+                    (Some(msn), None) | (Some(msn), Some(Some(_))) => {
+                        let part = part.flatten();
+                        if let Some(status) = wait_playlist(&hls_registry, &name, msn, part).await
+                        {
+                            return rejected(status);
+                        }
+                    }
+                    _ => return rejected(StatusCode::BAD_REQUEST),
+                }
+            }
+
+            //the master playlist of a rendition group
+            let playlist = if let Some(variants) = group_variants {
+                match master_playlist(&event_producer, &hls_registry, &app_name, variants).await {
+                    Some(playlist) => playlist.into(),
+                    None => return not_found(),
+                }
+            } else if let Some(playlist) = dvr_playlist(
+                &hls_registry,
+                &name,
+                query_pairs.get("start"),
+                query_pairs.get("offset"),
+            ) {
+                match playlist {
+                    Ok(playlist) => playlist.into(),
+                    Err(status) => return rejected(status),
+                }
+            } else {
+                match hls_registry.store().get(&name, "index.m3u8") {
+                    Some(playlist) => playlist,
+                    None => return not_found(),
+                }
+            };
+            /*the segments are authenticated too, so they must carry the same token*/
+            if auth.is_some() {
+                let segment_query = query_pairs
+                    .iter()
+                    .filter(|(k, _)| {
+                        !k.starts_with("_HLS_") && k.as_str() != "start" && k.as_str() != "offset"
+                    })
+                    .map(|(k, v)| format!("{k}={v}"))
+                    .collect::<Vec<String>>()
+                    .join("&");
+                if !segment_query.is_empty() {
+                    let playlist = String::from_utf8_lossy(&playlist);
+                    return Response::new(Body::from(append_query_to_segments(
+                        &playlist,
+                        &segment_query,
+                    )));
+                }
+            }
+            return Response::new(Body::from(playlist));
         }
-    } else if path.ends_with(".ts") {
-        //http://127.0.0.1/app_name/stream_name/ts_name.m3u8
-        let ts_index = path.find(".ts").unwrap();
+    } else if path.ends_with(".mpd") {
+        //http://127.0.0.1/app_name/stream_name/index.mpd
+        let rv: Vec<_> = path.split('/').collect();
+
+        if rv.len() == 4 {
+            let app_name = String::from(rv[1]);
+            let stream_name = String::from(rv[2]);
+
+            if let Err(status) = check_play_permission(
+                &auth,
+                &access,
+                &remote_addr,
+                &app_name,
+                &stream_name,
+                query_string.clone().map(SecretCarrier::Query),
+                false,
+            ) {
+                return rejected(status);
+            }
+
+            file_path = format!("./dash/{app_name}/{stream_name}/index.mpd");
+            if let (Some(_), Some(query)) = (&auth, &query_string) {
+                return manifest_send(file_path.as_str(), query, append_query_to_templates).await;
+            }
+        }
+    } else if path.ends_with(".key") {
+        //http://127.0.0.1/app_name/stream_name/key_0.key
+        let Some((app_name, stream_name, key_file_name)) = parse_key_path(path) else {
+            return not_found();
+        };
+        let app_name = String::from(app_name);
+        let stream_name = String::from(stream_name);
+
+        //the players which cannot carry the token in the key URI send it by the header
+        let secret = match req.headers().get(header::AUTHORIZATION) {
+            Some(value) => value
+                .to_str()
+                .ok()
+                .map(|v| SecretCarrier::Bearer(v.to_string())),
+            None => query_string.map(SecretCarrier::Query),
+        };
+        if let Err(status) = check_play_permission(
+            &auth,
+            &access,
+            &remote_addr,
+            &app_name,
+            &stream_name,
+            secret,
+            false,
+        ) {
+            return rejected(status);
+        }
+
+        let name = format!("{app_name}/{stream_name}");
+        return match hls_registry.store().get(&name, key_file_name) {
+            Some(key) => Response::builder()
+                .header(header::CONTENT_TYPE, "application/octet-stream")
+                .header(header::CACHE_CONTROL, "no-store")
+                .body(Body::from(key))
+                .unwrap(),
+            None => not_found(),
+        };
+    } else if let Some(extension) = SEGMENT_EXTENSIONS.iter().find(|e| path.ends_with(*e)) {
+        //http://127.0.0.1/app_name/stream_name/ts_name.ts
+        let ts_index = path.rfind(extension).unwrap();
 
         if ts_index > 0 {
             let (left, _) = path.split_at(ts_index);
 
             let rv: Vec<_> = left.split('/').collect();
+            if rv.len() < 4 {
+                return not_found();
+            }
 
             let app_name = String::from(rv[1]);
             let stream_name = String::from(rv[2]);
             let ts_name = String::from(rv[3]);
 
-            file_path = format!("./hls/{app_name}/{stream_name}/{ts_name}.ts");
+            if let Err(status) = check_play_permission(
+                &auth,
+                &access,
+                &remote_addr,
+                &app_name,
+                &stream_name,
+                query_string.map(SecretCarrier::Query),
+                false,
+            ) {
+                return rejected(status);
+            }
+
+            //the dash segments: app_name/stream_name/dash/segment_name
+            if ts_name == "dash" && rv.len() == 5 {
+                return simple_file_send(&format!(
+                    "./dash/{app_name}/{stream_name}/{}{extension}",
+                    rv[4]
+                ))
+                .await;
+            }
+
+            let name = format!("{app_name}/{stream_name}");
+            let file_name = format!("{ts_name}{extension}");
+            /*a LL-HLS part("{segment}.{part}.ts") advertised by the preload hint is
+            held until it is produced*/
+            if let Some((msn, part)) = ts_name.split_once('.') {
+                if let (Ok(msn), Ok(part)) = (msn.parse::<u64>(), part.parse::<u64>()) {
+                    if !hls_registry.store().contains(&name, &file_name) {
+                        if let Some(status) =
+                            wait_playlist(&hls_registry, &name, msn, Some(part)).await
+                        {
+                            return rejected(status);
+                        }
+                    }
+                }
+            }
+
+            if let Some(data) = hls_registry.store().get(&name, &file_name) {
+                return Response::new(Body::from(data));
+            }
+            //the segments out of the live window are only on disk if recorded
+            for record_path in hls_registry.record_paths(&name) {
+                let record_file = format!("{record_path}/{file_name}");
+                if tokio::fs::metadata(&record_file).await.is_ok() {
+                    return simple_file_send(&record_file).await;
+                }
+            }
+            return not_found();
         }
     }else if path.ends_with(".flv") {
         let flv_index = path.find(".flv").unwrap();
@@ -84,21 +380,26 @@ async fn handle_connection(
             let app_name = String::from(rv[1]);
             let stream_name = String::from(rv[2]);
 
-            if let Some(auth_val) = auth {
-                if auth_val
-                    .authenticate(
-                        &stream_name,
-                        &query_string.map(|q| SecretCarrier::Query(q)),
-                        true,
-                    )
-                    .is_err()
-                {
-                    return Response::builder()
-                        .status(StatusCode::UNAUTHORIZED)
-                        .body(UNAUTHORIZED.into())
-                        .unwrap();
-                }
-            }
+            //the token can be carried by the Authorization header or the query string
+            let secret = match req.headers().get(header::AUTHORIZATION) {
+                Some(value) => value
+                    .to_str()
+                    .ok()
+                    .map(|v| SecretCarrier::Bearer(v.to_string())),
+                None => query_string.map(SecretCarrier::Query),
+            };
+            let claims = match check_play_permission(
+                &auth,
+                &access,
+                &remote_addr,
+                &app_name,
+                &stream_name,
+                secret,
+                true,
+            ) {
+                Ok(claims) => claims,
+                Err(status) => return rejected(status),
+            };
 
             let (http_response_data_producer, http_response_data_consumer) = unbounded();
 
@@ -109,6 +410,7 @@ async fn handle_connection(
                 http_response_data_producer,
                 req.uri().to_string(),
                 remote_addr,
+                claims.and_then(|c| c.max_duration()),
             );
 
             tokio::spawn(async move {
@@ -127,6 +429,150 @@ async fn handle_connection(
     simple_file_send(file_path.as_str()).await
 }
 
+fn rejected(status: StatusCode) -> Response<Body> {
+    let body: Body = match status {
+        StatusCode::FORBIDDEN => FORBIDDEN.into(),
+        StatusCode::UNAUTHORIZED => UNAUTHORIZED.into(),
+        _ => status.canonical_reason().unwrap_or_default().into(),
+    };
+    Response::builder().status(status).body(body).unwrap()
+}
+
+/*hold the request until the playlist contains the segment/part, the status
+code is returned if the request should be rejected*/
+async fn wait_playlist(
+    hls_registry: &HlsRegistry,
+    name: &str,
+    msn: u64,
+    part: Option<u64>,
+) -> Option<StatusCode> {
+    match hls_registry.wait_for(name, msn, part).await {
+        WaitResult::Ready | WaitResult::NotFound => None,
+        WaitResult::BadRequest => Some(StatusCode::BAD_REQUEST),
+        WaitResult::Timeout => Some(StatusCode::SERVICE_UNAVAILABLE),
+    }
+}
+
+/*the time-shift playlist of a stream with a DVR window, an event playlist
+from `start`(epoch seconds) or a sliding playlist `offset` seconds behind the
+live edge, None if the live playlist is requested*/
+fn dvr_playlist(
+    hls_registry: &HlsRegistry,
+    name: &str,
+    start: Option<&String>,
+    offset: Option<&String>,
+) -> Option<std::result::Result<String, StatusCode>> {
+    if start.is_none() && offset.is_none() {
+        return None;
+    }
+    /*the live playlist is served if the DVR window is not enabled*/
+    let dvr = hls_registry.dvr(name)?;
+    let dvr = dvr.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let playlist = if let Some(start) = start {
+        match start
+            .parse::<i64>()
+            .ok()
+            .and_then(|start| DateTime::from_timestamp(start, 0))
+        {
+            Some(start) => dvr.event_playlist(start),
+            None => return Some(Err(StatusCode::BAD_REQUEST)),
+        }
+    } else {
+        match offset
+            .unwrap()
+            .parse::<i64>()
+            .ok()
+            .and_then(|offset| offset.checked_mul(1000))
+        {
+            Some(offset) => dvr.time_shift_playlist(offset),
+            None => return Some(Err(StatusCode::BAD_REQUEST)),
+        }
+    };
+    Some(playlist.ok_or(StatusCode::NOT_FOUND))
+}
+
+/*A recorded file is sent as it is with the range requests supported, or
+remuxed to http-flv from the `start` position(seconds). A mp4 file requested
+by its flv name is always remuxed.*/
+async fn vod_send(
+    vod: &VodService,
+    stream_name: &str,
+    start: Option<String>,
+    range: Option<&str>,
+) -> Response<Body> {
+    let Some(file_path) = vod.file_path(stream_name) else {
+        return not_found();
+    };
+    let start = match start.map(|start| start.parse::<f64>()) {
+        Some(Ok(start)) if start >= 0.0 => Some((start * 1000.0) as u32),
+        Some(_) => return rejected(StatusCode::BAD_REQUEST),
+        None => None,
+    };
+    let is_mp4 = file_path.extension().is_some_and(|e| e == "mp4");
+
+    if start.is_some() || (is_mp4 && stream_name.ends_with(".flv")) {
+        /*the frames of the file are indexed when it is opened*/
+        let file = match tokio::task::spawn_blocking(move || VodFile::open(&file_path)).await {
+            Ok(Ok(file)) => file,
+            Ok(Err(err)) => {
+                log::error!("open vod file: {} error: {}", stream_name, err);
+                return rejected(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            }
+            Err(_) => return rejected(StatusCode::INTERNAL_SERVER_ERROR),
+        };
+        let vod = HttpFlvVod::new(file, start.unwrap_or_default());
+        return Response::builder()
+            .header(header::CONTENT_TYPE, "video/x-flv")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(Body::from_stream(vod.into_stream()))
+            .unwrap();
+    }
+
+    let (Ok(mut file), Ok(metadata)) = (
+        File::open(&file_path).await,
+        tokio::fs::metadata(&file_path).await,
+    ) else {
+        return not_found();
+    };
+    let size = metadata.len();
+    let content_type = if is_mp4 { "video/mp4" } else { "video/x-flv" };
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header("Access-Control-Allow-Origin", "*");
+
+    /*several ranges are not supported, the whole file is sent*/
+    let Some(range) = range.filter(|range| !range.contains(',')) else {
+        let body = Body::from_stream(FramedRead::new(file, BytesCodec::new()));
+        return builder
+            .header(header::CONTENT_LENGTH, size)
+            .body(body)
+            .unwrap();
+    };
+    let Some((first, last)) = parse_range(range, size) else {
+        return builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{size}"))
+            .body(Body::empty())
+            .unwrap();
+    };
+    if file.seek(std::io::SeekFrom::Start(first)).await.is_err() {
+        return rejected(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let body = Body::from_stream(FramedRead::new(
+        file.take(last - first + 1),
+        BytesCodec::new(),
+    ));
+    builder
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(header::CONTENT_RANGE, format!("bytes {first}-{last}/{size}"))
+        .header(header::CONTENT_LENGTH, last - first + 1)
+        .body(body)
+        .unwrap()
+}
+
 /// HTTP status code 404
 fn not_found() -> Response<Body> {
     Response::builder()
@@ -147,10 +593,64 @@ async fn simple_file_send(filename: &str) -> Response<Body> {
     not_found()
 }
 
+/*Append the query string(the token) of the playlist request to every
+segment uri in the playlist, including the URI attributes of the tags.*/
+fn append_query_to_segments(playlist: &str, query: &str) -> String {
+    let append = |uri: &str| {
+        let separator = if uri.contains('?') { '&' } else { '?' };
+        format!("{uri}{separator}{query}")
+    };
+
+    let mut result = String::with_capacity(playlist.len());
+    for line in playlist.lines() {
+        if line.is_empty() {
+        } else if !line.starts_with('#') {
+            result.push_str(&append(line));
+        } else if let Some(start) = line.find("URI=\"") {
+            let start = start + 5;
+            match line[start..].find('"') {
+                Some(len) => {
+                    result.push_str(&line[..start]);
+                    result.push_str(&append(&line[start..start + len]));
+                    result.push_str(&line[start + len..]);
+                }
+                None => result.push_str(line),
+            }
+        } else {
+            result.push_str(line);
+        }
+        result.push('\n');
+    }
+    result
+}
+
+/*Append the query string(the token) of the mpd request to the initialization
+and media templates of the mpd.*/
+fn append_query_to_templates(mpd: &str, query: &str) -> String {
+    mpd.replace(".mp4\"", &format!(".mp4?{query}\""))
+        .replace(".m4s\"", &format!(".m4s?{query}\""))
+}
+
+/*serve a playlist or a mpd rewritten with the query string*/
+async fn manifest_send(
+    filename: &str,
+    query: &str,
+    rewrite: fn(&str, &str) -> String,
+) -> Response<Body> {
+    match tokio::fs::read_to_string(filename).await {
+        Ok(manifest) => Response::new(Body::from(rewrite(&manifest, query))),
+        Err(_) => not_found(),
+    }
+}
+
 pub async fn run(
     event_producer: StreamHubEventSender,
     port: usize,
     auth: Option<Auth>,
+    proxy_protocol: bool,
+    access: Option<Arc<AccessControl>>,
+    hls_registry: Arc<HlsRegistry>,
+    vod: Option<Arc<VodService>>,
 ) -> Result<()> {
     let listen_address = format!("0.0.0.0:{port}");
     let sock_addr: SocketAddr = listen_address.parse().unwrap();
@@ -159,12 +659,241 @@ pub async fn run(
 
     log::info!("Hls server listening on http://{}", sock_addr);
 
-    let handle_connection = handle_connection.with_state((event_producer.clone(), auth));
+    let handle_connection =
+        handle_connection.with_state((
+            event_producer.clone(),
+            auth,
+            access.clone(),
+            hls_registry,
+            vod,
+        ));
+
+    /*The connections are accepted here instead of by axum::serve, so that the
+    PROXY protocol header can be consumed before the HTTP request is parsed and
+    the real client address can be passed to the handler as ConnectInfo.*/
+    loop {
+        let (mut tcp_stream, peer_addr) = listener.accept().await?;
+        let handle_connection = handle_connection.clone();
+        let access = access.clone();
+
+        tokio::spawn(async move {
+            let remote_addr = if proxy_protocol {
+                match proxy_protocol::read_proxy_header(&mut tcp_stream, Duration::from_secs(5))
+                    .await
+                {
+                    Ok(header) => header.remote_addr_or(peer_addr),
+                    Err(err) => {
+                        log::error!(
+                            "read proxy protocol header from {} error: {}",
+                            peer_addr,
+                            err
+                        );
+                        return;
+                    }
+                }
+            } else {
+                peer_addr
+            };
+
+            //the connection slot is held until the connection is closed
+            let _connection_guard = match &access {
+                Some(access) => match access.on_connect(remote_addr.ip()) {
+                    Ok(guard) => Some(guard),
+                    Err(err) => {
+                        log::warn!("reject http connection from {}: {}", remote_addr, err);
+                        return;
+                    }
+                },
+                None => None,
+            };
+
+            let service = hyper::service::service_fn(move |mut req: Request<Incoming>| {
+                req.extensions_mut().insert(ConnectInfo(remote_addr));
+                handle_connection.clone().oneshot(req)
+            });
+
+            if let Err(err) = Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(tcp_stream), service)
+                .await
+            {
+                log::debug!("http connection from {} error: {}", remote_addr, err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::handle_connection;
+    use crate::hls::{
+        define::HlsSettings, encryption::parse_key_path, m3u8::M3u8, registry::HlsRegistry,
+    };
+    use axum::{
+        body::{to_bytes, Body},
+        extract::ConnectInfo,
+        handler::Handler,
+        http::{Request, StatusCode},
+    };
+    use bytes::BytesMut;
+    use commonlib::{
+        access::AccessControl,
+        auth::{Auth, AuthAlgorithm, AuthType},
+        config::{AccessConfig, AuthFailureBanConfig, HlsEncryptionConfig},
+    };
+    use std::{net::SocketAddr, sync::Arc};
+    use tokio::sync::mpsc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_key_request() {
+        let registry = Arc::new(HlsRegistry::default());
+        let mut settings = HlsSettings::new(None);
+        settings.encryption = Some(HlsEncryptionConfig {
+            enabled: true,
+            key_rotation: None,
+            key_uri: None,
+        });
+        let mut m3u8 = M3u8::new(
+            String::from("live"),
+            String::from("paid"),
+            settings,
+            registry.clone(),
+        );
+        m3u8.add_segment(2000, false, false, BytesMut::from(&[0x47; 188][..]))
+            .unwrap();
+        m3u8.refresh_playlist().unwrap();
 
-    axum::serve(
-        listener,
-        handle_connection.into_make_service_with_connect_info::<SocketAddr>(),
-    ).await?;
+        let (event_producer, _) = mpsc::unbounded_channel();
+        let service = handle_connection.with_state((event_producer, None, None, registry, None));
+        let get = |path: String| {
+            let request = Request::builder()
+                .uri(path)
+                .extension(ConnectInfo("127.0.0.1:10000".parse::<SocketAddr>().unwrap()))
+                .body(Body::empty())
+                .unwrap();
+            service.clone().oneshot(request)
+        };
 
-    Ok(())
+        let response = get(String::from("/live/paid/index.m3u8")).await.unwrap();
+        let playlist = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let playlist = String::from_utf8_lossy(&playlist);
+        let uri = playlist
+            .split("URI=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap();
+
+        /*the key URI is resolved against the playlist path*/
+        let path = format!("/live/paid/{uri}");
+        assert_eq!(
+            parse_key_path(&path),
+            Some(("live", "paid", "key_0.key"))
+        );
+        let response = get(path).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let key = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(key.len(), 16);
+
+        m3u8.clear().unwrap();
+        let response = get(String::from("/live/paid/key_0.key")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+    #[tokio::test]
+    async fn test_time_shift_request() {
+        let registry = Arc::new(HlsRegistry::default());
+        let mut settings = HlsSettings::new(None);
+        settings.dvr_window = 60 * 1000;
+        let mut m3u8 = M3u8::new(
+            String::from("live"),
+            String::from("dvr"),
+            settings,
+            registry.clone(),
+        );
+        m3u8.add_segment(2000, false, false, BytesMut::from(&[0x47; 188][..]))
+            .unwrap();
+
+        let (event_producer, _) = mpsc::unbounded_channel();
+        let service = handle_connection.with_state((event_producer, None, None, registry, None));
+        let get = |query: &str| {
+            let request = Request::builder()
+                .uri(format!("/live/dvr/index.m3u8?{query}"))
+                .extension(ConnectInfo("127.0.0.1:10000".parse::<SocketAddr>().unwrap()))
+                .body(Body::empty())
+                .unwrap();
+            service.clone().oneshot(request)
+        };
+
+        /*an offset which overflows in milliseconds is rejected, a large one is
+        limited to the window*/
+        let response = get("offset=-9223372036854775808").await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = get("offset=-9223372036854775").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        /*the stream is still packaged*/
+        m3u8.add_segment(2000, false, false, BytesMut::from(&[0x47; 188][..]))
+            .unwrap();
+        let response = get("offset=-2").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_auth_failure_counting() {
+        let auth = Auth::new(
+            String::from("key"),
+            String::new(),
+            None,
+            AuthAlgorithm::SecureLink,
+            AuthType::Pull,
+            false,
+            None,
+        );
+        let access = Arc::new(
+            AccessControl::new(&AccessConfig {
+                auth_failure_ban: Some(AuthFailureBanConfig {
+                    max_failures: 3,
+                    window_secs: 60,
+                    ban_secs: 600,
+                }),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let registry = Arc::new(HlsRegistry::default());
+        let (event_producer, _) = mpsc::unbounded_channel();
+        let service = handle_connection.with_state((
+            event_producer,
+            Some(auth),
+            Some(access.clone()),
+            registry,
+            None,
+        ));
+        let get = |path: &str| {
+            let request = Request::builder()
+                .uri(format!("{path}?token=expired&expires=1"))
+                .extension(ConnectInfo("1.1.1.1:10000".parse::<SocketAddr>().unwrap()))
+                .body(Body::empty())
+                .unwrap();
+            service.clone().oneshot(request)
+        };
+
+        /*a player whose token expired keeps requesting the segments and the keys*/
+        for path in [
+            "/live/test/0.ts",
+            "/live/test/1.m4s",
+            "/live/test/key_0.key",
+        ] {
+            for _ in 0..3 {
+                let response = get(path).await.unwrap();
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            }
+        }
+        assert!(access.on_connect("1.1.1.1".parse().unwrap()).is_ok());
+
+        for _ in 0..3 {
+            let response = get("/live/test/index.m3u8").await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert!(access.on_connect("1.1.1.1".parse().unwrap()).is_err());
+    }
 }
@@ -24,3 +24,4 @@ pub mod session;
 // pub mod statistics;
 pub mod user_control_messages;
 pub mod utils;
+pub mod virtual_channel;
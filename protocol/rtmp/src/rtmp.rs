@@ -1,8 +1,13 @@
 use streamhub::define::StreamHubEventSender;
 
 use super::session::server_session;
+use bytesio::proxy_protocol;
+use commonlib::access::AccessControl;
 use commonlib::auth::Auth;
+use commonlib::vod::VodService;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::Error;
 use tokio::net::TcpListener;
 
@@ -11,6 +16,12 @@ pub struct RtmpServer {
     event_producer: StreamHubEventSender,
     gop_num: usize,
     auth: Option<Auth>,
+    /*the server is behind a L4 load balancer which sends a PROXY protocol header*/
+    proxy_protocol: bool,
+    /*ip allow/deny lists and per ip limits, checked before the handshake*/
+    access: Option<Arc<AccessControl>>,
+    /*the recorded files played on the vod app*/
+    vod: Option<Arc<VodService>>,
 }
 
 impl RtmpServer {
@@ -19,12 +30,18 @@ impl RtmpServer {
         event_producer: StreamHubEventSender,
         gop_num: usize,
         auth: Option<Auth>,
+        proxy_protocol: bool,
+        access: Option<Arc<AccessControl>>,
+        vod: Option<Arc<VodService>>,
     ) -> Self {
         Self {
             address,
             event_producer,
             gop_num,
             auth,
+            proxy_protocol,
+            access,
+            vod,
         }
     }
 
@@ -34,16 +51,57 @@ impl RtmpServer {
 
         log::info!("Rtmp server listening on tcp://{}", socket_addr);
         loop {
-            let (tcp_stream, _) = listener.accept().await?;
+            let (mut tcp_stream, peer_addr) = listener.accept().await?;
             //tcp_stream.set_keepalive(Some(Duration::from_secs(30)))?;
 
-            let mut session = server_session::ServerSession::new(
-                tcp_stream,
-                self.event_producer.clone(),
-                self.gop_num,
-                self.auth.clone(),
-            );
+            let event_producer = self.event_producer.clone();
+            let gop_num = self.gop_num;
+            let auth = self.auth.clone();
+            let proxy_protocol = self.proxy_protocol;
+            let access = self.access.clone();
+            let vod = self.vod.clone();
+
             tokio::spawn(async move {
+                let remote_addr = if proxy_protocol {
+                    match proxy_protocol::read_proxy_header(&mut tcp_stream, Duration::from_secs(5))
+                        .await
+                    {
+                        Ok(header) => header.remote_addr_or(peer_addr),
+                        Err(err) => {
+                            log::error!(
+                                "read proxy protocol header from {} error: {}",
+                                peer_addr,
+                                err
+                            );
+                            return;
+                        }
+                    }
+                } else {
+                    peer_addr
+                };
+
+                //hold the connection slot until the session ends
+                let _connection_guard = match &access {
+                    Some(access) => match access.on_connect(remote_addr.ip()) {
+                        Ok(guard) => Some(guard),
+                        Err(err) => {
+                            log::warn!("reject rtmp connection from {}: {}", remote_addr, err);
+                            return;
+                        }
+                    },
+                    None => None,
+                };
+
+                let mut session = server_session::ServerSession::new(
+                    tcp_stream,
+                    event_producer,
+                    gop_num,
+                    auth,
+                    remote_addr,
+                    access,
+                    vod,
+                );
+
                 if let Err(err) = session.run().await {
                     log::info!(
                         "session run error: session_type: {}, app_name: {}, stream_name: {}, err: {}",
@@ -52,6 +52,8 @@ pub struct Common {
     remote_addr: Option<SocketAddr>,
     /*request URL from client*/
     pub request_url: String,
+    /*the label of the per stream publish key carried by the publisher*/
+    pub publish_key: Option<String>,
     pub stream_handler: Arc<RtmpStreamHandler>,
     /* now used for subscriber session */
     statistic_data_sender: Option<StatisticDataSender>,
@@ -78,11 +80,20 @@ impl Common {
             session_type,
             remote_addr,
             request_url: String::default(),
+            publish_key: None,
             stream_handler: Arc::new(RtmpStreamHandler::new()),
             statistic_data_sender: None,
             //cache: None,
         }
     }
+    /*the client ip used by the on_publish hook, it is the real client ip
+    if the session is accepted behind a PROXY protocol load balancer*/
+    pub fn remote_ip(&self) -> String {
+        self.remote_addr
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_default()
+    }
+
     pub async fn send_channel_data(&mut self) -> Result<(), SessionError> {
         let mut retry_times = 0;
         loop {
@@ -323,6 +334,7 @@ impl Common {
                 request_url: self.request_url.clone(),
                 remote_addr,
             },
+            publish_key: self.publish_key.clone(),
         }
     }
 
@@ -568,7 +580,9 @@ impl TStreamHandler for RtmpStreamHandler {
                 SubscribeType::PlayerRtmp
                 | SubscribeType::PlayerHttpFlv
                 | SubscribeType::PlayerHls
-                | SubscribeType::GenerateHls => {
+                | SubscribeType::GenerateHls
+                | SubscribeType::GenerateDash
+                | SubscribeType::Record => {
                     if let Some(gops_data) = cache.get_gops_data() {
                         for gop in gops_data {
                             for channel_data in gop.get_frame_data() {
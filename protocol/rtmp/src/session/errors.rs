@@ -10,10 +10,10 @@ use {
         user_control_messages::errors::EventMessagesError,
     },
     bytesio::{bytes_errors::BytesWriteError, bytesio_errors::BytesIOError},
-    commonlib::errors::AuthError,
+    commonlib::errors::{AccessError, AuthError},
     failure::{Backtrace, Fail},
     std::fmt,
-    streamhub::errors::StreamHubError,
+    streamhub::{errors::StreamHubError, vod::errors::VodError},
     tokio::sync::oneshot::error::RecvError,
     xflv::amf0::errors::Amf0WriteError,
 };
@@ -82,6 +82,14 @@ pub enum SessionErrorValue {
     Finish,
     #[fail(display = "Auth err: {}", _0)]
     AuthError(#[cause] AuthError),
+    #[fail(display = "Access err: {}", _0)]
+    AccessError(#[cause] AccessError),
+    #[fail(display = "the session duration allowed by the token is reached.")]
+    SessionDurationReached,
+    #[fail(display = "the bitrate {} kbps exceeds the limit of the token.", _0)]
+    BitrateExceeded(u64),
+    #[fail(display = "vod file error: {}", _0)]
+    VodError(#[cause] VodError),
 }
 
 impl From<Amf0WriteError> for SessionError {
@@ -92,6 +100,14 @@ impl From<Amf0WriteError> for SessionError {
     }
 }
 
+impl From<VodError> for SessionError {
+    fn from(error: VodError) -> Self {
+        SessionError {
+            value: SessionErrorValue::VodError(error),
+        }
+    }
+}
+
 impl From<BytesWriteError> for SessionError {
     fn from(error: BytesWriteError) -> Self {
         SessionError {
@@ -211,6 +227,14 @@ impl From<AuthError> for SessionError {
     }
 }
 
+impl From<AccessError> for SessionError {
+    fn from(error: AccessError) -> Self {
+        SessionError {
+            value: SessionErrorValue::AccessError(error),
+        }
+    }
+}
+
 impl fmt::Display for SessionError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Display::fmt(&self.value, f)
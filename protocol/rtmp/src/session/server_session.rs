@@ -1,7 +1,12 @@
 use serde_json::json;
-use commonlib::auth::SecretCarrier;
+use commonlib::auth::{parse_query, SecretCarrier};
+use commonlib::config::Streams;
+use commonlib::errors::{AuthError, AuthErrorValue};
 
-use crate::chunk::{errors::UnpackErrorValue, packetizer::ChunkPacketizer};
+use crate::chunk::{
+    errors::{UnpackError, UnpackErrorValue},
+    packetizer::ChunkPacketizer,
+};
 
 use {
     super::{
@@ -9,6 +14,7 @@ use {
         define,
         define::SessionType,
         errors::{SessionError, SessionErrorValue},
+        vod::VodPlayer,
     },
     crate::{
         chunk::{
@@ -28,16 +34,27 @@ use {
     bytesio::{
         bytes_writer::AsyncBytesWriter,
         bytesio::{TNetIO, TcpIO},
+        bytesio_errors::BytesIOErrorValue,
     },
-    commonlib::auth::Auth,
+    commonlib::{access::AccessControl, auth::Auth, vod::VodService},
     indexmap::IndexMap,
-    std::{sync::Arc, time::Duration},
-    streamhub::define::StreamHubEventSender,
+    std::{
+        net::SocketAddr,
+        sync::Arc,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+    streamhub::{
+        define::{FrameData, StreamHubEventSender},
+        vod::VodFile,
+    },
     tokio::{net::TcpStream, sync::Mutex},
     xflv::amf0::Amf0ValueType,
 };
 use commonlib::config::load_config;
 
+//the window over which a publisher's bitrate is measured
+const BITRATE_WINDOW: Duration = Duration::from_secs(10);
+
 enum ServerSessionState {
     Handshake,
     ReadChunk,
@@ -46,6 +63,8 @@ enum ServerSessionState {
     //Publish,
     DeleteStream,
     Play,
+    /*play a recorded file of the vod app*/
+    Vod,
 }
 
 pub struct ServerSession {
@@ -63,6 +82,16 @@ pub struct ServerSession {
     /*configure how many gops will be cached.*/
     gop_num: usize,
     auth: Option<Auth>,
+    /*the real client address, used for access control*/
+    remote_addr: SocketAddr,
+    access: Option<Arc<AccessControl>>,
+    /*limits carried by a jwt token*/
+    session_deadline: Option<Instant>,
+    max_bitrate: Option<u64>,
+    bitrate_window_start: Instant,
+    bitrate_window_bytes: usize,
+    vod: Option<Arc<VodService>>,
+    vod_player: Option<VodPlayer>,
 }
 
 impl ServerSession {
@@ -71,13 +100,11 @@ impl ServerSession {
         event_producer: StreamHubEventSender,
         gop_num: usize,
         auth: Option<Auth>,
+        remote_addr: SocketAddr,
+        access: Option<Arc<AccessControl>>,
+        vod: Option<Arc<VodService>>,
     ) -> Self {
-        let remote_addr = if let Ok(addr) = stream.peer_addr() {
-            log::info!("server session: {}", addr.to_string());
-            Some(addr)
-        } else {
-            None
-        };
+        log::info!("server session: {}", remote_addr);
 
         let tcp_io: Box<dyn TNetIO + Send + Sync> = Box::new(TcpIO::new(stream));
         let net_io = Arc::new(Mutex::new(tcp_io));
@@ -94,7 +121,7 @@ impl ServerSession {
                 Some(ChunkPacketizer::new(Arc::clone(&net_io))),
                 event_producer,
                 SessionType::Server,
-                remote_addr,
+                Some(remote_addr),
             ),
 
             bytesio_data: BytesMut::new(),
@@ -102,6 +129,14 @@ impl ServerSession {
             connect_properties: ConnectProperties::default(),
             gop_num,
             auth,
+            remote_addr,
+            access,
+            session_deadline: None,
+            max_bitrate: None,
+            bitrate_window_start: Instant::now(),
+            bitrate_window_bytes: 0,
+            vod,
+            vod_player: None,
         }
     }
 
@@ -117,6 +152,9 @@ impl ServerSession {
                 ServerSessionState::Play => {
                     self.play().await?;
                 }
+                ServerSessionState::Vod => {
+                    self.play_vod().await?;
+                }
                 ServerSessionState::DeleteStream => {
                     return Ok(());
                 }
@@ -175,11 +213,34 @@ impl ServerSession {
                 }
             }
 
+            if let Err(err) = self.check_session_limits(self.bytesio_data.len()) {
+                self.common
+                    .unpublish_to_channels(self.app_name.clone(), self.stream_name.clone())
+                    .await?;
+                return Err(err);
+            }
+
             self.unpacketizer.extend_data(&self.bytesio_data[..]);
         }
 
         self.has_remaing_data = false;
 
+        if let Err(err) = self.process_chunks().await {
+            if let SessionErrorValue::UnPackError(UnpackError {
+                value: UnpackErrorValue::CannotParse,
+            }) = err.value
+            {
+                self.common
+                    .unpublish_to_channels(self.app_name.clone(), self.stream_name.clone())
+                    .await?;
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /*process the messages of the chunks read so far*/
+    async fn process_chunks(&mut self) -> Result<(), SessionError> {
         loop {
             match self.unpacketizer.read_chunks() {
                 Ok(rv) => {
@@ -197,9 +258,6 @@ impl ServerSession {
                 }
                 Err(err) => {
                     if let UnpackErrorValue::CannotParse = err.value {
-                        self.common
-                            .unpublish_to_channels(self.app_name.clone(), self.stream_name.clone())
-                            .await?;
                         return Err(err)?;
                     }
                     break;
@@ -210,6 +268,13 @@ impl ServerSession {
     }
 
     async fn play(&mut self) -> Result<(), SessionError> {
+        if let Err(err) = self.check_session_limits(0) {
+            self.common
+                .unsubscribe_from_channels(self.app_name.clone(), self.stream_name.clone())
+                .await?;
+            return Err(err);
+        }
+
         match self.common.send_channel_data().await {
             Ok(_) => {}
             Err(err) => {
@@ -223,6 +288,64 @@ impl ServerSession {
         Ok(())
     }
 
+    /*send the due frames of the recorded file and process the client commands
+    like seek and pause in the meantime*/
+    async fn play_vod(&mut self) -> Result<(), SessionError> {
+        self.check_session_limits(0)?;
+
+        let Some(player) = &mut self.vod_player else {
+            self.state = ServerSessionState::ReadChunk;
+            return Ok(());
+        };
+        let frames = player.due_frames()?;
+        let completed = player.take_completion();
+        let wait_time = player.wait_time();
+
+        for frame in frames {
+            match frame {
+                FrameData::Video { timestamp, data } => {
+                    self.common.send_video(data, timestamp).await?
+                }
+                FrameData::Audio { timestamp, data } => {
+                    self.common.send_audio(data, timestamp).await?
+                }
+                FrameData::MetaData { timestamp, data } => {
+                    self.common.send_metadata(data, timestamp).await?
+                }
+                FrameData::MediaInfo { .. } | FrameData::Discontinuity => {}
+            }
+        }
+
+        if completed {
+            log::info!(
+                "[ S->C ] [play stop]  app_name: {}, stream_name: {}",
+                self.app_name,
+                self.stream_name
+            );
+            let mut netstream = NetStreamWriter::new(Arc::clone(&self.io));
+            netstream
+                .write_on_status(&0.0, "status", "NetStream.Play.Stop", "play stop")
+                .await?;
+            let mut event_messages =
+                EventMessagesWriter::new(AsyncBytesWriter::new(self.io.clone()));
+            event_messages.write_stream_eof(define::STREAM_ID as u32).await?;
+        }
+
+        let result = self.io.lock().await.read_timeout(wait_time).await;
+        match result {
+            Ok(data) => {
+                self.unpacketizer.extend_data(&data[..]);
+                self.process_chunks().await?;
+            }
+            Err(err) => {
+                if !matches!(err.value, BytesIOErrorValue::TimeoutError(_)) {
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub async fn send_set_chunk_size(&mut self) -> Result<(), SessionError> {
         let mut controlmessage =
             ProtocolControlMessagesWriter::new(AsyncBytesWriter::new(self.io.clone()));
@@ -335,6 +458,12 @@ impl ServerSession {
                 self.unpacketizer.session_type = config::SERVER_PUSH;
                 self.on_publish(transaction_id, stream_id, others).await?;
             }
+            "seek" => {
+                self.on_seek(transaction_id, stream_id, others).await?;
+            }
+            "pause" => {
+                self.on_pause(transaction_id, stream_id, others).await?;
+            }
             _ => {}
         }
 
@@ -514,6 +643,114 @@ impl ServerSession {
         Ok(())
     }
 
+    fn check_access(&self, is_pull: bool) -> Result<(), SessionError> {
+        if let Some(access) = &self.access {
+            access.check(&self.app_name, &self.remote_addr.ip(), is_pull)?;
+        }
+        Ok(())
+    }
+
+    /*an auth failure is counted for the temporary ip ban*/
+    fn check_auth(&mut self, is_pull: bool) -> Result<(), SessionError> {
+        if let Some(auth) = &self.auth {
+            match auth.authenticate(
+                &self.app_name,
+                &self.stream_name,
+                &self
+                    .query
+                    .as_ref()
+                    .map(|q| SecretCarrier::Query(q.to_string())),
+                is_pull,
+                Some(self.remote_addr.ip()),
+            ) {
+                Ok(Some(claims)) => {
+                    self.session_deadline = claims.max_duration().map(|d| Instant::now() + d);
+                    if !is_pull {
+                        self.max_bitrate = claims.max_bitrate;
+                        self.bitrate_window_start = Instant::now();
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    if let Some(access) = &self.access {
+                        access.on_auth_failure(&self.remote_addr.ip());
+                    }
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /*received_bytes: the bytes read from the publisher since the last check*/
+    fn check_session_limits(&mut self, received_bytes: usize) -> Result<(), SessionError> {
+        if let Some(deadline) = self.session_deadline {
+            if Instant::now() >= deadline {
+                return Err(SessionError {
+                    value: SessionErrorValue::SessionDurationReached,
+                });
+            }
+        }
+
+        if let Some(max_bitrate) = self.max_bitrate {
+            self.bitrate_window_bytes += received_bytes;
+            let elapsed = self.bitrate_window_start.elapsed();
+            if elapsed >= BITRATE_WINDOW {
+                let kbps = (self.bitrate_window_bytes as u128 * 8 / elapsed.as_millis()) as u64;
+                self.bitrate_window_start = Instant::now();
+                self.bitrate_window_bytes = 0;
+                if kbps > max_bitrate {
+                    return Err(SessionError {
+                        value: SessionErrorValue::BitrateExceeded(kbps),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /*the per stream publish keys are checked against the "key" query parameter*/
+    fn check_publish_key(&mut self, stream: &Streams) -> Result<(), SessionError> {
+        if !stream.requires_publish_key() {
+            return Ok(());
+        }
+
+        let key = self
+            .query
+            .as_ref()
+            .and_then(|query| parse_query(query).get("key").cloned());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        let err_value = match key {
+            Some(key) => match stream.find_publish_key(&key, now) {
+                Some(publish_key) => {
+                    log::info!(
+                        "publish key: {} is used by stream: {}",
+                        publish_key.label,
+                        stream.name
+                    );
+                    self.common.publish_key = Some(publish_key.label.clone());
+                    return Ok(());
+                }
+                None => AuthErrorValue::TokenIsNotCorrect,
+            },
+            None => AuthErrorValue::NoTokenFound,
+        };
+
+        log::error!(
+            "publish key check failed, stream: {} reason: {}",
+            stream.name,
+            err_value
+        );
+        if let Some(access) = &self.access {
+            access.on_auth_failure(&self.remote_addr.ip());
+        }
+        Err(AuthError { value: err_value }.into())
+    }
+
     fn get_request_url(&mut self, raw_stream_name: String) -> String {
         if let Some(tc_url) = &self.connect_properties.tc_url {
             format!("{tc_url}/{raw_stream_name}")
@@ -576,6 +813,16 @@ impl ServerSession {
             break;
         }
 
+        if self
+            .vod
+            .as_ref()
+            .is_some_and(|vod| vod.is_vod_app(&self.app_name))
+        {
+            return self
+                .on_play_vod(transaction_id, stream_id, stream_name, start, duration, reset)
+                .await;
+        }
+
         let mut event_messages = EventMessagesWriter::new(AsyncBytesWriter::new(self.io.clone()));
         event_messages.write_stream_begin(*stream_id).await?;
         log::info!(
@@ -628,16 +875,8 @@ impl ServerSession {
 
         (self.stream_name, self.query) =
             RtmpUrlParser::parse_stream_name_with_query(&raw_stream_name);
-        if let Some(auth) = &self.auth {
-            auth.authenticate(
-                &self.stream_name,
-                &self
-                    .query
-                    .as_ref()
-                    .map(|q| SecretCarrier::Query(q.to_string())),
-                true,
-            )?
-        }
+        self.check_access(true)?;
+        self.check_auth(true)?;
 
         let query = if let Some(query_val) = &self.query {
             query_val.clone()
@@ -663,6 +902,196 @@ impl ServerSession {
         Ok(())
     }
 
+    /*start and duration are seconds, a negative start plays from the beginning
+    and a negative duration plays to the end*/
+    async fn on_play_vod(
+        &mut self,
+        transaction_id: &f64,
+        stream_id: &u32,
+        stream_name: Option<String>,
+        start: Option<f64>,
+        duration: Option<f64>,
+        reset: Option<bool>,
+    ) -> Result<(), SessionError> {
+        let raw_stream_name = stream_name.unwrap_or_default();
+        (self.stream_name, self.query) =
+            RtmpUrlParser::parse_stream_name_with_query(&raw_stream_name);
+        self.check_access(true)?;
+        self.check_auth(true)?;
+        self.common.request_url = self.get_request_url(raw_stream_name);
+
+        let file = match self
+            .vod
+            .as_ref()
+            .and_then(|vod| vod.file_path(&self.stream_name))
+        {
+            Some(path) => {
+                /*the frames are indexed when the file is opened, it is done on
+                the blocking threads*/
+                let name = path.display().to_string();
+                match tokio::task::spawn_blocking(move || VodFile::open(&path)).await {
+                    Ok(Ok(file)) => Some(file),
+                    Ok(Err(err)) => {
+                        log::error!("open vod file: {} error: {}", name, err);
+                        None
+                    }
+                    Err(err) => {
+                        log::error!("open vod file: {} error: {}", name, err);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let mut netstream = NetStreamWriter::new(Arc::clone(&self.io));
+        let Some(file) = file else {
+            log::warn!(
+                "[ S->C ] [stream not found]  app_name: {}, stream_name: {}",
+                self.app_name,
+                self.stream_name
+            );
+            netstream
+                .write_on_status(
+                    transaction_id,
+                    "error",
+                    "NetStream.Play.StreamNotFound",
+                    "stream not found",
+                )
+                .await?;
+            return Ok(());
+        };
+
+        let mut event_messages = EventMessagesWriter::new(AsyncBytesWriter::new(self.io.clone()));
+        event_messages.write_stream_is_record(*stream_id).await?;
+        event_messages.write_stream_begin(*stream_id).await?;
+
+        if reset.unwrap_or(true) {
+            netstream
+                .write_on_status(transaction_id, "status", "NetStream.Play.Reset", "reset")
+                .await?;
+        }
+        netstream
+            .write_on_status(
+                transaction_id,
+                "status",
+                "NetStream.Play.Start",
+                "play start",
+            )
+            .await?;
+
+        let start = (start.unwrap_or(0.0).max(0.0) * 1000.0) as u32;
+        let duration = duration
+            .filter(|duration| *duration >= 0.0)
+            .map(|duration| (duration * 1000.0) as u32);
+        log::info!(
+            "[ S->C ] [vod play start]  app_name: {}, stream_name: {}, start: {}ms, duration: {:?}ms",
+            self.app_name,
+            self.stream_name,
+            start,
+            duration
+        );
+
+        self.vod_player = Some(VodPlayer::new(file, start, duration));
+        self.state = ServerSessionState::Vod;
+
+        Ok(())
+    }
+
+    /*the seek command carries the position in milliseconds*/
+    async fn on_seek(
+        &mut self,
+        transaction_id: &f64,
+        stream_id: &u32,
+        other_values: &[Amf0ValueType],
+    ) -> Result<(), SessionError> {
+        let Some(player) = &mut self.vod_player else {
+            return Ok(());
+        };
+        let position = match other_values.first() {
+            Some(Amf0ValueType::Number(ms)) => ms.max(0.0) as u32,
+            _ => {
+                return Err(SessionError {
+                    value: SessionErrorValue::Amf0ValueCountNotCorrect,
+                })
+            }
+        };
+        log::info!(
+            "[ S<-C ] [seek]  app_name: {}, stream_name: {}, position: {}ms",
+            self.app_name,
+            self.stream_name,
+            position
+        );
+        player.seek(position);
+
+        let mut event_messages = EventMessagesWriter::new(AsyncBytesWriter::new(self.io.clone()));
+        event_messages.write_stream_eof(*stream_id).await?;
+        event_messages.write_stream_begin(*stream_id).await?;
+
+        let mut netstream = NetStreamWriter::new(Arc::clone(&self.io));
+        netstream
+            .write_on_status(transaction_id, "status", "NetStream.Seek.Notify", "seek")
+            .await?;
+        netstream
+            .write_on_status(
+                transaction_id,
+                "status",
+                "NetStream.Play.Start",
+                "play start",
+            )
+            .await?;
+        Ok(())
+    }
+
+    /*the pause command carries the pause flag and the position in milliseconds,
+    the playback continues from where it was paused*/
+    async fn on_pause(
+        &mut self,
+        transaction_id: &f64,
+        stream_id: &u32,
+        other_values: &[Amf0ValueType],
+    ) -> Result<(), SessionError> {
+        let Some(player) = &mut self.vod_player else {
+            return Ok(());
+        };
+        let pause = match other_values.first() {
+            Some(Amf0ValueType::Boolean(pause)) => *pause,
+            _ => {
+                return Err(SessionError {
+                    value: SessionErrorValue::Amf0ValueCountNotCorrect,
+                })
+            }
+        };
+        log::info!(
+            "[ S<-C ] [pause]  app_name: {}, stream_name: {}, pause: {}",
+            self.app_name,
+            self.stream_name,
+            pause
+        );
+
+        let mut event_messages = EventMessagesWriter::new(AsyncBytesWriter::new(self.io.clone()));
+        let mut netstream = NetStreamWriter::new(Arc::clone(&self.io));
+        if pause {
+            player.pause();
+            netstream
+                .write_on_status(transaction_id, "status", "NetStream.Pause.Notify", "pause")
+                .await?;
+            event_messages.write_stream_eof(*stream_id).await?;
+        } else {
+            player.unpause();
+            event_messages.write_stream_begin(*stream_id).await?;
+            netstream
+                .write_on_status(
+                    transaction_id,
+                    "status",
+                    "NetStream.Unpause.Notify",
+                    "unpause",
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
     pub async fn on_publish(
         &mut self,
         transaction_id: &f64,
@@ -711,6 +1140,9 @@ impl ServerSession {
         println!("app_name: {:?}", self.app_name);
         println!("stream_name: {:?}", self.stream_name);
 
+        self.check_access(false)?;
+        self.check_auth(false)?;
+
         // check app_name and stream_name on config file to check if it is disabled
         let config = match load_config("config.json") {
             Ok(val) => val,
@@ -725,6 +1157,7 @@ impl ServerSession {
             for stream in streams.iter() {
                 if stream.name == format!("{}/{}", self.app_name, self.stream_name) && !stream.disabled.unwrap_or(false) {
                     println!("App_name/Stream_name matched");
+                    self.check_publish_key(stream)?;
                     if let Some(on_publish_url) = &stream.on_publish_url {
                         println!("On publish url: {:?}", on_publish_url);
                         let client = reqwest::Client::builder()
@@ -733,7 +1166,7 @@ impl ServerSession {
                             .unwrap();
                         let response = client
                             .post(on_publish_url)
-                            .json(&json!({"name": stream.name, "ip": ""}))
+                            .json(&json!({"name": stream.name, "ip": self.common.remote_ip(), "key": self.common.publish_key}))
                             .send()
                             .await;
                         match response {
@@ -0,0 +1,202 @@
+use {
+    std::time::{Duration, Instant},
+    streamhub::{
+        define::FrameData,
+        vod::{errors::VodError, VodFile},
+    },
+};
+
+/*the frames are sent ahead of the playback clock to fill the player buffer*/
+const SEND_AHEAD: u32 = 1000;
+/*the longest wait for the client commands between two sends*/
+const MAX_WAIT: Duration = Duration::from_secs(1);
+const MIN_WAIT: Duration = Duration::from_millis(10);
+
+/*
+ Paces the frames of a recorded file for a play session. The playback clock
+ follows the file timestamps from the start or the seek position, and stops
+ while the playback is paused.
+*/
+pub struct VodPlayer {
+    file: VodFile,
+    /*the next frame to be sent*/
+    index: usize,
+    /*the file timestamp at which the playback stops, None to play to the end*/
+    end_timestamp: Option<u32>,
+    /*the file timestamp played at clock_start*/
+    clock_timestamp: u32,
+    clock_start: Instant,
+    paused: bool,
+    /*the metadata and the sequence headers are sent again after a seek*/
+    send_headers: bool,
+    completed: bool,
+    completion_notified: bool,
+}
+
+impl VodPlayer {
+    /*start and duration are milliseconds, a zero duration plays a single frame*/
+    pub fn new(file: VodFile, start: u32, duration: Option<u32>) -> Self {
+        let mut player = Self {
+            file,
+            index: 0,
+            end_timestamp: None,
+            clock_timestamp: 0,
+            clock_start: Instant::now(),
+            paused: false,
+            send_headers: true,
+            completed: false,
+            completion_notified: false,
+        };
+        player.seek(start);
+        player.end_timestamp = duration.map(|duration| {
+            let start = player.file.timestamp(player.index).unwrap_or(start);
+            start.saturating_add(duration)
+        });
+        player
+    }
+
+    /*the playback starts again from the key frame before the timestamp*/
+    pub fn seek(&mut self, timestamp: u32) {
+        self.index = self.file.seek(timestamp);
+        self.clock_timestamp = self.file.timestamp(self.index).unwrap_or(timestamp);
+        self.clock_start = Instant::now();
+        self.send_headers = true;
+        self.completed = false;
+        self.completion_notified = false;
+    }
+
+    pub fn pause(&mut self) {
+        if !self.paused {
+            self.clock_timestamp = self.position();
+            self.paused = true;
+        }
+    }
+
+    pub fn unpause(&mut self) {
+        if self.paused {
+            self.clock_start = Instant::now();
+            self.paused = false;
+        }
+    }
+
+    /*the file timestamp being played*/
+    pub fn position(&self) -> u32 {
+        if self.paused {
+            return self.clock_timestamp;
+        }
+        let elapsed = self.clock_start.elapsed().as_millis() as u32;
+        self.clock_timestamp.saturating_add(elapsed)
+    }
+
+    /*the frames due to be sent, the headers are sent before the first frame
+    of the playback*/
+    pub fn due_frames(&mut self) -> Result<Vec<FrameData>, VodError> {
+        let mut frames = Vec::new();
+        if self.paused || self.completed {
+            return Ok(frames);
+        }
+
+        if self.send_headers {
+            self.send_headers = false;
+            let timestamp = self
+                .file
+                .timestamp(self.index)
+                .unwrap_or(self.clock_timestamp);
+            frames = self.file.read_headers(self.index, timestamp)?;
+        }
+
+        let send_until = self.position().saturating_add(SEND_AHEAD);
+        while let Some(timestamp) = self.file.timestamp(self.index) {
+            if self.end_timestamp.is_some_and(|end| timestamp > end) {
+                break;
+            }
+            if timestamp > send_until {
+                return Ok(frames);
+            }
+            frames.extend(self.file.read_frame(self.index)?);
+            self.index += 1;
+        }
+
+        self.completed = true;
+        Ok(frames)
+    }
+
+    /*true once when all the frames have been sent*/
+    pub fn take_completion(&mut self) -> bool {
+        if self.completed && !self.completion_notified {
+            self.completion_notified = true;
+            return true;
+        }
+        false
+    }
+
+    /*how long to wait for the client commands before the next frame is due*/
+    pub fn wait_time(&self) -> Duration {
+        if self.paused || self.completed {
+            return MAX_WAIT;
+        }
+        match self.file.timestamp(self.index) {
+            Some(timestamp) => {
+                let due = timestamp.saturating_sub(self.position().saturating_add(SEND_AHEAD));
+                Duration::from_millis(due as u64).clamp(MIN_WAIT, MAX_WAIT)
+            }
+            None => MIN_WAIT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VodPlayer;
+    use std::path::PathBuf;
+    use streamhub::vod::VodFile;
+
+    fn flv_tag(tag_type: u8, timestamp: u32, body: &[u8]) -> Vec<u8> {
+        let mut tag = vec![tag_type];
+        tag.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        tag.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+        tag.extend_from_slice(&[(timestamp >> 24) as u8, 0, 0, 0]);
+        tag.extend_from_slice(body);
+        tag.extend_from_slice(&(body.len() as u32 + 11).to_be_bytes());
+        tag
+    }
+
+    /*a key frame every second for ten seconds*/
+    fn write_file() -> PathBuf {
+        let mut data = vec![b'F', b'L', b'V', 1, 1, 0, 0, 0, 9, 0, 0, 0, 0];
+        data.extend(flv_tag(9, 0, &[0x17, 0x00, 0, 0, 0, 0x01]));
+        for timestamp in (0..10000).step_by(250) {
+            let frame_type = if timestamp % 1000 == 0 { 0x17 } else { 0x27 };
+            data.extend(flv_tag(9, timestamp, &[frame_type, 0x01, 0, 0, 0, 0xff]));
+        }
+        let path = std::env::temp_dir().join(format!("xiu_rtmp_vod_{}.flv", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_vod_player() {
+        let path = write_file();
+
+        /*the header and the frames of the first second are sent at once*/
+        let mut player = VodPlayer::new(VodFile::open(&path).unwrap(), 2500, None);
+        let frames = player.due_frames().unwrap();
+        assert_eq!(frames.len(), 1 + 5);
+        assert!(player.due_frames().unwrap().is_empty());
+        assert!(!player.take_completion());
+
+        player.pause();
+        let position = player.position();
+        assert!(position < 2100);
+        assert!(player.due_frames().unwrap().is_empty());
+        player.unpause();
+
+        /*a zero duration plays the key frame*/
+        let mut player = VodPlayer::new(VodFile::open(&path).unwrap(), 9900, Some(0));
+        assert_eq!(player.due_frames().unwrap().len(), 2);
+        assert!(player.take_completion());
+        assert!(!player.take_completion());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+#![allow(non_local_definitions)]
+
+use {
+    crate::session::errors::SessionError,
+    failure::{Backtrace, Fail},
+    std::fmt,
+    streamhub::vod::errors::VodError,
+    xflv::amf0::errors::Amf0WriteError,
+};
+
+#[derive(Debug)]
+pub struct VirtualChannelError {
+    pub value: VirtualChannelErrorValue,
+}
+
+#[derive(Debug, Fail)]
+pub enum VirtualChannelErrorValue {
+    #[fail(display = "session error: {}", _0)]
+    SessionError(#[cause] SessionError),
+    #[fail(display = "vod file error: {}", _0)]
+    VodError(#[cause] VodError),
+    #[fail(display = "amf0 write error: {}", _0)]
+    Amf0WriteError(#[cause] Amf0WriteError),
+    #[fail(display = "invalid schedule start time: {}", _0)]
+    InvalidStartTime(String),
+    #[fail(display = "the playlist and the schedule are empty")]
+    EmptyPlaylist,
+}
+
+impl From<SessionError> for VirtualChannelError {
+    fn from(error: SessionError) -> Self {
+        VirtualChannelError {
+            value: VirtualChannelErrorValue::SessionError(error),
+        }
+    }
+}
+
+impl From<VodError> for VirtualChannelError {
+    fn from(error: VodError) -> Self {
+        VirtualChannelError {
+            value: VirtualChannelErrorValue::VodError(error),
+        }
+    }
+}
+
+impl From<Amf0WriteError> for VirtualChannelError {
+    fn from(error: Amf0WriteError) -> Self {
+        VirtualChannelError {
+            value: VirtualChannelErrorValue::Amf0WriteError(error),
+        }
+    }
+}
+
+impl fmt::Display for VirtualChannelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.value, f)
+    }
+}
+
+impl Fail for VirtualChannelError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.value.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.value.backtrace()
+    }
+}
@@ -0,0 +1,482 @@
+pub mod errors;
+pub mod schedule;
+
+use {
+    crate::session::{common::Common, define::SessionType},
+    chrono::{Local, NaiveDateTime},
+    commonlib::config::VirtualChannelConfig,
+    errors::{VirtualChannelError, VirtualChannelErrorValue},
+    schedule::StartTime,
+    std::{collections::HashMap, io, path::Path, time::Duration},
+    streamhub::{
+        define::{FrameData, StreamHubEventSender},
+        vod::{errors::VodError, VodFile},
+    },
+    tokio::time::Instant,
+    xflv::amf0::amf0_writer::Amf0Writer,
+};
+
+/*the wait before the stream is published again after a failure*/
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+/*milliseconds between the last frame of a file and the first frame of the next one*/
+const FILE_GAP: u32 = 40;
+
+struct ScheduledProgram {
+    file: String,
+    start: StartTime,
+}
+
+/*
+ Publishes a playlist of recorded files as a live stream. The files are paced
+ in real time and the timestamps continue across the files, so the stream is
+ played like any other live publish. A scheduled program is started at its
+ wall-clock time, it cuts the file being played and the playlist continues
+ with the next file after it. A program which has already started when the
+ channel is published is joined at the current position.
+*/
+pub struct VirtualChannel {
+    app_name: String,
+    stream_name: String,
+    playlist: Vec<String>,
+    schedule: Vec<ScheduledProgram>,
+    event_producer: StreamHubEventSender,
+    gop_num: usize,
+    /*the next file of the playlist*/
+    playlist_index: usize,
+    /*the durations of the scheduled files, milliseconds*/
+    durations: HashMap<String, u32>,
+    /*the start of the program played last, the programs started before it
+    are not joined again*/
+    last_program_start: Option<NaiveDateTime>,
+    /*the timestamps of the stream are the milliseconds since the publish, the
+    RTMP timestamps are their lower 32 bits and wrap around after about 49.7
+    days*/
+    epoch: Instant,
+    next_timestamp: u64,
+}
+
+impl VirtualChannel {
+    pub fn new(
+        config: &VirtualChannelConfig,
+        event_producer: StreamHubEventSender,
+        gop_num: usize,
+    ) -> Result<Self, VirtualChannelError> {
+        let mut schedule = Vec::new();
+        for program in config.schedule.iter().flatten() {
+            let start = StartTime::parse(&program.start).ok_or_else(|| VirtualChannelError {
+                value: VirtualChannelErrorValue::InvalidStartTime(program.start.clone()),
+            })?;
+            schedule.push(ScheduledProgram {
+                file: program.file.clone(),
+                start,
+            });
+        }
+        if config.playlist.is_empty() && schedule.is_empty() {
+            return Err(VirtualChannelError {
+                value: VirtualChannelErrorValue::EmptyPlaylist,
+            });
+        }
+
+        Ok(Self {
+            app_name: config.app.clone(),
+            stream_name: config.stream.clone(),
+            playlist: config.playlist.clone(),
+            schedule,
+            event_producer,
+            gop_num,
+            playlist_index: 0,
+            durations: HashMap::new(),
+            last_program_start: None,
+            epoch: Instant::now(),
+            next_timestamp: 0,
+        })
+    }
+
+    pub async fn run(&mut self) -> Result<(), VirtualChannelError> {
+        loop {
+            let mut common =
+                Common::new(None, self.event_producer.clone(), SessionType::Client, None);
+            common.request_url = format!("virtual://{}/{}", self.app_name, self.stream_name);
+
+            if let Err(err) = common
+                .publish_to_channels(
+                    self.app_name.clone(),
+                    self.stream_name.clone(),
+                    self.gop_num,
+                )
+                .await
+            {
+                log::error!(
+                    "virtual channel {}/{} publish error: {}",
+                    self.app_name,
+                    self.stream_name,
+                    err
+                );
+                tokio::time::sleep(RETRY_INTERVAL).await;
+                continue;
+            }
+            log::info!(
+                "virtual channel {}/{} is published",
+                self.app_name,
+                self.stream_name
+            );
+
+            self.epoch = Instant::now();
+            self.next_timestamp = 0;
+            let result = self.play(&mut common).await;
+
+            common
+                .unpublish_to_channels(self.app_name.clone(), self.stream_name.clone())
+                .await?;
+            if let Err(err) = result {
+                log::error!(
+                    "virtual channel {}/{} error: {}",
+                    self.app_name,
+                    self.stream_name,
+                    err
+                );
+            }
+            tokio::time::sleep(RETRY_INTERVAL).await;
+        }
+    }
+
+    /*only returns if the frames cannot be published*/
+    async fn play(&mut self, common: &mut Common) -> Result<(), VirtualChannelError> {
+        let mut failures = 0;
+        loop {
+            let now = Local::now().naive_local();
+            let cut = self
+                .schedule
+                .iter()
+                .filter_map(|program| program.start.next_start(now))
+                .min();
+
+            let Some((path, start)) = self.next_item(now).await else {
+                /*nothing to play until the next program*/
+                let wait = cut.and_then(|cut| (cut - now).to_std().ok());
+                tokio::time::sleep(wait.unwrap_or(RETRY_INTERVAL)).await;
+                continue;
+            };
+
+            let cut = cut.and_then(|cut| (cut - now).to_std().ok());
+            let cut = cut.map(|cut| Instant::now() + cut);
+            match open_file(path.clone()).await {
+                Ok(mut file) => {
+                    log::info!(
+                        "virtual channel {}/{} plays {} from {}ms",
+                        self.app_name,
+                        self.stream_name,
+                        path,
+                        start
+                    );
+                    failures = 0;
+                    match self.play_file(common, &mut file, start, cut).await {
+                        Ok(()) => {}
+                        Err(VirtualChannelError {
+                            value: VirtualChannelErrorValue::VodError(err),
+                        }) => log::error!("virtual channel read file: {} error: {}", path, err),
+                        Err(err) => return Err(err),
+                    }
+                }
+                Err(err) => {
+                    log::error!("virtual channel open file: {} error: {}", path, err);
+                    /*do not spin if none of the files can be played*/
+                    failures += 1;
+                    if failures > self.playlist.len() + self.schedule.len() {
+                        failures = 0;
+                        tokio::time::sleep(RETRY_INTERVAL).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /*the file to be played and the start position(milliseconds) in it, the
+    latest program which is still running or the next file of the playlist*/
+    async fn next_item(&mut self, now: NaiveDateTime) -> Option<(String, u32)> {
+        let mut running: Option<(usize, NaiveDateTime, i64)> = None;
+        for (index, program) in self.schedule.iter().enumerate() {
+            let Some(start) = program.start.last_start(now) else {
+                continue;
+            };
+            if self
+                .last_program_start
+                .is_some_and(|last_start| start <= last_start)
+                || running.is_some_and(|(_, running_start, _)| running_start > start)
+            {
+                continue;
+            }
+
+            let duration = match self.durations.get(&program.file) {
+                Some(duration) => *duration,
+                None => match open_file(program.file.clone()).await {
+                    Ok(file) => {
+                        self.durations.insert(program.file.clone(), file.duration());
+                        file.duration()
+                    }
+                    Err(err) => {
+                        log::error!("virtual channel open file: {} error: {}", program.file, err);
+                        continue;
+                    }
+                },
+            };
+            let elapsed = (now - start).num_milliseconds();
+            if elapsed < duration as i64 {
+                running = Some((index, start, elapsed));
+            }
+        }
+
+        if let Some((index, start, elapsed)) = running {
+            self.last_program_start = Some(start);
+            return Some((self.schedule[index].file.clone(), elapsed as u32));
+        }
+
+        let file = self.playlist.get(self.playlist_index)?.clone();
+        self.playlist_index = (self.playlist_index + 1) % self.playlist.len();
+        Some((file, 0))
+    }
+
+    /*publish the frames from the key frame before the start position until the
+    end of the file or the cut*/
+    async fn play_file(
+        &mut self,
+        common: &mut Common,
+        file: &mut VodFile,
+        start: u32,
+        cut: Option<Instant>,
+    ) -> Result<(), VirtualChannelError> {
+        let first_index = file.seek(start);
+        let Some(first_timestamp) = file.timestamp(first_index) else {
+            return Ok(());
+        };
+        /*the timestamps keep up with the wall clock after a wait for a program*/
+        let base = self
+            .next_timestamp
+            .max(self.epoch.elapsed().as_millis() as u64);
+        let mut last_timestamp = base;
+
+        for header in file.read_headers(first_index, first_timestamp)? {
+            Self::publish_frame(common, header, rtmp_timestamp(base, 0)).await?;
+        }
+
+        for index in first_index..file.len() {
+            let offset = file
+                .timestamp(index)
+                .unwrap_or(first_timestamp)
+                .saturating_sub(first_timestamp);
+            let timestamp = base + offset as u64;
+            let due = self.epoch + Duration::from_millis(timestamp);
+            if cut.is_some_and(|cut| due >= cut) {
+                break;
+            }
+            tokio::time::sleep_until(due).await;
+
+            if let Some(frame) = file.read_frame(index)? {
+                Self::publish_frame(common, frame, rtmp_timestamp(base, offset)).await?;
+            }
+            last_timestamp = last_timestamp.max(timestamp);
+        }
+
+        self.next_timestamp = last_timestamp + FILE_GAP as u64;
+        Ok(())
+    }
+
+    async fn publish_frame(
+        common: &mut Common,
+        frame: FrameData,
+        timestamp: u32,
+    ) -> Result<(), VirtualChannelError> {
+        match frame {
+            FrameData::Video { mut data, .. } => {
+                common.on_video_data(&mut data, &timestamp).await?
+            }
+            FrameData::Audio { mut data, .. } => {
+                common.on_audio_data(&mut data, &timestamp).await?
+            }
+            FrameData::MetaData { data, .. } => {
+                /*the metadata of a rtmp publish is sent by @setDataFrame*/
+                let mut amf_writer = Amf0Writer::new();
+                amf_writer.write_string(&String::from("@setDataFrame"))?;
+                let mut metadata = amf_writer.extract_current_bytes();
+                metadata.extend_from_slice(&data[..]);
+                common.on_meta_data(&mut metadata, &timestamp).await?
+            }
+            FrameData::MediaInfo { .. } | FrameData::Discontinuity => {}
+        }
+        Ok(())
+    }
+}
+
+/*the RTMP timestamp of a frame offset(milliseconds) from the base timestamp of
+the stream*/
+fn rtmp_timestamp(base: u64, offset: u32) -> u32 {
+    (base as u32).wrapping_add(offset)
+}
+
+/*the frames are indexed when a file is opened*/
+async fn open_file(path: String) -> Result<VodFile, VodError> {
+    match tokio::task::spawn_blocking(move || VodFile::open(Path::new(&path))).await {
+        Ok(result) => result,
+        Err(err) => Err(io::Error::other(err).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VirtualChannel;
+    use crate::session::{common::Common, define::SessionType};
+    use chrono::NaiveDateTime;
+    use commonlib::config::{ScheduledProgramConfig, VirtualChannelConfig};
+    use std::{path::Path, time::Duration};
+    use streamhub::{
+        define::{
+            FrameData, NotifyInfo, StreamHubEvent, SubDataType, SubscribeType, SubscriberInfo,
+        },
+        stream::Protocol,
+        utils::{RandomDigitCount, Uuid},
+        vod::VodFile,
+        StreamsHub,
+    };
+    use tokio::{
+        sync::{mpsc, oneshot},
+        time::Instant,
+    };
+
+    fn write_file(name: &str) -> String {
+        let tag = |timestamp: u32, body: &[u8]| {
+            let mut tag = vec![9, 0, 0, body.len() as u8];
+            tag.extend_from_slice(&timestamp.to_be_bytes()[1..]);
+            tag.extend_from_slice(&[0, 0, 0, 0]);
+            tag.extend_from_slice(body);
+            tag.extend_from_slice(&(body.len() as u32 + 11).to_be_bytes());
+            tag
+        };
+        let mut data = vec![b'F', b'L', b'V', 1, 1, 0, 0, 0, 9, 0, 0, 0, 0];
+        for timestamp in (0..=10000).step_by(1000) {
+            data.extend(tag(timestamp, &[0x17, 0x01, 0, 0, 0, 0xff]));
+        }
+        let path = std::env::temp_dir().join(format!("xiu_{}_{}.flv", name, std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_next_item() {
+        let (show, filler) = (write_file("show"), write_file("filler"));
+        let config = VirtualChannelConfig {
+            enabled: true,
+            app: String::from("live"),
+            stream: String::from("channel"),
+            playlist: vec![filler.clone()],
+            schedule: Some(vec![ScheduledProgramConfig {
+                file: show.clone(),
+                start: String::from("12:00"),
+            }]),
+        };
+        let (event_producer, _) = mpsc::unbounded_channel();
+        let mut channel = VirtualChannel::new(&config, event_producer, 1).unwrap();
+
+        let now = |value: &str| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap();
+        /*the running program is joined once*/
+        assert_eq!(
+            channel.next_item(now("2024-05-01 12:00:04")).await,
+            Some((show.clone(), 4000))
+        );
+        assert_eq!(
+            channel.next_item(now("2024-05-01 12:00:08")).await,
+            Some((filler.clone(), 0))
+        );
+        /*the program of the next day*/
+        assert_eq!(
+            channel.next_item(now("2024-05-02 12:00:00")).await,
+            Some((show.clone(), 0))
+        );
+        assert_eq!(
+            channel.next_item(now("2024-05-02 12:00:20")).await,
+            Some((filler.clone(), 0))
+        );
+
+        std::fs::remove_file(show).unwrap();
+        std::fs::remove_file(filler).unwrap();
+    }
+
+    /*the frames are paced by the paused clock, which jumps to the due time of
+    the next frame*/
+    #[tokio::test(start_paused = true)]
+    async fn test_timestamps() {
+        let path = write_file("wrap");
+        let config = VirtualChannelConfig {
+            enabled: true,
+            app: String::from("live"),
+            stream: String::from("wrap"),
+            playlist: vec![path.clone()],
+            schedule: None,
+        };
+        let mut stream_hub = StreamsHub::new(None);
+        let event_producer = stream_hub.get_hub_event_sender();
+        tokio::spawn(async move { stream_hub.run().await });
+        let mut channel = VirtualChannel::new(&config, event_producer.clone(), 1).unwrap();
+
+        let mut common = Common::new(None, event_producer.clone(), SessionType::Client, None);
+        common
+            .publish_to_channels(String::from("live"), String::from("wrap"), 1)
+            .await
+            .unwrap();
+        let (result_sender, result_receiver) = oneshot::channel();
+        let subscribe_event = StreamHubEvent::Subscribe {
+            protocol: Protocol::Rtmp,
+            name: String::from("live/wrap"),
+            info: SubscriberInfo {
+                id: Uuid::new(RandomDigitCount::Four),
+                sub_type: SubscribeType::PlayerRtmp,
+                sub_data_type: SubDataType::Frame,
+                notify_info: NotifyInfo {
+                    request_url: String::from(""),
+                    remote_addr: String::from(""),
+                },
+            },
+            result_sender,
+        };
+        assert!(event_producer.send(subscribe_event).is_ok());
+        let mut frames = result_receiver
+            .await
+            .unwrap()
+            .unwrap()
+            .0
+            .frame_receiver
+            .unwrap();
+
+        /*the stream was published about 49.7 days ago, it wraps in the middle of
+        the first file*/
+        channel.next_timestamp = u32::MAX as u64 - 4999;
+        channel.epoch = Instant::now()
+            .checked_sub(Duration::from_millis(channel.next_timestamp))
+            .unwrap();
+        let mut file = VodFile::open(Path::new(&path)).unwrap();
+        for _ in 0..2 {
+            channel
+                .play_file(&mut common, &mut file, 0, None)
+                .await
+                .unwrap();
+        }
+        assert_eq!(channel.next_timestamp, u32::MAX as u64 + 15081);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut timestamps = Vec::new();
+        while let Ok(frame) = frames.try_recv() {
+            if let FrameData::Video { timestamp, .. } = frame {
+                timestamps.push(timestamp);
+            }
+        }
+        assert_eq!(timestamps.len(), 22);
+        assert_eq!(timestamps[0], u32::MAX - 4999);
+        assert_eq!(timestamps[21], 15040);
+        /*a second between the frames and the gap between the files*/
+        for (index, pair) in timestamps.windows(2).enumerate() {
+            let expected = if index == 10 { 40 } else { 1000 };
+            assert_eq!(pair[1].wrapping_sub(pair[0]), expected);
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+use chrono::{Duration, NaiveDateTime, NaiveTime};
+
+/*the wall-clock start of a scheduled program, in the local time*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartTime {
+    Daily(NaiveTime),
+    Once(NaiveDateTime),
+}
+
+impl StartTime {
+    /*"HH:MM[:SS]" every day or "YYYY-MM-DD HH:MM[:SS]" once*/
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+            if let Ok(date_time) = NaiveDateTime::parse_from_str(value, format) {
+                return Some(StartTime::Once(date_time));
+            }
+        }
+        for format in ["%H:%M:%S", "%H:%M"] {
+            if let Ok(time) = NaiveTime::parse_from_str(value, format) {
+                return Some(StartTime::Daily(time));
+            }
+        }
+        None
+    }
+
+    /*the latest start at or before now*/
+    pub fn last_start(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self {
+            StartTime::Daily(time) => {
+                let today = now.date().and_time(*time);
+                Some(if today <= now {
+                    today
+                } else {
+                    today - Duration::days(1)
+                })
+            }
+            StartTime::Once(date_time) => (*date_time <= now).then_some(*date_time),
+        }
+    }
+
+    /*the first start after now*/
+    pub fn next_start(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        match self {
+            StartTime::Daily(time) => {
+                let today = now.date().and_time(*time);
+                Some(if today > now {
+                    today
+                } else {
+                    today + Duration::days(1)
+                })
+            }
+            StartTime::Once(date_time) => (*date_time > now).then_some(*date_time),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StartTime;
+    use chrono::NaiveDateTime;
+
+    fn date_time(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_start_time() {
+        let now = date_time("2024-05-01 12:00:00");
+
+        let daily = StartTime::parse("20:30").unwrap();
+        assert_eq!(
+            daily.last_start(now),
+            Some(date_time("2024-04-30 20:30:00"))
+        );
+        assert_eq!(
+            daily.next_start(now),
+            Some(date_time("2024-05-01 20:30:00"))
+        );
+        let daily = StartTime::parse("12:00:00").unwrap();
+        assert_eq!(daily.last_start(now), Some(now));
+        assert_eq!(
+            daily.next_start(now),
+            Some(date_time("2024-05-02 12:00:00"))
+        );
+
+        let once = StartTime::parse("2024-05-01 18:00").unwrap();
+        assert_eq!(once.last_start(now), None);
+        assert_eq!(once.next_start(now), Some(date_time("2024-05-01 18:00:00")));
+        assert_eq!(once.next_start(date_time("2024-05-02 00:00:00")), None);
+
+        assert_eq!(StartTime::parse("25:00"), None);
+        assert_eq!(StartTime::parse("tomorrow"), None);
+    }
+}